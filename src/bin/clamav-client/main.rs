@@ -0,0 +1,374 @@
+//! Command-line scanner that talks to `clamd` like `clamdscan`
+//!
+//! Files are streamed to `clamd` with the INSTREAM command, so the scanner
+//! works with remote servers and does not need access to `clamd`'s file
+//! system. Results are printed like `clamdscan` does or as JSON, NDJSON or
+//! SARIF. The exit code is 0 if no virus was found, 1 if at least one virus
+//! was found and 2 if an error occurred.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use clamav_client::dir::{ScanDirOptions, SymlinkPolicy};
+use clamav_client::hash::HashAlgorithms;
+use clamav_client::report::{ReportFormat, ReportSummary, ScanRecord, ScanReport, ScanStatus};
+use clamav_client::{ClamAvClient, ClamAvSync, RetryPolicy, Tcp};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+mod watch;
+
+/// Scans files, directories or standard input with a ClamAV server
+#[derive(Debug, Parser)]
+#[command(
+    name = "clamav-client",
+    version,
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Watches a directory and scans new and modified files until interrupted
+    Watch(watch::WatchArgs),
+}
+
+/// Options for connecting to clamd
+#[derive(Debug, Args)]
+struct Connection {
+    /// Address of a clamd TCP socket
+    #[arg(long, value_name = "HOST:PORT", default_value = "127.0.0.1:3310")]
+    tcp: String,
+
+    /// Path of a clamd Unix socket, takes precedence over `--tcp`
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Chunk size in bytes for streaming data to clamd
+    #[arg(long, value_name = "BYTES")]
+    chunk_size: Option<usize>,
+
+    /// Report files larger than this as errors instead of sending them
+    #[arg(long, value_name = "BYTES")]
+    max_stream_length: Option<u64>,
+
+    /// Connect, read and write timeout in seconds
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Number of times to retry connecting to clamd
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+}
+
+/// Options for selecting files in directories
+#[derive(Debug, Args)]
+struct Filter {
+    /// Scan directories recursively
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// How to treat symbolic links inside directories
+    #[arg(long, value_enum, default_value_t = Symlinks::Files)]
+    symlinks: Symlinks,
+
+    /// Only scan files matching the glob pattern, can be repeated
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files and directories matching the glob pattern, can be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Skip files larger than this
+    #[arg(long, value_name = "BYTES")]
+    max_filesize: Option<u64>,
+
+    /// Skip files and directories starting with `.`
+    #[arg(long)]
+    skip_hidden: bool,
+}
+
+/// Options for printing results
+#[derive(Debug, Args)]
+struct Printing {
+    /// Only print infected files
+    #[arg(short, long)]
+    infected: bool,
+
+    /// Only print errors
+    #[arg(short, long, conflicts_with = "infected")]
+    quiet: bool,
+}
+
+#[derive(Debug, Args)]
+struct ScanArgs {
+    /// Files or directories to scan, `-` reads from standard input
+    #[arg(default_value = ".")]
+    paths: Vec<PathBuf>,
+
+    #[command(flatten)]
+    connection: Connection,
+
+    #[command(flatten)]
+    filter: Filter,
+
+    /// Number of files to scan concurrently
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    #[command(flatten)]
+    printing: Printing,
+
+    /// Do not print the scan summary
+    #[arg(long)]
+    no_summary: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Write the results to a file instead of standard output
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// Output format of the scan results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// clamdscan-style lines and summary
+    Text,
+    /// Single JSON document with records and summary
+    Json,
+    /// One JSON document per line and scanned file
+    Ndjson,
+    /// SARIF 2.1.0 log
+    Sarif,
+}
+
+/// How symbolic links are treated while walking directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Symlinks {
+    /// Ignore symbolic links
+    Skip,
+    /// Scan linked files, but do not descend into linked directories
+    Files,
+    /// Follow all symbolic links
+    Follow,
+}
+
+/// Work that is done with a configured client
+trait Task {
+    fn run<C>(self, client: ClamAvClient<C>, backend: &str) -> io::Result<ReportSummary>
+    where
+        C: ClamAvSync + Clone + Send + 'static;
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Some(Command::Watch(args)) => connect(&args.connection, args),
+        None => connect(&cli.scan.connection, &cli.scan),
+    };
+    match result {
+        Ok(summary) if summary.infected > 0 => ExitCode::from(1),
+        Ok(summary) if summary.errors > 0 => ExitCode::from(2),
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn connect(connection: &Connection, task: impl Task) -> io::Result<ReportSummary> {
+    #[cfg(unix)]
+    if let Some(path) = &connection.socket {
+        let client = configure(connection, clamav_client::Socket(path.clone()));
+        return task.run(client, &format!("unix://{}", path.display()));
+    }
+
+    let address = resolve(&connection.tcp)?;
+    let client = configure(connection, Tcp(address));
+    task.run(client, &format!("tcp://{address}"))
+}
+
+fn resolve(address: &str) -> io::Result<SocketAddr> {
+    address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("could not resolve {address}"),
+        )
+    })
+}
+
+fn configure<T>(args: &Connection, transport: T) -> ClamAvClient<T> {
+    let mut builder = ClamAvClient::builder(transport).retry_policy(RetryPolicy {
+        max_retries: args.retries,
+        ..RetryPolicy::default()
+    });
+    if let Some(chunk_size) = args.chunk_size {
+        builder = builder.chunk_size(chunk_size);
+    }
+    if let Some(max_stream_length) = args.max_stream_length {
+        builder = builder.max_stream_length(max_stream_length);
+    }
+    if let Some(timeout) = args.timeout {
+        let timeout = Duration::from_secs(timeout);
+        builder = builder
+            .connect_timeout(timeout)
+            .read_timeout(timeout)
+            .write_timeout(timeout);
+    }
+    builder.build()
+}
+
+fn dir_options(args: &Filter) -> ScanDirOptions {
+    let symlinks = match args.symlinks {
+        Symlinks::Skip => SymlinkPolicy::Skip,
+        Symlinks::Files => SymlinkPolicy::FollowFiles,
+        Symlinks::Follow => SymlinkPolicy::Follow,
+    };
+    let mut options = ScanDirOptions::new()
+        .recursive(args.recursive)
+        .symlinks(symlinks)
+        .skip_hidden(args.skip_hidden);
+    for pattern in &args.include {
+        options = options.include(pattern);
+    }
+    for pattern in &args.exclude {
+        options = options.exclude(pattern);
+    }
+    if let Some(max_filesize) = args.max_filesize {
+        options = options.max_file_size(max_filesize);
+    }
+    options
+}
+
+impl Task for &ScanArgs {
+    fn run<C>(self, client: ClamAvClient<C>, backend: &str) -> io::Result<ReportSummary>
+    where
+        C: ClamAvSync + Clone + Send + 'static,
+    {
+        let args = self;
+        let start = Instant::now();
+        let mut output: Box<dyn Write> = match &args.output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stdout().lock()),
+        };
+        let mut report = ScanReport::new();
+
+        for path in &args.paths {
+            let records: Box<dyn Iterator<Item = ScanRecord>> = if path.as_os_str() == "-" {
+                Box::new(std::iter::once(scan_stdin(&client)))
+            } else {
+                let options = dir_options(&args.filter)
+                    .concurrency(args.jobs.max(1))
+                    .hash(HashAlgorithms::all());
+                let scanned = client.scan_dir(path, options);
+                Box::new(scanned.map(|scanned| {
+                    let result = scanned
+                        .response
+                        .and_then(|response| client.verdict(&response));
+                    let record =
+                        ScanRecord::from_result(scanned.path.display().to_string(), result)
+                            .with_duration(scanned.duration);
+                    let record = match scanned.size {
+                        Some(size) => record.with_size(size),
+                        None => record,
+                    };
+                    match &scanned.digest {
+                        Some(digest) => record.with_digest(digest),
+                        None => record,
+                    }
+                }))
+            };
+
+            for record in records {
+                let record = record.with_backend(backend);
+                match args.format {
+                    Format::Text => print_record(&args.printing, &mut output, &record)?,
+                    Format::Ndjson => {
+                        record.write_ndjson(&mut output)?;
+                        output.flush()?;
+                    }
+                    Format::Json | Format::Sarif => {}
+                }
+                report.push(record);
+            }
+        }
+        report.set_duration(start.elapsed());
+
+        match args.format {
+            Format::Text if !args.no_summary => print_summary(&mut output, report.summary())?,
+            Format::Json => report.write(ReportFormat::Json, &mut output)?,
+            Format::Sarif => report.write(ReportFormat::Sarif, &mut output)?,
+            _ => {}
+        }
+        output.flush()?;
+        Ok(report.summary().clone())
+    }
+}
+
+fn scan_stdin<C: ClamAvSync>(client: &ClamAvClient<C>) -> ScanRecord {
+    let start = Instant::now();
+    // Standard input is streamed, the client stops at the maximum stream length
+    let input = io::stdin().lock();
+    let record = match client.scan_reader_hashed(input, None, HashAlgorithms::all()) {
+        Ok(hashed) => ScanRecord::from_result("stream", client.verdict(&hashed.response))
+            .with_digest(&hashed.digest),
+        Err(err) => ScanRecord::from_result("stream", Err(err)),
+    };
+    record.with_duration(start.elapsed())
+}
+
+fn print_record(args: &Printing, output: &mut dyn Write, record: &ScanRecord) -> io::Result<()> {
+    match record.status {
+        ScanStatus::Clean if !args.infected && !args.quiet => {
+            writeln!(output, "{}: OK", record.path)?;
+        }
+        ScanStatus::Infected if !args.quiet => {
+            for signature in &record.signatures {
+                writeln!(output, "{}: {signature} FOUND", record.path)?;
+            }
+        }
+        ScanStatus::Error => {
+            let message = record.error.as_deref().unwrap_or_default();
+            if message.ends_with("ERROR") {
+                writeln!(output, "{}: {message}", record.path)?;
+            } else {
+                writeln!(output, "{}: {message} ERROR", record.path)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn print_summary(output: &mut dyn Write, summary: &ReportSummary) -> io::Result<()> {
+    let seconds = summary.duration.as_secs();
+    writeln!(output)?;
+    writeln!(output, "----------- SCAN SUMMARY -----------")?;
+    writeln!(output, "Scanned files: {}", summary.scanned)?;
+    writeln!(output, "Infected files: {}", summary.infected)?;
+    writeln!(output, "Total errors: {}", summary.errors)?;
+    writeln!(
+        output,
+        "Time: {:.3} sec ({} m {} s)",
+        summary.duration.as_secs_f64(),
+        seconds / 60,
+        seconds % 60
+    )
+}
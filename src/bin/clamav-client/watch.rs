@@ -0,0 +1,168 @@
+//! `watch` subcommand
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+#[cfg(feature = "quarantine")]
+use clamav_client::quarantine::Quarantine;
+use clamav_client::report::{ReportSummary, ScanRecord, ScanReport};
+use clamav_client::watch::{Action, ActionTaken, WatchOptions, Watcher};
+use clamav_client::{ClamAvClient, ClamAvSync};
+use clap::{Args, ValueEnum};
+
+use super::{dir_options, print_record, Connection, Filter, Printing, Task};
+
+#[derive(Debug, Args)]
+pub(crate) struct WatchArgs {
+    /// Directory to watch
+    dir: PathBuf,
+
+    #[command(flatten)]
+    pub(crate) connection: Connection,
+
+    #[command(flatten)]
+    filter: Filter,
+
+    /// Milliseconds without events before a file is checked
+    #[arg(long, value_name = "MS", default_value_t = 200)]
+    debounce: u64,
+
+    /// Milliseconds the size and modification time of a file must stay the
+    /// same before it is scanned
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    settle: u64,
+
+    /// Also scan the files that exist when watching starts
+    #[arg(long)]
+    scan_existing: bool,
+
+    /// What to do with infected files
+    #[arg(long, value_enum, default_value_t = OnVerdict::Keep)]
+    on_infected: OnVerdict,
+
+    /// What to do with files that could not be scanned
+    #[arg(long, value_enum, default_value_t = OnVerdict::Keep)]
+    on_error: OnVerdict,
+
+    /// Directory files are moved to by `move`, must not be inside the
+    /// watched directory
+    #[arg(
+        long,
+        value_name = "DIR",
+        required_if_eq_any = [("on_infected", "move"), ("on_error", "move")]
+    )]
+    move_to: Option<PathBuf>,
+
+    /// Quarantine directory files are moved to by `quarantine`, must not be
+    /// inside the watched directory
+    #[cfg(feature = "quarantine")]
+    #[arg(
+        long,
+        value_name = "DIR",
+        required_if_eq_any = [("on_infected", "quarantine"), ("on_error", "quarantine")]
+    )]
+    quarantine_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    printing: Printing,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = WatchFormat::Text)]
+    format: WatchFormat,
+}
+
+/// What to do with a file after it was scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnVerdict {
+    /// Leave the file where it is
+    Keep,
+    /// Delete the file
+    Delete,
+    /// Move the file to the `--move-to` directory
+    Move,
+    /// Move the file into the `--quarantine-dir` quarantine
+    #[cfg(feature = "quarantine")]
+    Quarantine,
+}
+
+/// Output format of the watch results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum WatchFormat {
+    /// clamdscan-style lines
+    Text,
+    /// One JSON document per line and scanned file
+    Ndjson,
+}
+
+impl WatchArgs {
+    fn action(&self, on_verdict: OnVerdict) -> io::Result<Action> {
+        Ok(match (on_verdict, &self.move_to) {
+            (OnVerdict::Delete, _) => Action::Delete,
+            (OnVerdict::Move, Some(dir)) => Action::MoveTo(dir.clone()),
+            #[cfg(feature = "quarantine")]
+            (OnVerdict::Quarantine, _) => match &self.quarantine_dir {
+                Some(dir) => Action::Quarantine(Quarantine::open(dir)?),
+                None => Action::Keep,
+            },
+            _ => Action::Keep,
+        })
+    }
+}
+
+impl Task for &WatchArgs {
+    fn run<C>(self, client: ClamAvClient<C>, backend: &str) -> io::Result<ReportSummary>
+    where
+        C: ClamAvSync + Clone + Send + 'static,
+    {
+        let options = WatchOptions::new()
+            .filter(dir_options(&self.filter))
+            .debounce(Duration::from_millis(self.debounce))
+            .settle(Duration::from_millis(self.settle))
+            .scan_existing(self.scan_existing)
+            .on_infected(self.action(self.on_infected)?)
+            .on_error(self.action(self.on_error)?);
+        let watcher = Watcher::new(&self.dir, options)?;
+        let stop = watcher.stop_handle();
+        let mut output = io::stdout().lock();
+        let mut report = ScanReport::new();
+        let mut result = Ok(());
+
+        watcher.run(&client, |event| {
+            let path = event.path.display().to_string();
+            let mut record = ScanRecord::from_result(&path, event.verdict).with_backend(backend);
+            if let Some(size) = event.size {
+                record = record.with_size(size);
+            }
+            let action = event.action.map(|taken| match taken {
+                Ok(ActionTaken::Deleted) => "Removed.".to_string(),
+                Ok(ActionTaken::Moved(target)) => format!("moved to '{}'.", target.display()),
+                #[cfg(feature = "quarantine")]
+                Ok(ActionTaken::Quarantined(entry)) => format!("quarantined as {}.", entry.id),
+                Err(err) => format!("{err} ERROR"),
+            });
+            if let Some(action) = &action {
+                record = record.with_action(action.trim_end_matches('.'));
+            }
+
+            let written = match self.format {
+                WatchFormat::Text => {
+                    print_record(&self.printing, &mut output, &record).and_then(|_| match &action {
+                        Some(action) => writeln!(output, "{path}: {action}"),
+                        None => Ok(()),
+                    })
+                }
+                WatchFormat::Ndjson => record.write_ndjson(&mut output),
+            };
+            if let Err(err) = written.and_then(|_| output.flush()) {
+                result = Err(err);
+                stop.stop();
+            }
+            report.push(record);
+        });
+
+        result.map(|_| report.summary().clone())
+    }
+}
@@ -0,0 +1,89 @@
+//! HTTP gateway that scans uploads with ClamAV and answers with JSON
+//!
+//! `POST /scan` scans the raw request body or every part of a
+//! `multipart/form-data` upload. `GET /health`, `GET /version` and
+//! `GET /stats` report on the clamd server behind the gateway.
+
+use std::{io, process::ExitCode};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use clamav_client::gateway::{Gateway, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_CONCURRENT_SCANS};
+use clamav_client::ClamAvAsync;
+use clap::Parser;
+use tokio::net::TcpListener;
+
+/// Scans HTTP uploads with ClamAV
+#[derive(Debug, Parser)]
+#[command(name = "clamav-gateway", version)]
+struct Cli {
+    /// Address to listen on for HTTP requests
+    #[arg(long, value_name = "HOST:PORT", default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// TCP address of the clamd server
+    #[arg(long, value_name = "HOST:PORT", default_value = "127.0.0.1:3310")]
+    tcp: String,
+
+    /// Path of clamd's Unix socket, used instead of TCP
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Reject request bodies larger than this
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_BODY_SIZE)]
+    max_body_size: u64,
+
+    /// Number of scan requests served at the same time
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_CONCURRENT_SCANS)]
+    concurrency: usize,
+
+    /// Chunk size for streaming bodies to clamd
+    #[arg(long, value_name = "BYTES")]
+    chunk_size: Option<usize>,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .and_then(|runtime| runtime.block_on(run(&cli)));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+async fn run(cli: &Cli) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(path) = &cli.socket {
+        return serve(cli, clamav_client::tokio::Socket(path.clone())).await;
+    }
+    let address = tokio::net::lookup_host(&cli.tcp)
+        .await?
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no address for {}", cli.tcp),
+            )
+        })?;
+    serve(cli, clamav_client::tokio::Tcp(address)).await
+}
+
+async fn serve<T: ClamAvAsync + Send + Sync + 'static>(cli: &Cli, transport: T) -> io::Result<()> {
+    let mut gateway = Gateway::new(transport)
+        .max_body_size(cli.max_body_size)
+        .max_concurrent_scans(cli.concurrency);
+    if let Some(chunk_size) = cli.chunk_size {
+        gateway = gateway.chunk_size(chunk_size);
+    }
+    let listener = TcpListener::bind(&cli.listen).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    gateway.serve(listener).await
+}
@@ -0,0 +1,158 @@
+//! clamd-compatible proxy that forwards scans to a pool of clamd servers
+//!
+//! The proxy listens on a TCP address and/or a Unix socket, so existing
+//! `clamdscan --stream` setups only need to point to the proxy. Requests are
+//! spread over the backends, failed backends are skipped for a while, repeated
+//! scans of identical data are answered from a cache and every command is
+//! written to standard output as an access log line.
+
+use std::{io, path::PathBuf, process::ExitCode, time::Duration};
+
+use clamav_client::cache::{CachedClient, MemoryCache};
+use clamav_client::proxy::{
+    AccessEntry, Backend, Pool, Proxy, Strategy, DEFAULT_IDLE_TIMEOUT, DEFAULT_MAX_CONNECTIONS,
+    DEFAULT_MAX_STREAM_LENGTH, DEFAULT_READ_TIMEOUT,
+};
+use clamav_client::{ClamAvSync, Timeouts};
+use clap::{Parser, ValueEnum};
+
+/// Forwards clamd requests to a pool of clamd servers
+#[derive(Debug, Parser)]
+#[command(name = "clamav-proxy", version)]
+struct Cli {
+    /// TCP address to listen on
+    #[arg(long, value_name = "HOST:PORT")]
+    tcp: Option<String>,
+
+    /// Path of a Unix socket to listen on
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// clamd server as `HOST:PORT` or `unix:PATH`, can be repeated
+    #[arg(long = "backend", value_name = "ADDRESS", required = true)]
+    backends: Vec<Backend>,
+
+    /// How to pick the backend for a request
+    #[arg(long, value_enum, default_value_t = Balancing::RoundRobin)]
+    strategy: Balancing,
+
+    /// Seconds an unreachable or timed-out backend is skipped
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    cooldown: u64,
+
+    /// Connect, read and write timeout in seconds for backends
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Number of verdicts to cache by content hash, 0 disables the cache
+    #[arg(long, value_name = "N", default_value_t = 10_000)]
+    cache_size: usize,
+
+    /// Reject streams larger than this like clamd's `StreamMaxLength`
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_STREAM_LENGTH)]
+    max_stream_length: u64,
+
+    /// Maximum number of client connections served at the same time
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_CONNECTIONS)]
+    max_connections: usize,
+
+    /// Seconds to wait for data from a client like clamd's `ReadTimeout`, 0 disables it
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_READ_TIMEOUT.as_secs())]
+    read_timeout: u64,
+
+    /// Seconds to wait for the next command like clamd's `IdleTimeout`, 0 disables it
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_IDLE_TIMEOUT.as_secs())]
+    idle_timeout: u64,
+
+    /// Do not write access log lines
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+/// Load balancing strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Balancing {
+    /// Use the backends in turn
+    RoundRobin,
+    /// Use the backend with the fewest open connections
+    LeastConnections,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(cli: &Cli) -> io::Result<()> {
+    let timeout = cli.timeout.map(Duration::from_secs);
+    let pool = Pool::new(cli.backends.clone())
+        .strategy(match cli.strategy {
+            Balancing::RoundRobin => Strategy::RoundRobin,
+            Balancing::LeastConnections => Strategy::LeastConnections,
+        })
+        .cooldown(Duration::from_secs(cli.cooldown))
+        .timeouts(Timeouts {
+            connect: timeout,
+            read: timeout,
+            write: timeout,
+        });
+
+    if cli.cache_size > 0 {
+        serve(
+            cli,
+            CachedClient::new(pool, MemoryCache::new(cli.cache_size)),
+        )
+    } else {
+        serve(cli, pool)
+    }
+}
+
+fn serve<U: ClamAvSync + Send + Sync + 'static>(cli: &Cli, upstream: U) -> io::Result<()> {
+    let mut proxy = Proxy::new(upstream)
+        .max_stream_length(cli.max_stream_length)
+        .max_connections(cli.max_connections)
+        .read_timeout(Duration::from_secs(cli.read_timeout))
+        .idle_timeout(Duration::from_secs(cli.idle_timeout));
+    if !cli.quiet {
+        proxy = proxy.access_log(|entry: &AccessEntry| println!("{entry}"));
+    }
+
+    let tcp = match &cli.tcp {
+        Some(address) => {
+            let server = proxy.bind_tcp(address)?;
+            eprintln!("Listening on {}", server.transport().0);
+            Some(server)
+        }
+        None => None,
+    };
+    #[cfg(unix)]
+    let socket = match &cli.socket {
+        Some(path) => {
+            let server = proxy.bind_socket(path)?;
+            eprintln!("Listening on unix:{}", path.display());
+            Some(server)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let socket: Option<clamav_client::proxy::ProxyServer<()>> = None;
+
+    match (tcp, socket) {
+        (Some(tcp), _) => tcp.wait(),
+        (None, Some(socket)) => socket.wait(),
+        (None, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "nothing to listen on, use --tcp or --socket",
+            ))
+        }
+    }
+    Ok(())
+}
@@ -3,20 +3,48 @@ use std::{
     io::{Read, Write},
     net::TcpStream,
     path::Path,
+    time::{Instant, SystemTime},
 };
 
 #[cfg(unix)]
 use std::os::unix::net::UnixStream;
 
+#[cfg(feature = "hash")]
+use crate::hash::{Digester, HashAlgorithms, HashedResponse, HashingReader};
+#[cfg(feature = "mime")]
+use crate::mime::ScannedPart;
 use crate::{
-    IoResult, Socket, Tcp, DEFAULT_CHUNK_SIZE, END_OF_STREAM, INSTREAM, PING, SHUTDOWN, VERSION,
+    dir::{ScanDirIter, ScanDirOptions},
+    health::{HealthCheck, HealthReport, HealthThresholds, EICAR},
+    progress::{ProgressObserver, ProgressReader},
+    trace::{Operation, Sent},
+    IoResult, Socket, Tcp, Timeouts, DEFAULT_CHUNK_SIZE, END_OF_STREAM, INSTREAM, PING, SHUTDOWN,
+    STATS, VERSION,
 };
 
 impl ClamAvSync for Tcp {
     type Stream = TcpStream;
 
     fn connect(&self) -> std::io::Result<Self::Stream> {
-        TcpStream::connect(self.0)
+        let operation = Operation::connect(&self.0);
+        let result = operation.in_scope(|| TcpStream::connect(self.0));
+        operation.finish(&result);
+        result
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> std::io::Result<Self::Stream> {
+        let operation = Operation::connect(&self.0);
+        let result = operation.in_scope(|| {
+            let stream = match timeouts.connect {
+                Some(timeout) => TcpStream::connect_timeout(&self.0, timeout)?,
+                None => TcpStream::connect(self.0)?,
+            };
+            stream.set_read_timeout(timeouts.read)?;
+            stream.set_write_timeout(timeouts.write)?;
+            Ok(stream)
+        });
+        operation.finish(&result);
+        result
     }
 }
 
@@ -25,7 +53,23 @@ impl ClamAvSync for Socket {
     type Stream = UnixStream;
 
     fn connect(&self) -> std::io::Result<Self::Stream> {
-        UnixStream::connect(&self.0)
+        let operation = Operation::connect(&self.0.display());
+        let result = operation.in_scope(|| UnixStream::connect(&self.0));
+        operation.finish(&result);
+        result
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> std::io::Result<Self::Stream> {
+        let operation = Operation::connect(&self.0.display());
+        let result = operation.in_scope(|| {
+            // `UnixStream` has no `connect_timeout`, `timeouts.connect` is not applied
+            let stream = UnixStream::connect(&self.0)?;
+            stream.set_read_timeout(timeouts.read)?;
+            stream.set_write_timeout(timeouts.write)?;
+            Ok(stream)
+        });
+        operation.finish(&result);
+        result
     }
 }
 
@@ -36,6 +80,18 @@ pub trait ClamAvSync {
     /// Connecting to the ClamAV instance
     fn connect(&self) -> std::io::Result<Self::Stream>;
 
+    /// Connecting to the ClamAV instance, applying the given [`Timeouts`]
+    ///
+    /// The default implementation ignores the timeouts and calls
+    /// [`ClamAvSync::connect`]. [`Tcp`] and [`Socket`] apply them to the
+    /// underlying socket, except the connect timeout, which [`Socket`]
+    /// ignores because the standard library cannot bound a Unix socket
+    /// connect.
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> std::io::Result<Self::Stream> {
+        let _ = timeouts;
+        self.connect()
+    }
+
     /// Sends a ping request to ClamAV
     ///
     /// This function establishes a connection to a ClamAV server and sends the PING
@@ -71,6 +127,48 @@ pub trait ClamAvSync {
         send_command(stream, VERSION)
     }
 
+    /// Gets statistics about the ClamAV server
+    ///
+    /// This function establishes a connection to a ClamAV server and sends the
+    /// STATS command to it. The response describes the thread pool, the queue
+    /// and the memory usage of the server and ends with `END`.
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn get_stats(&self) -> IoResult {
+        let stream = self.connect()?;
+        send_command(stream, STATS)
+    }
+
+    /// Checks the health of the ClamAV server
+    ///
+    /// This function sends `PING` and measures its round-trip time, sends
+    /// `VERSION` to determine the age of the signatures and, if enabled in
+    /// `thresholds`, scans the [`EICAR`] test string with
+    /// [`scan_buffer`](Self::scan_buffer). Later checks are skipped if the
+    /// server is unreachable.
+    ///
+    /// # Arguments
+    ///
+    /// * `thresholds`: Limits above which the server is considered degraded or unhealthy
+    ///
+    /// # Returns
+    ///
+    /// A [`HealthReport`] with the results of all checks and the overall status
+    fn health(&self, thresholds: &HealthThresholds) -> HealthReport {
+        let mut check = HealthCheck::new(thresholds);
+        let started = Instant::now();
+        let response = self.ping();
+        if check.ping(response, started.elapsed()) {
+            check.version(self.get_version(), SystemTime::now());
+            if check.wants_self_test() {
+                check.self_test(self.scan_buffer(EICAR, None));
+            }
+        }
+        check.finish()
+    }
+
     /// Scans a file for viruses
     ///
     /// This function reads data from a file located at the specified `file_path`
@@ -91,8 +189,7 @@ pub trait ClamAvSync {
         chunk_size: Option<usize>,
     ) -> IoResult {
         let file = File::open(file_path)?;
-        let stream = self.connect()?;
-        scan(file, chunk_size, stream)
+        self.scan_reader(file, chunk_size)
     }
 
     /// Scans a data buffer for viruses
@@ -110,8 +207,216 @@ pub trait ClamAvSync {
     /// An [`IoResult`] containing the server's response as a vector of bytes
     ///
     fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+        self.scan_reader(buffer, chunk_size)
+    }
+
+    /// Scans data from a reader for viruses
+    ///
+    /// This function reads data from `reader` until it is exhausted and streams
+    /// it to a ClamAV server for scanning. The default implementations of the
+    /// other scan methods send their input through this method, so clients
+    /// that wrap another client can apply their behavior to all of them by
+    /// overriding it.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`: The reader to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn scan_reader<R: Read>(&self, reader: R, chunk_size: Option<usize>) -> IoResult {
         let stream = self.connect()?;
-        scan(buffer, chunk_size, stream)
+        scan(reader, chunk_size, stream)
+    }
+
+    /// Scans a file for viruses and reports the progress
+    ///
+    /// This function works like [`scan_file`](Self::scan_file) and reports
+    /// every phase of the scan and every sent chunk to `observer`. The total
+    /// number of bytes is the size of the file when it was opened.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path`: The path to the file to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `observer`: Receives the [`ScanProgress`](crate::progress::ScanProgress) reports
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn scan_file_with_progress<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+        observer: &dyn ProgressObserver,
+    ) -> IoResult {
+        let file = File::open(file_path)?;
+        let length = file.metadata()?.len();
+        let mut input = ProgressReader::new(file, observer, Some(length));
+        let result = self.scan_reader(&mut input, chunk_size);
+        input.finish(result)
+    }
+
+    /// Scans a data buffer for viruses and reports the progress
+    ///
+    /// This function works like [`scan_buffer`](Self::scan_buffer) and
+    /// reports every phase of the scan and every sent chunk to `observer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: The data to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `observer`: Receives the [`ScanProgress`](crate::progress::ScanProgress) reports
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn scan_buffer_with_progress(
+        &self,
+        buffer: &[u8],
+        chunk_size: Option<usize>,
+        observer: &dyn ProgressObserver,
+    ) -> IoResult {
+        let mut input = ProgressReader::new(buffer, observer, Some(buffer.len() as u64));
+        let result = self.scan_reader(&mut input, chunk_size);
+        input.finish(result)
+    }
+
+    /// Scans a file for viruses and hashes its content
+    ///
+    /// This function works like [`scan_file`](Self::scan_file) and computes
+    /// the selected digests over the bytes sent to ClamAV, so the file is read
+    /// only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path`: The path to the file to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `algorithms`: The digests to compute
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`](std::io::Result) containing the server's response and
+    /// the [`ScanDigest`](crate::hash::ScanDigest) of the file
+    #[cfg(feature = "hash")]
+    fn scan_file_hashed<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+        algorithms: HashAlgorithms,
+    ) -> std::io::Result<HashedResponse> {
+        let file = File::open(file_path)?;
+        self.scan_reader_hashed(file, chunk_size, algorithms)
+    }
+
+    /// Scans a data buffer for viruses and hashes it
+    ///
+    /// This function works like [`scan_buffer`](Self::scan_buffer) and
+    /// computes the selected digests over the bytes sent to ClamAV.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: The data to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `algorithms`: The digests to compute
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`](std::io::Result) containing the server's response and
+    /// the [`ScanDigest`](crate::hash::ScanDigest) of the buffer
+    #[cfg(feature = "hash")]
+    fn scan_buffer_hashed(
+        &self,
+        buffer: &[u8],
+        chunk_size: Option<usize>,
+        algorithms: HashAlgorithms,
+    ) -> std::io::Result<HashedResponse> {
+        self.scan_reader_hashed(buffer, chunk_size, algorithms)
+    }
+
+    /// Scans data from a reader for viruses and hashes it
+    ///
+    /// This function works like [`scan_reader`](Self::scan_reader) and
+    /// computes the selected digests over the bytes sent to ClamAV.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`: The reader to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `algorithms`: The digests to compute
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`](std::io::Result) containing the server's response and
+    /// the [`ScanDigest`](crate::hash::ScanDigest) of the data
+    #[cfg(feature = "hash")]
+    fn scan_reader_hashed<R: Read>(
+        &self,
+        reader: R,
+        chunk_size: Option<usize>,
+        algorithms: HashAlgorithms,
+    ) -> std::io::Result<HashedResponse> {
+        let mut digester = Digester::new(algorithms);
+        let input = HashingReader {
+            inner: reader,
+            digester: &mut digester,
+        };
+        let response = self.scan_reader(input, chunk_size)?;
+        Ok(digester.finish(response))
+    }
+
+    /// Scans every part of a MIME message for viruses
+    ///
+    /// This function splits `message` into its parts with
+    /// [`mime::parse`](crate::mime::parse), decodes their transfer encodings
+    /// and scans each part with [`scan_buffer`](Self::scan_buffer), so the
+    /// verdicts tell which attachment is infected.
+    ///
+    /// # Arguments
+    ///
+    /// * `message`: The raw message, e.g. the content of an `.eml` file
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    ///
+    /// # Returns
+    ///
+    /// A [`ScannedPart`](crate::mime::ScannedPart) with the file name, content
+    /// type and response for every part, in the order of the message
+    #[cfg(feature = "mime")]
+    fn scan_mime(&self, message: &[u8], chunk_size: Option<usize>) -> Vec<ScannedPart> {
+        crate::mime::parse(message)
+            .into_iter()
+            .map(|part| {
+                let response = self.scan_buffer(&part.data, chunk_size);
+                part.into_scanned(response)
+            })
+            .collect()
+    }
+
+    /// Scans all files in a directory for viruses
+    ///
+    /// This function walks the directory at `root` according to `options` and
+    /// scans every matching file with [`scan_file`](Self::scan_file). Up to
+    /// [`ScanDirOptions::concurrency`] files are scanned at the same time by
+    /// background threads, each using a clone of the client. If `root` is a
+    /// file, only that file is scanned.
+    ///
+    /// # Arguments
+    ///
+    /// * `root`: The directory to be scanned
+    /// * `options`: Recursion, symlink, filter and concurrency settings
+    ///
+    /// # Returns
+    ///
+    /// A [`ScanDirIter`] that yields a [`ScannedFile`](crate::dir::ScannedFile)
+    /// for every scanned file and every path that could not be read, in the
+    /// order in which the scans complete
+    fn scan_dir<P: AsRef<Path>>(&self, root: P, options: ScanDirOptions) -> ScanDirIter
+    where
+        Self: Clone + Send + 'static,
+    {
+        crate::dir::scan_dir_sync(self.clone(), root.as_ref(), options)
     }
 
     /// Shuts down a ClamAV server
@@ -133,31 +438,118 @@ pub trait ClamAvSync {
     }
 }
 
+/// Object-safe counterpart of [`ClamAvSync`]
+///
+/// [`ClamAvSync`] has an associated stream type and generic methods, so it
+/// cannot be used as a trait object. This trait offers the same operations with
+/// non-generic signatures and is implemented for every [`ClamAvSync`]
+/// implementor, which allows storing clients as e.g.
+/// `Arc<dyn DynClamAvSync + Send + Sync>`.
+pub trait DynClamAvSync {
+    /// Sends a ping request to ClamAV, see [`ClamAvSync::ping`]
+    fn ping(&self) -> IoResult;
+
+    /// Gets the version number from ClamAV, see [`ClamAvSync::get_version`]
+    fn get_version(&self) -> IoResult;
+
+    /// Gets statistics about the ClamAV server, see [`ClamAvSync::get_stats`]
+    fn get_stats(&self) -> IoResult;
+
+    /// Checks the health of the ClamAV server, see [`ClamAvSync::health`]
+    fn health(&self, thresholds: &HealthThresholds) -> HealthReport;
+
+    /// Scans a file for viruses, see [`ClamAvSync::scan_file`]
+    fn scan_file(&self, file_path: &Path, chunk_size: Option<usize>) -> IoResult;
+
+    /// Scans a data buffer for viruses, see [`ClamAvSync::scan_buffer`]
+    fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult;
+
+    /// Scans data from a reader for viruses, see [`ClamAvSync::scan_reader`]
+    fn scan_reader(&self, reader: &mut dyn Read, chunk_size: Option<usize>) -> IoResult;
+
+    /// Shuts down a ClamAV server, see [`ClamAvSync::shutdown`]
+    fn shutdown(&self) -> IoResult;
+}
+
+impl<T: ClamAvSync> DynClamAvSync for T {
+    fn ping(&self) -> IoResult {
+        ClamAvSync::ping(self)
+    }
+
+    fn get_version(&self) -> IoResult {
+        ClamAvSync::get_version(self)
+    }
+
+    fn get_stats(&self) -> IoResult {
+        ClamAvSync::get_stats(self)
+    }
+
+    fn health(&self, thresholds: &HealthThresholds) -> HealthReport {
+        ClamAvSync::health(self, thresholds)
+    }
+
+    fn scan_file(&self, file_path: &Path, chunk_size: Option<usize>) -> IoResult {
+        ClamAvSync::scan_file(self, file_path, chunk_size)
+    }
+
+    fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+        ClamAvSync::scan_buffer(self, buffer, chunk_size)
+    }
+
+    fn scan_reader(&self, reader: &mut dyn Read, chunk_size: Option<usize>) -> IoResult {
+        ClamAvSync::scan_reader(self, reader, chunk_size)
+    }
+
+    fn shutdown(&self) -> IoResult {
+        ClamAvSync::shutdown(self)
+    }
+}
+
 fn send_command<RW: Read + Write>(mut stream: RW, command: &[u8]) -> IoResult {
-    stream.write_all(command)?;
-    stream.flush()?;
+    let operation = Operation::command(command);
+    let result = operation.in_scope(|| {
+        stream.write_all(command)?;
+        stream.flush()?;
 
-    let mut response = Vec::new();
-    stream.read_to_end(&mut response)?;
-    Ok(response)
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        Ok(response)
+    });
+    operation.reply(&result);
+    result
 }
 
-fn scan<R: Read, RW: Read + Write>(
-    mut input: R,
+pub(crate) fn scan<R: Read, RW: Read + Write>(
+    input: R,
     chunk_size: Option<usize>,
-    mut stream: RW,
+    stream: RW,
 ) -> IoResult {
-    stream.write_all(INSTREAM)?;
-
     let chunk_size = chunk_size
         .unwrap_or(DEFAULT_CHUNK_SIZE)
         .min(u32::MAX as usize);
+    let operation = Operation::instream(chunk_size);
+    let mut sent = Sent::default();
+    let result = operation.in_scope(|| instream(input, chunk_size, stream, &mut sent));
+    operation.sent(&sent);
+    operation.reply(&result);
+    result
+}
+
+fn instream<R: Read, RW: Read + Write>(
+    mut input: R,
+    chunk_size: usize,
+    mut stream: RW,
+    sent: &mut Sent,
+) -> IoResult {
+    stream.write_all(INSTREAM)?;
+
     let mut buffer = vec![0; chunk_size];
     loop {
         let len = input.read(&mut buffer[..])?;
         if len != 0 {
             stream.write_all(&(len as u32).to_be_bytes())?;
             stream.write_all(&buffer[..len])?;
+            sent.chunk(len);
         } else {
             stream.write_all(END_OF_STREAM)?;
             stream.flush()?;
@@ -0,0 +1,593 @@
+//! Caching verdicts by content hash
+//!
+//! A [`CachedClient`] wraps a transport and remembers the response for every
+//! scanned file, buffer or stream, keyed by the SHA-256 digest of the data
+//! and the signature database version reported by `VERSION`. Identical data
+//! is only sent to ClamAV again after the signatures were updated, at which
+//! point the entries for the old database version are dropped.
+//!
+//! Files and buffers are hashed before they are scanned, so cache hits skip
+//! the scan. On a miss, the response is cached under the digest of the bytes
+//! that were actually streamed to ClamAV, so a file that changes between
+//! hashing and scanning cannot attach its verdict to other content. Readers
+//! and streams can only be read once: they are hashed while they are
+//! streamed and the response is cached for later files and buffers with the
+//! same content.
+//!
+//! Only clean and infected verdicts are cached, errors are not. Responses
+//! are stored by a [`CacheStore`]: [`MemoryCache`] keeps them in a bounded
+//! LRU map, [`DiskCache`] in a directory that survives restarts.
+//!
+//! ```no_run
+//! use clamav_client::cache::{CachedClient, MemoryCache};
+//! use clamav_client::ClamAvSync;
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let client = CachedClient::new(clamd_tcp, MemoryCache::new(10_000));
+//!
+//! let first = client.scan_file("upload.bin", None).unwrap();
+//! let second = client.scan_file("upload.bin", None).unwrap();
+//! assert_eq!(first, second);
+//! assert_eq!(client.hits(), 1);
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    digest::hex,
+    hash::{Digester, HashAlgorithms, HashingReader},
+    ClamAvSync, IoResult, Timeouts, Verdict,
+};
+
+/// Key of a cached response
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// SHA-256 digest of the scanned data
+    pub sha256: [u8; 32],
+    /// Signature database version, e.g. `27432`
+    pub db_version: String,
+}
+
+/// Storage for cached responses
+pub trait CacheStore: Send + Sync {
+    /// Returns the cached response for a key
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>>;
+
+    /// Stores a response
+    fn insert(&self, key: CacheKey, response: Vec<u8>);
+
+    /// Removes all responses that were not cached with the given database
+    /// version, called when a new version is detected
+    fn retain_version(&self, db_version: &str);
+
+    /// Removes all responses
+    fn clear(&self);
+}
+
+/// In-memory cache that evicts the least recently used entries
+#[derive(Debug)]
+pub struct MemoryCache {
+    capacity: usize,
+    inner: Mutex<Lru>,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<CacheKey, (Vec<u8>, u64)>,
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &CacheKey) -> Option<&Vec<u8>> {
+        let (response, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        self.tick += 1;
+        *last_used = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(response)
+    }
+}
+
+impl MemoryCache {
+    /// Creates a cache that holds up to `capacity` responses
+    pub fn new(capacity: usize) -> Self {
+        MemoryCache {
+            capacity,
+            inner: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// Returns the number of cached responses
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Returns `true` if no responses are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl CacheStore for MemoryCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        self.lock().touch(key).cloned()
+    }
+
+    fn insert(&self, key: CacheKey, response: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lru = self.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some((_, last_used)) = lru.entries.insert(key.clone(), (response, tick)) {
+            lru.order.remove(&last_used);
+        }
+        lru.order.insert(tick, key);
+        while lru.entries.len() > self.capacity {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            lru.entries.remove(&oldest);
+        }
+    }
+
+    fn retain_version(&self, db_version: &str) {
+        let mut lru = self.lock();
+        lru.entries.retain(|key, _| key.db_version == db_version);
+        lru.order.retain(|_, key| key.db_version == db_version);
+    }
+
+    fn clear(&self) {
+        let mut lru = self.lock();
+        lru.entries.clear();
+        lru.order.clear();
+    }
+}
+
+/// Cache that stores responses in a directory
+///
+/// Responses are stored as `<dir>/<db_version>/<sha256>`, entries of other
+/// database versions are deleted when a new version is detected. I/O errors
+/// are treated as cache misses.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    /// Opens a cache directory, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(DiskCache {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn version_dir(&self, db_version: &str) -> PathBuf {
+        let name: String = db_version
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect();
+        self.dir.join(name)
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.version_dir(&key.db_version).join(hex(&key.sha256))
+    }
+}
+
+impl CacheStore for DiskCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        fs::read(self.path(key)).ok()
+    }
+
+    fn insert(&self, key: CacheKey, response: Vec<u8>) {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let path = self.path(&key);
+        let part = path.with_extension(format!(
+            "{}-{}.part",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let created = fs::create_dir_all(self.version_dir(&key.db_version)).and_then(|_| {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&part)
+        });
+        // Concurrent inserts write separate files, the last rename wins
+        if let Ok(mut file) = created {
+            let written = file
+                .write_all(&response)
+                .and_then(|_| fs::rename(&part, &path));
+            if written.is_err() {
+                let _ = fs::remove_file(&part);
+            }
+        }
+    }
+
+    fn retain_version(&self, db_version: &str) {
+        let keep = self.version_dir(db_version);
+        if let Ok(entries) = fs::read_dir(&self.dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path != keep && path.is_dir() {
+                    let _ = fs::remove_dir_all(path);
+                }
+            }
+        }
+    }
+
+    fn clear(&self) {
+        self.retain_version("");
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    db_version: Mutex<Option<(String, Instant)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Transport wrapper that caches responses by content hash and database
+/// version
+///
+/// The wrapper implements [`ClamAvSync`] and
+/// [`ClamAvAsync`](crate::ClamAvAsync) if the wrapped transport does. Clones
+/// share the store and statistics.
+#[derive(Debug)]
+pub struct CachedClient<T, S> {
+    inner: T,
+    store: Arc<S>,
+    version_ttl: Duration,
+    state: Arc<State>,
+}
+
+impl<T: Clone, S> Clone for CachedClient<T, S> {
+    fn clone(&self) -> Self {
+        CachedClient {
+            inner: self.inner.clone(),
+            store: Arc::clone(&self.store),
+            version_ttl: self.version_ttl,
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<T, S: CacheStore> CachedClient<T, S> {
+    /// Wraps a transport, caching responses in `store`
+    pub fn new(inner: T, store: S) -> Self {
+        CachedClient {
+            inner,
+            store: Arc::new(store),
+            version_ttl: Duration::from_secs(60),
+            state: Arc::default(),
+        }
+    }
+
+    /// Sets how long the database version is reused before `VERSION` is sent
+    /// again, defaults to 60 s
+    pub fn version_ttl(mut self, version_ttl: Duration) -> Self {
+        self.version_ttl = version_ttl;
+        self
+    }
+
+    /// Returns the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the cache store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the number of scans answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.state.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of scans sent to ClamAV
+    pub fn misses(&self) -> u64 {
+        self.state.misses.load(Ordering::Relaxed)
+    }
+
+    /// Forgets the database version and removes all cached responses
+    pub fn invalidate(&self) {
+        *self.lock_version() = None;
+        self.store.clear();
+    }
+
+    fn lock_version(&self) -> std::sync::MutexGuard<'_, Option<(String, Instant)>> {
+        self.state
+            .db_version
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns the database version if it was queried recently
+    fn cached_version(&self) -> Option<String> {
+        match &*self.lock_version() {
+            Some((version, queried)) if queried.elapsed() < self.version_ttl => {
+                Some(version.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Records the database version from a `VERSION` response and drops
+    /// entries of older versions when it changed
+    fn update_version(&self, response: IoResult) -> Option<String> {
+        let version = db_version(&response.ok()?)?;
+        let mut current = self.lock_version();
+        if current.as_ref().map(|(known, _)| known) != Some(&version) {
+            self.store.retain_version(&version);
+        }
+        *current = Some((version.clone(), Instant::now()));
+        Some(version)
+    }
+
+    fn lookup(&self, key: &Option<CacheKey>) -> Option<Vec<u8>> {
+        let response = self.store.get(key.as_ref()?)?;
+        self.state.hits.fetch_add(1, Ordering::Relaxed);
+        Some(response)
+    }
+
+    fn remember(&self, key: Option<CacheKey>, response: IoResult) -> IoResult {
+        self.state.misses.fetch_add(1, Ordering::Relaxed);
+        if let (Some(key), Ok(response)) = (key, &response) {
+            if cacheable(response) {
+                self.store.insert(key, response.clone());
+            }
+        }
+        response
+    }
+}
+
+impl<T: ClamAvSync, S: CacheStore> CachedClient<T, S> {
+    /// Returns the current database version, or [`None`] if it cannot be
+    /// determined and the cache must be bypassed
+    fn db_version_sync(&self) -> Option<String> {
+        self.cached_version()
+            .or_else(|| self.update_version(self.inner.get_version()))
+    }
+
+    /// Scans `reader` with the wrapped transport and caches the response
+    /// under the digest of the bytes that were sent
+    fn scan_uncached<R: Read>(
+        &self,
+        reader: R,
+        chunk_size: Option<usize>,
+        db_version: Option<String>,
+    ) -> IoResult {
+        let mut digester = sha256_digester();
+        let input = HashingReader {
+            inner: reader,
+            digester: &mut digester,
+        };
+        let response = self.inner.scan_reader(input, chunk_size);
+        self.remember(cache_key(db_version, digester), response)
+    }
+}
+
+impl<T: ClamAvSync, S: CacheStore> ClamAvSync for CachedClient<T, S> {
+    type Stream = T::Stream;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        self.inner.connect()
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        self.inner.connect_with_timeouts(timeouts)
+    }
+
+    fn get_version(&self) -> IoResult {
+        let response = self.inner.get_version()?;
+        self.update_version(Ok(response.clone()));
+        Ok(response)
+    }
+
+    fn scan_file<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+    ) -> IoResult {
+        let mut file = File::open(file_path)?;
+        let db_version = self.db_version_sync();
+        if db_version.is_some() {
+            let mut digester = sha256_digester();
+            let mut input = HashingReader {
+                inner: &mut file,
+                digester: &mut digester,
+            };
+            io::copy(&mut input, &mut io::sink())?;
+            if let Some(response) = self.lookup(&cache_key(db_version.clone(), digester)) {
+                return Ok(response);
+            }
+            file.rewind()?;
+        }
+        self.scan_uncached(file, chunk_size, db_version)
+    }
+
+    fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+        let key = self.db_version_sync().map(|db_version| CacheKey {
+            sha256: Sha256::digest(buffer).into(),
+            db_version,
+        });
+        if let Some(response) = self.lookup(&key) {
+            return Ok(response);
+        }
+        let response = self.inner.scan_buffer(buffer, chunk_size);
+        self.remember(key, response)
+    }
+
+    fn scan_reader<R: Read>(&self, reader: R, chunk_size: Option<usize>) -> IoResult {
+        let db_version = self.db_version_sync();
+        self.scan_uncached(reader, chunk_size, db_version)
+    }
+}
+
+/// Extracts the signature database version from a `VERSION` response such as
+/// `ClamAV 1.4.1/27432/Mon Oct 14 10:30:00 2024`
+///
+/// Responses without a database version are used as a whole.
+fn db_version(response: &[u8]) -> Option<String> {
+    let response = std::str::from_utf8(response).ok()?;
+    let response = response.trim_end_matches(['\0', '\n']).trim();
+    let version = response.split('/').nth(1).unwrap_or(response);
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Returns a digester that only computes the SHA-256 digest used as key
+fn sha256_digester() -> Digester {
+    Digester::new(HashAlgorithms::new().sha256(true))
+}
+
+/// Returns the key of the data fed to a digester from [`sha256_digester`]
+fn cache_key(db_version: Option<String>, digester: Digester) -> Option<CacheKey> {
+    let sha256 = digester.digest().sha256?;
+    db_version.map(|db_version| CacheKey { sha256, db_version })
+}
+
+/// Returns `true` if the response is a clean or infected verdict
+///
+/// The caller parses the response again, so this check is not traced.
+fn cacheable(response: &[u8]) -> bool {
+    matches!(
+        std::str::from_utf8(response).map(Verdict::parse_lines),
+        Ok(Verdict::Clean | Verdict::Infected(_))
+    )
+}
+
+#[cfg(feature = "async-core")]
+mod async_cache {
+    use std::{io, path::Path};
+
+    use futures_lite::{AsyncRead, AsyncSeekExt, Stream, StreamExt};
+    use sha2::{Digest, Sha256};
+
+    use super::{cache_key, sha256_digester, CacheKey, CacheStore, CachedClient};
+    use crate::{hash::HashingReader, rt, ClamAvAsync, IoResult};
+
+    impl<T: ClamAvAsync, S: CacheStore> CachedClient<T, S> {
+        async fn db_version_async(&self) -> Option<String> {
+            match self.cached_version() {
+                Some(version) => Some(version),
+                None => self.update_version(self.inner.get_version().await),
+            }
+        }
+
+        /// Scans `reader` with the wrapped transport and caches the response
+        /// under the digest of the bytes that were sent
+        async fn scan_uncached_async<R: AsyncRead + Unpin + Send>(
+            &self,
+            reader: R,
+            chunk_size: Option<usize>,
+            db_version: Option<String>,
+        ) -> IoResult {
+            let mut digester = sha256_digester();
+            let input = HashingReader {
+                inner: reader,
+                digester: &mut digester,
+            };
+            let response = self.inner.scan_reader(input, chunk_size).await;
+            self.remember(cache_key(db_version, digester), response)
+        }
+    }
+
+    impl<T: ClamAvAsync, S: CacheStore> ClamAvAsync for CachedClient<T, S> {
+        type Stream = T::Stream;
+
+        async fn connect(&self) -> io::Result<Self::Stream> {
+            self.inner.connect().await
+        }
+
+        async fn get_version(&self) -> IoResult {
+            let response = self.inner.get_version().await?;
+            self.update_version(Ok(response.clone()));
+            Ok(response)
+        }
+
+        async fn scan_file<P: AsRef<Path> + Send>(
+            &self,
+            file_path: P,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let mut file = rt::open(file_path.as_ref()).await?;
+            let db_version = self.db_version_async().await;
+            if db_version.is_some() {
+                let mut digester = sha256_digester();
+                let input = HashingReader {
+                    inner: &mut file,
+                    digester: &mut digester,
+                };
+                futures_lite::io::copy(input, &mut futures_lite::io::sink()).await?;
+                if let Some(response) = self.lookup(&cache_key(db_version.clone(), digester)) {
+                    return Ok(response);
+                }
+                file.seek(io::SeekFrom::Start(0)).await?;
+            }
+            self.scan_uncached_async(file, chunk_size, db_version).await
+        }
+
+        async fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+            let key = self.db_version_async().await.map(|db_version| CacheKey {
+                sha256: Sha256::digest(buffer).into(),
+                db_version,
+            });
+            if let Some(response) = self.lookup(&key) {
+                return Ok(response);
+            }
+            let response = self.inner.scan_buffer(buffer, chunk_size).await;
+            self.remember(key, response)
+        }
+
+        async fn scan_reader<R: AsyncRead + Unpin + Send>(
+            &self,
+            reader: R,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let db_version = self.db_version_async().await;
+            self.scan_uncached_async(reader, chunk_size, db_version)
+                .await
+        }
+
+        async fn scan_stream<St: Stream<Item = Result<bytes::Bytes, io::Error>> + Send>(
+            &self,
+            input_stream: St,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let db_version = self.db_version_async().await;
+            let mut digester = sha256_digester();
+            let input_stream = input_stream.inspect(|bytes| {
+                if let Ok(bytes) = bytes {
+                    digester.update(bytes, usize::MAX);
+                }
+            });
+            let response = self.inner.scan_stream(input_stream, chunk_size).await;
+            self.remember(cache_key(db_version, digester), response)
+        }
+    }
+}
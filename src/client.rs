@@ -0,0 +1,588 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    blocking,
+    progress::{ProgressObserver, ProgressReader},
+    ClamAvSync, IoResult, Verdict, VerdictPolicy,
+};
+
+/// Timeouts applied to connections with ClamAV
+///
+/// A value of [`None`] means that the operation never times out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Maximum time to establish a connection
+    ///
+    /// The blocking API cannot bound the connection to a Unix [`Socket`](crate::Socket)
+    /// (the standard library has no `UnixStream::connect_timeout`), so it is
+    /// ignored there. Connecting to a local socket only blocks while clamd's
+    /// listen backlog is full.
+    pub connect: Option<Duration>,
+    /// Maximum time to wait for data from ClamAV
+    pub read: Option<Duration>,
+    /// Maximum time to wait until data can be sent to ClamAV
+    pub write: Option<Duration>,
+}
+
+/// Determines how often a failed connection attempt is retried
+///
+/// Only establishing the connection is retried, because the data of a failed
+/// scan may already have been consumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first failed attempt
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further retry
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+/// ClamAV client with shared configuration
+///
+/// The client wraps a transport, e.g. [`Tcp`](crate::Tcp) or
+/// [`Socket`](crate::Socket), and implements [`ClamAvSync`] and
+/// [`ClamAvAsync`](crate::ClamAvAsync) if the transport does. Passing [`None`]
+/// as chunk size to the scan methods uses the configured default chunk size.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use clamav_client::{ClamAvClient, ClamAvSync, Tcp};
+///
+/// let client = ClamAvClient::builder(Tcp("127.0.0.1:3310".parse().unwrap()))
+///     .chunk_size(64 * 1024)
+///     .max_stream_length(25 * 1024 * 1024)
+///     .connect_timeout(Duration::from_secs(5))
+///     .build();
+///
+/// let response = client.scan_file("README.md", None).unwrap();
+/// assert!(client.verdict(&response).unwrap().is_clean());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClamAvClient<T> {
+    transport: T,
+    chunk_size: Option<usize>,
+    max_stream_length: Option<u64>,
+    timeouts: Timeouts,
+    retry_policy: RetryPolicy,
+    verdict_policy: VerdictPolicy,
+}
+
+/// Builder for [`ClamAvClient`]
+#[derive(Debug, Clone)]
+pub struct ClamAvClientBuilder<T> {
+    client: ClamAvClient<T>,
+}
+
+impl<T> ClamAvClient<T> {
+    /// Creates a builder for a client using the given transport
+    pub fn builder(transport: T) -> ClamAvClientBuilder<T> {
+        ClamAvClientBuilder {
+            client: ClamAvClient {
+                transport,
+                chunk_size: None,
+                max_stream_length: None,
+                timeouts: Timeouts::default(),
+                retry_policy: RetryPolicy::default(),
+                verdict_policy: VerdictPolicy::default(),
+            },
+        }
+    }
+
+    /// Returns the wrapped transport
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns the configured timeouts
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// Parses a scan response and applies the configured [`VerdictPolicy`]
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the [`Verdict`]. Responses that are not
+    /// valid UTF-8 result in an error of kind [`io::ErrorKind::InvalidData`].
+    pub fn verdict(&self, response: &[u8]) -> io::Result<Verdict> {
+        self.verdict_policy.parse(response)
+    }
+
+    fn chunk_size(&self, chunk_size: Option<usize>) -> Option<usize> {
+        chunk_size.or(self.chunk_size)
+    }
+
+    fn check_length(&self, length: u64) -> io::Result<()> {
+        match self.max_stream_length {
+            Some(max) if length > max => Err(stream_too_long()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T> ClamAvClientBuilder<T> {
+    /// Sets the default chunk size used when [`None`] is passed to a scan method
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.client.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Sets the maximum number of bytes sent to ClamAV per scan
+    ///
+    /// Larger inputs are rejected with an error of kind
+    /// [`io::ErrorKind::InvalidInput`] instead of being sent to ClamAV. This
+    /// should not exceed `StreamMaxLength` of the ClamAV configuration.
+    pub fn max_stream_length(mut self, max_stream_length: u64) -> Self {
+        self.client.max_stream_length = Some(max_stream_length);
+        self
+    }
+
+    /// Sets all timeouts at once
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.client.timeouts = timeouts;
+        self
+    }
+
+    /// Sets the maximum time to establish a connection
+    ///
+    /// See [`Timeouts::connect`] for Unix sockets with the blocking API.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.client.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time to wait for data from ClamAV
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.client.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time to wait until data can be sent to ClamAV
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.client.timeouts.write = Some(timeout);
+        self
+    }
+
+    /// Sets the policy for retrying failed connection attempts
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.client.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets the policy used by [`ClamAvClient::verdict`]
+    pub fn verdict_policy(mut self, verdict_policy: VerdictPolicy) -> Self {
+        self.client.verdict_policy = verdict_policy;
+        self
+    }
+
+    /// Builds the client
+    pub fn build(self) -> ClamAvClient<T> {
+        self.client
+    }
+}
+
+fn stream_too_long() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "input exceeds the maximum stream length",
+    )
+}
+
+/// Reader that fails once more than `remaining` bytes have been read
+struct LimitedReader<R> {
+    inner: R,
+    remaining: Option<u64>,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining = remaining
+                .checked_sub(len as u64)
+                .ok_or_else(stream_too_long)?;
+        }
+        Ok(len)
+    }
+}
+
+impl<T: ClamAvSync> ClamAvSync for ClamAvClient<T> {
+    type Stream = T::Stream;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        self.connect_with_timeouts(&self.timeouts)
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        let mut retry = 0;
+        loop {
+            match self.transport.connect_with_timeouts(timeouts) {
+                Err(_) if retry < self.retry_policy.max_retries => {
+                    std::thread::sleep(self.retry_policy.delay(retry));
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn scan_file<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+    ) -> IoResult {
+        let file = File::open(file_path)?;
+        self.check_length(file.metadata()?.len())?;
+        self.scan_reader(file, chunk_size)
+    }
+
+    fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+        self.check_length(buffer.len() as u64)?;
+        self.scan_reader(buffer, chunk_size)
+    }
+
+    fn scan_reader<R: Read>(&self, reader: R, chunk_size: Option<usize>) -> IoResult {
+        let stream = self.connect()?;
+        let input = LimitedReader {
+            inner: reader,
+            remaining: self.max_stream_length,
+        };
+        blocking::scan(input, self.chunk_size(chunk_size), stream)
+    }
+
+    fn scan_file_with_progress<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+        observer: &dyn ProgressObserver,
+    ) -> IoResult {
+        let file = File::open(file_path)?;
+        let length = file.metadata()?.len();
+        self.check_length(length)?;
+        let mut input = ProgressReader::new(file, observer, Some(length));
+        let result = self.scan_reader(&mut input, chunk_size);
+        input.finish(result)
+    }
+
+    fn scan_buffer_with_progress(
+        &self,
+        buffer: &[u8],
+        chunk_size: Option<usize>,
+        observer: &dyn ProgressObserver,
+    ) -> IoResult {
+        self.check_length(buffer.len() as u64)?;
+        let mut input = ProgressReader::new(buffer, observer, Some(buffer.len() as u64));
+        let result = self.scan_reader(&mut input, chunk_size);
+        input.finish(result)
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_client {
+    use std::{
+        future::Future,
+        io,
+        path::Path,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use futures_lite::{AsyncRead, AsyncWrite, Stream, StreamExt};
+
+    use super::{stream_too_long, ClamAvClient, Timeouts};
+    #[cfg(feature = "hash")]
+    use crate::hash::{Digester, HashAlgorithms, HashedResponse};
+    use crate::{
+        nonblocking,
+        progress::{ProgressObserver, ProgressReader, ProgressStream},
+        rt::{self, Timer},
+        ClamAvAsync, IoResult,
+    };
+
+    /// Stream wrapper that applies read and write [`Timeouts`] to async I/O
+    #[derive(Debug)]
+    pub struct TimeoutStream<S> {
+        inner: S,
+        timeouts: Timeouts,
+        read_timer: Option<Timer>,
+        write_timer: Option<Timer>,
+    }
+
+    impl<S> TimeoutStream<S> {
+        /// Returns the wrapped stream
+        pub fn into_inner(self) -> S {
+            self.inner
+        }
+    }
+
+    fn poll_timer(
+        timer: &mut Option<Timer>,
+        timeout: Option<Duration>,
+        cx: &mut Context<'_>,
+    ) -> io::Result<()> {
+        if let Some(timeout) = timeout {
+            let timer = timer.get_or_insert_with(|| Timer::after(timeout));
+            if Pin::new(timer).poll(cx).is_ready() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+        Ok(())
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for TimeoutStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            match Pin::new(&mut this.inner).poll_read(cx, buf) {
+                Poll::Ready(result) => {
+                    this.read_timer = None;
+                    Poll::Ready(result)
+                }
+                Poll::Pending => {
+                    poll_timer(&mut this.read_timer, this.timeouts.read, cx)?;
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for TimeoutStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            match Pin::new(&mut this.inner).poll_write(cx, buf) {
+                Poll::Ready(result) => {
+                    this.write_timer = None;
+                    Poll::Ready(result)
+                }
+                Poll::Pending => {
+                    poll_timer(&mut this.write_timer, this.timeouts.write, cx)?;
+                    Poll::Pending
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            match Pin::new(&mut this.inner).poll_flush(cx) {
+                Poll::Ready(result) => {
+                    this.write_timer = None;
+                    Poll::Ready(result)
+                }
+                Poll::Pending => {
+                    poll_timer(&mut this.write_timer, this.timeouts.write, cx)?;
+                    Poll::Pending
+                }
+            }
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_close(cx)
+        }
+    }
+
+    /// Async reader that fails once more than `remaining` bytes have been read
+    struct LimitedReader<R> {
+        inner: R,
+        remaining: Option<u64>,
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for LimitedReader<R> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let len = futures_lite::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            if let Some(remaining) = this.remaining.as_mut() {
+                *remaining = remaining
+                    .checked_sub(len as u64)
+                    .ok_or_else(stream_too_long)?;
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl<T> ClamAvClient<T> {
+        /// Fails the stream once it exceeds the maximum stream length
+        fn limit_stream<S: Stream<Item = Result<bytes::Bytes, io::Error>>>(
+            &self,
+            input_stream: S,
+        ) -> impl Stream<Item = Result<bytes::Bytes, io::Error>> {
+            let mut remaining = self.max_stream_length;
+            input_stream.map(move |bytes| {
+                let bytes = bytes?;
+                if let Some(remaining) = remaining.as_mut() {
+                    *remaining = remaining
+                        .checked_sub(bytes.len() as u64)
+                        .ok_or_else(stream_too_long)?;
+                }
+                Ok(bytes)
+            })
+        }
+    }
+
+    impl<T: ClamAvAsync> ClamAvAsync for ClamAvClient<T> {
+        type Stream = TimeoutStream<T::Stream>;
+
+        async fn connect(&self) -> io::Result<Self::Stream> {
+            let mut retry = 0;
+            loop {
+                let connect = self.transport.connect();
+                let result = match self.timeouts.connect {
+                    Some(timeout) => {
+                        futures_lite::future::or(connect, async {
+                            Timer::after(timeout).await;
+                            Err(io::ErrorKind::TimedOut.into())
+                        })
+                        .await
+                    }
+                    None => connect.await,
+                };
+                match result {
+                    Err(_) if retry < self.retry_policy.max_retries => {
+                        Timer::after(self.retry_policy.delay(retry)).await;
+                        retry += 1;
+                    }
+                    result => {
+                        return result.map(|inner| TimeoutStream {
+                            inner,
+                            timeouts: self.timeouts,
+                            read_timer: None,
+                            write_timer: None,
+                        })
+                    }
+                }
+            }
+        }
+
+        async fn scan_file<P: AsRef<Path> + Send>(
+            &self,
+            file_path: P,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let file = rt::open(file_path.as_ref()).await?;
+            self.check_length(rt::file_len(&file).await?)?;
+            self.scan_reader(file, chunk_size).await
+        }
+
+        async fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+            self.check_length(buffer.len() as u64)?;
+            self.scan_reader(buffer, chunk_size).await
+        }
+
+        async fn scan_reader<R: AsyncRead + Unpin + Send>(
+            &self,
+            reader: R,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let stream = self.connect().await?;
+            let input = LimitedReader {
+                inner: reader,
+                remaining: self.max_stream_length,
+            };
+            nonblocking::scan(input, self.chunk_size(chunk_size), stream).await
+        }
+
+        async fn scan_stream<S: Stream<Item = Result<bytes::Bytes, io::Error>> + Send>(
+            &self,
+            input_stream: S,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let input_stream = self.limit_stream(input_stream);
+            let output_stream = self.connect().await?;
+            nonblocking::scan_stream(input_stream, self.chunk_size(chunk_size), output_stream).await
+        }
+
+        async fn scan_file_with_progress<P: AsRef<Path> + Send>(
+            &self,
+            file_path: P,
+            chunk_size: Option<usize>,
+            observer: &dyn ProgressObserver,
+        ) -> IoResult {
+            let file = rt::open(file_path.as_ref()).await?;
+            let length = rt::file_len(&file).await?;
+            self.check_length(length)?;
+            let mut input = ProgressReader::new(file, observer, Some(length));
+            let result = self.scan_reader(&mut input, chunk_size).await;
+            input.finish(result)
+        }
+
+        async fn scan_buffer_with_progress(
+            &self,
+            buffer: &[u8],
+            chunk_size: Option<usize>,
+            observer: &dyn ProgressObserver,
+        ) -> IoResult {
+            self.check_length(buffer.len() as u64)?;
+            let mut input = ProgressReader::new(buffer, observer, Some(buffer.len() as u64));
+            let result = self.scan_reader(&mut input, chunk_size).await;
+            input.finish(result)
+        }
+
+        async fn scan_stream_with_progress<
+            S: Stream<Item = Result<bytes::Bytes, io::Error>> + Send,
+        >(
+            &self,
+            input_stream: S,
+            chunk_size: Option<usize>,
+            observer: &dyn ProgressObserver,
+        ) -> IoResult {
+            let chunk_size = self.chunk_size(chunk_size);
+            let mut input_stream = ProgressStream::new(input_stream, chunk_size, observer);
+            let result = self.scan_stream(&mut input_stream, chunk_size).await;
+            input_stream.finish(result)
+        }
+
+        #[cfg(feature = "hash")]
+        async fn scan_stream_hashed<S: Stream<Item = Result<bytes::Bytes, io::Error>> + Send>(
+            &self,
+            input_stream: S,
+            chunk_size: Option<usize>,
+            algorithms: HashAlgorithms,
+        ) -> io::Result<HashedResponse> {
+            let mut digester = Digester::new(algorithms);
+            let effective_chunk_size = self
+                .chunk_size(chunk_size)
+                .unwrap_or(crate::DEFAULT_CHUNK_SIZE)
+                .min(u32::MAX as usize);
+            let input_stream = input_stream.inspect(|bytes| {
+                if let Ok(bytes) = bytes {
+                    digester.update(bytes, effective_chunk_size);
+                }
+            });
+            let response = ClamAvAsync::scan_stream(self, input_stream, chunk_size).await?;
+            Ok(digester.finish(response))
+        }
+    }
+}
+
+#[cfg(feature = "async-core")]
+pub use async_client::TimeoutStream;
@@ -0,0 +1,22 @@
+//! Helpers for content digests
+
+/// Encodes bytes as lowercase hex
+#[cfg(any(feature = "cache", feature = "hash", feature = "quarantine"))]
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decodes a hex-encoded SHA-256 digest, upper and lower case are accepted
+#[cfg(feature = "policy")]
+pub(crate) fn unhex_sha256(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.as_bytes();
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.chunks(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(digest)
+}
@@ -0,0 +1,726 @@
+//! Directory walking for [`ClamAvSync::scan_dir`](crate::ClamAvSync::scan_dir)
+//! and `ClamAvAsync::scan_dir`
+//!
+//! The walker reads one directory entry at a time, so memory use depends on
+//! the depth of the tree and the concurrency limit, not on the number of
+//! files. Results are yielded in completion order.
+
+use std::{
+    collections::HashSet,
+    fs::{self, ReadDir},
+    io,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "hash")]
+use crate::hash::{HashAlgorithms, ScanDigest};
+use crate::{ClamAvSync, IoResult};
+
+/// Determines how symbolic links are treated while walking a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Ignore symbolic links
+    #[default]
+    Skip,
+    /// Scan symbolic links to files, but do not descend into linked directories
+    FollowFiles,
+    /// Follow all symbolic links, directories that were already visited are
+    /// skipped to avoid cycles
+    Follow,
+}
+
+/// Options for scanning a directory
+///
+/// ```
+/// use clamav_client::dir::{ScanDirOptions, SymlinkPolicy};
+///
+/// let options = ScanDirOptions::new()
+///     .symlinks(SymlinkPolicy::FollowFiles)
+///     .include("*.pdf")
+///     .exclude("**/node_modules")
+///     .max_file_size(25 * 1024 * 1024)
+///     .skip_hidden(true)
+///     .concurrency(8);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScanDirOptions {
+    pub(crate) recursive: bool,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) include: Vec<Glob>,
+    pub(crate) exclude: Vec<Glob>,
+    pub(crate) max_file_size: Option<u64>,
+    pub(crate) skip_hidden: bool,
+    pub(crate) concurrency: usize,
+    pub(crate) chunk_size: Option<usize>,
+    #[cfg(feature = "hash")]
+    pub(crate) hash: Option<HashAlgorithms>,
+}
+
+impl Default for ScanDirOptions {
+    fn default() -> Self {
+        ScanDirOptions {
+            recursive: true,
+            symlinks: SymlinkPolicy::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_file_size: None,
+            skip_hidden: false,
+            concurrency: 4,
+            chunk_size: None,
+            #[cfg(feature = "hash")]
+            hash: None,
+        }
+    }
+}
+
+impl ScanDirOptions {
+    /// Creates options that scan all files recursively, four at a time
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether subdirectories are scanned, defaults to `true`
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Sets how symbolic links are treated, defaults to [`SymlinkPolicy::Skip`]
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Only scans files matching one of the include patterns
+    ///
+    /// Patterns support `*`, `?`, `[a-z]`, `[!a-z]` and `**`. Patterns
+    /// without a `/` are matched against the file name, other patterns
+    /// against the path relative to the scanned directory.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(Glob::new(pattern));
+        self
+    }
+
+    /// Skips files and directories matching the pattern, see
+    /// [`include`](Self::include) for the pattern syntax
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(Glob::new(pattern));
+        self
+    }
+
+    /// Skips files that are larger than `max_file_size` bytes
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// Sets whether files and directories starting with `.` are skipped,
+    /// defaults to `false`
+    pub fn skip_hidden(mut self, skip_hidden: bool) -> Self {
+        self.skip_hidden = skip_hidden;
+        self
+    }
+
+    /// Sets the maximum number of files scanned at the same time, defaults to 4
+    ///
+    /// # Panics
+    ///
+    /// Panics if `concurrency` is 0.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        assert!(concurrency > 0, "concurrency must be greater than 0");
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Sets the chunk size passed to `scan_file`
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Computes the selected digests of every scanned file, see
+    /// [`ScannedFile::digest`]
+    #[cfg(feature = "hash")]
+    pub fn hash(mut self, algorithms: HashAlgorithms) -> Self {
+        self.hash = Some(algorithms);
+        self
+    }
+}
+
+/// Result of scanning a single file in a directory
+#[derive(Debug)]
+pub struct ScannedFile {
+    /// Path of the file, or of the directory that could not be read
+    pub path: PathBuf,
+    /// Size of the file in bytes, if it could be determined
+    pub size: Option<u64>,
+    /// The server's response, or the error that occurred while walking the
+    /// directory or scanning the file
+    pub response: IoResult,
+    /// Time it took to scan the file
+    pub duration: Duration,
+    /// Digests of the scanned file, if [`ScanDirOptions::hash`] was set and
+    /// the scan succeeded
+    #[cfg(feature = "hash")]
+    pub digest: Option<ScanDigest>,
+}
+
+/// Shell-style pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Glob {
+    tokens: Vec<Token>,
+    match_path: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    GlobStar,
+    /// `**/`, matches zero or more complete directories
+    GlobStarDir,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    /// Parses a pattern, malformed classes are matched literally
+    pub(crate) fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 2;
+                    if chars.get(i) == Some(&'/') {
+                        i += 1;
+                        tokens.push(Token::GlobStarDir);
+                    } else {
+                        tokens.push(Token::GlobStar);
+                    }
+                    continue;
+                }
+                '*' => tokens.push(Token::Star),
+                '?' => tokens.push(Token::Any),
+                '[' => {
+                    if let Some((token, end)) = parse_class(&chars, i) {
+                        tokens.push(token);
+                        i = end;
+                    } else {
+                        tokens.push(Token::Char('['));
+                    }
+                }
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    tokens.push(Token::Char(chars[i]));
+                }
+                c => tokens.push(Token::Char(c)),
+            }
+            i += 1;
+        }
+        Glob {
+            match_path: pattern.contains('/'),
+            tokens,
+        }
+    }
+
+    /// Matches the pattern against a file name or a `/`-separated relative path
+    pub(crate) fn matches(&self, relative_path: &str) -> bool {
+        let text: Vec<char> = if self.match_path {
+            relative_path.chars().collect()
+        } else {
+            let name = relative_path.rsplit('/').next().unwrap_or(relative_path);
+            name.chars().collect()
+        };
+        match_tokens(&self.tokens, &text)
+    }
+}
+
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i));
+        }
+        first = false;
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+    None
+}
+
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    let mut matcher = Matcher {
+        tokens,
+        text,
+        failed: vec![false; (tokens.len() + 1) * (text.len() + 1)],
+    };
+    matcher.matches(0, 0)
+}
+
+/// Backtracking matcher that remembers failed positions, so every pair of
+/// token and text index is tried at most once
+struct Matcher<'a> {
+    tokens: &'a [Token],
+    text: &'a [char],
+    failed: Vec<bool>,
+}
+
+impl Matcher<'_> {
+    /// Matches `tokens[token..]` against `text[at..]`
+    fn matches(&mut self, token: usize, at: usize) -> bool {
+        let index = token * (self.text.len() + 1) + at;
+        if self.failed[index] {
+            return false;
+        }
+        let matched = self.match_token(token, at);
+        self.failed[index] = !matched;
+        matched
+    }
+
+    fn match_token(&mut self, token: usize, at: usize) -> bool {
+        let text = self.text;
+        let Some(current) = self.tokens.get(token) else {
+            return at == text.len();
+        };
+        let next = token + 1;
+        match current {
+            Token::GlobStar => (at..=text.len()).any(|i| self.matches(next, i)),
+            Token::GlobStarDir => (at..=text.len())
+                .filter(|&i| i == at || text[i - 1] == '/')
+                .any(|i| self.matches(next, i)),
+            Token::Star => {
+                for i in at..=text.len() {
+                    if self.matches(next, i) {
+                        return true;
+                    }
+                    if text.get(i) == Some(&'/') {
+                        break;
+                    }
+                }
+                false
+            }
+            Token::Any => text.get(at).is_some_and(|&c| c != '/') && self.matches(next, at + 1),
+            Token::Char(c) => text.get(at) == Some(c) && self.matches(next, at + 1),
+            Token::Class { negated, ranges } => {
+                text.get(at).is_some_and(|&c| {
+                    c != '/' && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+                }) && self.matches(next, at + 1)
+            }
+        }
+    }
+}
+
+/// Entry produced by [`Walker`]
+#[derive(Debug)]
+pub(crate) enum Walked {
+    File(PathBuf, u64),
+    Error(PathBuf, io::Error),
+}
+
+impl ScannedFile {
+    /// Result for a path that could not be walked
+    pub(crate) fn walk_error(path: PathBuf, err: io::Error) -> Self {
+        ScannedFile {
+            path,
+            size: None,
+            response: Err(err),
+            duration: Duration::ZERO,
+            #[cfg(feature = "hash")]
+            digest: None,
+        }
+    }
+}
+
+/// Lazy depth-first directory walker that applies [`ScanDirOptions`]
+pub(crate) struct Walker {
+    root: PathBuf,
+    options: ScanDirOptions,
+    start: Option<(PathBuf, bool)>,
+    stack: Vec<(PathBuf, ReadDir)>,
+    visited: HashSet<PathBuf>,
+}
+
+impl Walker {
+    pub(crate) fn new(root: &Path, options: ScanDirOptions) -> Self {
+        Walker {
+            root: root.to_path_buf(),
+            options,
+            start: Some((root.to_path_buf(), true)),
+            stack: Vec::new(),
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Creates a walker for a path below `root` that applies all filters,
+    /// including those of the directories between `root` and `path`
+    #[cfg(feature = "watch")]
+    pub(crate) fn below(root: &Path, path: PathBuf, options: ScanDirOptions) -> Self {
+        let mut walker = Walker::new(root, options);
+        let hidden_or_excluded = path
+            .strip_prefix(root)
+            .ok()
+            .and_then(Path::parent)
+            .into_iter()
+            .flat_map(Path::ancestors)
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| walker.hidden(ancestor) || walker.excluded(&root.join(ancestor)));
+        walker.start = (!hidden_or_excluded).then_some((path, false));
+        walker
+    }
+
+    fn hidden(&self, path: &Path) -> bool {
+        self.options.skip_hidden
+            && path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+    }
+
+    fn relative_path(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let parts: Vec<_> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        parts.join("/")
+    }
+
+    fn excluded(&self, path: &Path) -> bool {
+        let relative = self.relative_path(path);
+        self.options
+            .exclude
+            .iter()
+            .any(|glob| glob.matches(&relative))
+    }
+
+    fn included(&self, path: &Path) -> bool {
+        let relative = self.relative_path(path);
+        self.options.include.is_empty()
+            || self
+                .options
+                .include
+                .iter()
+                .any(|glob| glob.matches(&relative))
+    }
+
+    /// Enters a directory, returns an error entry if it cannot be read
+    fn enter(&mut self, path: PathBuf) -> Option<Walked> {
+        if self.options.symlinks == SymlinkPolicy::Follow {
+            match fs::canonicalize(&path) {
+                Ok(canonical) => {
+                    if !self.visited.insert(canonical) {
+                        return None;
+                    }
+                }
+                Err(err) => return Some(Walked::Error(path, err)),
+            }
+        }
+        match fs::read_dir(&path) {
+            Ok(entries) => {
+                self.stack.push((path, entries));
+                None
+            }
+            Err(err) => Some(Walked::Error(path, err)),
+        }
+    }
+
+    /// Decides what to do with a path, returns an entry to yield if any
+    fn visit(&mut self, path: PathBuf, is_root: bool) -> Option<Walked> {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) => return Some(Walked::Error(path, err)),
+        };
+
+        let metadata = if metadata.file_type().is_symlink() {
+            if !is_root && self.options.symlinks == SymlinkPolicy::Skip {
+                return None;
+            }
+            match fs::metadata(&path) {
+                Ok(target) if target.is_dir() && !is_root => {
+                    if self.options.symlinks != SymlinkPolicy::Follow {
+                        return None;
+                    }
+                    target
+                }
+                Ok(target) => target,
+                Err(err) => return Some(Walked::Error(path, err)),
+            }
+        } else {
+            metadata
+        };
+
+        if !is_root && (self.hidden(&path) || self.excluded(&path)) {
+            return None;
+        }
+
+        if metadata.is_dir() {
+            if is_root || self.options.recursive {
+                return self.enter(path);
+            }
+            return None;
+        }
+
+        // Sockets, FIFOs and devices are never scanned
+        let too_large = self
+            .options
+            .max_file_size
+            .is_some_and(|max| metadata.len() > max);
+        if !metadata.is_file() || too_large || (!is_root && !self.included(&path)) {
+            return None;
+        }
+        Some(Walked::File(path, metadata.len()))
+    }
+}
+
+impl Iterator for Walker {
+    type Item = Walked;
+
+    fn next(&mut self) -> Option<Walked> {
+        if let Some((start, is_root)) = self.start.take() {
+            if let Some(walked) = self.visit(start, is_root) {
+                return Some(walked);
+            }
+        }
+        loop {
+            let (dir, entries) = self.stack.last_mut()?;
+            match entries.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Err(err)) => {
+                    let dir = dir.clone();
+                    self.stack.pop();
+                    return Some(Walked::Error(dir, err));
+                }
+                Some(Ok(entry)) => {
+                    if let Some(walked) = self.visit(entry.path(), false) {
+                        return Some(walked);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the results of
+/// [`ClamAvSync::scan_dir`](crate::ClamAvSync::scan_dir)
+///
+/// The directory is walked and scanned by background threads. Dropping the
+/// iterator stops them after the files that are currently being scanned.
+#[derive(Debug)]
+pub struct ScanDirIter {
+    results: mpsc::Receiver<ScannedFile>,
+}
+
+impl Iterator for ScanDirIter {
+    type Item = ScannedFile;
+
+    fn next(&mut self) -> Option<ScannedFile> {
+        self.results.recv().ok()
+    }
+}
+
+pub(crate) fn scan_dir_sync<C>(client: C, root: &Path, options: ScanDirOptions) -> ScanDirIter
+where
+    C: ClamAvSync + Clone + Send + 'static,
+{
+    let concurrency = options.concurrency;
+    let chunk_size = options.chunk_size;
+    #[cfg(feature = "hash")]
+    let hash = options.hash;
+    let (walked_tx, walked_rx) = mpsc::sync_channel::<Walked>(concurrency);
+    let (result_tx, result_rx) = mpsc::sync_channel::<ScannedFile>(concurrency);
+    let walked_rx = Arc::new(Mutex::new(walked_rx));
+
+    let walker = Walker::new(root, options);
+    thread::spawn(move || {
+        for walked in walker {
+            if walked_tx.send(walked).is_err() {
+                break;
+            }
+        }
+    });
+
+    for _ in 0..concurrency {
+        let client = client.clone();
+        let walked_rx = Arc::clone(&walked_rx);
+        let result_tx = result_tx.clone();
+        thread::spawn(move || loop {
+            let walked = match walked_rx.lock().unwrap().recv() {
+                Ok(walked) => walked,
+                Err(_) => break,
+            };
+            let result = match walked {
+                Walked::File(path, size) => {
+                    let start = Instant::now();
+                    #[cfg(feature = "hash")]
+                    let (response, digest) = match hash {
+                        Some(algorithms) => {
+                            match client.scan_file_hashed(&path, chunk_size, algorithms) {
+                                Ok(hashed) => (Ok(hashed.response), Some(hashed.digest)),
+                                Err(err) => (Err(err), None),
+                            }
+                        }
+                        None => (client.scan_file(&path, chunk_size), None),
+                    };
+                    #[cfg(not(feature = "hash"))]
+                    let response = client.scan_file(&path, chunk_size);
+                    ScannedFile {
+                        response,
+                        path,
+                        size: Some(size),
+                        duration: start.elapsed(),
+                        #[cfg(feature = "hash")]
+                        digest,
+                    }
+                }
+                Walked::Error(path, err) => ScannedFile::walk_error(path, err),
+            };
+            if result_tx.send(result).is_err() {
+                break;
+            }
+        });
+    }
+
+    ScanDirIter { results: result_rx }
+}
+
+#[cfg(feature = "async-core")]
+pub use async_dir::ScanDirStream;
+
+#[cfg(feature = "async-core")]
+mod async_dir {
+    use std::{
+        fmt,
+        path::{Path, PathBuf},
+        pin::Pin,
+        task::{Context, Poll},
+        time::Instant,
+    };
+
+    use futures_lite::Stream;
+
+    use super::{ScanDirOptions, ScannedFile, Walked, Walker};
+    use crate::{rt::Unblock, BoxFuture, ClamAvAsync};
+
+    type ScanFn<'a> = Box<dyn Fn(PathBuf, u64) -> BoxFuture<'a, ScannedFile> + Send + 'a>;
+
+    /// Stream of the results of `ClamAvAsync::scan_dir`
+    ///
+    /// The directory is walked on a blocking thread pool, at most
+    /// `concurrency` files are scanned at the same time.
+    pub struct ScanDirStream<'a> {
+        walker: Option<Unblock<Walker>>,
+        in_flight: Vec<BoxFuture<'a, ScannedFile>>,
+        concurrency: usize,
+        scan: ScanFn<'a>,
+    }
+
+    impl fmt::Debug for ScanDirStream<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("ScanDirStream")
+                .field("in_flight", &self.in_flight.len())
+                .field("concurrency", &self.concurrency)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl<'a> ScanDirStream<'a> {
+        pub(crate) fn new<C: ClamAvAsync>(
+            client: &'a C,
+            root: &Path,
+            options: ScanDirOptions,
+        ) -> Self {
+            let concurrency = options.concurrency;
+            let chunk_size = options.chunk_size;
+            #[cfg(feature = "hash")]
+            let hash = options.hash;
+            let walker = Unblock::new(Walker::new(root, options), concurrency);
+            ScanDirStream {
+                walker: Some(walker),
+                in_flight: Vec::with_capacity(concurrency),
+                concurrency,
+                scan: Box::new(move |path, size| {
+                    Box::pin(async move {
+                        let start = Instant::now();
+                        #[cfg(feature = "hash")]
+                        let (response, digest) = match hash {
+                            Some(algorithms) => {
+                                match client.scan_file_hashed(&path, chunk_size, algorithms).await {
+                                    Ok(hashed) => (Ok(hashed.response), Some(hashed.digest)),
+                                    Err(err) => (Err(err), None),
+                                }
+                            }
+                            None => (client.scan_file(&path, chunk_size).await, None),
+                        };
+                        #[cfg(not(feature = "hash"))]
+                        let response = client.scan_file(&path, chunk_size).await;
+                        ScannedFile {
+                            response,
+                            path,
+                            size: Some(size),
+                            duration: start.elapsed(),
+                            #[cfg(feature = "hash")]
+                            digest,
+                        }
+                    })
+                }),
+            }
+        }
+    }
+
+    impl Stream for ScanDirStream<'_> {
+        type Item = ScannedFile;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ScannedFile>> {
+            let this = &mut *self;
+            while this.in_flight.len() < this.concurrency {
+                let Some(walker) = this.walker.as_mut() else {
+                    break;
+                };
+                match Pin::new(walker).poll_next(cx) {
+                    Poll::Ready(Some(walked)) => match walked {
+                        Walked::File(path, size) => this.in_flight.push((this.scan)(path, size)),
+                        Walked::Error(path, err) => {
+                            return Poll::Ready(Some(ScannedFile::walk_error(path, err)));
+                        }
+                    },
+                    Poll::Ready(None) => this.walker = None,
+                    Poll::Pending => break,
+                }
+            }
+
+            for i in 0..this.in_flight.len() {
+                if let Poll::Ready(result) = this.in_flight[i].as_mut().poll(cx) {
+                    drop(this.in_flight.swap_remove(i));
+                    return Poll::Ready(Some(result));
+                }
+            }
+
+            if this.walker.is_none() && this.in_flight.is_empty() {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+}
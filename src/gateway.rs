@@ -0,0 +1,442 @@
+//! HTTP gateway that scans uploads with ClamAV and answers with JSON
+//!
+//! A [`Gateway`] lets services that cannot speak the clamd protocol scan data
+//! over HTTP:
+//!
+//! | Route          | Response                                                  |
+//! |----------------|-----------------------------------------------------------|
+//! | `POST /scan`   | [`ScanResponse`] for the raw body or every uploaded file  |
+//! | `GET /health`  | Result of [`health`](ClamAvAsync::health), 503 if unhealthy |
+//! | `GET /version` | Engine and signature database version                     |
+//! | `GET /stats`   | Output of clamd's `STATS` command                         |
+//!
+//! Request bodies are streamed to clamd while they arrive. A body with the
+//! content type `multipart/form-data` is split into its parts with
+//! [`Multipart`] and every part, file or form field, is scanned in its own
+//! session. An upload without parts is answered with 400. Any other body is
+//! scanned as a whole.
+//!
+//! Scan responses have the status 200 if every file was scanned, whether it
+//! is clean or infected, and 502 if clamd could not scan a file. Bodies
+//! larger than the [maximum body size](Gateway::max_body_size) are answered
+//! with 413 and requests beyond the [concurrency
+//! limit](Gateway::max_concurrent_scans) with 503.
+//!
+//! ```no_run
+//! use clamav_client::{gateway::Gateway, tokio::Tcp};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let clamd = Tcp("127.0.0.1:3310".parse().unwrap());
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await.unwrap();
+//! Gateway::new(clamd)
+//!     .max_body_size(100 * 1024 * 1024)
+//!     .serve(listener)
+//!     .await;
+//! # }
+//! ```
+
+use std::{
+    convert::Infallible,
+    error::Error as StdError,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use futures_lite::{Stream, StreamExt};
+use http_body_util::{BodyStream, Full};
+use hyper::{
+    body::Body, header, server::conn::http1, service::service_fn, Method, Request, Response,
+    StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::Semaphore};
+
+use crate::{
+    health::{EngineVersion, HealthStatus, HealthThresholds, SelfTest},
+    multipart::{self, Multipart},
+    report::ScanRecord,
+    ClamAvAsync, Verdict,
+};
+
+/// Default maximum body size, clamd's default `StreamMaxLength` (25 MB)
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 25 * 1024 * 1024;
+
+/// Default maximum number of scan requests served at the same time
+pub const DEFAULT_MAX_CONCURRENT_SCANS: usize = 64;
+
+/// Response to `POST /scan`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanResponse {
+    /// `true` if every file was scanned and no virus was found
+    pub clean: bool,
+    /// Results of the scanned parts, a single entry for raw bodies
+    pub files: Vec<FileVerdict>,
+}
+
+/// Result of scanning a request body or an uploaded file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileVerdict {
+    /// Name of the form field, for multipart uploads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Content type of the body or the uploaded file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// The scan result; the path is the file name, or `stream` for raw bodies
+    /// and form fields
+    #[serde(flatten)]
+    pub record: ScanRecord,
+}
+
+/// Counts the bytes of a body and stops it at the maximum body size
+struct Limit {
+    max: u64,
+    read: AtomicU64,
+    exceeded: AtomicBool,
+}
+
+impl Limit {
+    fn new(max: u64) -> Self {
+        Limit {
+            max,
+            read: AtomicU64::new(0),
+            exceeded: AtomicBool::new(false),
+        }
+    }
+
+    fn check(&self, chunk: io::Result<Bytes>) -> io::Result<Bytes> {
+        let chunk = chunk?;
+        let read = self.read.fetch_add(chunk.len() as u64, Ordering::Relaxed) + chunk.len() as u64;
+        if read > self.max {
+            self.exceeded.store(true, Ordering::Relaxed);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "body too large"));
+        }
+        Ok(chunk)
+    }
+
+    fn read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Relaxed)
+    }
+}
+
+/// HTTP server that scans request bodies with ClamAV
+///
+/// Clones share the transport and the concurrency limit.
+#[derive(Debug)]
+pub struct Gateway<T> {
+    transport: Arc<T>,
+    max_body_size: u64,
+    scans: Arc<Semaphore>,
+    chunk_size: Option<usize>,
+    thresholds: HealthThresholds,
+}
+
+impl<T> Clone for Gateway<T> {
+    fn clone(&self) -> Self {
+        Gateway {
+            transport: self.transport.clone(),
+            max_body_size: self.max_body_size,
+            scans: self.scans.clone(),
+            chunk_size: self.chunk_size,
+            thresholds: self.thresholds,
+        }
+    }
+}
+
+impl<T: ClamAvAsync + Send + Sync + 'static> Gateway<T> {
+    /// Creates a gateway that scans with `transport`
+    pub fn new(transport: T) -> Self {
+        Gateway {
+            transport: Arc::new(transport),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            scans: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_SCANS)),
+            chunk_size: None,
+            thresholds: HealthThresholds::default(),
+        }
+    }
+
+    /// Sets the maximum number of bytes of a scan request body
+    pub fn max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Sets how many scan requests are served at the same time
+    ///
+    /// Further scan requests are answered with 503 until a scan finishes.
+    pub fn max_concurrent_scans(mut self, max_concurrent_scans: usize) -> Self {
+        self.scans = Arc::new(Semaphore::new(max_concurrent_scans));
+        self
+    }
+
+    /// Sets the chunk size for streaming bodies to clamd
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Sets the thresholds used by `GET /health`
+    pub fn health_thresholds(mut self, thresholds: HealthThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Returns the transport used for scanning
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Serves HTTP/1.1 connections from `listener`
+    ///
+    /// Every connection is served on its own Tokio task. This function only
+    /// returns if the listener fails.
+    pub async fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let gateway = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let gateway = gateway.clone();
+                    async move { Ok::<_, Infallible>(gateway.handle(request).await) }
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    }
+
+    /// Answers a single request
+    ///
+    /// Use this to mount the gateway in an existing hyper server.
+    ///
+    /// # Arguments
+    ///
+    /// * `request`: The HTTP request
+    ///
+    /// # Returns
+    ///
+    /// The HTTP response with a JSON body
+    pub async fn handle<B>(&self, request: Request<B>) -> Response<Full<Bytes>>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        match (request.method(), request.uri().path()) {
+            (&Method::POST, "/scan") => self.scan(request).await,
+            (&Method::GET, "/health") => self.health().await,
+            (&Method::GET, "/version") => self.version().await,
+            (&Method::GET, "/stats") => self.stats().await,
+            (_, "/scan" | "/health" | "/version" | "/stats") => {
+                error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => error(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    async fn scan<B>(&self, request: Request<B>) -> Response<Full<Bytes>>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let Ok(_permit) = self.scans.try_acquire() else {
+            return error(StatusCode::SERVICE_UNAVAILABLE, "too many concurrent scans");
+        };
+        if request.body().size_hint().lower() > self.max_body_size {
+            return error(StatusCode::PAYLOAD_TOO_LARGE, "body too large");
+        }
+
+        let content_type = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let body = body_stream(request.into_body());
+        let files = match content_type.as_deref() {
+            Some(mime) if mime.starts_with("multipart/form-data") => {
+                match self.scan_multipart(body, mime).await {
+                    Ok(files) => files,
+                    Err(response) => return response,
+                }
+            }
+            _ => {
+                let limit = Limit::new(self.max_body_size);
+                let started = Instant::now();
+                let body = body.map(|chunk| limit.check(chunk));
+                let result = self.transport.scan_stream(body, self.chunk_size).await;
+                if limit.exceeded() {
+                    return error(StatusCode::PAYLOAD_TOO_LARGE, "body too large");
+                }
+                let record = record("stream", result)
+                    .with_size(limit.read())
+                    .with_duration(started.elapsed());
+                vec![FileVerdict {
+                    field: None,
+                    content_type,
+                    record,
+                }]
+            }
+        };
+
+        let scanned = files.iter().all(|file| file.record.error.is_none());
+        let response = ScanResponse {
+            clean: scanned && files.iter().all(|file| file.record.signatures.is_empty()),
+            files,
+        };
+        let status = if scanned {
+            StatusCode::OK
+        } else {
+            StatusCode::BAD_GATEWAY
+        };
+        json_response(status, &response)
+    }
+
+    async fn scan_multipart<S>(
+        &self,
+        body: S,
+        content_type: &str,
+    ) -> Result<Vec<FileVerdict>, Response<Full<Bytes>>>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        let Some(boundary) = multipart::boundary(content_type) else {
+            return Err(error(StatusCode::BAD_REQUEST, "multipart boundary missing"));
+        };
+        let limit = Limit::new(self.max_body_size);
+        let body = body.map(|chunk| limit.check(chunk));
+        let mut multipart = Multipart::new(Box::pin(body), &boundary);
+        let upload_error = |err: io::Error| {
+            if limit.exceeded() {
+                error(StatusCode::PAYLOAD_TOO_LARGE, "body too large")
+            } else {
+                error(StatusCode::BAD_REQUEST, &err.to_string())
+            }
+        };
+
+        let mut files = Vec::new();
+        while let Some(part) = multipart.next_part().await.map_err(upload_error)? {
+            let started = Instant::now();
+            let (result, size) =
+                multipart::scan_part(&*self.transport, &mut multipart, self.chunk_size)
+                    .await
+                    .map_err(upload_error)?;
+            let record = record(part.filename.as_deref().unwrap_or("stream"), result)
+                .with_size(size)
+                .with_duration(started.elapsed());
+            files.push(FileVerdict {
+                field: part.name,
+                content_type: part.content_type,
+                record,
+            });
+        }
+        if files.is_empty() {
+            return Err(error(
+                StatusCode::BAD_REQUEST,
+                "multipart body without parts",
+            ));
+        }
+        Ok(files)
+    }
+
+    async fn health(&self) -> Response<Full<Bytes>> {
+        let report = self.transport.health(&self.thresholds).await;
+        let self_test = match &report.self_test {
+            SelfTest::Skipped => json!({ "status": "skipped" }),
+            SelfTest::Passed(signature) => json!({ "status": "passed", "signature": signature }),
+            SelfTest::Failed(message) => json!({ "status": "failed", "message": message }),
+        };
+        let body = json!({
+            "status": report.status,
+            "reachable": report.reachable,
+            "latency_ms": report.latency.map(|latency| latency.as_secs_f64() * 1000.0),
+            "version": report.version.as_ref().map(version_json),
+            "signature_age_secs": report.signature_age.map(|age| age.as_secs()),
+            "self_test": self_test,
+            "problems": report.problems,
+        });
+        let status = match report.status {
+            HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::OK,
+        };
+        json_response(status, &body)
+    }
+
+    async fn version(&self) -> Response<Full<Bytes>> {
+        match self.transport.get_version().await {
+            Ok(response) => match EngineVersion::parse(&response) {
+                Some(version) => json_response(StatusCode::OK, &version_json(&version)),
+                None => error(StatusCode::BAD_GATEWAY, "invalid VERSION response"),
+            },
+            Err(err) => error(StatusCode::BAD_GATEWAY, &err.to_string()),
+        }
+    }
+
+    async fn stats(&self) -> Response<Full<Bytes>> {
+        match self.transport.get_stats().await {
+            Ok(response) => {
+                let stats = String::from_utf8_lossy(&response);
+                let stats = stats.trim_end_matches(['\0', '\n']);
+                json_response(StatusCode::OK, &json!({ "stats": stats }))
+            }
+            Err(err) => error(StatusCode::BAD_GATEWAY, &err.to_string()),
+        }
+    }
+}
+
+/// Turns a body into a stream of its data frames
+fn body_stream<B>(body: B) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    BodyStream::new(body).filter_map(|frame| match frame {
+        Ok(frame) => frame.into_data().ok().map(Ok),
+        Err(err) => Some(Err(io::Error::other(err))),
+    })
+}
+
+fn record(path: impl Into<String>, result: crate::IoResult) -> ScanRecord {
+    let verdict = result.and_then(|response| {
+        Verdict::parse(&response).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    });
+    ScanRecord::from_result(path, verdict)
+}
+
+fn version_json(version: &EngineVersion) -> Value {
+    let database_time = version.database_time.and_then(|time| {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        Some(since_epoch.as_secs())
+    });
+    json!({
+        "engine": version.engine,
+        "database": version.database,
+        "database_time": database_time,
+    })
+}
+
+fn json_response<S: Serialize>(status: StatusCode, body: &S) -> Response<Full<Bytes>> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    json_response(status, &json!({ "error": message }))
+}
@@ -0,0 +1,194 @@
+//! Content hashes computed while scanning
+//!
+//! The `*_hashed` methods of [`ClamAvSync`](crate::ClamAvSync) and
+//! [`ClamAvAsync`](crate::ClamAvAsync) hash the bytes while they are sent
+//! with `INSTREAM`, so the data is read only once. Besides the selected
+//! digests, a [`ScanDigest`] contains the number of bytes and chunks that
+//! were sent.
+//!
+//! ```no_run
+//! use clamav_client::hash::HashAlgorithms;
+//! use clamav_client::ClamAvSync;
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let scan = clamd_tcp
+//!     .scan_file_hashed("upload.bin", None, HashAlgorithms::all())
+//!     .unwrap();
+//!
+//! println!(
+//!     "{} bytes, sha256 {}, clean: {:?}",
+//!     scan.digest.bytes,
+//!     scan.digest.sha256_hex().unwrap(),
+//!     clamav_client::clean(&scan.response),
+//! );
+//! ```
+
+use std::io::{self, Read};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::digest::hex;
+
+/// Digests to compute while scanning
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HashAlgorithms {
+    sha256: bool,
+    md5: bool,
+}
+
+impl HashAlgorithms {
+    /// Selects no digests, only bytes and chunks are counted
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects all supported digests
+    pub fn all() -> Self {
+        HashAlgorithms {
+            sha256: true,
+            md5: true,
+        }
+    }
+
+    /// Sets whether the SHA-256 digest is computed
+    pub fn sha256(mut self, sha256: bool) -> Self {
+        self.sha256 = sha256;
+        self
+    }
+
+    /// Sets whether the MD5 digest is computed
+    pub fn md5(mut self, md5: bool) -> Self {
+        self.md5 = md5;
+        self
+    }
+}
+
+/// Digests and counts of the data sent with `INSTREAM`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanDigest {
+    /// Number of bytes sent
+    pub bytes: u64,
+    /// Number of chunks sent, excluding the terminating zero-length chunk
+    pub chunks: u64,
+    /// SHA-256 digest, if selected
+    pub sha256: Option<[u8; 32]>,
+    /// MD5 digest, if selected
+    pub md5: Option<[u8; 16]>,
+}
+
+impl ScanDigest {
+    /// Returns the hex-encoded SHA-256 digest, if selected
+    pub fn sha256_hex(&self) -> Option<String> {
+        self.sha256.as_ref().map(|digest| hex(digest))
+    }
+
+    /// Returns the hex-encoded MD5 digest, if selected
+    pub fn md5_hex(&self) -> Option<String> {
+        self.md5.as_ref().map(|digest| hex(digest))
+    }
+}
+
+/// Response of a scan together with the digest of the scanned data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashedResponse {
+    /// The server's response
+    pub response: Vec<u8>,
+    /// Digests and counts of the scanned data
+    pub digest: ScanDigest,
+}
+
+/// Incremental state of a [`ScanDigest`]
+#[derive(Debug, Clone)]
+pub(crate) struct Digester {
+    bytes: u64,
+    chunks: u64,
+    sha256: Option<Sha256>,
+    md5: Option<Md5>,
+}
+
+impl Digester {
+    pub(crate) fn new(algorithms: HashAlgorithms) -> Self {
+        Digester {
+            bytes: 0,
+            chunks: 0,
+            sha256: algorithms.sha256.then(Sha256::new),
+            md5: algorithms.md5.then(Md5::new),
+        }
+    }
+
+    /// Adds data that is sent in chunks of at most `chunk_size` bytes
+    pub(crate) fn update(&mut self, data: &[u8], chunk_size: usize) {
+        if data.is_empty() {
+            return;
+        }
+        self.bytes += data.len() as u64;
+        self.chunks += data.len().div_ceil(chunk_size.max(1)) as u64;
+        if let Some(sha256) = self.sha256.as_mut() {
+            sha256.update(data);
+        }
+        if let Some(md5) = self.md5.as_mut() {
+            md5.update(data);
+        }
+    }
+
+    pub(crate) fn finish(self, response: Vec<u8>) -> HashedResponse {
+        HashedResponse {
+            response,
+            digest: self.digest(),
+        }
+    }
+
+    pub(crate) fn digest(self) -> ScanDigest {
+        ScanDigest {
+            bytes: self.bytes,
+            chunks: self.chunks,
+            sha256: self.sha256.map(|sha256| sha256.finalize().into()),
+            md5: self.md5.map(|md5| md5.finalize().into()),
+        }
+    }
+}
+
+/// Reader that feeds everything it reads into a [`Digester`]
+///
+/// The scan loops send every non-empty read as one chunk, so each read
+/// counts as a chunk.
+#[derive(Debug)]
+pub(crate) struct HashingReader<'a, R> {
+    pub(crate) inner: R,
+    pub(crate) digester: &'a mut Digester,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.digester.update(&buf[..len], usize::MAX);
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_hash {
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use futures_lite::AsyncRead;
+
+    use super::HashingReader;
+
+    impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<'_, R> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let len = futures_lite::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            this.digester.update(&buf[..len], usize::MAX);
+            Poll::Ready(Ok(len))
+        }
+    }
+}
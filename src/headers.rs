@@ -0,0 +1,253 @@
+//! Header parameters and encoded words shared by the MIME and
+//! `multipart/form-data` parsers
+
+/// Splits a header value into its first token and its parameters
+pub(crate) fn split_parameters(value: &str) -> (String, Vec<(String, String)>) {
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == ';' && !quoted {
+            segments.push(std::mem::take(&mut segment));
+            continue;
+        }
+        segment.push(c);
+    }
+    segments.push(segment);
+
+    let mut segments = segments.into_iter();
+    let value = segments.next().unwrap_or_default().trim().to_string();
+    let parameters = segments
+        .filter_map(|segment| {
+            let (name, value) = segment.split_once('=')?;
+            Some((name.trim().to_ascii_lowercase(), unquote(value.trim())))
+        })
+        .collect();
+    (value, parameters)
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .map(|v| v.strip_suffix('"').unwrap_or(v))
+    else {
+        return value.to_string();
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Returns a parameter, decoding RFC 2231 continuations and charsets as well
+/// as RFC 2047 encoded words
+pub(crate) fn parameter(parameters: &[(String, String)], name: &str) -> Option<String> {
+    let mut segments: Vec<(u32, bool, &str)> = Vec::new();
+    for (key, value) in parameters {
+        let Some(rest) = key.strip_prefix(name) else {
+            continue;
+        };
+        if rest.is_empty() {
+            return Some(decode_words(value));
+        }
+        let Some(rest) = rest.strip_prefix('*') else {
+            continue;
+        };
+        if rest.is_empty() {
+            return Some(decode_extended(&[(true, value)]));
+        }
+        let (index, extended) = match rest.strip_suffix('*') {
+            Some(index) => (index, true),
+            None => (rest, false),
+        };
+        if let Ok(index) = index.parse() {
+            segments.push((index, extended, value));
+        }
+    }
+    if segments.is_empty() {
+        return None;
+    }
+    segments.sort_by_key(|(index, _, _)| *index);
+    let segments: Vec<(bool, &str)> = segments
+        .into_iter()
+        .map(|(_, extended, value)| (extended, value))
+        .collect();
+    Some(decode_extended(&segments))
+}
+
+/// Decodes RFC 2231 segments, the first extended one starts with
+/// `charset'language'`
+fn decode_extended(segments: &[(bool, &str)]) -> String {
+    let mut charset = None;
+    let mut bytes = Vec::new();
+    for (extended, value) in segments {
+        if !extended {
+            bytes.extend_from_slice(value.as_bytes());
+            continue;
+        }
+        let mut value = *value;
+        if charset.is_none() {
+            let mut fields = value.splitn(3, '\'');
+            if let (Some(name), Some(_), Some(rest)) = (fields.next(), fields.next(), fields.next())
+            {
+                charset = Some(name.to_string());
+                value = rest;
+            }
+        }
+        bytes.extend(percent_decode(value.as_bytes()));
+    }
+    decode_charset(charset.as_deref().unwrap_or("utf-8"), &bytes)
+}
+
+fn percent_decode(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == b'%' {
+            if let Some(byte) = data.get(i + 1..i + 3).and_then(hex_byte) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(data[i]);
+        i += 1;
+    }
+    decoded
+}
+
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+/// Decodes RFC 2047 encoded words such as `=?UTF-8?B?...?=`
+fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let Some(word) = encoded_word(&rest[start..]) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&word.0);
+        rest = &rest[start + word.1..];
+        after_word = true;
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decodes the encoded word at the start of `text`, returns the text and
+/// the length of the word
+fn encoded_word(text: &str) -> Option<(String, usize)> {
+    let mut fields = text[2..].splitn(3, '?');
+    let charset = fields.next()?;
+    let encoding = fields.next()?;
+    let rest = fields.next()?;
+    let end = rest.find("?=")?;
+    let encoded = &rest[..end];
+    let length = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(encoded.as_bytes()),
+        "Q" | "q" => decode_quoted_printable(encoded.replace('_', " ").as_bytes()),
+        _ => return None,
+    };
+    // The language may follow the charset, as in `UTF-8*en`
+    let charset = charset.split('*').next().unwrap_or(charset);
+    Some((decode_charset(charset, &bytes), length))
+}
+
+fn decode_charset(charset: &str, bytes: &[u8]) -> String {
+    match charset.to_ascii_lowercase().as_str() {
+        "iso-8859-1" | "latin1" | "windows-1252" | "cp1252" => {
+            bytes.iter().map(|&b| char::from(b)).collect()
+        }
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Decodes base64, ignoring line breaks and other characters outside the
+/// alphabet
+///
+/// Padding ends a block, decoding continues after it so concatenated padded
+/// blocks are decoded completely.
+pub(crate) fn decode_base64(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &b in data {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => {
+                buffer = 0;
+                bits = 0;
+                continue;
+            }
+            _ => continue,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    decoded
+}
+
+/// Decodes quoted-printable, invalid escapes are kept as they are
+pub(crate) fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != b'=' {
+            decoded.push(data[i]);
+            i += 1;
+            continue;
+        }
+        let rest = &data[i + 1..];
+        // A soft line break, possibly with trailing whitespace
+        let padding = rest
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        if rest[padding..].starts_with(b"\r\n") {
+            i += 1 + padding + 2;
+        } else if rest[padding..].starts_with(b"\n") {
+            i += 1 + padding + 1;
+        } else if let Some(byte) = rest.get(..2).and_then(hex_byte) {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(b'=');
+            i += 1;
+        }
+    }
+    decoded
+}
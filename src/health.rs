@@ -0,0 +1,306 @@
+//! Health checks for readiness and liveness probes
+//!
+//! [`ClamAvSync::health`](crate::ClamAvSync::health) and
+//! [`ClamAvAsync::health`](crate::ClamAvAsync::health) send `PING`, `VERSION`
+//! and, unless disabled, scan the [`EICAR`] test string. The resulting
+//! [`HealthReport`] contains the round-trip latency, the engine and signature
+//! database versions, the age of the signatures and the outcome of the self
+//! test, together with an overall [`HealthStatus`] that is derived from the
+//! [`HealthThresholds`].
+//!
+//! ```no_run
+//! use clamav_client::health::{HealthStatus, HealthThresholds};
+//! use clamav_client::ClamAvSync;
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let report = clamd_tcp.health(&HealthThresholds::default());
+//! if report.status != HealthStatus::Healthy {
+//!     eprintln!("clamd is {:?}: {:?}", report.status, report.problems);
+//! }
+//! ```
+
+use std::{
+    io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{IoResult, Verdict, PONG};
+
+/// The EICAR anti-virus test string
+pub const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// Overall health of a ClamAV server, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum HealthStatus {
+    /// All checks passed
+    Healthy,
+    /// The server works, but is slow or its signatures are outdated
+    Degraded,
+    /// The server is unreachable, does not answer correctly or does not
+    /// detect the test string
+    Unhealthy,
+}
+
+/// Limits above which a server is considered degraded or unhealthy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// Maximum `PING` round-trip time of a healthy server
+    pub max_latency: Duration,
+    /// Maximum age of the signatures of a healthy server
+    pub max_signature_age: Duration,
+    /// Signatures older than this make the server unhealthy
+    pub unhealthy_signature_age: Duration,
+    /// Whether the [`EICAR`] test string is scanned as self test
+    pub self_test: bool,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        HealthThresholds {
+            max_latency: Duration::from_secs(1),
+            max_signature_age: Duration::from_secs(24 * 60 * 60),
+            unhealthy_signature_age: Duration::from_secs(7 * 24 * 60 * 60),
+            self_test: true,
+        }
+    }
+}
+
+/// Engine and signature database versions reported by `VERSION`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineVersion {
+    /// Version of the ClamAV engine, e.g. `1.4.1`
+    pub engine: String,
+    /// Version of the signature database, if one is loaded
+    pub database: Option<u32>,
+    /// Build time of the signature database, if one is loaded
+    pub database_time: Option<SystemTime>,
+}
+
+impl EngineVersion {
+    /// Parses a `VERSION` response such as
+    /// `ClamAV 1.4.1/27430/Wed Oct 16 08:37:08 2024`
+    ///
+    /// The database time is interpreted as UTC.
+    ///
+    /// # Returns
+    ///
+    /// The parsed versions, or [`None`] if the response does not start with
+    /// `ClamAV`
+    pub fn parse(response: &[u8]) -> Option<Self> {
+        let response = std::str::from_utf8(response).ok()?;
+        let response = response.trim_end_matches(['\0', '\n']);
+        let mut parts = response.strip_prefix("ClamAV ")?.splitn(3, '/');
+        Some(EngineVersion {
+            engine: parts.next()?.trim().to_string(),
+            database: parts.next().and_then(|db| db.trim().parse().ok()),
+            database_time: parts.next().and_then(parse_ctime),
+        })
+    }
+}
+
+/// Outcome of scanning the [`EICAR`] test string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelfTest {
+    /// The self test was disabled or the server was unreachable
+    Skipped,
+    /// The test string was detected with this signature
+    Passed(String),
+    /// The test string was not detected or the scan failed
+    Failed(String),
+}
+
+/// Result of a health check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Overall status
+    pub status: HealthStatus,
+    /// Whether the server answered `PING` with `PONG`
+    pub reachable: bool,
+    /// Round-trip time of `PING`, including connecting
+    pub latency: Option<Duration>,
+    /// Versions reported by `VERSION`
+    pub version: Option<EngineVersion>,
+    /// Time since the signature database was built
+    pub signature_age: Option<Duration>,
+    /// Outcome of the self test
+    pub self_test: SelfTest,
+    /// Reasons why the server is not healthy
+    pub problems: Vec<String>,
+}
+
+impl HealthReport {
+    /// Returns `true` if the status is [`HealthStatus::Healthy`]
+    pub fn is_healthy(&self) -> bool {
+        self.status == HealthStatus::Healthy
+    }
+}
+
+/// Builds a [`HealthReport`] from the responses of the individual checks
+pub(crate) struct HealthCheck<'a> {
+    thresholds: &'a HealthThresholds,
+    report: HealthReport,
+}
+
+impl<'a> HealthCheck<'a> {
+    pub(crate) fn new(thresholds: &'a HealthThresholds) -> Self {
+        HealthCheck {
+            thresholds,
+            report: HealthReport {
+                status: HealthStatus::Healthy,
+                reachable: false,
+                latency: None,
+                version: None,
+                signature_age: None,
+                self_test: SelfTest::Skipped,
+                problems: Vec::new(),
+            },
+        }
+    }
+
+    fn problem(&mut self, status: HealthStatus, problem: String) {
+        self.report.status = self.report.status.max(status);
+        self.report.problems.push(problem);
+    }
+
+    /// Records the `PING` response and returns whether the server is reachable
+    pub(crate) fn ping(&mut self, response: IoResult, latency: Duration) -> bool {
+        match response {
+            Ok(response) if response == PONG => {
+                self.report.reachable = true;
+                self.report.latency = Some(latency);
+                if latency > self.thresholds.max_latency {
+                    self.problem(
+                        HealthStatus::Degraded,
+                        format!(
+                            "latency of {latency:?} exceeds {:?}",
+                            self.thresholds.max_latency
+                        ),
+                    );
+                }
+            }
+            Ok(response) => self.problem(
+                HealthStatus::Unhealthy,
+                format!(
+                    "unexpected PING response {:?}",
+                    String::from_utf8_lossy(&response)
+                ),
+            ),
+            Err(err) => self.problem(HealthStatus::Unhealthy, format!("unreachable: {err}")),
+        }
+        self.report.reachable
+    }
+
+    /// Records the `VERSION` response and checks the signature age
+    pub(crate) fn version(&mut self, response: IoResult, now: SystemTime) {
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                return self.problem(HealthStatus::Unhealthy, format!("VERSION failed: {err}"))
+            }
+        };
+        let Some(version) = EngineVersion::parse(&response) else {
+            return self.problem(
+                HealthStatus::Unhealthy,
+                format!(
+                    "unexpected VERSION response {:?}",
+                    String::from_utf8_lossy(&response)
+                ),
+            );
+        };
+
+        match version.database_time {
+            Some(time) => {
+                let age = now.duration_since(time).unwrap_or_default();
+                self.report.signature_age = Some(age);
+                if age > self.thresholds.unhealthy_signature_age {
+                    self.problem(
+                        HealthStatus::Unhealthy,
+                        format!("signatures are {age:?} old"),
+                    );
+                } else if age > self.thresholds.max_signature_age {
+                    self.problem(
+                        HealthStatus::Degraded,
+                        format!("signatures are {age:?} old"),
+                    );
+                }
+            }
+            None => self.problem(
+                HealthStatus::Degraded,
+                String::from("signature database version unknown"),
+            ),
+        }
+        self.report.version = Some(version);
+    }
+
+    /// Records the response to scanning the [`EICAR`] test string
+    pub(crate) fn self_test(&mut self, response: IoResult) {
+        let self_test = match response.and_then(|response| {
+            Verdict::parse(&response).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }) {
+            Ok(Verdict::Infected(signatures)) => SelfTest::Passed(signatures[0].clone()),
+            Ok(Verdict::Clean) => SelfTest::Failed(String::from("EICAR test string not detected")),
+            Ok(Verdict::Error(message)) => SelfTest::Failed(message),
+            Err(err) => SelfTest::Failed(err.to_string()),
+        };
+        if let SelfTest::Failed(reason) = &self_test {
+            self.problem(
+                HealthStatus::Unhealthy,
+                format!("self test failed: {reason}"),
+            );
+        }
+        self.report.self_test = self_test;
+    }
+
+    pub(crate) fn wants_self_test(&self) -> bool {
+        self.thresholds.self_test
+    }
+
+    pub(crate) fn finish(self) -> HealthReport {
+        self.report
+    }
+}
+
+/// Parses a C `asctime` timestamp such as `Wed Oct 16 08:37:08 2024` as UTC
+fn parse_ctime(time: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let fields: Vec<&str> = time.split_whitespace().collect();
+    let [_, month, day, clock, year] = fields[..] else {
+        return None;
+    };
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let day: u8 = day.parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    let mut clock = clock.split(':').map(|n| n.parse::<u8>().ok());
+    let (hours, minutes, seconds) = (clock.next()??, clock.next()??, clock.next()??);
+    // 60 is a leap second
+    if !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    if clock.next().is_some() {
+        return None;
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 {
+        (year.checked_sub(1)?, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y.rem_euclid(400);
+    let day_of_year = (153 * m + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era
+        .checked_mul(146_097)?
+        .checked_add(day_of_era - 719_468)?;
+
+    let seconds = days
+        .checked_mul(86_400)?
+        .checked_add(i64::from(hours) * 3_600 + i64::from(minutes) * 60 + i64::from(seconds))?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(seconds).ok()?))
+}
@@ -0,0 +1,621 @@
+//! ICAP server for HTTP proxies such as Squid (RFC 3507)
+//!
+//! An [`IcapServer`] answers `OPTIONS`, `REQMOD` and `RESPMOD` requests. The
+//! encapsulated HTTP body is decoded while it arrives and streamed to clamd
+//! with [`ClamAvAsync::scan_stream`], so bodies are not buffered unless the
+//! client does not accept `204 No Content` responses and the unmodified body
+//! has to be sent back.
+//!
+//! - Clean messages are answered with `204 No Content`, or with the original
+//!   message if the client did not send `Allow: 204`. Bodies that have to be
+//!   sent back are buffered up to the [maximum body
+//!   size](IcapServer::max_body_size), larger ones are blocked.
+//! - Infected messages are replaced with an HTTP `403 Forbidden` block page
+//!   and the `X-Infection-Found` and `X-Virus-ID` headers are set.
+//! - If a message cannot be scanned, the [`VerdictPolicy`] decides: fail
+//!   closed returns the block page, fail open lets the message pass and
+//!   strict answers with `500 Server Error`.
+//!
+//! Previews are supported: if the whole body fits into the preview, the
+//! client never has to send more; otherwise the server asks for the rest with
+//! `100 Continue`.
+//!
+//! ```no_run
+//! use clamav_client::{icap::IcapServer, Tcp};
+//!
+//! let clamd = Tcp("127.0.0.1:3310".parse().unwrap());
+//! let server = IcapServer::new(clamd).service("ClamAV");
+//! server.bind("0.0.0.0:1344").unwrap().wait();
+//! ```
+//!
+//! Squid is then configured with, e.g.:
+//!
+//! ```text
+//! icap_enable on
+//! icap_service clamav respmod_precache bypass=0 icap://127.0.0.1:1344/respmod
+//! adaptation_access clamav allow all
+//! ```
+
+use std::{
+    fmt, io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use futures_lite::{io::BufReader, stream, AsyncRead, AsyncWrite, AsyncWriteExt, StreamExt};
+
+use crate::{health::EngineVersion, ClamAvAsync, Verdict, VerdictPolicy};
+
+mod protocol;
+use protocol::{invalid, read_request, ChunkedDecoder, Encapsulated, Request, Response};
+
+/// Default preview size in bytes announced in `OPTIONS` responses
+pub const DEFAULT_PREVIEW: usize = 1024;
+
+/// Default block page, see [`IcapServer::block_page`]
+pub const DEFAULT_BLOCK_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><title>Access denied</title></head>
+<body>
+<h1>Access denied</h1>
+<p>The content of {url} was blocked: {reason}</p>
+</body>
+</html>
+";
+
+/// Default maximum size of a body that is sent back unmodified, clamd's
+/// default `StreamMaxLength` (25 MB)
+pub const DEFAULT_MAX_BODY_SIZE: usize = 25 * 1024 * 1024;
+
+/// Default maximum number of connections served at the same time
+pub const DEFAULT_MAX_CONNECTIONS: usize = 100;
+
+/// Number of bytes read from the client per chunk sent to clamd
+const READ_SIZE: usize = 64 * 1024;
+
+/// Time to wait before accepting again after accepting a connection failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// ICAP server that scans HTTP messages with ClamAV
+///
+/// Clones share the transport and the ISTag.
+pub struct IcapServer<T> {
+    transport: Arc<T>,
+    service: String,
+    istag: Arc<Mutex<String>>,
+    fixed_istag: bool,
+    preview: usize,
+    options_ttl: u32,
+    verdict_policy: VerdictPolicy,
+    block_page: Arc<str>,
+    chunk_size: Option<usize>,
+    max_body_size: usize,
+    max_connections: usize,
+}
+
+impl<T> Clone for IcapServer<T> {
+    fn clone(&self) -> Self {
+        IcapServer {
+            transport: self.transport.clone(),
+            service: self.service.clone(),
+            istag: self.istag.clone(),
+            fixed_istag: self.fixed_istag,
+            preview: self.preview,
+            options_ttl: self.options_ttl,
+            verdict_policy: self.verdict_policy,
+            block_page: self.block_page.clone(),
+            chunk_size: self.chunk_size,
+            max_body_size: self.max_body_size,
+            max_connections: self.max_connections,
+        }
+    }
+}
+
+impl<T> fmt::Debug for IcapServer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcapServer")
+            .field("service", &self.service)
+            .field("istag", &self.current_istag())
+            .field("preview", &self.preview)
+            .field("options_ttl", &self.options_ttl)
+            .field("verdict_policy", &self.verdict_policy)
+            .field("chunk_size", &self.chunk_size)
+            .field("max_body_size", &self.max_body_size)
+            .field("max_connections", &self.max_connections)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> IcapServer<T> {
+    fn current_istag(&self) -> String {
+        self.istag
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+}
+
+impl<T: ClamAvAsync + Send + Sync + 'static> IcapServer<T> {
+    /// Creates a server that scans messages with `transport`
+    ///
+    /// The server fails closed, announces a preview of
+    /// [`DEFAULT_PREVIEW`] bytes and derives its ISTag from the signature
+    /// database version reported by clamd.
+    pub fn new(transport: T) -> Self {
+        IcapServer {
+            transport: Arc::new(transport),
+            service: String::from("clamav-client ICAP"),
+            istag: Arc::new(Mutex::new(String::from("clamav-client"))),
+            fixed_istag: false,
+            preview: DEFAULT_PREVIEW,
+            options_ttl: 3600,
+            verdict_policy: VerdictPolicy::FailClosed,
+            block_page: Arc::from(DEFAULT_BLOCK_PAGE),
+            chunk_size: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+
+    /// Sets the service name sent in the `Service` header
+    pub fn service(mut self, service: impl Into<String>) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    /// Sets a fixed ISTag instead of the signature database version
+    ///
+    /// Clients use the ISTag to invalidate cached responses, so it should
+    /// change whenever verdicts may change.
+    pub fn istag(mut self, istag: impl Into<String>) -> Self {
+        self.istag = Arc::new(Mutex::new(istag.into()));
+        self.fixed_istag = true;
+        self
+    }
+
+    /// Sets the preview size announced in `OPTIONS` responses
+    pub fn preview(mut self, preview: usize) -> Self {
+        self.preview = preview;
+        self
+    }
+
+    /// Sets how many seconds clients may cache the `OPTIONS` response
+    pub fn options_ttl(mut self, seconds: u32) -> Self {
+        self.options_ttl = seconds;
+        self
+    }
+
+    /// Sets how messages that could not be scanned are answered
+    pub fn verdict_policy(mut self, verdict_policy: VerdictPolicy) -> Self {
+        self.verdict_policy = verdict_policy;
+        self
+    }
+
+    /// Sets the HTML page returned instead of blocked messages
+    ///
+    /// `{url}` is replaced with the URL of the request and `{reason}` with
+    /// the found signatures or the scan error. Both are HTML-escaped.
+    pub fn block_page(mut self, template: impl Into<String>) -> Self {
+        self.block_page = Arc::from(template.into());
+        self
+    }
+
+    /// Sets the chunk size for streaming bodies to clamd
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Sets the maximum size of a body that is buffered to be sent back
+    ///
+    /// Clients that do not send `Allow: 204` expect the unmodified message
+    /// back if it is clean. Larger clean bodies are answered with the block
+    /// page. Defaults to [`DEFAULT_MAX_BODY_SIZE`].
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Sets the maximum number of connections served at the same time
+    ///
+    /// The limit is announced in the `Max-Connections` header of `OPTIONS`
+    /// responses, connections beyond it are closed right after they are
+    /// accepted. Defaults to [`DEFAULT_MAX_CONNECTIONS`].
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Returns the transport used for scanning
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Serves ICAP requests on a connection until the client closes it
+    ///
+    /// # Arguments
+    ///
+    /// * `stream`: The connection with the ICAP client
+    ///
+    /// # Returns
+    ///
+    /// An error if reading from or writing to the client failed. Malformed
+    /// requests are answered with `400 Bad Request` and close the connection.
+    pub async fn handle<S>(&self, stream: S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut reader = BufReader::new(stream);
+        loop {
+            let keep_alive = match self.serve(&mut reader).await {
+                Ok(keep_alive) => keep_alive,
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    let response = Response::new(400, "Bad Request")
+                        .header("ISTag", quoted(&self.current_istag()))
+                        .header("Connection", "close");
+                    return write(&mut reader, &response).await;
+                }
+                Err(err) => return Err(err),
+            };
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Starts listening on a TCP address
+    ///
+    /// Every connection is served on its own thread, up to the [maximum
+    /// number of connections](Self::max_connections). Use port 0 to let the
+    /// operating system pick a free port.
+    pub fn bind<A: ToSocketAddrs>(&self, address: A) -> io::Result<IcapListener> {
+        let listener = TcpListener::bind(address)?;
+        let local_addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let server = self.clone();
+        let handle = {
+            let stopped = stopped.clone();
+            thread::spawn(move || loop {
+                let accepted = listener.accept();
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok((stream, _)) = accepted else {
+                    // Do not spin while the process is out of resources
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                };
+                if connections.fetch_add(1, Ordering::SeqCst) >= server.max_connections {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let active = ActiveConnection(connections.clone());
+                let server = server.clone();
+                thread::spawn(move || {
+                    let _active = active;
+                    if let Ok(stream) = async_net::TcpStream::try_from(stream) {
+                        let _ = async_io::block_on(server.handle(stream));
+                    }
+                });
+            })
+        };
+        Ok(IcapListener {
+            local_addr,
+            stopped,
+            handle: Some(handle),
+        })
+    }
+
+    /// Reads and answers one request, returns whether to keep the connection
+    async fn serve<S>(&self, reader: &mut BufReader<S>) -> io::Result<bool>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let Some(request) = read_request(reader).await? else {
+            return Ok(false);
+        };
+        let encapsulated = Encapsulated::read(reader, request.header("Encapsulated")).await?;
+
+        let (response, keep_alive) = match request.method.as_str() {
+            "OPTIONS" => {
+                if encapsulated.body.is_some() {
+                    let mut decoder = ChunkedDecoder::default();
+                    while decoder.next(reader, READ_SIZE).await?.is_some() {}
+                }
+                (self.options().await, true)
+            }
+            "REQMOD" | "RESPMOD" => self.modify(reader, &request, encapsulated).await?,
+            _ => (Response::new(501, "Method Not Implemented"), false),
+        };
+        let keep_alive = keep_alive && !request.closes_connection();
+        let response = if keep_alive {
+            response
+        } else {
+            response.header("Connection", "close")
+        };
+        write(
+            reader,
+            &response.header("ISTag", quoted(&self.current_istag())),
+        )
+        .await?;
+        Ok(keep_alive)
+    }
+
+    async fn options(&self) -> Response {
+        if !self.fixed_istag {
+            let version = self.transport.get_version().await.ok();
+            let database = version.and_then(|version| EngineVersion::parse(&version)?.database);
+            if let Some(database) = database {
+                *self.istag.lock().unwrap_or_else(|err| err.into_inner()) =
+                    format!("clamav-{database}");
+            }
+        }
+        Response::new(200, "OK")
+            .header("Methods", "REQMOD, RESPMOD")
+            .header("Service", self.service.clone())
+            .header("Max-Connections", self.max_connections.to_string())
+            .header("Options-TTL", self.options_ttl.to_string())
+            .header("Allow", "204")
+            .header("Preview", self.preview.to_string())
+            .header("Transfer-Preview", "*")
+    }
+
+    /// Scans the body of a `REQMOD` or `RESPMOD` request
+    async fn modify<S>(
+        &self,
+        reader: &mut BufReader<S>,
+        request: &Request,
+        encapsulated: Encapsulated,
+    ) -> io::Result<(Response, bool)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let Some(body_name) = encapsulated.body else {
+            // Nothing to scan
+            let response = if request.allows_204() {
+                Response::new(204, "No Content")
+            } else {
+                echo(encapsulated, None)
+            };
+            return Ok((response, true));
+        };
+
+        let mut decoder = ChunkedDecoder::default();
+        let mut preview = Vec::new();
+        let mut allows_204 = request.allows_204();
+        if let Some(size) = request.preview()? {
+            while let Some(data) = decoder.next(reader, READ_SIZE).await? {
+                preview.extend_from_slice(&data);
+                if preview.len() > size {
+                    return Err(invalid("preview larger than announced"));
+                }
+            }
+            if decoder.ieof() {
+                // The whole body fits into the preview
+                allows_204 = true;
+            } else {
+                reader
+                    .get_mut()
+                    .write_all(b"ICAP/1.0 100 Continue\r\n\r\n")
+                    .await?;
+                reader.get_mut().flush().await?;
+                decoder = ChunkedDecoder::default();
+            }
+        }
+
+        let mut copy = (!allows_204).then(|| {
+            let mut copy = BodyCopy::new(self.max_body_size);
+            copy.push(&preview);
+            copy
+        });
+        let rest = stream::unfold(
+            (&mut *reader, &mut decoder, copy.as_mut()),
+            |(reader, decoder, mut copy)| async move {
+                match decoder.next(reader, READ_SIZE).await {
+                    Ok(Some(data)) => {
+                        if let Some(copy) = copy.as_deref_mut() {
+                            copy.push(&data);
+                        }
+                        Some((Ok(bytes::Bytes::from(data)), (reader, decoder, copy)))
+                    }
+                    Ok(None) => None,
+                    Err(err) => Some((Err(err), (reader, decoder, copy))),
+                }
+            },
+        );
+        let body = stream::once(Ok(bytes::Bytes::from(preview))).chain(rest);
+        let response = self.transport.scan_stream(body, self.chunk_size).await;
+
+        // Read what clamd did not need, so that the connection can be reused
+        while let Some(data) = decoder.next(reader, READ_SIZE).await? {
+            if let Some(copy) = copy.as_mut() {
+                copy.push(&data);
+            }
+        }
+        if let Some(kind) = decoder.error() {
+            return Err(io::Error::new(kind, "reading the body failed"));
+        }
+
+        let verdict = match response {
+            Ok(response) => {
+                Verdict::parse(&response).unwrap_or_else(|err| Verdict::Error(err.to_string()))
+            }
+            Err(err) => Verdict::Error(err.to_string()),
+        };
+        let url = encapsulated.url();
+        let response = match self.verdict_policy.apply(verdict) {
+            Ok(Verdict::Clean) if allows_204 => Response::new(204, "No Content"),
+            Ok(Verdict::Clean) => match copy.and_then(BodyCopy::into_data) {
+                Some(copy) => echo(encapsulated, Some((body_name, copy))),
+                None => self.block(url.as_deref(), "message too large to return unmodified"),
+            },
+            Ok(Verdict::Infected(signatures)) => {
+                let reason = format!("virus found: {}", signatures.join(", "));
+                let mut response = self.block(url.as_deref(), &reason);
+                for signature in &signatures {
+                    response = response.header(
+                        "X-Infection-Found",
+                        format!("Type=0; Resolution=2; Threat={signature};"),
+                    );
+                }
+                response.header("X-Virus-ID", signatures.join(", "))
+            }
+            Ok(Verdict::Error(message)) => {
+                self.block(url.as_deref(), &format!("scan failed: {message}"))
+            }
+            Err(_) => Response::new(500, "Server Error"),
+        };
+        Ok((response, true))
+    }
+
+    /// Returns a response replacing the message with the block page
+    fn block(&self, url: Option<&str>, reason: &str) -> Response {
+        let page = self
+            .block_page
+            .replace("{url}", &escape_html(url.unwrap_or("the requested URL")))
+            .replace("{reason}", &escape_html(reason));
+        let headers = format!(
+            "HTTP/1.1 403 Forbidden\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Cache-Control: no-store\r\n\
+             Connection: close\r\n\r\n",
+            page.len()
+        );
+        Response::new(200, "OK")
+            .section("res-hdr", headers.into_bytes())
+            .body("res-body", page.into_bytes())
+    }
+}
+
+/// Returns a response with the unmodified message
+/// Body buffered to be sent back, dropped once it exceeds the maximum size
+struct BodyCopy {
+    data: Vec<u8>,
+    max_size: usize,
+    exceeded: bool,
+}
+
+impl BodyCopy {
+    fn new(max_size: usize) -> Self {
+        BodyCopy {
+            data: Vec::new(),
+            max_size,
+            exceeded: false,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if self.exceeded {
+            return;
+        }
+        if self.data.len() + data.len() > self.max_size {
+            self.exceeded = true;
+            self.data = Vec::new();
+        } else {
+            self.data.extend_from_slice(data);
+        }
+    }
+
+    /// Returns the body, [`None`] if it exceeded the maximum size
+    fn into_data(self) -> Option<Vec<u8>> {
+        (!self.exceeded).then_some(self.data)
+    }
+}
+
+/// Counts a connection as served until it is dropped
+struct ActiveConnection(Arc<AtomicUsize>);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn echo(encapsulated: Encapsulated, body: Option<(&'static str, Vec<u8>)>) -> Response {
+    let mut response = Response::new(200, "OK");
+    if let Some(req_hdr) = encapsulated.req_hdr {
+        response = response.section("req-hdr", req_hdr);
+    }
+    if let Some(res_hdr) = encapsulated.res_hdr {
+        response = response.section("res-hdr", res_hdr);
+    }
+    match body {
+        Some((name, body)) => response.body(name, body),
+        None => response,
+    }
+}
+
+async fn write<S: AsyncWrite + Unpin>(
+    reader: &mut BufReader<S>,
+    response: &Response,
+) -> io::Result<()> {
+    let stream = reader.get_mut();
+    stream.write_all(&response.encode()).await?;
+    stream.flush().await
+}
+
+fn quoted(istag: &str) -> String {
+    format!("\"{istag}\"")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Running ICAP listener
+///
+/// The listener is stopped when this handle is dropped; connections that
+/// were already accepted are served until the client closes them.
+#[derive(Debug)]
+pub struct IcapListener {
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl IcapListener {
+    /// Returns the address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Blocks the current thread and serves connections until the process exits
+    pub fn wait(mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    /// Stops accepting connections and waits for the listener thread to exit
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.stopped.store(true, Ordering::SeqCst);
+            let _ = TcpStream::connect(self.local_addr);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for IcapListener {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,348 @@
+//! Parsing and serialization of ICAP messages (RFC 3507)
+
+use std::io;
+
+use futures_lite::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Longest accepted request line, header line or chunk size line
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+/// Largest accepted size of the ICAP headers or the encapsulated HTTP headers
+const MAX_HEADERS_LENGTH: usize = 64 * 1024;
+
+pub(crate) fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a line without the trailing CRLF
+///
+/// Returns [`None`] if the peer closed the connection.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(MAX_LINE_LENGTH)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(invalid("line too long or truncated"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid("line is not valid UTF-8"))
+}
+
+/// An ICAP request line with its headers
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Returns the value of the first header called `name`, ignoring case
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the announced preview size
+    pub(crate) fn preview(&self) -> io::Result<Option<usize>> {
+        self.header("Preview")
+            .map(|value| value.parse().map_err(|_| invalid("invalid Preview header")))
+            .transpose()
+    }
+
+    /// Returns `true` if the client accepts `204 No Content` responses
+    pub(crate) fn allows_204(&self) -> bool {
+        self.header("Allow")
+            .is_some_and(|value| value.split(',').any(|v| v.trim() == "204"))
+    }
+
+    /// Returns `true` if the client asked to close the connection
+    pub(crate) fn closes_connection(&self) -> bool {
+        self.header("Connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"))
+    }
+}
+
+/// Reads the next request line and headers
+///
+/// Empty lines before the request line are skipped. Returns [`None`] if the
+/// client closed the connection between requests.
+pub(crate) async fn read_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<Request>> {
+    let line = loop {
+        match read_line(reader).await? {
+            None => return Ok(None),
+            Some(line) if line.is_empty() => continue,
+            Some(line) => break line,
+        }
+    };
+    let mut parts = line.split(' ');
+    let (Some(method), Some(_uri), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid(format!("invalid request line {line:?}")));
+    };
+    if !version.starts_with("ICAP/1.") {
+        return Err(invalid(format!("unsupported version {version:?}")));
+    }
+
+    let mut headers = Vec::new();
+    let mut length = line.len();
+    loop {
+        let line = read_line(reader)
+            .await?
+            .ok_or_else(|| invalid("connection closed within headers"))?;
+        if line.is_empty() {
+            break;
+        }
+        length += line.len();
+        if length > MAX_HEADERS_LENGTH {
+            return Err(invalid("headers too long"));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid(format!("invalid header {line:?}")))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        headers,
+    }))
+}
+
+/// The encapsulated HTTP headers of a request and the kind of its body
+#[derive(Debug, Default)]
+pub(crate) struct Encapsulated {
+    pub(crate) req_hdr: Option<Vec<u8>>,
+    pub(crate) res_hdr: Option<Vec<u8>>,
+    /// `req-body`, `res-body` or `opt-body` if a chunked body follows
+    pub(crate) body: Option<&'static str>,
+}
+
+impl Encapsulated {
+    /// Reads the header sections announced by the `Encapsulated` header
+    pub(crate) async fn read<R: AsyncBufRead + Unpin>(
+        reader: &mut R,
+        header: Option<&str>,
+    ) -> io::Result<Self> {
+        let Some(header) = header else {
+            return Ok(Encapsulated::default());
+        };
+
+        let mut entries = Vec::new();
+        for entry in header.split(',') {
+            let (name, offset) = entry
+                .trim()
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("invalid Encapsulated entry {entry:?}")))?;
+            let offset: usize = offset
+                .parse()
+                .map_err(|_| invalid(format!("invalid Encapsulated offset {offset:?}")))?;
+            if entries.last().is_some_and(|&(_, last)| offset < last) {
+                return Err(invalid("Encapsulated offsets are not ascending"));
+            }
+            entries.push((name, offset));
+        }
+
+        let Some(&(last, length)) = entries.last() else {
+            return Err(invalid("empty Encapsulated header"));
+        };
+        if length > MAX_HEADERS_LENGTH {
+            return Err(invalid("encapsulated headers too long"));
+        }
+        let mut sections = vec![0; length];
+        reader.read_exact(&mut sections).await?;
+
+        let mut encapsulated = Encapsulated {
+            body: match last {
+                "req-body" => Some("req-body"),
+                "res-body" => Some("res-body"),
+                "opt-body" => Some("opt-body"),
+                "null-body" => None,
+                _ => return Err(invalid(format!("Encapsulated ends with {last:?}"))),
+            },
+            ..Encapsulated::default()
+        };
+        for pair in entries.windows(2) {
+            let [(name, start), (_, end)] = pair else {
+                unreachable!()
+            };
+            let section = sections[*start..*end].to_vec();
+            match *name {
+                "req-hdr" => encapsulated.req_hdr = Some(section),
+                "res-hdr" => encapsulated.res_hdr = Some(section),
+                _ => return Err(invalid(format!("unexpected Encapsulated entry {name:?}"))),
+            }
+        }
+        Ok(encapsulated)
+    }
+
+    /// Returns the request URL from the encapsulated HTTP request line
+    pub(crate) fn url(&self) -> Option<String> {
+        let request = self.req_hdr.as_deref()?;
+        let line = request.split(|&b| b == b'\n').next()?;
+        let url = String::from_utf8_lossy(line).split(' ').nth(1)?.to_string();
+        Some(url)
+    }
+}
+
+/// Decoder for chunked ICAP bodies
+#[derive(Debug, Default)]
+pub(crate) struct ChunkedDecoder {
+    remaining: usize,
+    finished: bool,
+    ieof: bool,
+    error: Option<io::ErrorKind>,
+}
+
+impl ChunkedDecoder {
+    /// Returns the next piece of data with at most `max_length` bytes
+    ///
+    /// Returns [`None`] after the last chunk. Errors are sticky: once reading
+    /// failed, [`error`](Self::error) returns its kind and no more data is
+    /// returned.
+    pub(crate) async fn next<R: AsyncBufRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        max_length: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        if self.finished || self.error.is_some() {
+            return Ok(None);
+        }
+        let result = self.read(reader, max_length).await;
+        if let Err(err) = &result {
+            self.error = Some(err.kind());
+        }
+        result
+    }
+
+    async fn read<R: AsyncBufRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        max_length: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        if self.remaining == 0 {
+            let line = read_line(reader)
+                .await?
+                .ok_or_else(|| invalid("connection closed within body"))?;
+            let (size, extension) = line.split_once(';').unwrap_or((&line, ""));
+            let size = usize::from_str_radix(size.trim(), 16)
+                .map_err(|_| invalid(format!("invalid chunk size {size:?}")))?;
+            if size == 0 {
+                // Skip the trailer up to the empty line
+                while !read_line(reader)
+                    .await?
+                    .ok_or_else(|| invalid("connection closed within body"))?
+                    .is_empty()
+                {}
+                self.finished = true;
+                self.ieof = extension.trim() == "ieof";
+                return Ok(None);
+            }
+            self.remaining = size;
+        }
+
+        let mut data = vec![0; self.remaining.min(max_length)];
+        reader.read_exact(&mut data).await?;
+        self.remaining -= data.len();
+        if self.remaining == 0 {
+            let mut crlf = [0; 2];
+            reader.read_exact(&mut crlf).await?;
+            if &crlf != b"\r\n" {
+                return Err(invalid("chunk not terminated by CRLF"));
+            }
+        }
+        Ok(Some(data))
+    }
+
+    /// Returns `true` if the last chunk had the `ieof` extension
+    pub(crate) fn ieof(&self) -> bool {
+        self.ieof
+    }
+
+    /// Returns the kind of the error that stopped decoding, if any
+    pub(crate) fn error(&self) -> Option<io::ErrorKind> {
+        self.error
+    }
+}
+
+/// An ICAP response
+#[derive(Debug)]
+pub(crate) struct Response {
+    status: u16,
+    reason: &'static str,
+    headers: Vec<(&'static str, String)>,
+    sections: Vec<(&'static str, Vec<u8>)>,
+    body: Option<(&'static str, Vec<u8>)>,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16, reason: &'static str) -> Self {
+        Response {
+            status,
+            reason,
+            headers: Vec::new(),
+            sections: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub(crate) fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    /// Adds an encapsulated header section, e.g. `res-hdr`
+    pub(crate) fn section(mut self, name: &'static str, section: Vec<u8>) -> Self {
+        self.sections.push((name, section));
+        self
+    }
+
+    /// Sets the encapsulated body, which is sent chunked
+    pub(crate) fn body(mut self, name: &'static str, body: Vec<u8>) -> Self {
+        self.body = Some((name, body));
+        self
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut encapsulated = Vec::new();
+        let mut offset = 0;
+        for (name, section) in &self.sections {
+            encapsulated.push(format!("{name}={offset}"));
+            offset += section.len();
+        }
+        let body_name = self.body.as_ref().map_or("null-body", |(name, _)| name);
+        encapsulated.push(format!("{body_name}={offset}"));
+
+        let mut out = format!("ICAP/1.0 {} {}\r\n", self.status, self.reason).into_bytes();
+        for (name, value) in &self.headers {
+            out.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+        }
+        out.extend_from_slice(
+            format!("Encapsulated: {}\r\n\r\n", encapsulated.join(", ")).as_bytes(),
+        );
+        for (_, section) in &self.sections {
+            out.extend_from_slice(section);
+        }
+        if let Some((_, body)) = &self.body {
+            if !body.is_empty() {
+                out.extend_from_slice(format!("{:x}\r\n", body.len()).as_bytes());
+                out.extend_from_slice(body);
+                out.extend_from_slice(b"\r\n");
+            }
+            out.extend_from_slice(b"0\r\n\r\n");
+        }
+        out
+    }
+}
@@ -4,14 +4,115 @@
 use std::{net::SocketAddr, path::PathBuf};
 
 /// Async ClamAV client that is abstract over all runtimes
-#[cfg(feature = "async")]
+#[cfg(feature = "async-core")]
 mod nonblocking;
-#[cfg(feature = "async")]
-pub use nonblocking::ClamAvAsync;
+#[cfg(feature = "async-core")]
+pub use nonblocking::{BoxFuture, BoxStream, ClamAvAsync, DynClamAvAsync};
+
+/// Async ClamAV client using Tokio's own TCP and Unix socket streams
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+#[cfg(feature = "async-core")]
+mod rt;
 
 /// Synchronous ClamAV client
 pub mod blocking;
-pub use blocking::ClamAvSync;
+pub use blocking::{ClamAvSync, DynClamAvSync};
+
+/// Progress reports for long scans
+pub mod progress;
+
+/// Health checks combining PING, VERSION, signature age and a self test
+pub mod health;
+
+/// Recursive directory scanning with filters and bounded concurrency
+pub mod dir;
+
+/// Scanning new and modified files in a watched directory
+#[cfg(feature = "watch")]
+pub mod watch;
+
+/// Caching verdicts by content hash and signature database version
+#[cfg(feature = "cache")]
+pub mod cache;
+
+/// SHA-256 and MD5 digests computed while scanning
+#[cfg(feature = "hash")]
+pub mod hash;
+
+/// MIME parsing to scan every part and attachment of a message separately
+#[cfg(feature = "mime")]
+pub mod mime;
+
+/// Streaming `multipart/form-data` parser that scans uploaded files
+#[cfg(feature = "multipart")]
+pub mod multipart;
+
+#[cfg(any(feature = "mime", feature = "multipart"))]
+mod headers;
+
+/// Allowlists and denylists for signatures, content hashes and errors
+#[cfg(feature = "policy")]
+pub mod policy;
+
+/// Scan traffic metrics in the OpenMetrics text format
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+/// clamd-compatible proxy with load balancing, failover, caching and access logs
+#[cfg(feature = "proxy")]
+pub mod proxy;
+
+/// ICAP server that scans HTTP messages for proxies such as Squid
+#[cfg(feature = "icap")]
+pub mod icap;
+
+/// HTTP gateway with JSON verdicts for services that cannot speak clamd's protocol
+#[cfg(feature = "gateway")]
+pub mod gateway;
+
+/// Milter server that scans mail at the MTA, with a client for testing
+#[cfg(feature = "milter")]
+pub mod milter;
+
+/// Tower layer that scans request bodies before they reach the inner service
+#[cfg(feature = "tower")]
+pub mod tower;
+
+/// Quarantine directory with metadata sidecars, restore and purge
+#[cfg(feature = "quarantine")]
+pub mod quarantine;
+
+/// Client with shared configuration for chunk size, limits, timeouts and retries
+pub mod client;
+#[cfg(feature = "async-core")]
+pub use client::TimeoutStream;
+pub use client::{ClamAvClient, ClamAvClientBuilder, RetryPolicy, Timeouts};
+
+/// Mock ClamAV server for testing code that uses this crate
+#[cfg(feature = "testing")]
+pub mod testing;
+
+/// Serializable scan reports with JSON, NDJSON and SARIF writers
+#[cfg(feature = "serde")]
+pub mod report;
+
+#[cfg(any(
+    feature = "cache",
+    feature = "hash",
+    feature = "policy",
+    feature = "quarantine"
+))]
+mod digest;
+
+mod trace;
+
+#[cfg(any(feature = "testing", feature = "proxy"))]
+mod wire;
+
+mod verdict;
+pub use verdict::{Verdict, VerdictPolicy};
 
 /// Custom result type
 pub type IoResult = Result<Vec<u8>, std::io::Error>;
@@ -25,6 +126,7 @@ const DEFAULT_CHUNK_SIZE: usize = 4096;
 /// ClamAV commands
 const PING: &[u8; 6] = b"zPING\0";
 const VERSION: &[u8; 9] = b"zVERSION\0";
+const STATS: &[u8; 7] = b"zSTATS\0";
 const SHUTDOWN: &[u8; 10] = b"zSHUTDOWN\0";
 const INSTREAM: &[u8; 10] = b"zINSTREAM\0";
 const END_OF_STREAM: &[u8; 4] = &[0, 0, 0, 0];
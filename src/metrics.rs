@@ -0,0 +1,781 @@
+//! Metrics for scan traffic in the OpenMetrics text format
+//!
+//! A [`Metrics`] registry counts scans by verdict, errors by kind and scanned
+//! bytes, and keeps a latency histogram per endpoint. [`MeteredClient`] wraps
+//! a transport and records every scan; its in-flight scans are exported as
+//! active connections. A [`Pool`](crate::proxy::Pool) with
+//! [`metrics`](crate::proxy::Pool::metrics) exports the open connections of
+//! each backend, and other connection pools can report their idle
+//! connections with [`Metrics::set_idle_connections`].
+//!
+//! The state of clamd itself is collected with `STATS`: [`Metrics::collect_stats`]
+//! queries it once, a [`StatsCollector`] periodically on a background thread.
+//!
+//! [`Metrics::encode`] renders all metrics in the
+//! [OpenMetrics](https://openmetrics.io) text format, which Prometheus can
+//! scrape:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use clamav_client::metrics::{MeteredClient, Metrics, StatsCollector};
+//! use clamav_client::ClamAvSync;
+//!
+//! let metrics = Metrics::new();
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let client = MeteredClient::new(clamd_tcp.clone(), metrics.clone(), "127.0.0.1:3310");
+//! let _collector = StatsCollector::spawn(
+//!     clamd_tcp,
+//!     metrics.clone(),
+//!     "127.0.0.1:3310",
+//!     Duration::from_secs(15),
+//! );
+//!
+//! client.scan_file("upload.bin", None).unwrap();
+//! print!("{}", metrics.encode());
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io::{self, Read},
+    path::Path,
+    sync::{mpsc, Arc, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    health::{HealthReport, HealthThresholds},
+    ClamAvSync, IoResult, Timeouts, Verdict,
+};
+
+/// Default upper bounds of the latency histogram buckets in seconds
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Thread pool and queue state reported by clamd's `STATS` command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClamdStats {
+    /// Number of thread pools
+    pub pools: u64,
+    /// Threads that are currently running
+    pub threads_live: u64,
+    /// Threads that are waiting for work
+    pub threads_idle: u64,
+    /// Maximum number of threads
+    pub threads_max: u64,
+    /// Number of queued requests
+    pub queue_items: u64,
+}
+
+impl ClamdStats {
+    /// Parses a `STATS` response
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the statistics, or an error of kind
+    /// [`io::ErrorKind::InvalidData`] if the response lacks the `THREADS` or
+    /// `QUEUE` line
+    pub fn parse(response: &[u8]) -> io::Result<Self> {
+        let response = String::from_utf8_lossy(response);
+        let mut stats = ClamdStats::default();
+        let (mut threads, mut queue) = (false, false);
+
+        for line in response.lines().map(str::trim) {
+            if let Some(pools) = line.strip_prefix("POOLS:") {
+                stats.pools = pools.trim().parse().unwrap_or_default();
+            } else if let Some(rest) = line.strip_prefix("THREADS:") {
+                let words: Vec<&str> = rest.split_whitespace().collect();
+                for pair in words.windows(2) {
+                    let value = pair[1].parse().ok();
+                    match (pair[0], value) {
+                        ("live", Some(value)) => stats.threads_live = value,
+                        ("idle", Some(value)) => stats.threads_idle = value,
+                        ("max", Some(value)) => stats.threads_max = value,
+                        _ => {}
+                    }
+                }
+                threads = true;
+            } else if let Some(rest) = line.strip_prefix("QUEUE:") {
+                let items = rest.split_whitespace().next().and_then(|n| n.parse().ok());
+                stats.queue_items = items.unwrap_or_default();
+                queue = true;
+            }
+        }
+
+        if threads && queue {
+            Ok(stats)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "STATS response lacks THREADS or QUEUE",
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    buckets: Vec<f64>,
+    scans: BTreeMap<(String, &'static str), u64>,
+    errors: BTreeMap<(String, String), u64>,
+    bytes: BTreeMap<String, u64>,
+    latency: BTreeMap<String, Histogram>,
+    active: BTreeMap<String, u64>,
+    idle: BTreeMap<String, u64>,
+    stats: BTreeMap<String, Option<ClamdStats>>,
+}
+
+/// Registry of scan traffic metrics
+///
+/// Clones share the same registry, so one registry can be passed to several
+/// clients and collectors.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS)
+    }
+}
+
+impl Metrics {
+    /// Creates an empty registry with the [`DEFAULT_BUCKETS`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty registry with custom latency histogram buckets
+    ///
+    /// # Arguments
+    ///
+    /// * `buckets`: Upper bounds of the buckets in seconds; they are sorted
+    ///   and the `+Inf` bucket is added automatically
+    pub fn with_buckets(buckets: &[f64]) -> Self {
+        let mut buckets: Vec<f64> = buckets.iter().copied().filter(|b| b.is_finite()).collect();
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        Metrics {
+            registry: Arc::new(Mutex::new(Registry {
+                buckets,
+                ..Registry::default()
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records a completed scan
+    ///
+    /// Responses are counted by verdict; ClamAV error responses are also
+    /// counted as errors of kind `clamd`, failed scans as errors of the kind
+    /// of the [`io::Error`].
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint`: Label identifying the ClamAV server
+    /// * `result`: The result of the scan
+    /// * `bytes`: Number of bytes that were scanned
+    /// * `duration`: Time the scan took
+    pub fn record_scan(&self, endpoint: &str, result: &IoResult, bytes: u64, duration: Duration) {
+        let mut registry = self.lock();
+        let error = match result {
+            Ok(response) => {
+                let verdict = match Verdict::parse(response) {
+                    Ok(Verdict::Clean) => "clean",
+                    Ok(Verdict::Infected(_)) => "infected",
+                    Ok(Verdict::Error(_)) | Err(_) => "error",
+                };
+                *registry
+                    .scans
+                    .entry((endpoint.to_string(), verdict))
+                    .or_default() += 1;
+                *registry.bytes.entry(endpoint.to_string()).or_default() += bytes;
+                (verdict == "error").then(|| String::from("clamd"))
+            }
+            Err(err) => Some(error_kind(err)),
+        };
+        if let Some(kind) = error {
+            *registry
+                .errors
+                .entry((endpoint.to_string(), kind))
+                .or_default() += 1;
+        }
+
+        let seconds = duration.as_secs_f64();
+        let buckets = registry.buckets.clone();
+        let histogram = registry
+            .latency
+            .entry(endpoint.to_string())
+            .or_insert_with(|| Histogram {
+                counts: vec![0; buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        for (count, bound) in histogram.counts.iter_mut().zip(&buckets) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Sets the number of idle connections that a pool keeps to an endpoint
+    pub fn set_idle_connections(&self, endpoint: &str, idle: u64) {
+        self.lock().idle.insert(endpoint.to_string(), idle);
+    }
+
+    /// Records the result of a `STATS` query, [`None`] if it failed
+    pub fn record_stats(&self, endpoint: &str, stats: Option<ClamdStats>) {
+        self.lock().stats.insert(endpoint.to_string(), stats);
+    }
+
+    /// Queries clamd's `STATS` and records the result
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the parsed statistics
+    pub fn collect_stats<T: ClamAvSync>(
+        &self,
+        client: &T,
+        endpoint: &str,
+    ) -> io::Result<ClamdStats> {
+        let stats = client
+            .get_stats()
+            .and_then(|response| ClamdStats::parse(&response));
+        self.record_stats(endpoint, stats.as_ref().ok().copied());
+        stats
+    }
+
+    /// Queries clamd's `STATS` asynchronously and records the result
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the parsed statistics
+    #[cfg(feature = "async-core")]
+    pub async fn collect_stats_async<T: crate::ClamAvAsync>(
+        &self,
+        client: &T,
+        endpoint: &str,
+    ) -> io::Result<ClamdStats> {
+        let stats = client
+            .get_stats()
+            .await
+            .and_then(|response| ClamdStats::parse(&response));
+        self.record_stats(endpoint, stats.as_ref().ok().copied());
+        stats
+    }
+
+    /// Renders all metrics in the OpenMetrics text format
+    pub fn encode(&self) -> String {
+        let registry = self.lock();
+        let mut out = String::new();
+
+        family(
+            &mut out,
+            "clamav_scans",
+            "counter",
+            "Completed scans by verdict",
+        );
+        for ((endpoint, verdict), count) in &registry.scans {
+            sample(
+                &mut out,
+                "clamav_scans_total",
+                &[("endpoint", endpoint), ("verdict", verdict)],
+                *count,
+            );
+        }
+
+        family(
+            &mut out,
+            "clamav_errors",
+            "counter",
+            "Failed scans by error kind",
+        );
+        for ((endpoint, kind), count) in &registry.errors {
+            sample(
+                &mut out,
+                "clamav_errors_total",
+                &[("endpoint", endpoint), ("kind", kind)],
+                *count,
+            );
+        }
+
+        family(
+            &mut out,
+            "clamav_scanned_bytes",
+            "counter",
+            "Bytes sent to ClamAV",
+        );
+        for (endpoint, bytes) in &registry.bytes {
+            sample(
+                &mut out,
+                "clamav_scanned_bytes_total",
+                &[("endpoint", endpoint)],
+                *bytes,
+            );
+        }
+
+        family(
+            &mut out,
+            "clamav_scan_duration_seconds",
+            "histogram",
+            "Scan latency",
+        );
+        for (endpoint, histogram) in &registry.latency {
+            for (count, bound) in histogram.counts.iter().zip(&registry.buckets) {
+                let le = format!("{bound:?}");
+                let labels = [("endpoint", endpoint.as_str()), ("le", &le)];
+                sample(
+                    &mut out,
+                    "clamav_scan_duration_seconds_bucket",
+                    &labels,
+                    *count,
+                );
+            }
+            let labels = [("endpoint", endpoint.as_str()), ("le", "+Inf")];
+            sample(
+                &mut out,
+                "clamav_scan_duration_seconds_bucket",
+                &labels,
+                histogram.count,
+            );
+            let labels = [("endpoint", endpoint.as_str())];
+            sample(
+                &mut out,
+                "clamav_scan_duration_seconds_sum",
+                &labels,
+                histogram.sum,
+            );
+            sample(
+                &mut out,
+                "clamav_scan_duration_seconds_count",
+                &labels,
+                histogram.count,
+            );
+        }
+
+        family(
+            &mut out,
+            "clamav_connections",
+            "gauge",
+            "Connections to ClamAV by state",
+        );
+        for (endpoint, active) in &registry.active {
+            sample(
+                &mut out,
+                "clamav_connections",
+                &[("endpoint", endpoint), ("state", "active")],
+                *active,
+            );
+        }
+        for (endpoint, idle) in &registry.idle {
+            sample(
+                &mut out,
+                "clamav_connections",
+                &[("endpoint", endpoint), ("state", "idle")],
+                *idle,
+            );
+        }
+
+        family(
+            &mut out,
+            "clamd_up",
+            "gauge",
+            "Whether the last STATS query succeeded",
+        );
+        for (endpoint, stats) in &registry.stats {
+            sample(
+                &mut out,
+                "clamd_up",
+                &[("endpoint", endpoint)],
+                u64::from(stats.is_some()),
+            );
+        }
+        family(
+            &mut out,
+            "clamd_queue_items",
+            "gauge",
+            "Requests queued by clamd",
+        );
+        for (endpoint, stats) in successful(&registry.stats) {
+            sample(
+                &mut out,
+                "clamd_queue_items",
+                &[("endpoint", endpoint)],
+                stats.queue_items,
+            );
+        }
+        family(&mut out, "clamd_threads", "gauge", "clamd threads by state");
+        for (endpoint, stats) in successful(&registry.stats) {
+            for (state, value) in [
+                ("live", stats.threads_live),
+                ("idle", stats.threads_idle),
+                ("max", stats.threads_max),
+            ] {
+                sample(
+                    &mut out,
+                    "clamd_threads",
+                    &[("endpoint", endpoint), ("state", state)],
+                    value,
+                );
+            }
+        }
+        family(&mut out, "clamd_pools", "gauge", "clamd thread pools");
+        for (endpoint, stats) in successful(&registry.stats) {
+            sample(
+                &mut out,
+                "clamd_pools",
+                &[("endpoint", endpoint)],
+                stats.pools,
+            );
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+
+    pub(crate) fn connection_started(&self, endpoint: &str) {
+        *self.lock().active.entry(endpoint.to_string()).or_default() += 1;
+    }
+
+    pub(crate) fn connection_finished(&self, endpoint: &str) {
+        if let Some(active) = self.lock().active.get_mut(endpoint) {
+            *active = active.saturating_sub(1);
+        }
+    }
+}
+
+fn successful(
+    stats: &BTreeMap<String, Option<ClamdStats>>,
+) -> impl Iterator<Item = (&String, &ClamdStats)> {
+    stats
+        .iter()
+        .filter_map(|(endpoint, stats)| Some((endpoint, stats.as_ref()?)))
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "# HELP {name} {help}");
+}
+
+fn sample<V: std::fmt::Display>(out: &mut String, name: &str, labels: &[(&str, &str)], value: V) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        out.push('{');
+        for (i, (label, value)) in labels.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            let _ = write!(out, "{label}=\"{value}\"");
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+/// Converts an [`io::ErrorKind`] such as `TimedOut` into `timed_out`
+fn error_kind(err: &io::Error) -> String {
+    let mut kind = String::new();
+    for (i, c) in format!("{:?}", err.kind()).chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                kind.push('_');
+            }
+            kind.push(c.to_ascii_lowercase());
+        } else {
+            kind.push(c);
+        }
+    }
+    kind
+}
+
+/// Counts a scan as an active connection while it is alive
+struct ActiveScan<'a> {
+    metrics: &'a Metrics,
+    endpoint: &'a str,
+    started: Instant,
+}
+
+impl<'a> ActiveScan<'a> {
+    fn start(metrics: &'a Metrics, endpoint: &'a str) -> Self {
+        metrics.connection_started(endpoint);
+        ActiveScan {
+            metrics,
+            endpoint,
+            started: Instant::now(),
+        }
+    }
+
+    fn finish(self, result: IoResult, bytes: u64) -> IoResult {
+        let duration = self.started.elapsed();
+        self.metrics
+            .record_scan(self.endpoint, &result, bytes, duration);
+        result
+    }
+}
+
+impl Drop for ActiveScan<'_> {
+    fn drop(&mut self) {
+        self.metrics.connection_finished(self.endpoint);
+    }
+}
+
+/// Reader that counts the bytes it returns
+struct CountingReader<R> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes += len as u64;
+        Ok(len)
+    }
+}
+
+/// Transport wrapper that records every scan in a [`Metrics`] registry
+///
+/// Scans with progress, the hashed scans and
+/// [`scan_mime`](ClamAvSync::scan_mime) are recorded as well, because their
+/// default implementations go through the overridden scan methods. The
+/// [`EICAR`](crate::health::EICAR) self-test of
+/// [`health`](ClamAvSync::health) checks is not recorded.
+///
+/// The wrapper implements [`ClamAvSync`] and
+/// [`ClamAvAsync`](crate::ClamAvAsync) if the wrapped transport does.
+#[derive(Debug, Clone)]
+pub struct MeteredClient<T> {
+    inner: T,
+    metrics: Metrics,
+    endpoint: String,
+}
+
+impl<T> MeteredClient<T> {
+    /// Wraps a transport, labelling its metrics with `endpoint`
+    pub fn new<E: Into<String>>(inner: T, metrics: Metrics, endpoint: E) -> Self {
+        MeteredClient {
+            inner,
+            metrics,
+            endpoint: endpoint.into(),
+        }
+    }
+
+    /// Returns the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the registry
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns the endpoint label
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+}
+
+impl<T: ClamAvSync> ClamAvSync for MeteredClient<T> {
+    type Stream = T::Stream;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        self.inner.connect()
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        self.inner.connect_with_timeouts(timeouts)
+    }
+
+    fn scan_file<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+    ) -> IoResult {
+        let scan = ActiveScan::start(&self.metrics, &self.endpoint);
+        let bytes = fs::metadata(&file_path).map_or(0, |metadata| metadata.len());
+        let response = self.inner.scan_file(file_path, chunk_size);
+        scan.finish(response, bytes)
+    }
+
+    fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+        let scan = ActiveScan::start(&self.metrics, &self.endpoint);
+        let response = self.inner.scan_buffer(buffer, chunk_size);
+        scan.finish(response, buffer.len() as u64)
+    }
+
+    fn scan_reader<R: Read>(&self, reader: R, chunk_size: Option<usize>) -> IoResult {
+        let scan = ActiveScan::start(&self.metrics, &self.endpoint);
+        let mut input = CountingReader {
+            inner: reader,
+            bytes: 0,
+        };
+        let response = self.inner.scan_reader(&mut input, chunk_size);
+        scan.finish(response, input.bytes)
+    }
+
+    fn health(&self, thresholds: &HealthThresholds) -> HealthReport {
+        self.inner.health(thresholds)
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_metrics {
+    use std::{
+        io,
+        path::Path,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use futures_lite::{AsyncRead, Stream, StreamExt};
+
+    use super::{ActiveScan, CountingReader, MeteredClient};
+    use crate::{
+        health::{HealthReport, HealthThresholds},
+        rt, ClamAvAsync, IoResult,
+    };
+
+    impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let len = futures_lite::ready!(std::pin::Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            this.bytes += len as u64;
+            std::task::Poll::Ready(Ok(len))
+        }
+    }
+
+    impl<T: ClamAvAsync> ClamAvAsync for MeteredClient<T> {
+        type Stream = T::Stream;
+
+        async fn connect(&self) -> io::Result<Self::Stream> {
+            self.inner.connect().await
+        }
+
+        async fn scan_file<P: AsRef<Path> + Send>(
+            &self,
+            file_path: P,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let scan = ActiveScan::start(&self.metrics, &self.endpoint);
+            let bytes = rt::file_size(file_path.as_ref()).await.unwrap_or(0);
+            let response = self.inner.scan_file(file_path, chunk_size).await;
+            scan.finish(response, bytes)
+        }
+
+        async fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+            let scan = ActiveScan::start(&self.metrics, &self.endpoint);
+            let response = self.inner.scan_buffer(buffer, chunk_size).await;
+            scan.finish(response, buffer.len() as u64)
+        }
+
+        async fn scan_reader<R: AsyncRead + Unpin + Send>(
+            &self,
+            reader: R,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let scan = ActiveScan::start(&self.metrics, &self.endpoint);
+            let mut input = CountingReader {
+                inner: reader,
+                bytes: 0,
+            };
+            let response = self.inner.scan_reader(&mut input, chunk_size).await;
+            scan.finish(response, input.bytes)
+        }
+
+        async fn health(&self, thresholds: &HealthThresholds) -> HealthReport {
+            self.inner.health(thresholds).await
+        }
+
+        async fn scan_stream<St: Stream<Item = Result<bytes::Bytes, io::Error>> + Send>(
+            &self,
+            input_stream: St,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let scan = ActiveScan::start(&self.metrics, &self.endpoint);
+            let bytes = AtomicU64::new(0);
+            let input_stream = input_stream.inspect(|chunk| {
+                if let Ok(chunk) = chunk {
+                    bytes.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+            });
+            let response = self.inner.scan_stream(input_stream, chunk_size).await;
+            scan.finish(response, bytes.into_inner())
+        }
+    }
+}
+
+/// Background thread that periodically collects clamd's `STATS`
+///
+/// The thread is stopped when the collector is dropped.
+#[derive(Debug)]
+pub struct StatsCollector {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatsCollector {
+    /// Starts collecting statistics, the first query is sent immediately
+    ///
+    /// # Arguments
+    ///
+    /// * `client`: The client used to send `STATS`
+    /// * `metrics`: The registry in which the statistics are recorded
+    /// * `endpoint`: Label identifying the ClamAV server
+    /// * `interval`: Time between two queries
+    pub fn spawn<T, E>(client: T, metrics: Metrics, endpoint: E, interval: Duration) -> Self
+    where
+        T: ClamAvSync + Send + 'static,
+        E: Into<String>,
+    {
+        let endpoint = endpoint.into();
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::spawn(move || loop {
+            let _ = metrics.collect_stats(&client, &endpoint);
+            match stopped.recv_timeout(interval) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        });
+        StatsCollector {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the collector and waits for the background thread
+    pub fn stop(self) {}
+}
+
+impl Drop for StatsCollector {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
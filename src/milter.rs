@@ -0,0 +1,549 @@
+//! Milter server that scans mail at the MTA
+//!
+//! A [`Milter`] speaks the milter protocol used by Postfix and Sendmail. It
+//! collects the headers and the body of every message, streams them to
+//! clamd with [`ClamAvAsync::scan_stream`] at the end of the message and
+//! tells the MTA what to do with it:
+//!
+//! - clean messages are accepted,
+//! - infected messages are handled according to
+//!   [`on_infected`](Milter::on_infected), rejected by default,
+//! - messages that could not be scanned are handled according to
+//!   [`on_error`](Milter::on_error), temporarily rejected by default.
+//!
+//! Accepted and quarantined messages get an `X-Virus-Scanned` and an
+//! `X-Virus-Status` header.
+//!
+//! ```no_run
+//! use clamav_client::milter::{Milter, MilterAction};
+//! use clamav_client::Tcp;
+//!
+//! let clamd = Tcp("127.0.0.1:3310".parse().unwrap());
+//! let milter = Milter::new(clamd).on_infected(MilterAction::Quarantine);
+//! milter.bind_tcp("127.0.0.1:7357").unwrap().wait();
+//! ```
+//!
+//! Postfix is then configured with, e.g.:
+//!
+//! ```text
+//! smtpd_milters = inet:127.0.0.1:7357
+//! milter_default_action = tempfail
+//! ```
+//!
+//! [`MilterClient`] plays the role of the MTA, so milters can be tested
+//! without a mail server.
+
+use std::{
+    fmt, io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use futures_lite::{stream, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::{ClamAvAsync, Verdict};
+
+mod client;
+pub use client::{EndOfMessage, MilterClient, MilterResponse};
+
+mod protocol;
+use protocol::{invalid, read_packet, Packet};
+
+/// Default maximum message size, clamd's default `StreamMaxLength` (25 MB)
+pub const DEFAULT_MAX_MESSAGE_SIZE: u64 = 25 * 1024 * 1024;
+
+/// Default maximum number of MTA connections served at the same time
+pub const DEFAULT_MAX_CONNECTIONS: usize = 100;
+
+/// Time to wait before accepting again after accepting a connection failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// What the MTA is told to do with a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MilterAction {
+    /// Deliver the message
+    Accept,
+    /// Reject the message with a permanent SMTP error (`550 5.7.1`)
+    Reject,
+    /// Reject the message with a temporary SMTP error (`451 4.7.1`)
+    Tempfail,
+    /// Accept the message and put it into the MTA's hold queue
+    ///
+    /// If the MTA does not allow the milter to quarantine messages, the
+    /// message is rejected instead.
+    Quarantine,
+    /// Accept the message and silently drop it
+    Discard,
+}
+
+/// Message of the current SMTP transaction
+#[derive(Debug, Default)]
+struct Message {
+    parts: Vec<Bytes>,
+    size: u64,
+    too_large: bool,
+}
+
+impl Message {
+    fn push(&mut self, data: Vec<u8>, max_size: u64) {
+        self.size += data.len() as u64;
+        self.too_large |= self.size > max_size;
+        if !self.too_large {
+            self.parts.push(Bytes::from(data));
+        }
+    }
+}
+
+/// Milter server that scans messages with ClamAV
+///
+/// Clones share the transport.
+pub struct Milter<T> {
+    transport: Arc<T>,
+    on_infected: MilterAction,
+    on_error: MilterAction,
+    scanned_header: String,
+    max_message_size: u64,
+    max_connections: usize,
+    chunk_size: Option<usize>,
+}
+
+impl<T> Clone for Milter<T> {
+    fn clone(&self) -> Self {
+        Milter {
+            transport: self.transport.clone(),
+            on_infected: self.on_infected,
+            on_error: self.on_error,
+            scanned_header: self.scanned_header.clone(),
+            max_message_size: self.max_message_size,
+            max_connections: self.max_connections,
+            chunk_size: self.chunk_size,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Milter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Milter")
+            .field("on_infected", &self.on_infected)
+            .field("on_error", &self.on_error)
+            .field("scanned_header", &self.scanned_header)
+            .field("max_message_size", &self.max_message_size)
+            .field("max_connections", &self.max_connections)
+            .field("chunk_size", &self.chunk_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: ClamAvAsync + Send + Sync + 'static> Milter<T> {
+    /// Creates a milter that scans messages with `transport`
+    ///
+    /// Infected messages are rejected and messages that could not be scanned
+    /// are temporarily rejected.
+    pub fn new(transport: T) -> Self {
+        Milter {
+            transport: Arc::new(transport),
+            on_infected: MilterAction::Reject,
+            on_error: MilterAction::Tempfail,
+            scanned_header: String::from("clamav-client"),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            chunk_size: None,
+        }
+    }
+
+    /// Sets the action for infected messages
+    pub fn on_infected(mut self, action: MilterAction) -> Self {
+        self.on_infected = action;
+        self
+    }
+
+    /// Sets the action for messages that could not be scanned
+    ///
+    /// This includes messages larger than the maximum message size.
+    pub fn on_error(mut self, action: MilterAction) -> Self {
+        self.on_error = action;
+        self
+    }
+
+    /// Sets the value of the `X-Virus-Scanned` header
+    pub fn scanned_header(mut self, value: impl Into<String>) -> Self {
+        self.scanned_header = value.into();
+        self
+    }
+
+    /// Sets the maximum size of headers and body that is sent to clamd
+    pub fn max_message_size(mut self, max_message_size: u64) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Sets the maximum number of MTA connections served at the same time
+    ///
+    /// Connections beyond the limit are closed right after they are accepted,
+    /// the MTA then applies its default milter action. Defaults to
+    /// [`DEFAULT_MAX_CONNECTIONS`].
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Sets the chunk size for streaming messages to clamd
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Returns the transport used for scanning
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Serves an MTA connection until the MTA closes it
+    ///
+    /// # Arguments
+    ///
+    /// * `stream`: The connection with the MTA
+    ///
+    /// # Returns
+    ///
+    /// An error if reading from or writing to the MTA failed or the MTA sent
+    /// an invalid packet
+    pub async fn handle<S>(&self, mut stream: S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut actions = 0;
+        let mut message = Message::default();
+        while let Some(packet) = read_packet(&mut stream).await? {
+            let replies = match packet.command {
+                protocol::OPTIONS => {
+                    let [version, offered_actions, offered_protocol] = packet.options()?;
+                    if version < 2 {
+                        return Err(invalid(format!("unsupported milter version {version}")));
+                    }
+                    actions = offered_actions
+                        & (protocol::ACTION_ADD_HEADERS | protocol::ACTION_QUARANTINE);
+                    let skipped = offered_protocol
+                        & (protocol::NO_CONNECT
+                            | protocol::NO_HELO
+                            | protocol::NO_MAIL
+                            | protocol::NO_RCPT
+                            | protocol::NO_UNKNOWN
+                            | protocol::NO_DATA);
+                    vec![Packet::new(
+                        protocol::OPTIONS,
+                        protocol::options(version.min(protocol::VERSION), actions, skipped),
+                    )]
+                }
+                protocol::MACRO => Vec::new(),
+                protocol::MAIL => {
+                    message = Message::default();
+                    vec![Packet::new(protocol::CONTINUE, [])]
+                }
+                protocol::HEADER => {
+                    let strings = packet.strings();
+                    let name = strings.first().map_or("", String::as_str);
+                    let value = strings.get(1).map_or("", String::as_str);
+                    let header = format!("{name}: {value}\r\n").into_bytes();
+                    message.push(header, self.max_message_size);
+                    vec![Packet::new(protocol::CONTINUE, [])]
+                }
+                protocol::END_OF_HEADERS => {
+                    message.push(b"\r\n".to_vec(), self.max_message_size);
+                    vec![Packet::new(protocol::CONTINUE, [])]
+                }
+                protocol::BODY => {
+                    message.push(packet.data, self.max_message_size);
+                    vec![Packet::new(protocol::CONTINUE, [])]
+                }
+                protocol::END_OF_BODY => {
+                    let message = std::mem::take(&mut message);
+                    self.end_of_message(message, actions).await
+                }
+                protocol::ABORT => {
+                    message = Message::default();
+                    Vec::new()
+                }
+                protocol::QUIT => return Ok(()),
+                protocol::QUIT_NEW_CONNECTION => {
+                    message = Message::default();
+                    Vec::new()
+                }
+                protocol::CONNECT
+                | protocol::HELO
+                | protocol::RCPT
+                | protocol::DATA
+                | protocol::UNKNOWN => vec![Packet::new(protocol::CONTINUE, [])],
+                command => {
+                    return Err(invalid(format!(
+                        "unknown milter command {:?}",
+                        command as char
+                    )))
+                }
+            };
+            if !replies.is_empty() {
+                let data: Vec<u8> = replies.iter().flat_map(Packet::encode).collect();
+                stream.write_all(&data).await?;
+                stream.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scans the message and returns the modifications and the final reply
+    async fn end_of_message(&self, message: Message, actions: u32) -> Vec<Packet> {
+        let verdict = if message.too_large {
+            Verdict::Error(format!(
+                "message larger than {} bytes",
+                self.max_message_size
+            ))
+        } else {
+            let data = stream::iter(message.parts.into_iter().map(Ok));
+            match self.transport.scan_stream(data, self.chunk_size).await {
+                Ok(response) => {
+                    Verdict::parse(&response).unwrap_or_else(|err| Verdict::Error(err.to_string()))
+                }
+                Err(err) => Verdict::Error(err.to_string()),
+            }
+        };
+
+        let (action, status, reply) = match &verdict {
+            Verdict::Clean => (MilterAction::Accept, String::from("Clean"), String::new()),
+            Verdict::Infected(signatures) => (
+                self.on_infected,
+                format!("Infected ({})", signatures.join(", ")),
+                format!("Virus found: {}", signatures.join(", ")),
+            ),
+            Verdict::Error(_) => (
+                self.on_error,
+                String::from("Error"),
+                String::from("Virus scan failed"),
+            ),
+        };
+
+        // Never deliver a message that should have been held
+        let action = match action {
+            MilterAction::Quarantine if actions & protocol::ACTION_QUARANTINE == 0 => {
+                MilterAction::Reject
+            }
+            action => action,
+        };
+
+        let mut packets = Vec::new();
+        if matches!(action, MilterAction::Accept | MilterAction::Quarantine)
+            && actions & protocol::ACTION_ADD_HEADERS != 0
+        {
+            packets.push(Packet::new(
+                protocol::ADD_HEADER,
+                protocol::strings(&["X-Virus-Scanned", &self.scanned_header]),
+            ));
+            packets.push(Packet::new(
+                protocol::ADD_HEADER,
+                protocol::strings(&["X-Virus-Status", &status]),
+            ));
+        }
+        match action {
+            MilterAction::Accept => packets.push(Packet::new(protocol::ACCEPT, [])),
+            MilterAction::Quarantine => {
+                packets.push(Packet::new(
+                    protocol::QUARANTINE,
+                    protocol::strings(&[&reply]),
+                ));
+                packets.push(Packet::new(protocol::ACCEPT, []));
+            }
+            MilterAction::Discard => packets.push(Packet::new(protocol::DISCARD, [])),
+            MilterAction::Reject => packets.push(Packet::new(
+                protocol::REPLY_CODE,
+                protocol::strings(&[&format!("550 5.7.1 {}", smtp_text(&reply))]),
+            )),
+            MilterAction::Tempfail => packets.push(Packet::new(
+                protocol::REPLY_CODE,
+                protocol::strings(&[&format!("451 4.7.1 {}", smtp_text(&reply))]),
+            )),
+        }
+        packets
+    }
+
+    /// Starts listening on a TCP address
+    ///
+    /// Every connection is served on its own thread, up to the [maximum
+    /// number of connections](Self::max_connections). Use port 0 to let the
+    /// operating system pick a free port.
+    pub fn bind_tcp<A: ToSocketAddrs>(&self, address: A) -> io::Result<MilterListener> {
+        let listener = TcpListener::bind(address)?;
+        let local_addr = listener.local_addr()?;
+        let milter = self.clone();
+        let listener = self.spawn(
+            move || {
+                let stream = listener.accept()?.0;
+                let stream = async_net::TcpStream::try_from(stream)?;
+                let milter = milter.clone();
+                Ok(Box::pin(async move { milter.handle(stream).await }))
+            },
+            Box::new(move || {
+                let _ = TcpStream::connect(local_addr);
+            }),
+        );
+        Ok(MilterListener {
+            local_addr: Some(local_addr),
+            listener: Some(listener),
+            #[cfg(unix)]
+            cleanup: None,
+        })
+    }
+
+    /// Starts listening on a Unix socket
+    ///
+    /// Every connection is served on its own thread, up to the [maximum
+    /// number of connections](Self::max_connections). The socket file is
+    /// removed when the listener is stopped.
+    #[cfg(unix)]
+    pub fn bind_socket<P: AsRef<Path>>(&self, path: P) -> io::Result<MilterListener> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let milter = self.clone();
+        let wake = path.clone();
+        let listener = self.spawn(
+            move || {
+                let stream = listener.accept()?.0;
+                let stream = async_net::unix::UnixStream::try_from(stream)?;
+                let milter = milter.clone();
+                Ok(Box::pin(async move { milter.handle(stream).await }))
+            },
+            Box::new(move || {
+                let _ = UnixStream::connect(&wake);
+            }),
+        );
+        Ok(MilterListener {
+            local_addr: None,
+            listener: Some(listener),
+            cleanup: Some(path),
+        })
+    }
+
+    fn spawn<A>(&self, mut accept: A, wake: Box<dyn Fn() + Send + Sync>) -> Listener
+    where
+        A: FnMut() -> io::Result<crate::BoxFuture<'static, io::Result<()>>> + Send + 'static,
+    {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let max_connections = self.max_connections;
+        let handle = {
+            let stopped = stopped.clone();
+            thread::spawn(move || loop {
+                let accepted = accept();
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(connection) = accepted else {
+                    // Do not spin while the process is out of resources
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                };
+                // Dropping the connection closes it
+                if connections.fetch_add(1, Ordering::SeqCst) >= max_connections {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let active = ActiveConnection(connections.clone());
+                thread::spawn(move || {
+                    let _active = active;
+                    async_io::block_on(connection)
+                });
+            })
+        };
+        Listener {
+            stopped,
+            wake,
+            handle,
+        }
+    }
+}
+
+/// Turns a message into text that is safe for an SMTP reply
+fn smtp_text(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .replace('%', "%%")
+}
+
+/// Counts a connection as served until it is dropped
+struct ActiveConnection(Arc<AtomicUsize>);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Listener {
+    stopped: Arc<AtomicBool>,
+    wake: Box<dyn Fn() + Send + Sync>,
+    handle: JoinHandle<()>,
+}
+
+/// Running milter listener
+///
+/// The listener is stopped when this handle is dropped; connections that
+/// were already accepted are served until the MTA closes them.
+pub struct MilterListener {
+    local_addr: Option<SocketAddr>,
+    listener: Option<Listener>,
+    #[cfg(unix)]
+    cleanup: Option<PathBuf>,
+}
+
+impl MilterListener {
+    /// Returns the TCP address the milter is listening on, if any
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Blocks the current thread and serves connections until the process exits
+    pub fn wait(mut self) {
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.handle.join();
+        }
+    }
+
+    /// Stops accepting connections and waits for the listener thread to exit
+    pub fn stop(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            listener.stopped.store(true, Ordering::SeqCst);
+            (listener.wake)();
+            let _ = listener.handle.join();
+        }
+        #[cfg(unix)]
+        if let Some(path) = self.cleanup.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for MilterListener {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl fmt::Debug for MilterListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MilterListener")
+            .field("local_addr", &self.local_addr)
+            .field("running", &self.listener.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+};
+
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
+
+use super::protocol::{self, invalid, read_packet_blocking, Packet};
+
+/// Largest body chunk sent at once, like Postfix and Sendmail do
+const BODY_CHUNK_SIZE: usize = 65535;
+
+/// Reply of a milter to a step of an SMTP transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MilterResponse {
+    /// Go on with the next step
+    Continue,
+    /// Accept the message
+    Accept,
+    /// Reject the message
+    Reject,
+    /// Reject the message temporarily
+    Tempfail,
+    /// Accept the message and drop it
+    Discard,
+    /// Reject the message with an SMTP reply, e.g. `550 5.7.1 Virus found`
+    ReplyCode(String),
+}
+
+impl MilterResponse {
+    fn from_packet(packet: &Packet) -> io::Result<Self> {
+        Ok(match packet.command {
+            protocol::CONTINUE => MilterResponse::Continue,
+            protocol::ACCEPT => MilterResponse::Accept,
+            protocol::REJECT => MilterResponse::Reject,
+            protocol::TEMPFAIL => MilterResponse::Tempfail,
+            protocol::DISCARD => MilterResponse::Discard,
+            protocol::REPLY_CODE => {
+                MilterResponse::ReplyCode(packet.strings().into_iter().next().unwrap_or_default())
+            }
+            command => {
+                return Err(invalid(format!(
+                    "unexpected milter reply {:?}",
+                    command as char
+                )))
+            }
+        })
+    }
+
+    /// Returns `true` if the message goes on to be delivered
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, MilterResponse::Continue | MilterResponse::Accept)
+    }
+}
+
+/// Reply of a milter at the end of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndOfMessage {
+    /// The final reply
+    pub response: MilterResponse,
+    /// Headers the milter asked to add
+    pub added_headers: Vec<(String, String)>,
+    /// Reason if the milter asked to quarantine the message
+    pub quarantine: Option<String>,
+}
+
+impl EndOfMessage {
+    /// Returns the value of the first added header called `name`
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.added_headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Milter client that plays the role of the MTA
+///
+/// The client negotiates the protocol when it is created and skips the steps
+/// of a transaction the milter does not want to see.
+///
+/// ```no_run
+/// use clamav_client::milter::MilterClient;
+///
+/// let mut client = MilterClient::connect_tcp("127.0.0.1:7357").unwrap();
+/// let message = b"Subject: Test\r\n\r\nHello\r\n";
+/// let reply = client
+///     .send_message("sender@example.com", &["recipient@example.com"], message)
+///     .unwrap();
+/// println!("{:?} {:?}", reply.response, reply.header("X-Virus-Status"));
+/// ```
+#[derive(Debug)]
+pub struct MilterClient<S> {
+    stream: S,
+    actions: u32,
+    skipped: u32,
+}
+
+impl MilterClient<TcpStream> {
+    /// Connects to a milter listening on a TCP address
+    pub fn connect_tcp<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Self::new(TcpStream::connect(address)?)
+    }
+}
+
+#[cfg(unix)]
+impl MilterClient<UnixStream> {
+    /// Connects to a milter listening on a Unix socket
+    pub fn connect_socket<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(UnixStream::connect(path)?)
+    }
+}
+
+impl<S: Read + Write> MilterClient<S> {
+    /// Negotiates the protocol on an open connection
+    pub fn new(stream: S) -> io::Result<Self> {
+        Self::with_quarantine(stream, true)
+    }
+
+    /// Negotiates the protocol on an open connection, offering the milter to
+    /// quarantine messages only if `quarantine` is `true`
+    pub fn with_quarantine(stream: S, quarantine: bool) -> io::Result<Self> {
+        let mut client = MilterClient {
+            stream,
+            actions: 0,
+            skipped: 0,
+        };
+        let mut offered_actions = protocol::ACTION_ADD_HEADERS;
+        if quarantine {
+            offered_actions |= protocol::ACTION_QUARANTINE;
+        }
+        let offered_protocol = protocol::NO_CONNECT
+            | protocol::NO_HELO
+            | protocol::NO_MAIL
+            | protocol::NO_RCPT
+            | protocol::NO_UNKNOWN
+            | protocol::NO_DATA;
+        client.send(
+            protocol::OPTIONS,
+            protocol::options(protocol::VERSION, offered_actions, offered_protocol),
+        )?;
+        let reply = read_packet_blocking(&mut client.stream)?;
+        if reply.command != protocol::OPTIONS {
+            return Err(invalid("milter did not negotiate options"));
+        }
+        let [_, actions, skipped] = reply.options()?;
+        client.actions = actions & offered_actions;
+        client.skipped = skipped & offered_protocol;
+        Ok(client)
+    }
+
+    /// Sends the SMTP client's host name and address
+    pub fn connect(
+        &mut self,
+        hostname: &str,
+        address: Option<SocketAddr>,
+    ) -> io::Result<MilterResponse> {
+        let mut data = protocol::strings(&[hostname]);
+        match address {
+            Some(address) => {
+                data.push(if address.is_ipv4() { b'4' } else { b'6' });
+                data.extend_from_slice(&address.port().to_be_bytes());
+                data.extend_from_slice(&protocol::strings(&[&address.ip().to_string()]));
+            }
+            None => data.push(b'U'),
+        }
+        self.step(protocol::NO_CONNECT, protocol::CONNECT, data)
+    }
+
+    /// Sends the `HELO` or `EHLO` name
+    pub fn helo(&mut self, name: &str) -> io::Result<MilterResponse> {
+        self.step(
+            protocol::NO_HELO,
+            protocol::HELO,
+            protocol::strings(&[name]),
+        )
+    }
+
+    /// Sends the `MAIL FROM` address, which starts a new message
+    pub fn mail_from(&mut self, sender: &str) -> io::Result<MilterResponse> {
+        let sender = format!("<{sender}>");
+        self.step(
+            protocol::NO_MAIL,
+            protocol::MAIL,
+            protocol::strings(&[&sender]),
+        )
+    }
+
+    /// Sends a `RCPT TO` address
+    pub fn rcpt_to(&mut self, recipient: &str) -> io::Result<MilterResponse> {
+        let recipient = format!("<{recipient}>");
+        self.step(
+            protocol::NO_RCPT,
+            protocol::RCPT,
+            protocol::strings(&[&recipient]),
+        )
+    }
+
+    /// Announces the `DATA` command
+    pub fn data(&mut self) -> io::Result<MilterResponse> {
+        self.step(protocol::NO_DATA, protocol::DATA, Vec::new())
+    }
+
+    /// Sends a header of the message
+    pub fn header(&mut self, name: &str, value: &str) -> io::Result<MilterResponse> {
+        self.step(0, protocol::HEADER, protocol::strings(&[name, value]))
+    }
+
+    /// Announces the end of the headers
+    pub fn end_of_headers(&mut self) -> io::Result<MilterResponse> {
+        self.step(0, protocol::END_OF_HEADERS, Vec::new())
+    }
+
+    /// Sends a part of the message body
+    ///
+    /// Large parts are split into chunks of at most 64 KiB.
+    pub fn body(&mut self, body: &[u8]) -> io::Result<MilterResponse> {
+        for chunk in body.chunks(BODY_CHUNK_SIZE) {
+            let response = self.step(0, protocol::BODY, chunk.to_vec())?;
+            if response != MilterResponse::Continue {
+                return Ok(response);
+            }
+        }
+        Ok(MilterResponse::Continue)
+    }
+
+    /// Announces the end of the message and returns the milter's decision
+    pub fn end_of_message(&mut self) -> io::Result<EndOfMessage> {
+        self.send(protocol::END_OF_BODY, Vec::new())?;
+        let mut added_headers = Vec::new();
+        let mut quarantine = None;
+        loop {
+            let packet = read_packet_blocking(&mut self.stream)?;
+            match packet.command {
+                protocol::ADD_HEADER => {
+                    let mut strings = packet.strings().into_iter();
+                    let name = strings.next().unwrap_or_default();
+                    added_headers.push((name, strings.next().unwrap_or_default()));
+                }
+                protocol::QUARANTINE => {
+                    quarantine = Some(packet.strings().into_iter().next().unwrap_or_default());
+                }
+                _ => {
+                    return Ok(EndOfMessage {
+                        response: MilterResponse::from_packet(&packet)?,
+                        added_headers,
+                        quarantine,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Aborts the current message
+    pub fn abort(&mut self) -> io::Result<()> {
+        self.send(protocol::ABORT, Vec::new())
+    }
+
+    /// Ends the session
+    pub fn quit(mut self) -> io::Result<()> {
+        self.send(protocol::QUIT, Vec::new())
+    }
+
+    /// Runs a complete transaction for a raw message
+    ///
+    /// The headers of `message` are split off at the first empty line and
+    /// sent one by one, followed by the body. If the milter rejects the
+    /// message before its end, that reply is returned.
+    pub fn send_message(
+        &mut self,
+        sender: &str,
+        recipients: &[&str],
+        message: &[u8],
+    ) -> io::Result<EndOfMessage> {
+        let rejected = |response| EndOfMessage {
+            response,
+            added_headers: Vec::new(),
+            quarantine: None,
+        };
+
+        let response = self.mail_from(sender)?;
+        if response != MilterResponse::Continue {
+            return Ok(rejected(response));
+        }
+        for recipient in recipients {
+            let response = self.rcpt_to(recipient)?;
+            if response != MilterResponse::Continue {
+                return Ok(rejected(response));
+            }
+        }
+        let response = self.data()?;
+        if response != MilterResponse::Continue {
+            return Ok(rejected(response));
+        }
+
+        let (headers, body) = split_message(message);
+        for (name, value) in headers {
+            let response = self.header(&name, &value)?;
+            if response != MilterResponse::Continue {
+                return Ok(rejected(response));
+            }
+        }
+        let response = self.end_of_headers()?;
+        if response != MilterResponse::Continue {
+            return Ok(rejected(response));
+        }
+        let response = self.body(body)?;
+        if response != MilterResponse::Continue {
+            return Ok(rejected(response));
+        }
+        self.end_of_message()
+    }
+
+    /// Returns `true` if the milter may add headers
+    pub fn can_add_headers(&self) -> bool {
+        self.actions & protocol::ACTION_ADD_HEADERS != 0
+    }
+
+    /// Returns `true` if the milter may quarantine messages
+    pub fn can_quarantine(&self) -> bool {
+        self.actions & protocol::ACTION_QUARANTINE != 0
+    }
+
+    /// Sends a step unless the milter asked to skip it
+    fn step(&mut self, skip_flag: u32, command: u8, data: Vec<u8>) -> io::Result<MilterResponse> {
+        if self.skipped & skip_flag != 0 {
+            return Ok(MilterResponse::Continue);
+        }
+        self.send(command, data)?;
+        let reply = read_packet_blocking(&mut self.stream)?;
+        MilterResponse::from_packet(&reply)
+    }
+
+    fn send(&mut self, command: u8, data: Vec<u8>) -> io::Result<()> {
+        self.stream
+            .write_all(&Packet::new(command, data).encode())?;
+        self.stream.flush()
+    }
+}
+
+/// Splits a raw message into unfolded headers and the body
+fn split_message(message: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let (head, body) = [&b"\r\n\r\n"[..], b"\n\n"]
+        .iter()
+        .filter_map(|separator| {
+            let position = message
+                .windows(separator.len())
+                .position(|window| window == *separator)?;
+            Some((position, separator.len()))
+        })
+        .min()
+        .map_or((message, &[][..]), |(position, length)| {
+            (&message[..position], &message[position + length..])
+        });
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in String::from_utf8_lossy(head).lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str("\r\n");
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.to_string(), value.trim_start().to_string()));
+        }
+    }
+    (headers, body)
+}
@@ -0,0 +1,157 @@
+//! Packets of the milter protocol, version 6
+//!
+//! Every packet starts with its length as a big-endian `u32`, followed by a
+//! command byte and the command's data. Strings are null-terminated.
+
+use std::io::{self, Read};
+
+use futures_lite::{AsyncRead, AsyncReadExt};
+
+/// Protocol version spoken by the server and the client
+pub(crate) const VERSION: u32 = 6;
+
+/// Largest accepted packet; MTAs send the body in chunks of at most 64 KiB
+const MAX_PACKET_LENGTH: usize = 1024 * 1024;
+
+// Commands sent by the MTA
+pub(crate) const ABORT: u8 = b'A';
+pub(crate) const BODY: u8 = b'B';
+pub(crate) const CONNECT: u8 = b'C';
+pub(crate) const MACRO: u8 = b'D';
+pub(crate) const END_OF_BODY: u8 = b'E';
+pub(crate) const HELO: u8 = b'H';
+pub(crate) const QUIT_NEW_CONNECTION: u8 = b'K';
+pub(crate) const HEADER: u8 = b'L';
+pub(crate) const MAIL: u8 = b'M';
+pub(crate) const END_OF_HEADERS: u8 = b'N';
+pub(crate) const OPTIONS: u8 = b'O';
+pub(crate) const QUIT: u8 = b'Q';
+pub(crate) const RCPT: u8 = b'R';
+pub(crate) const DATA: u8 = b'T';
+pub(crate) const UNKNOWN: u8 = b'U';
+
+// Replies sent by the milter
+pub(crate) const ADD_HEADER: u8 = b'h';
+pub(crate) const QUARANTINE: u8 = b'q';
+pub(crate) const ACCEPT: u8 = b'a';
+pub(crate) const CONTINUE: u8 = b'c';
+pub(crate) const DISCARD: u8 = b'd';
+pub(crate) const REJECT: u8 = b'r';
+pub(crate) const TEMPFAIL: u8 = b't';
+pub(crate) const REPLY_CODE: u8 = b'y';
+
+// Actions the milter may take at the end of a message
+pub(crate) const ACTION_ADD_HEADERS: u32 = 0x01;
+pub(crate) const ACTION_QUARANTINE: u32 = 0x20;
+
+// Protocol steps the milter does not need
+pub(crate) const NO_CONNECT: u32 = 0x01;
+pub(crate) const NO_HELO: u32 = 0x02;
+pub(crate) const NO_MAIL: u32 = 0x04;
+pub(crate) const NO_RCPT: u32 = 0x08;
+pub(crate) const NO_UNKNOWN: u32 = 0x100;
+pub(crate) const NO_DATA: u32 = 0x200;
+
+pub(crate) fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// A command or reply with its data
+#[derive(Debug)]
+pub(crate) struct Packet {
+    pub(crate) command: u8,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Packet {
+    pub(crate) fn new(command: u8, data: impl Into<Vec<u8>>) -> Self {
+        Packet {
+            command,
+            data: data.into(),
+        }
+    }
+
+    /// Encodes the packet with its length prefix
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(5 + self.data.len());
+        out.extend_from_slice(&(self.data.len() as u32 + 1).to_be_bytes());
+        out.push(self.command);
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    fn decode(mut data: Vec<u8>) -> Self {
+        let command = data.remove(0);
+        Packet { command, data }
+    }
+
+    /// Returns the null-terminated strings of the data
+    pub(crate) fn strings(&self) -> Vec<String> {
+        let data = self.data.strip_suffix(b"\0").unwrap_or(&self.data);
+        data.split(|&b| b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect()
+    }
+
+    /// Returns the three `u32` of an option negotiation
+    pub(crate) fn options(&self) -> io::Result<[u32; 3]> {
+        let field = |i: usize| {
+            let bytes = self.data.get(i * 4..i * 4 + 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().ok()?))
+        };
+        match (field(0), field(1), field(2)) {
+            (Some(version), Some(actions), Some(protocol)) => Ok([version, actions, protocol]),
+            _ => Err(invalid("option negotiation too short")),
+        }
+    }
+}
+
+/// Encodes strings as null-terminated data
+pub(crate) fn strings(strings: &[&str]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for s in strings {
+        data.extend_from_slice(s.as_bytes());
+        data.push(0);
+    }
+    data
+}
+
+/// Encodes the three `u32` of an option negotiation
+pub(crate) fn options(version: u32, actions: u32, protocol: u32) -> Vec<u8> {
+    [version, actions, protocol]
+        .iter()
+        .flat_map(|n| n.to_be_bytes())
+        .collect()
+}
+
+fn check_length(length: [u8; 4]) -> io::Result<usize> {
+    let length = u32::from_be_bytes(length) as usize;
+    if length == 0 || length > MAX_PACKET_LENGTH {
+        return Err(invalid(format!("invalid packet length {length}")));
+    }
+    Ok(length)
+}
+
+/// Reads a packet, returns [`None`] if the peer closed the connection
+pub(crate) async fn read_packet<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<Packet>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut data = vec![0; check_length(length)?];
+    reader.read_exact(&mut data).await?;
+    Ok(Some(Packet::decode(data)))
+}
+
+/// Reads a packet from a blocking reader
+pub(crate) fn read_packet_blocking<R: Read>(reader: &mut R) -> io::Result<Packet> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let mut data = vec![0; check_length(length)?];
+    reader.read_exact(&mut data)?;
+    Ok(Packet::decode(data))
+}
@@ -0,0 +1,332 @@
+//! MIME parsing for [`ClamAvSync::scan_mime`](crate::ClamAvSync::scan_mime)
+//! and `ClamAvAsync::scan_mime`
+//!
+//! Scanning a whole message only tells whether it is infected. The `scan_mime`
+//! methods split a message into its parts instead, decode `base64` and
+//! `quoted-printable` bodies, and scan every part in its own `INSTREAM`
+//! session, so the verdicts name the infected attachments:
+//!
+//! ```no_run
+//! use clamav_client::ClamAvSync;
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let message = std::fs::read("message.eml").unwrap();
+//! for part in clamd_tcp.scan_mime(&message, None) {
+//!     println!(
+//!         "{} {} {:?}: {:?}",
+//!         part.id,
+//!         part.content_type,
+//!         part.filename,
+//!         part.verdict()
+//!     );
+//! }
+//! ```
+//!
+//! Parts are numbered like IMAP does: the parts of a multipart message are
+//! `1`, `2`, ..., their subparts `1.1`, `1.2`, ... The parts of attached
+//! messages (`message/rfc822`) are numbered below the attachment. Text before
+//! the first and after the last boundary of a multipart is returned as the
+//! parts `preamble` and `epilogue`, e.g. `1.preamble`, unless it is blank.
+
+use crate::{
+    headers::{decode_base64, decode_quoted_printable, parameter, split_parameters},
+    IoResult, Verdict,
+};
+
+/// Nesting deeper than this is scanned without parsing it further
+const MAX_DEPTH: usize = 32;
+
+/// Decoded leaf part of a MIME message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimePart {
+    /// Position of the part in the message, e.g. `2.1`
+    pub id: String,
+    /// Lowercase media type without parameters, e.g. `application/pdf`
+    pub content_type: String,
+    /// File name from `Content-Disposition` or the `name` parameter of
+    /// `Content-Type`
+    pub filename: Option<String>,
+    /// `true` if the part is an attachment rather than inline content
+    pub attachment: bool,
+    /// Content with the transfer encoding removed
+    pub data: Vec<u8>,
+}
+
+impl MimePart {
+    pub(crate) fn into_scanned(self, response: IoResult) -> ScannedPart {
+        ScannedPart {
+            size: self.data.len() as u64,
+            id: self.id,
+            content_type: self.content_type,
+            filename: self.filename,
+            attachment: self.attachment,
+            response,
+        }
+    }
+}
+
+/// Result of scanning a single part of a MIME message
+#[derive(Debug)]
+pub struct ScannedPart {
+    /// Position of the part in the message, e.g. `2.1`
+    pub id: String,
+    /// Lowercase media type without parameters, e.g. `application/pdf`
+    pub content_type: String,
+    /// File name of the part, if it has one
+    pub filename: Option<String>,
+    /// `true` if the part is an attachment rather than inline content
+    pub attachment: bool,
+    /// Size of the decoded content in bytes
+    pub size: u64,
+    /// The server's response, or the error that occurred while scanning
+    pub response: IoResult,
+}
+
+impl ScannedPart {
+    /// Returns the verdict for the part, failed scans are [`Verdict::Error`]
+    pub fn verdict(&self) -> Verdict {
+        match &self.response {
+            Ok(response) => {
+                Verdict::parse(response).unwrap_or_else(|err| Verdict::Error(err.to_string()))
+            }
+            Err(err) => Verdict::Error(err.to_string()),
+        }
+    }
+}
+
+/// Splits a message into its decoded leaf parts
+///
+/// The parser is lenient: missing boundaries, broken encodings and
+/// malformed headers never fail, the affected data is returned as it is. A
+/// multipart whose boundary never appears is returned as a single part.
+///
+/// # Arguments
+///
+/// * `message`: The raw message, e.g. the content of an `.eml` file
+///
+/// # Returns
+///
+/// The leaf parts in the order in which they appear in the message
+pub fn parse(message: &[u8]) -> Vec<MimePart> {
+    let mut parts = Vec::new();
+    parse_entity(message, "", true, "text/plain", 0, &mut parts);
+    parts
+}
+
+fn parse_entity(
+    data: &[u8],
+    id: &str,
+    message_root: bool,
+    default_type: &str,
+    depth: usize,
+    parts: &mut Vec<MimePart>,
+) {
+    let (headers, body) = split_headers(data);
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+
+    let (content_type, type_parameters) = match header("content-type") {
+        Some(value) => {
+            let (value, parameters) = split_parameters(value);
+            if value.contains('/') {
+                (value.to_ascii_lowercase(), parameters)
+            } else {
+                (default_type.to_string(), parameters)
+            }
+        }
+        None => (default_type.to_string(), Vec::new()),
+    };
+    let encoding = header("content-transfer-encoding")
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    if depth < MAX_DEPTH {
+        if content_type.starts_with("multipart/") {
+            if let Some(boundary) = parameter(&type_parameters, "boundary") {
+                let child_type = if content_type == "multipart/digest" {
+                    "message/rfc822"
+                } else {
+                    "text/plain"
+                };
+                // Without a single delimiter line or any content between
+                // them, the body is scanned as a leaf
+                if let Some(multipart) = split_multipart(body, &boundary) {
+                    let count = parts.len();
+                    push_text(parts, id, "preamble", multipart.preamble);
+                    for (index, child) in multipart.parts.into_iter().enumerate() {
+                        let child_id = child_id(id, index + 1);
+                        parse_entity(child, &child_id, false, child_type, depth + 1, parts);
+                    }
+                    push_text(parts, id, "epilogue", multipart.epilogue);
+                    if parts.len() > count {
+                        return;
+                    }
+                }
+            }
+        } else if content_type == "message/rfc822" {
+            let nested = decode(&encoding, body);
+            parse_entity(&nested, id, true, "text/plain", depth + 1, parts);
+            return;
+        }
+    }
+
+    let (disposition, disposition_parameters) = match header("content-disposition") {
+        Some(value) => {
+            let (value, parameters) = split_parameters(value);
+            (value.to_ascii_lowercase(), parameters)
+        }
+        None => (String::new(), Vec::new()),
+    };
+    let filename = parameter(&disposition_parameters, "filename")
+        .or_else(|| parameter(&type_parameters, "name"))
+        .filter(|filename| !filename.is_empty());
+
+    parts.push(MimePart {
+        id: if message_root {
+            child_id(id, 1)
+        } else {
+            id.to_string()
+        },
+        content_type,
+        attachment: disposition == "attachment" || filename.is_some(),
+        filename,
+        data: decode(&encoding, body),
+    });
+}
+
+fn child_id(id: &str, index: impl std::fmt::Display) -> String {
+    if id.is_empty() {
+        index.to_string()
+    } else {
+        format!("{id}.{index}")
+    }
+}
+
+/// Adds the preamble or epilogue of a multipart unless it is blank, so data
+/// hidden outside of the parts is scanned too
+fn push_text(parts: &mut Vec<MimePart>, id: &str, name: &str, data: &[u8]) {
+    if !data.iter().all(u8::is_ascii_whitespace) {
+        parts.push(MimePart {
+            id: child_id(id, name),
+            content_type: String::from("text/plain"),
+            filename: None,
+            attachment: false,
+            data: data.to_vec(),
+        });
+    }
+}
+
+/// Splits an entity into unfolded headers and its body
+///
+/// The headers end at the first empty line, or at the first line that is
+/// neither a header nor a continuation line.
+fn split_headers(data: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let end = line_end(data, position);
+        let line = trim_line_break(&data[position..end]);
+        if line.is_empty() {
+            return (headers, &data[end..]);
+        }
+        if line[0] == b' ' || line[0] == b'\t' {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(String::from_utf8_lossy(line).trim());
+                position = end;
+                continue;
+            }
+        }
+        let Some(colon) = line.iter().position(|&b| b == b':') else {
+            break;
+        };
+        let name = &line[..colon];
+        if name.is_empty() || name.iter().any(|b| b.is_ascii_whitespace()) {
+            break;
+        }
+        headers.push((
+            String::from_utf8_lossy(name).into_owned(),
+            String::from_utf8_lossy(&line[colon + 1..])
+                .trim()
+                .to_string(),
+        ));
+        position = end;
+    }
+    (headers, &data[position..])
+}
+
+/// Returns the index after the end of the line starting at `start`
+fn line_end(data: &[u8], start: usize) -> usize {
+    data[start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| start + i + 1)
+}
+
+fn trim_line_break(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Body of a multipart entity split at its boundary lines
+struct Multipart<'a> {
+    /// Data before the first boundary line
+    preamble: &'a [u8],
+    /// Bodies between the boundary lines, without the line break that belongs
+    /// to each boundary
+    parts: Vec<&'a [u8]>,
+    /// Data after the closing boundary line
+    epilogue: &'a [u8],
+}
+
+/// Splits a multipart body, or returns [`None`] if no boundary line is found
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Option<Multipart<'a>> {
+    let delimiter = format!("--{boundary}");
+    let mut multipart: Option<Multipart> = None;
+    let mut start = 0;
+    let mut position = 0;
+    while position < body.len() {
+        let end = line_end(body, position);
+        if let Some(rest) = body[position..end].strip_prefix(delimiter.as_bytes()) {
+            let closing = rest.starts_with(b"--");
+            let rest = if closing { &rest[2..] } else { rest };
+            if rest.iter().all(u8::is_ascii_whitespace) {
+                let data = trim_line_break(&body[start..position]);
+                match &mut multipart {
+                    Some(multipart) => multipart.parts.push(data),
+                    None => {
+                        multipart = Some(Multipart {
+                            preamble: data,
+                            parts: Vec::new(),
+                            epilogue: &[],
+                        })
+                    }
+                }
+                if closing {
+                    if let Some(multipart) = &mut multipart {
+                        multipart.epilogue = &body[end..];
+                    }
+                    return multipart;
+                }
+                start = end;
+            }
+        }
+        position = end;
+    }
+    if let Some(multipart) = &mut multipart {
+        multipart.parts.push(&body[start..]);
+    }
+    multipart
+}
+
+fn decode(encoding: &str, body: &[u8]) -> Vec<u8> {
+    match encoding {
+        "base64" => decode_base64(body),
+        "quoted-printable" => decode_quoted_printable(body),
+        _ => body.to_vec(),
+    }
+}
@@ -0,0 +1,549 @@
+//! Streaming `multipart/form-data` parser for
+//! `ClamAvAsync::scan_multipart`
+//!
+//! [`Multipart`] reads an upload from a stream of [`Bytes`] and yields its
+//! parts one after another. The content of a part is returned in the chunks
+//! in which it arrives, so uploads are never buffered as a whole.
+//!
+//! `scan_multipart` sends every file part to clamd in its own `INSTREAM`
+//! session while the upload arrives, and returns the other form fields
+//! together with a verdict for every file:
+//!
+//! ```no_run
+//! # async fn example(body: impl futures_lite::Stream<Item = std::io::Result<bytes::Bytes>> + Send) -> std::io::Result<()> {
+//! use clamav_client::multipart::{self, MultipartOptions};
+//! use clamav_client::ClamAvAsync;
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let boundary = multipart::boundary("multipart/form-data; boundary=X").unwrap();
+//! let upload = clamd_tcp
+//!     .scan_multipart(body, &boundary, MultipartOptions::new())
+//!     .await?;
+//! for file in &upload.files {
+//!     println!("{} {}: {:?}", file.field, file.filename, file.verdict());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_lite::{stream, Stream, StreamExt};
+
+use crate::{
+    headers::{parameter, split_parameters},
+    ClamAvAsync, IoResult, Verdict,
+};
+
+/// Default maximum size of a form field that is not a file (64 KiB)
+pub const DEFAULT_MAX_FIELD_SIZE: usize = 64 * 1024;
+
+/// Maximum size of the headers of a part
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Returns the boundary of a `multipart/*` content type
+///
+/// # Arguments
+///
+/// * `content_type`: The value of the `Content-Type` header
+///
+/// # Returns
+///
+/// The boundary, or [`None`] if the content type is not multipart or has no
+/// boundary
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (media_type, parameters) = split_parameters(content_type);
+    if !media_type.to_ascii_lowercase().starts_with("multipart/") {
+        return None;
+    }
+    parameter(&parameters, "boundary").filter(|boundary| !boundary.is_empty())
+}
+
+/// Headers of a part
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Part {
+    /// Name of the form field
+    pub name: Option<String>,
+    /// File name, if the part is a file
+    pub filename: Option<String>,
+    /// Content type of the part, e.g. `image/png`
+    pub content_type: Option<String>,
+    /// All headers of the part
+    pub headers: Vec<(String, String)>,
+}
+
+impl Part {
+    /// Returns `true` if the part is a file rather than a form field
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    fn parse(headers: Vec<(String, String)>) -> Self {
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        let (name, filename) = match header("content-disposition") {
+            Some(value) => {
+                let (_, parameters) = split_parameters(value);
+                (
+                    parameter(&parameters, "name"),
+                    parameter(&parameters, "filename"),
+                )
+            }
+            None => (None, None),
+        };
+        let content_type = header("content-type").map(str::to_string);
+        Part {
+            name,
+            filename,
+            content_type,
+            headers,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Headers,
+    Body,
+    Done,
+}
+
+/// Streaming `multipart/form-data` parser
+///
+/// ```
+/// # futures_lite::future::block_on(async {
+/// use bytes::Bytes;
+/// use clamav_client::multipart::Multipart;
+///
+/// let body = "--X\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--X--\r\n";
+/// let stream = futures_lite::stream::once(Ok(Bytes::from(body)));
+/// let mut multipart = Multipart::new(stream, "X");
+///
+/// let part = multipart.next_part().await.unwrap().unwrap();
+/// assert_eq!(part.name.as_deref(), Some("a"));
+/// assert_eq!(multipart.chunk().await.unwrap(), Some(Bytes::from("value")));
+/// assert_eq!(multipart.chunk().await.unwrap(), None);
+/// assert_eq!(multipart.next_part().await.unwrap(), None);
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Multipart<S> {
+    stream: S,
+    buffer: BytesMut,
+    delimiter: Vec<u8>,
+    /// Number of bytes at the start of the buffer added by
+    /// [`put_line_break`](Self::put_line_break)
+    line_break: usize,
+    state: State,
+    eof: bool,
+}
+
+impl<S: Stream<Item = io::Result<Bytes>> + Unpin> Multipart<S> {
+    /// Creates a parser for a body with the given boundary
+    ///
+    /// # Arguments
+    ///
+    /// * `stream`: The request body
+    /// * `boundary`: The boundary, see [`boundary`]
+    pub fn new(stream: S, boundary: &str) -> Self {
+        let mut multipart = Multipart {
+            stream,
+            buffer: BytesMut::new(),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            line_break: 0,
+            state: State::Preamble,
+            eof: false,
+        };
+        // The line break before the first boundary is optional
+        multipart.put_line_break();
+        multipart
+    }
+
+    /// Puts a line break in front of the buffer, so that a delimiter right
+    /// at the start is found
+    ///
+    /// The line break is not part of the content.
+    fn put_line_break(&mut self) {
+        let mut buffer = BytesMut::with_capacity(2 + self.buffer.len());
+        buffer.extend_from_slice(b"\r\n");
+        buffer.extend_from_slice(&self.buffer);
+        self.buffer = buffer;
+        self.line_break = 2;
+    }
+
+    /// Returns the headers of the next part
+    ///
+    /// The rest of the current part is skipped.
+    ///
+    /// # Returns
+    ///
+    /// The part, [`None`] after the last part, or an error if the body could
+    /// not be read or is malformed
+    pub async fn next_part(&mut self) -> io::Result<Option<Part>> {
+        loop {
+            match self.state {
+                State::Preamble | State::Body => while self.read_data().await?.is_some() {},
+                State::Headers => return self.read_headers().await.map(Some),
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    /// Returns the next chunk of the current part's content
+    ///
+    /// # Returns
+    ///
+    /// The chunk, [`None`] at the end of the part, or an error if the body
+    /// could not be read or is malformed
+    pub async fn chunk(&mut self) -> io::Result<Option<Bytes>> {
+        if self.state != State::Body {
+            return Ok(None);
+        }
+        self.read_data().await
+    }
+
+    /// Reads data up to the next delimiter, returns [`None`] at the delimiter
+    async fn read_data(&mut self) -> io::Result<Option<Bytes>> {
+        loop {
+            let (length, at_delimiter) = match find(&self.buffer, &self.delimiter) {
+                Some(position) => (position, true),
+                // The end of the buffer may be the start of the delimiter
+                None => (
+                    (self.buffer.len() + 1).saturating_sub(self.delimiter.len()),
+                    false,
+                ),
+            };
+            if at_delimiter && length == 0 {
+                self.buffer.advance(self.delimiter.len());
+                self.line_break = 0;
+                self.read_boundary_end().await?;
+                return Ok(None);
+            }
+            let mut data = self.buffer.split_to(length).freeze();
+            let line_break = self.line_break.min(data.len());
+            data.advance(line_break);
+            self.line_break -= line_break;
+            if !data.is_empty() {
+                return Ok(Some(data));
+            }
+            if !at_delimiter && !self.fill().await? {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "multipart body ended before the closing boundary",
+                ));
+            }
+        }
+    }
+
+    /// Reads what follows a boundary: `--` or the end of the line
+    async fn read_boundary_end(&mut self) -> io::Result<()> {
+        while self.buffer.len() < 2 {
+            if !self.fill().await? {
+                return Err(invalid("multipart body ended after a boundary"));
+            }
+        }
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(());
+        }
+        let line = self.read_line().await?;
+        if !line.iter().all(|b| *b == b' ' || *b == b'\t') {
+            return Err(invalid("invalid multipart boundary"));
+        }
+        self.state = State::Headers;
+        Ok(())
+    }
+
+    async fn read_headers(&mut self) -> io::Result<Part> {
+        let mut headers: Vec<(String, String)> = Vec::new();
+        let mut size = 0;
+        loop {
+            let line = self.read_line().await?;
+            size += line.len();
+            if size > MAX_HEADER_SIZE {
+                return Err(invalid("multipart headers too large"));
+            }
+            if line.is_empty() {
+                break;
+            }
+            let line = String::from_utf8_lossy(&line);
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                    continue;
+                }
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("invalid multipart header"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        self.put_line_break();
+        self.state = State::Body;
+        Ok(Part::parse(headers))
+    }
+
+    /// Reads a line without its line break
+    async fn read_line(&mut self) -> io::Result<Bytes> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let mut line = self.buffer.split_to(end + 1).freeze();
+                line.truncate(end);
+                if line.ends_with(b"\r") {
+                    line.truncate(end - 1);
+                }
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_HEADER_SIZE {
+                return Err(invalid("multipart header line too long"));
+            }
+            if !self.fill().await? {
+                return Err(invalid("multipart body ended in the headers of a part"));
+            }
+        }
+    }
+
+    /// Reads more data into the buffer, returns `false` at the end of the
+    /// stream
+    async fn fill(&mut self) -> io::Result<bool> {
+        while !self.eof {
+            match self.stream.next().await {
+                Some(chunk) => {
+                    let chunk = chunk?;
+                    if !chunk.is_empty() {
+                        self.buffer.extend_from_slice(&chunk);
+                        return Ok(true);
+                    }
+                }
+                None => self.eof = true,
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Returns the position of the first occurrence of `needle`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Options for scanning a `multipart/form-data` upload
+///
+/// ```
+/// use clamav_client::multipart::MultipartOptions;
+///
+/// let options = MultipartOptions::new()
+///     .max_field_size(1024)
+///     .chunk_size(64 * 1024);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MultipartOptions {
+    pub(crate) max_field_size: usize,
+    pub(crate) chunk_size: Option<usize>,
+}
+
+impl Default for MultipartOptions {
+    fn default() -> Self {
+        MultipartOptions {
+            max_field_size: DEFAULT_MAX_FIELD_SIZE,
+            chunk_size: None,
+        }
+    }
+}
+
+impl MultipartOptions {
+    /// Creates options with a field size limit of 64 KiB
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum size of a form field that is not a file
+    ///
+    /// Fields are kept in memory, larger fields fail the upload. Files are
+    /// not limited by this setting, clamd's `StreamMaxLength` applies.
+    pub fn max_field_size(mut self, max_field_size: usize) -> Self {
+        self.max_field_size = max_field_size;
+        self
+    }
+
+    /// Sets the chunk size for streaming files to clamd
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+}
+
+/// Form field that is not a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormField {
+    /// Name of the field
+    pub name: String,
+    /// Content type of the field, if it has one
+    pub content_type: Option<String>,
+    /// Value of the field
+    pub value: Bytes,
+}
+
+/// Result of scanning a single file of an upload
+#[derive(Debug)]
+pub struct ScannedUpload {
+    /// Name of the form field
+    pub field: String,
+    /// File name sent by the client
+    pub filename: String,
+    /// Content type sent by the client
+    pub content_type: Option<String>,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// The server's response, or the error that occurred while scanning
+    pub response: IoResult,
+}
+
+impl ScannedUpload {
+    /// Returns the verdict for the file, failed scans are [`Verdict::Error`]
+    pub fn verdict(&self) -> Verdict {
+        match &self.response {
+            Ok(response) => {
+                Verdict::parse(response).unwrap_or_else(|err| Verdict::Error(err.to_string()))
+            }
+            Err(err) => Verdict::Error(err.to_string()),
+        }
+    }
+}
+
+/// Result of scanning a `multipart/form-data` upload
+#[derive(Debug, Default)]
+pub struct ScannedMultipart {
+    /// Form fields that are not files, in the order of the upload
+    pub fields: Vec<FormField>,
+    /// Scanned files, in the order of the upload
+    pub files: Vec<ScannedUpload>,
+}
+
+impl ScannedMultipart {
+    /// Returns `true` if every file was scanned and is clean
+    pub fn is_clean(&self) -> bool {
+        self.files.iter().all(|file| file.verdict().is_clean())
+    }
+
+    /// Returns the value of the first form field called `name`
+    pub fn field(&self, name: &str) -> Option<&Bytes> {
+        self.fields
+            .iter()
+            .find(|field| field.name == name)
+            .map(|field| &field.value)
+    }
+
+    /// Returns the result for a file by field name and file name
+    pub fn file(&self, field: &str, filename: &str) -> Option<&ScannedUpload> {
+        self.files
+            .iter()
+            .find(|file| file.field == field && file.filename == filename)
+    }
+}
+
+pub(crate) async fn scan_multipart<C, S>(
+    client: &C,
+    body: S,
+    boundary: &str,
+    options: MultipartOptions,
+) -> io::Result<ScannedMultipart>
+where
+    C: ClamAvAsync + ?Sized,
+    S: Stream<Item = io::Result<Bytes>> + Send,
+{
+    let mut multipart = Multipart::new(Box::pin(body), boundary);
+    let mut scanned = ScannedMultipart::default();
+    while let Some(part) = multipart.next_part().await? {
+        let name = part.name.clone().unwrap_or_default();
+        let Some(filename) = part.filename else {
+            let mut value = BytesMut::new();
+            while let Some(chunk) = multipart.chunk().await? {
+                if value.len() + chunk.len() > options.max_field_size {
+                    return Err(invalid(format!("form field {name:?} too large")));
+                }
+                value.extend_from_slice(&chunk);
+            }
+            scanned.fields.push(FormField {
+                name,
+                content_type: part.content_type,
+                value: value.freeze(),
+            });
+            continue;
+        };
+
+        let (response, size) = scan_part(client, &mut multipart, options.chunk_size).await?;
+        scanned.files.push(ScannedUpload {
+            field: name,
+            filename,
+            content_type: part.content_type,
+            size,
+            response,
+        });
+    }
+    Ok(scanned)
+}
+
+/// Streams the content of the current part to clamd
+///
+/// # Returns
+///
+/// The server's response and the size of the part, or the error that
+/// occurred while reading the upload
+pub(crate) async fn scan_part<C, S>(
+    client: &C,
+    multipart: &mut Multipart<S>,
+    chunk_size: Option<usize>,
+) -> io::Result<(IoResult, u64)>
+where
+    C: ClamAvAsync + ?Sized,
+    S: Stream<Item = io::Result<Bytes>> + Unpin + Send,
+{
+    // Errors of the upload end the INSTREAM session and are returned
+    // instead of a verdict
+    let mut size = 0;
+    let mut upload_error = None;
+    let file = stream::unfold(
+        (&mut *multipart, &mut size, &mut upload_error),
+        |(multipart, size, upload_error)| async move {
+            if upload_error.is_some() {
+                return None;
+            }
+            match multipart.chunk().await {
+                Ok(Some(chunk)) => {
+                    *size += chunk.len() as u64;
+                    Some((Ok(chunk), (multipart, size, upload_error)))
+                }
+                Ok(None) => None,
+                Err(err) => {
+                    let kind = err.kind();
+                    *upload_error = Some(err);
+                    let err = io::Error::new(kind, "upload failed");
+                    Some((Err(err), (multipart, size, upload_error)))
+                }
+            }
+        },
+    );
+    let response = client.scan_stream(file, chunk_size).await;
+    if let Some(err) = upload_error {
+        return Err(err);
+    }
+    // clamd may answer before the end of the part, e.g. if it is too large
+    while let Some(chunk) = multipart.chunk().await? {
+        size += chunk.len() as u64;
+    }
+    Ok((response, size))
+}
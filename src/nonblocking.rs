@@ -1,28 +1,55 @@
-use async_fs::File;
-use async_net::TcpStream;
 use futures_lite::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Stream, StreamExt};
-use std::path::Path;
-
-#[cfg(unix)]
-use async_net::unix::UnixStream;
+use std::{
+    future::Future,
+    path::Path,
+    pin::Pin,
+    time::{Instant, SystemTime},
+};
 
+#[cfg(feature = "hash")]
+use crate::hash::{Digester, HashAlgorithms, HashedResponse, HashingReader};
+#[cfg(feature = "mime")]
+use crate::mime::ScannedPart;
+#[cfg(feature = "multipart")]
+use crate::multipart::{MultipartOptions, ScannedMultipart};
+use crate::{
+    dir::{ScanDirOptions, ScanDirStream},
+    health::{HealthCheck, HealthReport, HealthThresholds, EICAR},
+    progress::{ProgressObserver, ProgressReader, ProgressStream},
+    rt,
+    trace::{Operation, Sent},
+};
+#[cfg(feature = "async")]
 use crate::{Socket, Tcp};
 
-use super::{IoResult, DEFAULT_CHUNK_SIZE, END_OF_STREAM, INSTREAM, PING, SHUTDOWN, VERSION};
+use super::{
+    IoResult, DEFAULT_CHUNK_SIZE, END_OF_STREAM, INSTREAM, PING, SHUTDOWN, STATS, VERSION,
+};
 
+#[cfg(feature = "async")]
 impl ClamAvAsync for Tcp {
-    type Stream = TcpStream;
-    fn connect(&self) -> impl std::future::Future<Output = std::io::Result<TcpStream>> + Send {
-        TcpStream::connect(&self.0)
+    type Stream = async_net::TcpStream;
+    async fn connect(&self) -> std::io::Result<Self::Stream> {
+        let operation = Operation::connect(&self.0);
+        let result = operation
+            .instrument(async_net::TcpStream::connect(self.0))
+            .await;
+        operation.finish(&result);
+        result
     }
 }
 
-#[cfg(unix)]
+#[cfg(all(feature = "async", unix))]
 impl ClamAvAsync for Socket {
-    type Stream = UnixStream;
+    type Stream = async_net::unix::UnixStream;
 
-    fn connect(&self) -> impl std::future::Future<Output = std::io::Result<Self::Stream>> + Send {
-        UnixStream::connect(&self.0)
+    async fn connect(&self) -> std::io::Result<Self::Stream> {
+        let operation = Operation::connect(&self.0.display());
+        let result = operation
+            .instrument(async_net::unix::UnixStream::connect(&self.0))
+            .await;
+        operation.finish(&result);
+        result
     }
 }
 
@@ -72,6 +99,55 @@ pub trait ClamAvAsync: Send + Sync {
         }
     }
 
+    /// Gets statistics about the ClamAV server
+    ///
+    /// This function establishes a connection to a ClamAV server and sends the
+    /// STATS command to it. The response describes the thread pool, the queue
+    /// and the memory usage of the server and ends with `END`.
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn get_stats(&self) -> impl std::future::Future<Output = IoResult> + Send {
+        async {
+            let stream = self.connect().await?;
+            send_command(stream, STATS).await
+        }
+    }
+
+    /// Checks the health of the ClamAV server
+    ///
+    /// This function sends `PING` and measures its round-trip time, sends
+    /// `VERSION` to determine the age of the signatures and, if enabled in
+    /// `thresholds`, scans the [`EICAR`] test string with
+    /// [`scan_buffer`](Self::scan_buffer). Later checks are skipped if the
+    /// server is unreachable.
+    ///
+    /// # Arguments
+    ///
+    /// * `thresholds`: Limits above which the server is considered degraded or unhealthy
+    ///
+    /// # Returns
+    ///
+    /// A [`HealthReport`] with the results of all checks and the overall status
+    fn health(
+        &self,
+        thresholds: &HealthThresholds,
+    ) -> impl std::future::Future<Output = HealthReport> + Send {
+        async move {
+            let mut check = HealthCheck::new(thresholds);
+            let started = Instant::now();
+            let response = self.ping().await;
+            if check.ping(response, started.elapsed()) {
+                check.version(self.get_version().await, SystemTime::now());
+                if check.wants_self_test() {
+                    check.self_test(self.scan_buffer(EICAR, None).await);
+                }
+            }
+            check.finish()
+        }
+    }
+
     /// Scans a file for viruses
     ///
     /// This function reads data from a file located at the specified `file_path`
@@ -92,9 +168,8 @@ pub trait ClamAvAsync: Send + Sync {
         chunk_size: Option<usize>,
     ) -> impl std::future::Future<Output = IoResult> + Send {
         async move {
-            let file = File::open(file_path).await?;
-            let stream = self.connect().await?;
-            scan(file, chunk_size, stream).await
+            let file = rt::open(file_path.as_ref()).await?;
+            self.scan_reader(file, chunk_size).await
         }
     }
 
@@ -115,10 +190,34 @@ pub trait ClamAvAsync: Send + Sync {
         &self,
         buffer: &[u8],
         chunk_size: Option<usize>,
+    ) -> impl std::future::Future<Output = IoResult> + Send {
+        self.scan_reader(buffer, chunk_size)
+    }
+
+    /// Scans data from an async reader for viruses
+    ///
+    /// This function reads data from `reader` until it is exhausted and streams
+    /// it to a ClamAV server for scanning. The default implementations of the
+    /// other scan methods send their input through this method or
+    /// [`scan_stream`](Self::scan_stream), so clients that wrap another client
+    /// can apply their behavior to all of them by overriding both.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`: The reader to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn scan_reader<R: AsyncRead + Unpin + Send>(
+        &self,
+        reader: R,
+        chunk_size: Option<usize>,
     ) -> impl std::future::Future<Output = IoResult> + Send {
         async move {
             let stream = self.connect().await?;
-            scan(buffer, chunk_size, stream).await
+            scan(reader, chunk_size, stream).await
         }
     }
 
@@ -146,6 +245,282 @@ pub trait ClamAvAsync: Send + Sync {
         }
     }
 
+    /// Scans a file for viruses and reports the progress
+    ///
+    /// This function works like [`scan_file`](Self::scan_file) and reports
+    /// every phase of the scan and every sent chunk to `observer`. The total
+    /// number of bytes is the size of the file when it was opened.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path`: The path to the file to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `observer`: Receives the [`ScanProgress`](crate::progress::ScanProgress) reports
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn scan_file_with_progress<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+        observer: &dyn ProgressObserver,
+    ) -> impl std::future::Future<Output = IoResult> + Send {
+        async move {
+            let file = rt::open(file_path.as_ref()).await?;
+            let length = rt::file_len(&file).await?;
+            let mut input = ProgressReader::new(file, observer, Some(length));
+            let result = self.scan_reader(&mut input, chunk_size).await;
+            input.finish(result)
+        }
+    }
+
+    /// Scans a data buffer for viruses and reports the progress
+    ///
+    /// This function works like [`scan_buffer`](Self::scan_buffer) and
+    /// reports every phase of the scan and every sent chunk to `observer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: The data to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `observer`: Receives the [`ScanProgress`](crate::progress::ScanProgress) reports
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn scan_buffer_with_progress(
+        &self,
+        buffer: &[u8],
+        chunk_size: Option<usize>,
+        observer: &dyn ProgressObserver,
+    ) -> impl std::future::Future<Output = IoResult> + Send {
+        async move {
+            let mut input = ProgressReader::new(buffer, observer, Some(buffer.len() as u64));
+            let result = self.scan_reader(&mut input, chunk_size).await;
+            input.finish(result)
+        }
+    }
+
+    /// Scans a stream for viruses and reports the progress
+    ///
+    /// This function works like [`scan_stream`](Self::scan_stream) and
+    /// reports every phase of the scan and every sent chunk to `observer`.
+    /// The total number of bytes of a stream is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_stream`: The stream to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `observer`: Receives the [`ScanProgress`](crate::progress::ScanProgress) reports
+    ///
+    /// # Returns
+    ///
+    /// An [`IoResult`] containing the server's response as a vector of bytes
+    fn scan_stream_with_progress<S: Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send>(
+        &self,
+        input_stream: S,
+        chunk_size: Option<usize>,
+        observer: &dyn ProgressObserver,
+    ) -> impl std::future::Future<Output = IoResult> + Send {
+        async move {
+            let mut input_stream = ProgressStream::new(input_stream, chunk_size, observer);
+            let result = self.scan_stream(&mut input_stream, chunk_size).await;
+            input_stream.finish(result)
+        }
+    }
+
+    /// Scans a file for viruses and hashes its content
+    ///
+    /// This function works like [`scan_file`](Self::scan_file) and computes
+    /// the selected digests over the bytes sent to ClamAV, so the file is read
+    /// only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path`: The path to the file to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `algorithms`: The digests to compute
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`](std::io::Result) containing the server's response and
+    /// the [`ScanDigest`](crate::hash::ScanDigest) of the file
+    #[cfg(feature = "hash")]
+    fn scan_file_hashed<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+        algorithms: HashAlgorithms,
+    ) -> impl std::future::Future<Output = std::io::Result<HashedResponse>> + Send {
+        async move {
+            let file = rt::open(file_path.as_ref()).await?;
+            let mut digester = Digester::new(algorithms);
+            let input = HashingReader {
+                inner: file,
+                digester: &mut digester,
+            };
+            let response = self.scan_reader(input, chunk_size).await?;
+            Ok(digester.finish(response))
+        }
+    }
+
+    /// Scans a data buffer for viruses and hashes it
+    ///
+    /// This function works like [`scan_buffer`](Self::scan_buffer) and
+    /// computes the selected digests over the bytes sent to ClamAV.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: The data to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `algorithms`: The digests to compute
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`](std::io::Result) containing the server's response and
+    /// the [`ScanDigest`](crate::hash::ScanDigest) of the buffer
+    #[cfg(feature = "hash")]
+    fn scan_buffer_hashed(
+        &self,
+        buffer: &[u8],
+        chunk_size: Option<usize>,
+        algorithms: HashAlgorithms,
+    ) -> impl std::future::Future<Output = std::io::Result<HashedResponse>> + Send {
+        async move {
+            let mut digester = Digester::new(algorithms);
+            let input = HashingReader {
+                inner: buffer,
+                digester: &mut digester,
+            };
+            let response = self.scan_reader(input, chunk_size).await?;
+            Ok(digester.finish(response))
+        }
+    }
+
+    /// Scans a stream for viruses and hashes its content
+    ///
+    /// This function works like [`scan_stream`](Self::scan_stream) and
+    /// computes the selected digests over the bytes sent to ClamAV.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_stream`: The stream to be scanned
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    /// * `algorithms`: The digests to compute
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`](std::io::Result) containing the server's response and
+    /// the [`ScanDigest`](crate::hash::ScanDigest) of the stream
+    #[cfg(feature = "hash")]
+    fn scan_stream_hashed<S: Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send>(
+        &self,
+        input_stream: S,
+        chunk_size: Option<usize>,
+        algorithms: HashAlgorithms,
+    ) -> impl std::future::Future<Output = std::io::Result<HashedResponse>> + Send {
+        async move {
+            let mut digester = Digester::new(algorithms);
+            let effective_chunk_size = chunk_size
+                .unwrap_or(DEFAULT_CHUNK_SIZE)
+                .min(u32::MAX as usize);
+            let input_stream = input_stream.inspect(|bytes| {
+                if let Ok(bytes) = bytes {
+                    digester.update(bytes, effective_chunk_size);
+                }
+            });
+            let response = self.scan_stream(input_stream, chunk_size).await?;
+            Ok(digester.finish(response))
+        }
+    }
+
+    /// Scans every part of a MIME message for viruses
+    ///
+    /// This function splits `message` into its parts with
+    /// [`mime::parse`](crate::mime::parse), decodes their transfer encodings
+    /// and scans each part with [`scan_buffer`](Self::scan_buffer), so the
+    /// verdicts tell which attachment is infected.
+    ///
+    /// # Arguments
+    ///
+    /// * `message`: The raw message, e.g. the content of an `.eml` file
+    /// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+    ///
+    /// # Returns
+    ///
+    /// A [`ScannedPart`](crate::mime::ScannedPart) with the file name, content
+    /// type and response for every part, in the order of the message
+    #[cfg(feature = "mime")]
+    fn scan_mime(
+        &self,
+        message: &[u8],
+        chunk_size: Option<usize>,
+    ) -> impl std::future::Future<Output = Vec<ScannedPart>> + Send {
+        async move {
+            let mut scanned = Vec::new();
+            for part in crate::mime::parse(message) {
+                let response = self.scan_buffer(&part.data, chunk_size).await;
+                scanned.push(part.into_scanned(response));
+            }
+            scanned
+        }
+    }
+
+    /// Scans the files of a `multipart/form-data` upload for viruses
+    ///
+    /// This function parses `body` while it arrives and sends every file to
+    /// [`scan_stream`](Self::scan_stream) in its own session, so uploads are
+    /// never buffered as a whole. Form fields that are not files are passed
+    /// through in the result, up to [`MultipartOptions::max_field_size`].
+    ///
+    /// # Arguments
+    ///
+    /// * `body`: The request body
+    /// * `boundary`: The boundary from the `Content-Type` header, see
+    ///   [`multipart::boundary`](crate::multipart::boundary)
+    /// * `options`: Field size limit and chunk size
+    ///
+    /// # Returns
+    ///
+    /// A [`ScannedMultipart`] with the form fields and a
+    /// [`ScannedUpload`](crate::multipart::ScannedUpload) for every file, or
+    /// an error if the body could not be read or is malformed
+    #[cfg(feature = "multipart")]
+    fn scan_multipart<S: Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send>(
+        &self,
+        body: S,
+        boundary: &str,
+        options: MultipartOptions,
+    ) -> impl std::future::Future<Output = std::io::Result<ScannedMultipart>> + Send {
+        crate::multipart::scan_multipart(self, body, boundary, options)
+    }
+
+    /// Scans all files in a directory for viruses
+    ///
+    /// This function walks the directory at `root` according to `options` on a
+    /// blocking thread pool and scans every matching file with
+    /// [`scan_file`](Self::scan_file). Up to [`ScanDirOptions::concurrency`]
+    /// files are scanned at the same time. If `root` is a file, only that file
+    /// is scanned.
+    ///
+    /// # Arguments
+    ///
+    /// * `root`: The directory to be scanned
+    /// * `options`: Recursion, symlink, filter and concurrency settings
+    ///
+    /// # Returns
+    ///
+    /// A [`ScanDirStream`] that yields a [`ScannedFile`](crate::dir::ScannedFile)
+    /// for every scanned file and every path that could not be read, in the
+    /// order in which the scans complete
+    fn scan_dir<P: AsRef<Path>>(&self, root: P, options: ScanDirOptions) -> ScanDirStream<'_>
+    where
+        Self: Sized,
+    {
+        ScanDirStream::new(self, root.as_ref(), options)
+    }
+
     /// Shuts down a ClamAV server
     ///
     /// This function establishes a connection to a ClamAV server and sends the
@@ -167,30 +542,168 @@ pub trait ClamAvAsync: Send + Sync {
     }
 }
 
+/// Boxed future returned by the methods of [`DynClamAvAsync`]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Boxed stream of data accepted by [`DynClamAvAsync::scan_stream`]
+pub type BoxStream<'a> =
+    Pin<Box<dyn Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + 'a>>;
+
+/// Object-safe counterpart of [`ClamAvAsync`]
+///
+/// [`ClamAvAsync`] returns `impl Future` and has generic methods, so it cannot
+/// be used as a trait object. This trait offers the same operations returning
+/// [`BoxFuture`]s and is implemented for every [`ClamAvAsync`] implementor,
+/// which allows storing clients as e.g. `Arc<dyn DynClamAvAsync>`.
+pub trait DynClamAvAsync: Send + Sync {
+    /// Sends a ping request to ClamAV, see [`ClamAvAsync::ping`]
+    fn ping(&self) -> BoxFuture<'_, IoResult>;
+
+    /// Gets the version number from ClamAV, see [`ClamAvAsync::get_version`]
+    fn get_version(&self) -> BoxFuture<'_, IoResult>;
+
+    /// Gets statistics about the ClamAV server, see [`ClamAvAsync::get_stats`]
+    fn get_stats(&self) -> BoxFuture<'_, IoResult>;
+
+    /// Checks the health of the ClamAV server, see [`ClamAvAsync::health`]
+    fn health<'a>(&'a self, thresholds: &'a HealthThresholds) -> BoxFuture<'a, HealthReport>;
+
+    /// Scans a file for viruses, see [`ClamAvAsync::scan_file`]
+    fn scan_file<'a>(
+        &'a self,
+        file_path: &'a Path,
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult>;
+
+    /// Scans a data buffer for viruses, see [`ClamAvAsync::scan_buffer`]
+    fn scan_buffer<'a>(
+        &'a self,
+        buffer: &'a [u8],
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult>;
+
+    /// Scans data from an async reader for viruses, see [`ClamAvAsync::scan_reader`]
+    fn scan_reader<'a>(
+        &'a self,
+        reader: &'a mut (dyn AsyncRead + Unpin + Send),
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult>;
+
+    /// Scans a stream for viruses, see [`ClamAvAsync::scan_stream`]
+    fn scan_stream<'a>(
+        &'a self,
+        input_stream: BoxStream<'a>,
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult>;
+
+    /// Shuts down a ClamAV server, see [`ClamAvAsync::shutdown`]
+    fn shutdown(&self) -> BoxFuture<'_, IoResult>;
+}
+
+impl<T: ClamAvAsync> DynClamAvAsync for T {
+    fn ping(&self) -> BoxFuture<'_, IoResult> {
+        Box::pin(ClamAvAsync::ping(self))
+    }
+
+    fn get_version(&self) -> BoxFuture<'_, IoResult> {
+        Box::pin(ClamAvAsync::get_version(self))
+    }
+
+    fn get_stats(&self) -> BoxFuture<'_, IoResult> {
+        Box::pin(ClamAvAsync::get_stats(self))
+    }
+
+    fn health<'a>(&'a self, thresholds: &'a HealthThresholds) -> BoxFuture<'a, HealthReport> {
+        Box::pin(ClamAvAsync::health(self, thresholds))
+    }
+
+    fn scan_file<'a>(
+        &'a self,
+        file_path: &'a Path,
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult> {
+        Box::pin(ClamAvAsync::scan_file(self, file_path, chunk_size))
+    }
+
+    fn scan_buffer<'a>(
+        &'a self,
+        buffer: &'a [u8],
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult> {
+        Box::pin(ClamAvAsync::scan_buffer(self, buffer, chunk_size))
+    }
+
+    fn scan_reader<'a>(
+        &'a self,
+        reader: &'a mut (dyn AsyncRead + Unpin + Send),
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult> {
+        Box::pin(ClamAvAsync::scan_reader(self, reader, chunk_size))
+    }
+
+    fn scan_stream<'a>(
+        &'a self,
+        input_stream: BoxStream<'a>,
+        chunk_size: Option<usize>,
+    ) -> BoxFuture<'a, IoResult> {
+        Box::pin(ClamAvAsync::scan_stream(self, input_stream, chunk_size))
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, IoResult> {
+        Box::pin(ClamAvAsync::shutdown(self))
+    }
+}
+
 /// Sends a command to ClamAV
 pub async fn send_command<RW: AsyncRead + AsyncWrite + Unpin>(
     mut stream: RW,
     command: &[u8],
 ) -> IoResult {
-    stream.write_all(command).await?;
-    // stream.flush().await?;
+    let operation = Operation::command(command);
+    let result = operation
+        .instrument(async {
+            stream.write_all(command).await?;
+            // stream.flush().await?;
 
-    let mut response = Vec::new();
-    stream.read_to_end(&mut response).await?;
-    Ok(response)
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            Ok(response)
+        })
+        .await;
+    operation.reply(&result);
+    result
 }
 
 /// Scan async readable data with ClamAV
 pub async fn scan<R: AsyncRead + Unpin, RW: AsyncRead + AsyncWrite + Unpin>(
-    mut input: R,
+    input: R,
     chunk_size: Option<usize>,
-    mut stream: RW,
+    stream: RW,
 ) -> IoResult {
-    stream.write_all(INSTREAM).await?;
-
     let chunk_size = chunk_size
         .unwrap_or(DEFAULT_CHUNK_SIZE)
         .min(u32::MAX as usize);
+    let operation = Operation::instream(chunk_size);
+    let mut sent = Sent::default();
+    let result = operation
+        .instrument(instream(input, chunk_size, stream, &mut sent))
+        .await;
+    operation.sent(&sent);
+    operation.reply(&result);
+    result
+}
+
+async fn instream<R, RW>(
+    mut input: R,
+    chunk_size: usize,
+    mut stream: RW,
+    sent: &mut Sent,
+) -> IoResult
+where
+    R: AsyncRead + Unpin,
+    RW: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(INSTREAM).await?;
 
     let mut buffer = vec![0; chunk_size];
 
@@ -199,6 +712,7 @@ pub async fn scan<R: AsyncRead + Unpin, RW: AsyncRead + AsyncWrite + Unpin>(
         if len != 0 {
             stream.write_all(&(len as u32).to_be_bytes()).await?;
             stream.write_all(&buffer[..len]).await?;
+            sent.chunk(len);
         } else {
             stream.write_all(END_OF_STREAM).await?;
             stream.flush().await?;
@@ -215,17 +729,41 @@ pub async fn scan<R: AsyncRead + Unpin, RW: AsyncRead + AsyncWrite + Unpin>(
 pub async fn scan_stream<S, RW>(
     input_stream: S,
     chunk_size: Option<usize>,
-    mut output_stream: RW,
+    output_stream: RW,
 ) -> IoResult
 where
     S: Stream<Item = Result<bytes::Bytes, std::io::Error>>,
     RW: AsyncRead + AsyncWrite + Unpin,
 {
-    output_stream.write_all(INSTREAM).await?;
-
     let chunk_size = chunk_size
         .unwrap_or(DEFAULT_CHUNK_SIZE)
         .min(u32::MAX as usize);
+    let operation = Operation::instream(chunk_size);
+    let mut sent = Sent::default();
+    let result = operation
+        .instrument(instream_stream(
+            input_stream,
+            chunk_size,
+            output_stream,
+            &mut sent,
+        ))
+        .await;
+    operation.sent(&sent);
+    operation.reply(&result);
+    result
+}
+
+async fn instream_stream<S, RW>(
+    input_stream: S,
+    chunk_size: usize,
+    mut output_stream: RW,
+    sent: &mut Sent,
+) -> IoResult
+where
+    S: Stream<Item = Result<bytes::Bytes, std::io::Error>>,
+    RW: AsyncRead + AsyncWrite + Unpin,
+{
+    output_stream.write_all(INSTREAM).await?;
 
     let mut input_stream = std::pin::pin!(input_stream);
 
@@ -236,6 +774,7 @@ where
             let len = chunk.len();
             output_stream.write_all(&(len as u32).to_be_bytes()).await?;
             output_stream.write_all(chunk).await?;
+            sent.chunk(len);
         }
     }
 
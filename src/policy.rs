@@ -0,0 +1,532 @@
+//! Allowlists and denylists applied to scan results
+//!
+//! A [`Policy`] post-processes parsed verdicts: signatures that are known
+//! false positives for an application (e.g. `PUA.*` or heuristics) can be
+//! ignored, while escalated signatures are always reported, even if an
+//! ignore rule or an allowlisted content hash would drop them. Error
+//! responses are handled according to a [`VerdictPolicy`].
+//!
+//! Signatures are matched by exact name, shell-style glob or regular
+//! expression. Content hashes are SHA-256 digests of the scanned data; an
+//! infected file or buffer whose digest is allowlisted is reported clean.
+//!
+//! [`Policy::apply`] works on a [`Verdict`]. A [`PolicyClient`] wraps a
+//! transport and applies the policy to every scan response, so the sync and
+//! async API, [`clean`](crate::clean) and [`Verdict::parse`] all observe the
+//! same result.
+//!
+//! Policies can be loaded from TOML or JSON files:
+//!
+//! ```toml
+//! on_error = "fail_open"
+//! allow_sha256 = ["275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f"]
+//!
+//! [[ignore]]
+//! glob = "PUA.*"
+//!
+//! [[ignore]]
+//! name = "Heuristics.Encrypted.PDF"
+//!
+//! [[escalate]]
+//! regex = "^PUA\\.Win\\.(Exploit|Trojan)\\."
+//! ```
+//!
+//! ```no_run
+//! use clamav_client::policy::{Policy, PolicyClient};
+//! use clamav_client::ClamAvSync;
+//!
+//! let policy = Policy::from_file("clamav-policy.toml").unwrap();
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let client = PolicyClient::new(clamd_tcp, policy);
+//!
+//! let response = client.scan_file("upload.bin", None).unwrap();
+//! println!("clean: {:?}", clamav_client::clean(&response));
+//! ```
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read},
+    path::Path,
+    sync::Arc,
+};
+
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    digest::unhex_sha256,
+    dir::Glob,
+    hash::{Digester, HashAlgorithms, HashingReader},
+    ClamAvSync, IoResult, Timeouts, Verdict, VerdictPolicy,
+};
+
+/// Pattern that matches signature names
+#[derive(Debug, Clone)]
+pub struct SignaturePattern(Matcher);
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Name(String),
+    Glob(Glob),
+    Regex(Regex),
+}
+
+impl SignaturePattern {
+    /// Matches exactly one signature name, e.g. `Heuristics.Encrypted.PDF`
+    pub fn name<S: Into<String>>(name: S) -> Self {
+        SignaturePattern(Matcher::Name(name.into()))
+    }
+
+    /// Matches signature names with a shell-style pattern, e.g. `PUA.*`
+    ///
+    /// `*` and `?` match any characters, `[...]` matches character classes.
+    pub fn glob(pattern: &str) -> Self {
+        SignaturePattern(Matcher::Glob(Glob::new(pattern)))
+    }
+
+    /// Matches signature names with a regular expression
+    ///
+    /// The expression is not anchored, use `^` and `$` to match whole names.
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the pattern, or an error of kind
+    /// [`io::ErrorKind::InvalidInput`] if the expression is invalid
+    pub fn regex(pattern: &str) -> io::Result<Self> {
+        Regex::new(pattern)
+            .map(|regex| SignaturePattern(Matcher::Regex(regex)))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+
+    /// Returns `true` if the pattern matches the signature name
+    pub fn matches(&self, signature: &str) -> bool {
+        match &self.0 {
+            Matcher::Name(name) => name == signature,
+            Matcher::Glob(glob) => glob.matches(signature),
+            Matcher::Regex(regex) => regex.is_match(signature),
+        }
+    }
+}
+
+/// Rules applied to parsed scan results
+///
+/// The default policy changes nothing: no signature is ignored, no hash is
+/// allowlisted and errors are kept ([`VerdictPolicy::FailClosed`]).
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    ignore: Vec<SignaturePattern>,
+    escalate: Vec<SignaturePattern>,
+    allow_sha256: HashSet<[u8; 32]>,
+    on_error: VerdictPolicy,
+}
+
+impl Policy {
+    /// Creates an empty policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops matching signatures from infected verdicts
+    pub fn ignore(mut self, pattern: SignaturePattern) -> Self {
+        self.ignore.push(pattern);
+        self
+    }
+
+    /// Always reports matching signatures, overriding ignore rules and
+    /// allowlisted hashes
+    pub fn escalate(mut self, pattern: SignaturePattern) -> Self {
+        self.escalate.push(pattern);
+        self
+    }
+
+    /// Reports data with the given SHA-256 digest as clean, unless an
+    /// escalated signature matched
+    pub fn allow_sha256(mut self, sha256: [u8; 32]) -> Self {
+        self.allow_sha256.insert(sha256);
+        self
+    }
+
+    /// Sets how ClamAV error responses are treated, defaults to
+    /// [`VerdictPolicy::FailClosed`]
+    pub fn on_error(mut self, on_error: VerdictPolicy) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Parses a policy from TOML
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the policy, or an error of kind
+    /// [`io::ErrorKind::InvalidData`] if the document or one of its patterns
+    /// or digests is invalid
+    pub fn from_toml(toml: &str) -> io::Result<Self> {
+        let config: PolicyConfig = toml::from_str(toml).map_err(invalid_data)?;
+        config.compile()
+    }
+
+    /// Parses a policy from JSON
+    ///
+    /// The document has the same structure as the TOML format.
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the policy, or an error of kind
+    /// [`io::ErrorKind::InvalidData`] if the document or one of its patterns
+    /// or digests is invalid
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let config: PolicyConfig = serde_json::from_str(json).map_err(invalid_data)?;
+        config.compile()
+    }
+
+    /// Loads a policy from a file
+    ///
+    /// Files with a `.json` extension are parsed as JSON, all other files as
+    /// TOML.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path to the policy file
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the policy
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let policy = if path.extension().is_some_and(|ext| ext == "json") {
+            Self::from_json(&content)
+        } else {
+            Self::from_toml(&content)
+        };
+        policy.map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+
+    /// Returns `true` if the signature is dropped by an ignore rule and not
+    /// escalated
+    pub fn is_ignored(&self, signature: &str) -> bool {
+        !self.is_escalated(signature) && self.ignore.iter().any(|p| p.matches(signature))
+    }
+
+    /// Returns `true` if the signature matches an escalate rule
+    pub fn is_escalated(&self, signature: &str) -> bool {
+        self.escalate.iter().any(|p| p.matches(signature))
+    }
+
+    /// Returns `true` if data with the SHA-256 digest is allowlisted
+    pub fn is_allowed(&self, sha256: &[u8; 32]) -> bool {
+        self.allow_sha256.contains(sha256)
+    }
+
+    /// Applies the policy to a parsed [`Verdict`]
+    ///
+    /// # Arguments
+    ///
+    /// * `verdict`: The verdict of a scan
+    /// * `sha256`: SHA-256 digest of the scanned data, if known; allowlisted
+    ///   hashes are only checked if it is given
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the resulting verdict, or an error if the
+    /// verdict is an error and errors are handled with
+    /// [`VerdictPolicy::Strict`]
+    pub fn apply(&self, verdict: Verdict, sha256: Option<&[u8; 32]>) -> io::Result<Verdict> {
+        match verdict {
+            Verdict::Infected(signatures) => {
+                let allowed = sha256.is_some_and(|sha256| self.is_allowed(sha256));
+                let signatures: Vec<String> = signatures
+                    .into_iter()
+                    .filter(|signature| {
+                        self.is_escalated(signature) || !(allowed || self.is_ignored(signature))
+                    })
+                    .collect();
+                if signatures.is_empty() {
+                    Ok(Verdict::Clean)
+                } else {
+                    Ok(Verdict::Infected(signatures))
+                }
+            }
+            verdict => self.on_error.apply(verdict),
+        }
+    }
+
+    /// Returns `true` if applying the policy to the response requires the
+    /// digest of the scanned data
+    fn needs_sha256(&self, verdict: &Verdict) -> bool {
+        !self.allow_sha256.is_empty() && matches!(verdict, Verdict::Infected(_))
+    }
+}
+
+/// Serialized form of a [`Policy`]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyConfig {
+    #[serde(default)]
+    ignore: Vec<PatternConfig>,
+    #[serde(default)]
+    escalate: Vec<PatternConfig>,
+    #[serde(default)]
+    allow_sha256: Vec<String>,
+    #[serde(default)]
+    on_error: VerdictPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum PatternConfig {
+    Name(String),
+    Glob(String),
+    Regex(String),
+}
+
+impl PatternConfig {
+    fn compile(self) -> io::Result<SignaturePattern> {
+        match self {
+            PatternConfig::Name(name) => Ok(SignaturePattern::name(name)),
+            PatternConfig::Glob(pattern) => Ok(SignaturePattern::glob(&pattern)),
+            PatternConfig::Regex(pattern) => {
+                SignaturePattern::regex(&pattern).map_err(invalid_data)
+            }
+        }
+    }
+}
+
+impl PolicyConfig {
+    fn compile(self) -> io::Result<Policy> {
+        let mut policy = Policy::new().on_error(self.on_error);
+        for pattern in self.ignore {
+            policy = policy.ignore(pattern.compile()?);
+        }
+        for pattern in self.escalate {
+            policy = policy.escalate(pattern.compile()?);
+        }
+        for hex in self.allow_sha256 {
+            let sha256 = unhex_sha256(&hex)
+                .ok_or_else(|| invalid_data(format!("invalid SHA-256 digest: {hex}")))?;
+            policy = policy.allow_sha256(sha256);
+        }
+        Ok(policy)
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Transport wrapper that applies a [`Policy`] to every scan response
+///
+/// Responses whose verdict the policy changes are rewritten: clean verdicts
+/// become `stream: OK`, infected verdicts list the remaining signatures.
+/// Unchanged responses are returned as they are. Errors handled with
+/// [`VerdictPolicy::Strict`] are returned as [`io::Error`].
+///
+/// If the policy allowlists hashes, files, readers and streams are hashed
+/// while they are sent to ClamAV, so the digest checked against the
+/// allowlist is always that of the scanned bytes. Scans with progress, the
+/// hashed scans and [`scan_mime`](ClamAvSync::scan_mime) go through the same
+/// methods and observe the policy as well.
+///
+/// The wrapper implements [`ClamAvSync`] and
+/// [`ClamAvAsync`](crate::ClamAvAsync) if the wrapped transport does.
+#[derive(Debug)]
+pub struct PolicyClient<T> {
+    inner: T,
+    policy: Arc<Policy>,
+}
+
+impl<T: Clone> Clone for PolicyClient<T> {
+    fn clone(&self) -> Self {
+        PolicyClient {
+            inner: self.inner.clone(),
+            policy: Arc::clone(&self.policy),
+        }
+    }
+}
+
+impl<T> PolicyClient<T> {
+    /// Wraps a transport, applying `policy` to its scan responses
+    pub fn new(inner: T, policy: Policy) -> Self {
+        PolicyClient {
+            inner,
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// Returns the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the policy
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Returns the digests to compute while scanning, if any
+    fn algorithms(&self) -> Option<HashAlgorithms> {
+        let needed = !self.policy.allow_sha256.is_empty();
+        needed.then(|| HashAlgorithms::new().sha256(true))
+    }
+
+    /// Applies the policy to a response
+    ///
+    /// `sha256` is only called if the digest is needed.
+    fn enforce<F>(&self, response: Vec<u8>, sha256: F) -> IoResult
+    where
+        F: FnOnce() -> io::Result<Option<[u8; 32]>>,
+    {
+        let Ok(verdict) = Verdict::parse(&response) else {
+            return Ok(response);
+        };
+        let sha256 = if self.policy.needs_sha256(&verdict) {
+            sha256()?
+        } else {
+            None
+        };
+        let enforced = self.policy.apply(verdict.clone(), sha256.as_ref())?;
+        if enforced == verdict {
+            Ok(response)
+        } else {
+            Ok(encode(&enforced))
+        }
+    }
+}
+
+/// Encodes a clean or infected verdict as a ClamAV stream response
+fn encode(verdict: &Verdict) -> Vec<u8> {
+    match verdict {
+        Verdict::Infected(signatures) => signatures
+            .iter()
+            .flat_map(|signature| format!("stream: {signature} FOUND\0").into_bytes())
+            .collect(),
+        _ => b"stream: OK\0".to_vec(),
+    }
+}
+
+impl<T: ClamAvSync> ClamAvSync for PolicyClient<T> {
+    type Stream = T::Stream;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        self.inner.connect()
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        self.inner.connect_with_timeouts(timeouts)
+    }
+
+    fn scan_file<P: AsRef<Path> + Send>(
+        &self,
+        file_path: P,
+        chunk_size: Option<usize>,
+    ) -> IoResult {
+        let Some(algorithms) = self.algorithms() else {
+            let response = self.inner.scan_file(file_path, chunk_size)?;
+            return self.enforce(response, || Ok(None));
+        };
+        let scan = self
+            .inner
+            .scan_file_hashed(file_path, chunk_size, algorithms)?;
+        self.enforce(scan.response, || Ok(scan.digest.sha256))
+    }
+
+    fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+        let response = self.inner.scan_buffer(buffer, chunk_size)?;
+        self.enforce(response, || Ok(Some(Sha256::digest(buffer).into())))
+    }
+
+    fn scan_reader<R: Read>(&self, reader: R, chunk_size: Option<usize>) -> IoResult {
+        let Some(algorithms) = self.algorithms() else {
+            let response = self.inner.scan_reader(reader, chunk_size)?;
+            return self.enforce(response, || Ok(None));
+        };
+        let mut digester = Digester::new(algorithms);
+        let input = HashingReader {
+            inner: reader,
+            digester: &mut digester,
+        };
+        let response = self.inner.scan_reader(input, chunk_size)?;
+        let scan = digester.finish(response);
+        self.enforce(scan.response, || Ok(scan.digest.sha256))
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_policy {
+    use std::{io, path::Path};
+
+    use futures_lite::{AsyncRead, Stream};
+    use sha2::{Digest, Sha256};
+
+    use super::PolicyClient;
+    use crate::{
+        hash::{Digester, HashingReader},
+        ClamAvAsync, IoResult,
+    };
+
+    impl<T: ClamAvAsync> ClamAvAsync for PolicyClient<T> {
+        type Stream = T::Stream;
+
+        async fn connect(&self) -> io::Result<Self::Stream> {
+            self.inner.connect().await
+        }
+
+        async fn scan_file<P: AsRef<Path> + Send>(
+            &self,
+            file_path: P,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let Some(algorithms) = self.algorithms() else {
+                let response = self.inner.scan_file(file_path, chunk_size).await?;
+                return self.enforce(response, || Ok(None));
+            };
+            let scan = self
+                .inner
+                .scan_file_hashed(file_path, chunk_size, algorithms)
+                .await?;
+            self.enforce(scan.response, || Ok(scan.digest.sha256))
+        }
+
+        async fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+            let response = self.inner.scan_buffer(buffer, chunk_size).await?;
+            self.enforce(response, || Ok(Some(Sha256::digest(buffer).into())))
+        }
+
+        async fn scan_reader<R: AsyncRead + Unpin + Send>(
+            &self,
+            reader: R,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let Some(algorithms) = self.algorithms() else {
+                let response = self.inner.scan_reader(reader, chunk_size).await?;
+                return self.enforce(response, || Ok(None));
+            };
+            let mut digester = Digester::new(algorithms);
+            let input = HashingReader {
+                inner: reader,
+                digester: &mut digester,
+            };
+            let response = self.inner.scan_reader(input, chunk_size).await?;
+            let scan = digester.finish(response);
+            self.enforce(scan.response, || Ok(scan.digest.sha256))
+        }
+
+        async fn scan_stream<St: Stream<Item = Result<bytes::Bytes, io::Error>> + Send>(
+            &self,
+            input_stream: St,
+            chunk_size: Option<usize>,
+        ) -> IoResult {
+            let Some(algorithms) = self.algorithms() else {
+                let response = self.inner.scan_stream(input_stream, chunk_size).await?;
+                return self.enforce(response, || Ok(None));
+            };
+            let scan = self
+                .inner
+                .scan_stream_hashed(input_stream, chunk_size, algorithms)
+                .await?;
+            self.enforce(scan.response, || Ok(scan.digest.sha256))
+        }
+    }
+}
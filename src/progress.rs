@@ -0,0 +1,273 @@
+//! Progress reporting for long scans
+//!
+//! The `*_with_progress` methods of [`ClamAvSync`](crate::ClamAvSync) and
+//! [`ClamAvAsync`](crate::ClamAvAsync) call a [`ProgressObserver`] when the
+//! scan enters a new [`ScanPhase`] and after every chunk that was sent. Each
+//! report carries the time elapsed since the scan started, so stalls can be
+//! detected by comparing it with the time of the previous report.
+//!
+//! ```no_run
+//! use clamav_client::progress::ScanProgress;
+//! use clamav_client::ClamAvSync;
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let response = clamd_tcp
+//!     .scan_file_with_progress("disk.img", None, &|progress: &ScanProgress| {
+//!         if let Some(fraction) = progress.fraction() {
+//!             eprint!("\r{:?} {:.0}%", progress.phase, fraction * 100.0);
+//!         }
+//!     })
+//!     .unwrap();
+//! ```
+
+use std::{
+    io::{self, Read},
+    time::{Duration, Instant},
+};
+
+use crate::IoResult;
+
+/// Stage of a scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanPhase {
+    /// Connecting to ClamAV, including retries
+    Connecting,
+    /// Sending the data with `INSTREAM`
+    Streaming,
+    /// All data was sent, waiting for ClamAV's response
+    AwaitingVerdict,
+    /// The response was received
+    Finished,
+}
+
+/// Snapshot of a running scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// Current stage of the scan
+    pub phase: ScanPhase,
+    /// Number of bytes sent so far
+    pub bytes_sent: u64,
+    /// Number of chunks sent so far, excluding the terminating zero-length
+    /// chunk
+    pub chunks_sent: u64,
+    /// Total number of bytes to send, if known in advance
+    pub total_bytes: Option<u64>,
+    /// Time since the scan started
+    pub elapsed: Duration,
+}
+
+impl ScanProgress {
+    /// Returns the fraction of the data that was sent, between `0.0` and
+    /// `1.0`, if the total size is known
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total_bytes? {
+            0 => Some(1.0),
+            total => Some((self.bytes_sent as f64 / total as f64).min(1.0)),
+        }
+    }
+}
+
+/// Receives progress reports of a scan
+///
+/// Reports are delivered on the thread or task that runs the scan, so
+/// implementations should return quickly. Closures taking a
+/// [`&ScanProgress`](ScanProgress) implement this trait.
+pub trait ProgressObserver: Send + Sync {
+    /// Called when the scan enters a new phase and after every sent chunk
+    fn on_progress(&self, progress: &ScanProgress);
+}
+
+impl<F: Fn(&ScanProgress) + Send + Sync> ProgressObserver for F {
+    fn on_progress(&self, progress: &ScanProgress) {
+        self(progress)
+    }
+}
+
+/// Progress state of a single scan, reporting to an observer
+pub(crate) struct Progress<'a> {
+    observer: &'a dyn ProgressObserver,
+    started: Instant,
+    state: ScanProgress,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(observer: &'a dyn ProgressObserver, total_bytes: Option<u64>) -> Self {
+        Progress {
+            observer,
+            started: Instant::now(),
+            state: ScanProgress {
+                phase: ScanPhase::Connecting,
+                bytes_sent: 0,
+                chunks_sent: 0,
+                total_bytes,
+                elapsed: Duration::ZERO,
+            },
+        }
+    }
+
+    pub(crate) fn phase(&mut self, phase: ScanPhase) {
+        self.state.phase = phase;
+        self.report();
+    }
+
+    pub(crate) fn chunk(&mut self, len: usize) {
+        self.state.bytes_sent += len as u64;
+        self.state.chunks_sent += 1;
+        self.report();
+    }
+
+    /// Reports that the input is being read, unless it already was
+    fn start_input(&mut self) {
+        if self.state.phase == ScanPhase::Connecting {
+            self.phase(ScanPhase::Streaming);
+        }
+    }
+
+    /// Reports that the input is exhausted, unless it already was
+    fn end_input(&mut self) {
+        if self.state.phase == ScanPhase::Streaming {
+            self.phase(ScanPhase::AwaitingVerdict);
+        }
+    }
+
+    fn report(&mut self) {
+        self.state.elapsed = self.started.elapsed();
+        self.observer.on_progress(&self.state);
+    }
+}
+
+/// Reader that reports the progress of a scan while it is read
+///
+/// The scan methods send every non-empty read as one chunk, so each read is
+/// reported as a chunk. Because the reader is passed to
+/// [`scan_reader`](crate::ClamAvSync::scan_reader), clients that override it
+/// apply their behavior to scans with progress as well.
+pub(crate) struct ProgressReader<'a, R> {
+    inner: R,
+    progress: Progress<'a>,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    /// Wraps `inner` and reports the [`Connecting`](ScanPhase::Connecting)
+    /// phase
+    pub(crate) fn new(inner: R, observer: &'a dyn ProgressObserver, total: Option<u64>) -> Self {
+        let mut progress = Progress::new(observer, total);
+        progress.phase(ScanPhase::Connecting);
+        ProgressReader { inner, progress }
+    }
+
+    /// Reports the [`Finished`](ScanPhase::Finished) phase if the scan
+    /// succeeded
+    pub(crate) fn finish(mut self, result: IoResult) -> IoResult {
+        if result.is_ok() {
+            self.progress.phase(ScanPhase::Finished);
+        }
+        result
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.progress.start_input();
+        let len = self.inner.read(buf)?;
+        match len {
+            0 => self.progress.end_input(),
+            len => self.progress.chunk(len),
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_progress {
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use bytes::Bytes;
+    use futures_lite::{ready, AsyncRead, Stream};
+
+    use super::{Progress, ProgressObserver, ProgressReader, ScanPhase};
+    use crate::IoResult;
+
+    impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<'_, R> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.progress.start_input();
+            let len = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            match len {
+                0 => this.progress.end_input(),
+                len => this.progress.chunk(len),
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    /// Stream that reports the progress of a scan while it is polled
+    ///
+    /// Items are reported as the chunks of at most `chunk_size` bytes they
+    /// are split into when sent with `INSTREAM`.
+    pub(crate) struct ProgressStream<'a, S> {
+        inner: Pin<Box<S>>,
+        chunk_size: usize,
+        progress: Progress<'a>,
+    }
+
+    impl<'a, S> ProgressStream<'a, S> {
+        /// Wraps `inner` and reports the
+        /// [`Connecting`](ScanPhase::Connecting) phase
+        pub(crate) fn new(
+            inner: S,
+            chunk_size: Option<usize>,
+            observer: &'a dyn ProgressObserver,
+        ) -> Self {
+            let mut progress = Progress::new(observer, None);
+            progress.phase(ScanPhase::Connecting);
+            ProgressStream {
+                inner: Box::pin(inner),
+                chunk_size: chunk_size
+                    .unwrap_or(crate::DEFAULT_CHUNK_SIZE)
+                    .clamp(1, u32::MAX as usize),
+                progress,
+            }
+        }
+
+        /// Reports the [`Finished`](ScanPhase::Finished) phase if the scan
+        /// succeeded
+        pub(crate) fn finish(mut self, result: IoResult) -> IoResult {
+            if result.is_ok() {
+                self.progress.phase(ScanPhase::Finished);
+            }
+            result
+        }
+    }
+
+    impl<S: Stream<Item = io::Result<Bytes>>> Stream for ProgressStream<'_, S> {
+        type Item = io::Result<Bytes>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            this.progress.start_input();
+            let item = ready!(this.inner.as_mut().poll_next(cx));
+            match &item {
+                Some(Ok(bytes)) => {
+                    for chunk in bytes.chunks(this.chunk_size) {
+                        this.progress.chunk(chunk.len());
+                    }
+                }
+                Some(Err(_)) => {}
+                None => this.progress.end_input(),
+            }
+            Poll::Ready(item)
+        }
+    }
+}
+
+#[cfg(feature = "async-core")]
+pub(crate) use async_progress::ProgressStream;
@@ -0,0 +1,490 @@
+//! clamd-compatible proxy that forwards requests to ClamAV servers
+//!
+//! A [`Proxy`] listens on TCP and Unix sockets and speaks the parts of the
+//! clamd protocol that stream-based clients such as `clamdscan --stream` use:
+//! `PING`, `VERSION`, `STATS`, `INSTREAM` and `IDSESSION` sessions. Every
+//! request is forwarded to an upstream transport, so proxies compose with the
+//! other transports of this crate:
+//!
+//! - a [`Pool`] spreads requests over several backends and fails over to the
+//!   next one if a backend is unreachable,
+//! - a [`CachedClient`](crate::cache::CachedClient) answers repeated scans of
+//!   identical data without a backend,
+//! - an [`AccessLog`] records every command with its client, size, reply and
+//!   duration.
+//!
+//! Streams are buffered up to the [maximum stream
+//! length](Proxy::max_stream_length) before they are forwarded, so that a
+//! scan can be retried on another backend. The proxy therefore buffers up to
+//! [`max_connections`](Proxy::max_connections) times that length, 100 × 25 MB
+//! by default. Like clamd, connections are closed after a [read
+//! timeout](Proxy::read_timeout) or an [idle timeout](Proxy::idle_timeout)
+//! between commands. Commands that need access to clamd's file system
+//! (`SCAN`, `CONTSCAN`, ...) and `SHUTDOWN` are answered with `UNKNOWN
+//! COMMAND`.
+//!
+//! ```no_run
+//! use clamav_client::cache::{CachedClient, MemoryCache};
+//! use clamav_client::proxy::{Backend, Pool, Proxy};
+//!
+//! let pool: Pool<Backend> = Pool::new(vec![
+//!     "10.0.0.1:3310".parse().unwrap(),
+//!     "10.0.0.2:3310".parse().unwrap(),
+//! ]);
+//! let proxy = Proxy::new(CachedClient::new(pool, MemoryCache::new(10_000)))
+//!     .access_log(|entry: &clamav_client::proxy::AccessEntry| eprintln!("{entry}"));
+//! let server = proxy.bind_tcp("127.0.0.1:3310").unwrap();
+//! server.wait();
+//! ```
+
+use std::{
+    fmt,
+    io::{self, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use crate::Socket;
+use crate::{
+    wire::{read_command, read_instream, write_reply, SIZE_LIMIT_EXCEEDED},
+    ClamAvSync, IoResult, Tcp,
+};
+
+mod pool;
+pub use pool::{Backend, BackendStream, Pool, PoolStream, Strategy};
+
+/// Default maximum stream length, clamd's default `StreamMaxLength` (25 MB)
+pub const DEFAULT_MAX_STREAM_LENGTH: u64 = 25 * 1024 * 1024;
+
+/// Default maximum number of connections served at the same time
+pub const DEFAULT_MAX_CONNECTIONS: usize = 100;
+
+/// Default time to wait for data of a command, clamd's default `ReadTimeout`
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Default time to wait for the next command, clamd's default `IdleTimeout`
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause after a failed `accept`, e.g. when the process is out of file
+/// descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A command handled by the proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessEntry {
+    /// Address of the client, or `unix` for Unix socket clients
+    pub peer: String,
+    /// The command without prefix and delimiter, e.g. `INSTREAM`
+    pub command: String,
+    /// Number of the command within an `IDSESSION`, if any
+    pub session_id: Option<u64>,
+    /// Number of bytes streamed by the client
+    pub bytes: u64,
+    /// The reply sent to the client without delimiter
+    pub reply: String,
+    /// Time from reading the command until the reply was available
+    pub duration: Duration,
+}
+
+impl fmt::Display for AccessEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.peer, self.command)?;
+        if let Some(id) = self.session_id {
+            write!(f, " session_id={id}")?;
+        }
+        write!(
+            f,
+            " bytes={} duration_ms={:.1} reply={:?}",
+            self.bytes,
+            self.duration.as_secs_f64() * 1000.0,
+            self.reply
+        )
+    }
+}
+
+/// Receives an [`AccessEntry`] for every command handled by a [`Proxy`]
+///
+/// Entries are delivered on the connection's thread. Closures taking an
+/// [`&AccessEntry`](AccessEntry) implement this trait.
+pub trait AccessLog: Send + Sync {
+    /// Called after the reply to a command was determined
+    fn log(&self, entry: &AccessEntry);
+}
+
+impl<F: Fn(&AccessEntry) + Send + Sync> AccessLog for F {
+    fn log(&self, entry: &AccessEntry) {
+        self(entry)
+    }
+}
+
+/// clamd-compatible server that forwards requests to an upstream transport
+///
+/// Clones share the upstream transport and the access log, so the same proxy
+/// can listen on TCP and a Unix socket at the same time.
+pub struct Proxy<U> {
+    upstream: Arc<U>,
+    max_stream_length: u64,
+    max_connections: usize,
+    read_timeout: Duration,
+    idle_timeout: Duration,
+    access_log: Option<Arc<dyn AccessLog>>,
+}
+
+impl<U> Clone for Proxy<U> {
+    fn clone(&self) -> Self {
+        Proxy {
+            upstream: self.upstream.clone(),
+            max_stream_length: self.max_stream_length,
+            max_connections: self.max_connections,
+            read_timeout: self.read_timeout,
+            idle_timeout: self.idle_timeout,
+            access_log: self.access_log.clone(),
+        }
+    }
+}
+
+impl<U> fmt::Debug for Proxy<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("max_stream_length", &self.max_stream_length)
+            .field("max_connections", &self.max_connections)
+            .field("read_timeout", &self.read_timeout)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("access_log", &self.access_log.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<U: ClamAvSync + Send + Sync + 'static> Proxy<U> {
+    /// Creates a proxy that forwards requests to `upstream`
+    pub fn new(upstream: U) -> Self {
+        Proxy {
+            upstream: Arc::new(upstream),
+            max_stream_length: DEFAULT_MAX_STREAM_LENGTH,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            access_log: None,
+        }
+    }
+
+    /// Sets the maximum number of bytes a client may stream with `INSTREAM`
+    ///
+    /// Longer streams are answered with `INSTREAM size limit exceeded. ERROR`
+    /// like clamd does.
+    pub fn max_stream_length(mut self, max_stream_length: u64) -> Self {
+        self.max_stream_length = max_stream_length;
+        self
+    }
+
+    /// Sets the maximum number of connections served at the same time
+    ///
+    /// Connections beyond the limit are closed right after they are accepted.
+    /// Every connection may buffer a stream of up to the [maximum stream
+    /// length](Self::max_stream_length).
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Sets how long to wait for data while a command is read or a stream is
+    /// received, like clamd's `ReadTimeout`
+    ///
+    /// The timeout also applies to sending replies. A zero duration disables
+    /// the timeout.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long to wait for the next command before the connection is
+    /// closed, like clamd's `IdleTimeout`
+    ///
+    /// A zero duration disables the timeout.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the access log
+    pub fn access_log<L: AccessLog + 'static>(mut self, access_log: L) -> Self {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// Returns the upstream transport
+    pub fn upstream(&self) -> &U {
+        &self.upstream
+    }
+
+    /// Starts listening on a TCP address
+    ///
+    /// Use port 0 to let the operating system pick a free port.
+    pub fn bind_tcp<A: ToSocketAddrs>(&self, address: A) -> io::Result<ProxyServer<Tcp>> {
+        let listener = TcpListener::bind(address)?;
+        let transport = Tcp(listener.local_addr()?);
+        let wake = transport.0;
+        let handle = self.spawn(
+            move || {
+                let (stream, peer) = listener.accept()?;
+                Ok((stream, peer.to_string()))
+            },
+            Box::new(move || {
+                let _ = TcpStream::connect(wake);
+            }),
+        );
+        Ok(ProxyServer::new(transport, handle))
+    }
+
+    /// Starts listening on a Unix socket
+    ///
+    /// The socket file is removed when the server is stopped.
+    #[cfg(unix)]
+    pub fn bind_socket<P: AsRef<Path>>(&self, path: P) -> io::Result<ProxyServer<Socket>> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let wake = path.clone();
+        let handle = self.spawn(
+            move || Ok((listener.accept()?.0, String::from("unix"))),
+            Box::new(move || {
+                let _ = UnixStream::connect(&wake);
+            }),
+        );
+        let mut server = ProxyServer::new(Socket(path.clone()), handle);
+        server.cleanup = Some(path);
+        Ok(server)
+    }
+
+    fn spawn<S, A>(&self, mut accept: A, wake: Box<dyn Fn() + Send + Sync>) -> Listener
+    where
+        S: Connection + Send + 'static,
+        A: FnMut() -> io::Result<(S, String)> + Send + 'static,
+    {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let proxy = self.clone();
+        let handle = {
+            let stopped = stopped.clone();
+            thread::spawn(move || loop {
+                let accepted = accept();
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(_) => {
+                        // Do not spin while the process is out of resources
+                        thread::sleep(ACCEPT_BACKOFF);
+                        continue;
+                    }
+                };
+                if connections.fetch_add(1, Ordering::SeqCst) >= proxy.max_connections {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let active = ActiveConnection(connections.clone());
+                let proxy = proxy.clone();
+                thread::spawn(move || {
+                    let _active = active;
+                    let mut stream = BufReader::new(stream);
+                    let _ = proxy.handle_connection(&mut stream, &peer);
+                });
+            })
+        };
+        Listener {
+            stopped,
+            wake,
+            handle,
+        }
+    }
+
+    fn handle_connection<S: Connection>(
+        &self,
+        stream: &mut BufReader<S>,
+        peer: &str,
+    ) -> io::Result<()> {
+        stream.get_ref().set_timeouts(Some(self.read_timeout))?;
+        let Some((command, delimiter)) = read_command(stream)? else {
+            return Ok(());
+        };
+
+        if command == "IDSESSION" {
+            let mut id = 0;
+            loop {
+                stream.get_ref().set_timeouts(Some(self.idle_timeout))?;
+                let Some((command, delimiter)) = read_command(stream)? else {
+                    break;
+                };
+                stream.get_ref().set_timeouts(Some(self.read_timeout))?;
+                if command == "END" {
+                    break;
+                }
+                id += 1;
+                let reply = self.execute(&command, stream, peer, Some(id))?;
+                write_reply(stream.get_mut(), &format!("{id}: {reply}"), delimiter)?;
+            }
+            return Ok(());
+        }
+
+        let reply = self.execute(&command, stream, peer, None)?;
+        write_reply(stream.get_mut(), &reply, delimiter)
+    }
+
+    fn execute<R: Read>(
+        &self,
+        command: &str,
+        stream: &mut R,
+        peer: &str,
+        session_id: Option<u64>,
+    ) -> io::Result<String> {
+        let started = Instant::now();
+        let mut bytes = 0;
+        let reply = match command {
+            "PING" => forward(self.upstream.ping()),
+            "VERSION" => forward(self.upstream.get_version()),
+            "STATS" => forward(self.upstream.get_stats()),
+            "INSTREAM" => match read_instream(stream, self.max_stream_length)? {
+                Some(data) => {
+                    bytes = data.len() as u64;
+                    forward(self.upstream.scan_buffer(&data, None))
+                }
+                None => String::from(SIZE_LIMIT_EXCEEDED),
+            },
+            _ => String::from("UNKNOWN COMMAND"),
+        };
+
+        if let Some(access_log) = &self.access_log {
+            access_log.log(&AccessEntry {
+                peer: peer.to_string(),
+                command: command.to_string(),
+                session_id,
+                bytes,
+                reply: reply.clone(),
+                duration: started.elapsed(),
+            });
+        }
+        Ok(reply)
+    }
+}
+
+/// Turns an upstream response into a reply without delimiter
+fn forward(response: IoResult) -> String {
+    match response {
+        Ok(response) => {
+            let response = String::from_utf8_lossy(&response);
+            response.trim_end_matches(['\0', '\n']).to_string()
+        }
+        Err(err) => format!("{err} ERROR"),
+    }
+}
+
+/// Client connection whose read and write timeouts can be set
+trait Connection: Read + Write {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.filter(|timeout| !timeout.is_zero());
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.filter(|timeout| !timeout.is_zero());
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+/// Counts a connection as served until it is dropped
+struct ActiveConnection(Arc<AtomicUsize>);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Listener {
+    stopped: Arc<AtomicBool>,
+    wake: Box<dyn Fn() + Send + Sync>,
+    handle: JoinHandle<()>,
+}
+
+/// Running proxy listener
+///
+/// The listener is stopped when this handle is dropped; connections that
+/// were already accepted are served until the client closes them.
+pub struct ProxyServer<T> {
+    transport: T,
+    listener: Option<Listener>,
+    #[cfg(unix)]
+    cleanup: Option<PathBuf>,
+}
+
+impl<T> ProxyServer<T> {
+    fn new(transport: T, listener: Listener) -> Self {
+        ProxyServer {
+            transport,
+            listener: Some(listener),
+            #[cfg(unix)]
+            cleanup: None,
+        }
+    }
+
+    /// Returns a transport connected to this proxy
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Blocks the current thread and serves connections until the process exits
+    pub fn wait(mut self) {
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.handle.join();
+        }
+    }
+
+    /// Stops accepting connections and waits for the listener thread to exit
+    pub fn stop(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            listener.stopped.store(true, Ordering::SeqCst);
+            (listener.wake)();
+            let _ = listener.handle.join();
+        }
+        #[cfg(unix)]
+        if let Some(path) = self.cleanup.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl<T> Drop for ProxyServer<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ProxyServer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyServer")
+            .field("transport", &self.transport)
+            .field("running", &self.listener.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+#[cfg(unix)]
+use crate::Socket;
+use crate::{blocking, ClamAvSync, IoResult, Tcp, Timeouts};
+
+/// How a [`Pool`] picks the backend for a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Use the backends in turn
+    #[default]
+    RoundRobin,
+    /// Use the backend with the fewest open connections, in turn if several
+    /// have the same number
+    LeastConnections,
+}
+
+struct Member<T> {
+    backend: T,
+    active: Arc<AtomicU64>,
+    down_until: Mutex<Option<Instant>>,
+    #[cfg(feature = "metrics")]
+    gauge: Option<Gauge>,
+}
+
+/// Registry and endpoint label that a backend's connections are reported to
+#[cfg(feature = "metrics")]
+#[derive(Clone, Debug)]
+struct Gauge {
+    metrics: Metrics,
+    endpoint: Arc<str>,
+}
+
+impl<T> Member<T> {
+    fn is_down(&self, now: Instant) -> bool {
+        let down_until = self
+            .down_until
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        down_until.is_some_and(|until| now < until)
+    }
+
+    fn set_down_until(&self, until: Option<Instant>) {
+        *self
+            .down_until
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = until;
+    }
+}
+
+/// Transport that spreads requests over several ClamAV servers
+///
+/// A backend that cannot be reached or times out is skipped for the
+/// [cooldown](Pool::cooldown) period and the request is retried on the next
+/// one. If all backends are cooling down, they are tried anyway.
+///
+/// Failed buffer scans are retried on another backend, because the data can
+/// be sent again, unless the backend already started answering. Files and
+/// readers are only retried if connecting failed.
+pub struct Pool<T> {
+    members: Vec<Member<T>>,
+    strategy: Strategy,
+    cooldown: Duration,
+    timeouts: Timeouts,
+    next: AtomicUsize,
+}
+
+impl<T: fmt::Debug> fmt::Debug for Pool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field(
+                "backends",
+                &self.members.iter().map(|m| &m.backend).collect::<Vec<_>>(),
+            )
+            .field("strategy", &self.strategy)
+            .field("cooldown", &self.cooldown)
+            .field("timeouts", &self.timeouts)
+            .finish()
+    }
+}
+
+impl<T> Pool<T> {
+    /// Creates a round-robin pool with a cooldown of 10 seconds
+    pub fn new(backends: Vec<T>) -> Self {
+        Pool {
+            members: backends
+                .into_iter()
+                .map(|backend| Member {
+                    backend,
+                    active: Arc::new(AtomicU64::new(0)),
+                    down_until: Mutex::new(None),
+                    #[cfg(feature = "metrics")]
+                    gauge: None,
+                })
+                .collect(),
+            strategy: Strategy::default(),
+            cooldown: Duration::from_secs(10),
+            timeouts: Timeouts::default(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the strategy for picking backends
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets how long a backend that could not be reached or timed out is
+    /// skipped
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Sets the timeouts applied to connections with the backends
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Reports the open connections of each backend as active connections
+    /// to `metrics`, labelled with the backend's address
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, metrics: Metrics) -> Self
+    where
+        T: fmt::Display,
+    {
+        for member in &mut self.members {
+            member.gauge = Some(Gauge {
+                metrics: metrics.clone(),
+                endpoint: member.backend.to_string().into(),
+            });
+        }
+        self
+    }
+
+    /// Returns the backends
+    pub fn backends(&self) -> impl Iterator<Item = &T> {
+        self.members.iter().map(|member| &member.backend)
+    }
+
+    /// Returns the number of open connections to each backend
+    pub fn active_connections(&self) -> Vec<u64> {
+        let active = self.members.iter().map(|m| m.active.load(Ordering::SeqCst));
+        active.collect()
+    }
+
+    /// Returns whether each backend is currently used, i.e. not cooling down
+    pub fn available(&self) -> Vec<bool> {
+        let now = Instant::now();
+        self.members.iter().map(|m| !m.is_down(now)).collect()
+    }
+
+    /// Returns the indices of the backends in the order they should be tried
+    fn candidates(&self) -> Vec<usize> {
+        let len = self.members.len();
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % len.max(1);
+        let mut order: Vec<usize> = (0..len).map(|i| (start + i) % len).collect();
+        if self.strategy == Strategy::LeastConnections {
+            order.sort_by_key(|&i| self.members[i].active.load(Ordering::SeqCst));
+        }
+        order.sort_by_key(|&i| self.members[i].is_down(now));
+        order
+    }
+
+    /// Runs `f` on the candidate backends until it succeeds or fails in a
+    /// way that must not be retried
+    fn failover<R>(&self, mut f: impl FnMut(&Member<T>) -> Result<R, Failure>) -> io::Result<R> {
+        let mut last_error = None;
+        for i in self.candidates() {
+            let member = &self.members[i];
+            match f(member) {
+                Ok(result) => {
+                    member.set_down_until(None);
+                    return Ok(result);
+                }
+                Err(failure) => {
+                    if failure.unavailable {
+                        member.set_down_until(Some(Instant::now() + self.cooldown));
+                    }
+                    if !failure.retry {
+                        return Err(failure.error);
+                    }
+                    last_error = Some(failure.error);
+                }
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "pool has no backends")))
+    }
+}
+
+/// Error of a request on a single backend
+struct Failure {
+    error: io::Error,
+    /// Whether the backend is skipped for the cooldown period
+    unavailable: bool,
+    /// Whether the request may be sent to the next backend
+    retry: bool,
+}
+
+impl Failure {
+    /// The backend could not be reached
+    fn connect(error: io::Error) -> Self {
+        Failure {
+            error,
+            unavailable: true,
+            retry: true,
+        }
+    }
+
+    /// The request failed on an open connection, the backend is only
+    /// considered unavailable if it timed out
+    fn request(error: io::Error, answered: bool) -> Self {
+        Failure {
+            unavailable: matches!(
+                error.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ),
+            // The backend may already have acted on the request
+            retry: !answered,
+            error,
+        }
+    }
+}
+
+impl<T: ClamAvSync> Pool<T> {
+    fn connect_member(
+        member: &Member<T>,
+        timeouts: &Timeouts,
+    ) -> io::Result<PoolStream<T::Stream>> {
+        let inner = member.backend.connect_with_timeouts(timeouts)?;
+        member.active.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "metrics")]
+        if let Some(gauge) = &member.gauge {
+            gauge.metrics.connection_started(&gauge.endpoint);
+        }
+        Ok(PoolStream {
+            inner,
+            answered: false,
+            active: member.active.clone(),
+            #[cfg(feature = "metrics")]
+            gauge: member.gauge.clone(),
+        })
+    }
+}
+
+impl<T: ClamAvSync> ClamAvSync for Pool<T> {
+    type Stream = PoolStream<T::Stream>;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        self.connect_with_timeouts(&self.timeouts)
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        self.failover(|member| Self::connect_member(member, timeouts).map_err(Failure::connect))
+    }
+
+    fn scan_buffer(&self, buffer: &[u8], chunk_size: Option<usize>) -> IoResult {
+        self.failover(|member| {
+            let mut stream =
+                Self::connect_member(member, &self.timeouts).map_err(Failure::connect)?;
+            blocking::scan(buffer, chunk_size, &mut stream)
+                .map_err(|err| Failure::request(err, stream.answered))
+        })
+    }
+}
+
+/// Connection to a backend of a [`Pool`], counted while it is open
+#[derive(Debug)]
+pub struct PoolStream<S> {
+    inner: S,
+    /// Whether the backend sent any data
+    answered: bool,
+    active: Arc<AtomicU64>,
+    #[cfg(feature = "metrics")]
+    gauge: Option<Gauge>,
+}
+
+impl<S> Drop for PoolStream<S> {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        #[cfg(feature = "metrics")]
+        if let Some(gauge) = &self.gauge {
+            gauge.metrics.connection_finished(&gauge.endpoint);
+        }
+    }
+}
+
+impl<S: Read> Read for PoolStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.answered |= read > 0;
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for PoolStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A ClamAV server reachable over TCP or a Unix socket
+///
+/// Backends are parsed from `HOST:PORT` or `unix:PATH`, so pools can mix
+/// both kinds of servers.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// A TCP server
+    Tcp(Tcp),
+    /// A Unix socket
+    #[cfg(unix)]
+    Socket(Socket),
+}
+
+impl FromStr for Backend {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Backend::Socket(Socket(path.into())));
+        }
+        let address = s.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("no address for {s}"))
+        })?;
+        Ok(Backend::Tcp(Tcp(address)))
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Tcp(tcp) => write!(f, "{}", tcp.0),
+            #[cfg(unix)]
+            Backend::Socket(socket) => write!(f, "unix:{}", socket.0.display()),
+        }
+    }
+}
+
+impl ClamAvSync for Backend {
+    type Stream = BackendStream;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        match self {
+            Backend::Tcp(tcp) => tcp.connect().map(BackendStream::Tcp),
+            #[cfg(unix)]
+            Backend::Socket(socket) => socket.connect().map(BackendStream::Unix),
+        }
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        match self {
+            Backend::Tcp(tcp) => tcp.connect_with_timeouts(timeouts).map(BackendStream::Tcp),
+            #[cfg(unix)]
+            Backend::Socket(socket) => socket
+                .connect_with_timeouts(timeouts)
+                .map(BackendStream::Unix),
+        }
+    }
+}
+
+/// Connection to a [`Backend`]
+#[derive(Debug)]
+pub enum BackendStream {
+    /// A TCP connection
+    Tcp(TcpStream),
+    /// A Unix socket connection
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for BackendStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BackendStream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            BackendStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for BackendStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BackendStream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            BackendStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BackendStream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            BackendStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
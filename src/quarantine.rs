@@ -0,0 +1,500 @@
+//! Moving infected files into a quarantine directory
+//!
+//! A [`Quarantine`] stores every quarantined file as two files named after
+//! the entry ID: `<id>.bin` with the (optionally obfuscated) content and
+//! `<id>.json`, a metadata sidecar with the original path, permissions,
+//! matched signatures, timestamp, size and SHA-256 digest of the original
+//! content. Entries can be listed, restored to their original location and
+//! purged.
+//!
+//! Quarantining is atomic in the sense that an entry is either complete or
+//! absent: content and sidecar are written to temporary files and renamed
+//! into place, and the original file is only removed afterwards. If it
+//! cannot be removed, the entry is rolled back.
+//!
+//! ```no_run
+//! use clamav_client::quarantine::Quarantine;
+//! use clamav_client::{ClamAvSync, Verdict};
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! let quarantine = Quarantine::open("/var/lib/clamav-quarantine").unwrap();
+//!
+//! let response = clamd_tcp.scan_file("upload.bin", None).unwrap();
+//! if let Verdict::Infected(signatures) = Verdict::parse(&response).unwrap() {
+//!     let entry = quarantine.quarantine("upload.bin", &signatures).unwrap();
+//!     println!("quarantined as {}", entry.id);
+//! }
+//!
+//! for entry in quarantine.list().unwrap() {
+//!     println!("{}: {} {:?}", entry.id, entry.original_path.display(), entry.signatures);
+//! }
+//! ```
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::digest::hex;
+
+/// Key used by [`Obfuscation::default`]
+const DEFAULT_XOR_KEY: &[u8] = b"clamav-client-quarantine";
+
+/// How the content of quarantined files is stored
+///
+/// Obfuscation prevents quarantined files from being executed or picked up
+/// by other scanners, it is not meant to keep the content secret: the key is
+/// stored in the sidecar.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Obfuscation {
+    /// Store the content unchanged
+    None,
+    /// XOR the content with a repeating key
+    Xor(Vec<u8>),
+}
+
+impl Default for Obfuscation {
+    fn default() -> Self {
+        Obfuscation::Xor(DEFAULT_XOR_KEY.to_vec())
+    }
+}
+
+impl Obfuscation {
+    /// Applies the obfuscation to a chunk that starts at `offset` in the
+    /// content, applying it twice restores the original chunk
+    fn apply(&self, offset: u64, chunk: &mut [u8]) {
+        if let Obfuscation::Xor(key) = self {
+            if key.is_empty() {
+                return;
+            }
+            let start = (offset % key.len() as u64) as usize;
+            for (byte, k) in chunk.iter_mut().zip(key.iter().cycle().skip(start)) {
+                *byte ^= k;
+            }
+        }
+    }
+}
+
+/// Metadata of a quarantined file, stored in the sidecar
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantineEntry {
+    /// ID of the entry within the quarantine directory
+    pub id: String,
+    /// Absolute path the file was quarantined from
+    pub original_path: PathBuf,
+    /// Unix permission bits of the original file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Whether the original file was read-only
+    #[serde(default)]
+    pub readonly: bool,
+    /// Names of the signatures that matched
+    #[serde(default)]
+    pub signatures: Vec<String>,
+    /// Time the file was quarantined
+    #[serde(rename = "quarantined_at_ms", with = "unix_ms")]
+    pub quarantined_at: SystemTime,
+    /// Size of the original file in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 digest of the original content
+    pub sha256: String,
+    /// How the content is stored
+    pub obfuscation: Obfuscation,
+}
+
+/// Quarantine directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quarantine {
+    dir: PathBuf,
+    obfuscation: Obfuscation,
+}
+
+impl Quarantine {
+    /// Opens a quarantine directory, creating it if it does not exist
+    ///
+    /// New directories are only accessible by the owner on Unix. Files are
+    /// obfuscated with [`Obfuscation::default`].
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the [`Quarantine`], or an error if the
+    /// directory cannot be created
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        if !dir.is_dir() {
+            let mut builder = fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder.create(dir)?;
+        }
+        Ok(Quarantine {
+            dir: fs::canonicalize(dir)?,
+            obfuscation: Obfuscation::default(),
+        })
+    }
+
+    /// Sets how the content of newly quarantined files is stored
+    ///
+    /// Existing entries keep the obfuscation recorded in their sidecar.
+    pub fn obfuscation(mut self, obfuscation: Obfuscation) -> Self {
+        self.obfuscation = obfuscation;
+        self
+    }
+
+    /// Returns the quarantine directory
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves a file into the quarantine
+    ///
+    /// The content and the sidecar are written to temporary files that only
+    /// the owner can access on Unix, synced and renamed into place before the
+    /// original file is removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path of the file
+    /// * `signatures`: Names of the signatures that matched, e.g. from
+    ///   [`Verdict::Infected`](crate::Verdict::Infected)
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the new [`QuarantineEntry`]
+    pub fn quarantine<P: AsRef<Path>>(
+        &self,
+        path: P,
+        signatures: &[String],
+    ) -> io::Result<QuarantineEntry> {
+        let original_path = fs::canonicalize(path)?;
+        let mut source = File::open(&original_path)?;
+        let metadata = source.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only regular files can be quarantined",
+            ));
+        }
+
+        let (id, part, file) = self.reserve()?;
+        let result = (|| {
+            let (size, sha256) = copy_obfuscated(&mut source, file, &self.obfuscation, false)?;
+            let entry = QuarantineEntry {
+                id: id.clone(),
+                original_path: original_path.clone(),
+                mode: mode(&metadata),
+                readonly: metadata.permissions().readonly(),
+                signatures: signatures.to_vec(),
+                quarantined_at: now_ms(),
+                size,
+                sha256,
+                obfuscation: self.obfuscation.clone(),
+            };
+            fs::rename(&part, self.content_path(&id))?;
+            self.write_sidecar(&entry)?;
+            drop(source);
+            fs::remove_file(&original_path)?;
+            Ok(entry)
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&part);
+            let _ = fs::remove_file(self.content_path(&id));
+            let _ = fs::remove_file(self.sidecar_path(&id));
+        }
+        result
+    }
+
+    /// Returns all entries, oldest first
+    ///
+    /// Sidecars that cannot be read are skipped.
+    pub fn list(&self) -> io::Result<Vec<QuarantineEntry>> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                if let Ok(entry) = read_sidecar(&path) {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by(|a, b| (a.quarantined_at, &a.id).cmp(&(b.quarantined_at, &b.id)));
+        Ok(entries)
+    }
+
+    /// Returns the entry with the given ID
+    pub fn get(&self, id: &str) -> io::Result<QuarantineEntry> {
+        read_sidecar(&self.sidecar_path(check_id(id)?))
+    }
+
+    /// Restores a file to its original path and removes the entry
+    ///
+    /// Fails with [`io::ErrorKind::AlreadyExists`] if a file exists at the
+    /// original path.
+    pub fn restore(&self, id: &str) -> io::Result<PathBuf> {
+        let entry = self.get(id)?;
+        let target = entry.original_path.clone();
+        self.restore_entry(&entry, target)
+    }
+
+    /// Restores a file to another path and removes the entry
+    ///
+    /// Fails with [`io::ErrorKind::AlreadyExists`] if a file exists at the
+    /// target path.
+    pub fn restore_to<P: AsRef<Path>>(&self, id: &str, target: P) -> io::Result<PathBuf> {
+        let entry = self.get(id)?;
+        self.restore_entry(&entry, target.as_ref().to_path_buf())
+    }
+
+    /// Deletes an entry
+    pub fn purge(&self, id: &str) -> io::Result<()> {
+        let id = check_id(id)?;
+        remove_if_exists(&self.content_path(id))?;
+        fs::remove_file(self.sidecar_path(id))
+    }
+
+    /// Deletes all entries that were quarantined more than `age` ago
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the number of deleted entries
+    pub fn purge_older_than(&self, age: Duration) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut purged = 0;
+        for entry in self.list()? {
+            let elapsed = now.duration_since(entry.quarantined_at).unwrap_or_default();
+            if elapsed >= age {
+                self.purge(&entry.id)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    fn content_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.bin"))
+    }
+
+    fn sidecar_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    /// Creates an empty temporary file with a new unique ID
+    fn reserve(&self) -> io::Result<(String, PathBuf, File)> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        loop {
+            let id = format!(
+                "{}-{:09}-{}-{}",
+                now.as_secs(),
+                now.subsec_nanos(),
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let part = self.dir.join(format!("{id}.part"));
+            match private_file().create_new(true).open(&part) {
+                Ok(file) => return Ok((id, part, file)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn write_sidecar(&self, entry: &QuarantineEntry) -> io::Result<()> {
+        let part = self.dir.join(format!("{}.json.part", entry.id));
+        let file = private_file().create(true).truncate(true).open(&part)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, entry)?;
+        writer.write_all(b"\n")?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&part, self.sidecar_path(&entry.id))
+    }
+
+    fn restore_entry(&self, entry: &QuarantineEntry, target: PathBuf) -> io::Result<PathBuf> {
+        let already_exists = || {
+            io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", target.display()),
+            )
+        };
+        if target.symlink_metadata().is_ok() {
+            return Err(already_exists());
+        }
+        let parent = target
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)?;
+
+        let name = target
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+        let mut part_name = name.to_os_string();
+        part_name.push(format!(".{}.part", entry.id));
+        let part = parent.join(part_name);
+        let mut content = File::open(self.content_path(&entry.id))?;
+        // Never follow or reuse a file planted under the temporary name
+        let file = private_file().create_new(true).open(&part)?;
+
+        let result = (|| {
+            let (_, sha256) = copy_obfuscated(&mut content, file, &entry.obfuscation, true)?;
+            if sha256 != entry.sha256 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("SHA-256 mismatch for quarantine entry {}", entry.id),
+                ));
+            }
+            set_permissions(&part, entry)?;
+            // Unlike a rename, linking fails if the target was created since
+            // the check above
+            fs::hard_link(&part, &target).map_err(|err| match err.kind() {
+                io::ErrorKind::AlreadyExists => already_exists(),
+                _ => err,
+            })
+        })();
+        let _ = fs::remove_file(&part);
+        result?;
+
+        self.purge(&entry.id)?;
+        Ok(target)
+    }
+}
+
+/// Copies a file while applying an obfuscation
+///
+/// # Arguments
+///
+/// * `source`: The file to copy
+/// * `target`: The new file, synced to disk after copying
+/// * `obfuscation`: The obfuscation to apply
+/// * `restoring`: `true` if the source is obfuscated, `false` if the target
+///   should be
+///
+/// # Returns
+///
+/// The size and hex-encoded SHA-256 digest of the unobfuscated content
+fn copy_obfuscated(
+    source: &mut File,
+    target: File,
+    obfuscation: &Obfuscation,
+    restoring: bool,
+) -> io::Result<(u64, String)> {
+    let mut reader = BufReader::new(source);
+    let mut writer = BufWriter::new(target);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut offset = 0;
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let chunk = &mut buffer[..len];
+        if restoring {
+            obfuscation.apply(offset, chunk);
+            hasher.update(&*chunk);
+        } else {
+            hasher.update(&*chunk);
+            obfuscation.apply(offset, chunk);
+        }
+        writer.write_all(chunk)?;
+        offset += len as u64;
+    }
+    writer.into_inner()?.sync_all()?;
+    Ok((offset, hex(&hasher.finalize())))
+}
+
+/// Returns options for writing a file that is created accessible only by the
+/// owner on Unix, before it is renamed into place
+fn private_file() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+/// Returns the current time truncated to the precision stored in sidecars
+fn now_ms() -> SystemTime {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    UNIX_EPOCH + Duration::from_millis(since_epoch.as_millis() as u64)
+}
+
+fn read_sidecar(path: &Path) -> io::Result<QuarantineEntry> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Rejects IDs that could refer to files outside the quarantine directory
+fn check_id(id: &str) -> io::Result<&str> {
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid quarantine ID: {id}"),
+        ));
+    }
+    Ok(id)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+fn set_permissions(path: &Path, entry: &QuarantineEntry) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    #[cfg(unix)]
+    if let Some(mode) = entry.mode {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(mode);
+        return fs::set_permissions(path, permissions);
+    }
+    permissions.set_readonly(entry.readonly);
+    fs::set_permissions(path, permissions)
+}
+
+mod unix_ms {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        serializer.serialize_u64(since_epoch.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let millis = u64::deserialize(deserializer)?;
+        Ok(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+}
@@ -0,0 +1,398 @@
+//! Serializable scan results
+//!
+//! A [`ScanRecord`] describes the outcome of scanning a single file or
+//! stream and is built from a parsed [`Verdict`]. Records are collected in a
+//! [`ScanReport`], which can be written as JSON, newline-delimited JSON or
+//! [SARIF](https://sarifweb.azurewebsites.net/) for consumption by CI systems.
+//!
+//! ```
+//! use std::time::Duration;
+//! use clamav_client::report::{ReportFormat, ScanRecord, ScanReport};
+//! use clamav_client::Verdict;
+//!
+//! let verdict = Verdict::parse(b"stream: Eicar-Signature FOUND\0").unwrap();
+//! let record = ScanRecord::from_verdict("eicar.txt", verdict)
+//!     .with_size(68)
+//!     .with_backend("tcp://127.0.0.1:3310");
+//!
+//! let mut report = ScanReport::new();
+//! report.push(record);
+//! report.set_duration(Duration::from_millis(12));
+//! assert_eq!(report.summary().infected, 1);
+//!
+//! let mut sarif = Vec::new();
+//! report.write(ReportFormat::Sarif, &mut sarif).unwrap();
+//! ```
+
+use std::{fmt, io, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::Verdict;
+
+/// Overall status of a scanned file or stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanStatus {
+    /// No virus was found
+    Clean,
+    /// One or more signatures matched
+    Infected,
+    /// The data could not be scanned
+    Error,
+}
+
+/// Result of scanning a single file or stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanRecord {
+    /// Path of the scanned file, or a name such as `stream` for other input
+    pub path: String,
+    /// Number of bytes scanned, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Hex-encoded SHA-256 digest of the scanned data, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Hex-encoded MD5 digest of the scanned data, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    /// Overall status
+    pub status: ScanStatus,
+    /// Names of the matched signatures
+    #[serde(default)]
+    pub signatures: Vec<String>,
+    /// Error message if the status is [`ScanStatus::Error`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time it took to scan the data
+    #[serde(rename = "duration_ms", with = "duration_ms", default)]
+    pub duration: Duration,
+    /// Server that scanned the data, e.g. `tcp://127.0.0.1:3310`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// What was done with the file after scanning, e.g. `Removed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+}
+
+impl ScanRecord {
+    /// Creates a record from a parsed [`Verdict`]
+    pub fn from_verdict(path: impl Into<String>, verdict: Verdict) -> Self {
+        let (status, signatures, error) = match verdict {
+            Verdict::Clean => (ScanStatus::Clean, Vec::new(), None),
+            Verdict::Infected(signatures) => (ScanStatus::Infected, signatures, None),
+            Verdict::Error(message) => (ScanStatus::Error, Vec::new(), Some(message)),
+        };
+        ScanRecord {
+            path: path.into(),
+            size: None,
+            sha256: None,
+            md5: None,
+            status,
+            signatures,
+            error,
+            duration: Duration::ZERO,
+            backend: None,
+            action: None,
+        }
+    }
+
+    /// Creates a record for data that could not be scanned
+    pub fn from_error(path: impl Into<String>, error: impl fmt::Display) -> Self {
+        Self::from_verdict(path, Verdict::Error(error.to_string()))
+    }
+
+    /// Creates a record from the result of parsing a scan response
+    pub fn from_result(path: impl Into<String>, result: io::Result<Verdict>) -> Self {
+        match result {
+            Ok(verdict) => Self::from_verdict(path, verdict),
+            Err(err) => Self::from_error(path, err),
+        }
+    }
+
+    /// Sets the number of bytes scanned
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Sets the hex-encoded SHA-256 digest of the scanned data
+    pub fn with_sha256(mut self, sha256: impl Into<String>) -> Self {
+        self.sha256 = Some(sha256.into());
+        self
+    }
+
+    /// Sets the hex-encoded MD5 digest of the scanned data
+    pub fn with_md5(mut self, md5: impl Into<String>) -> Self {
+        self.md5 = Some(md5.into());
+        self
+    }
+
+    /// Sets the size and the digests computed while scanning
+    #[cfg(feature = "hash")]
+    pub fn with_digest(mut self, digest: &crate::hash::ScanDigest) -> Self {
+        self.size = Some(digest.bytes);
+        self.sha256 = digest.sha256_hex().or(self.sha256);
+        self.md5 = digest.md5_hex().or(self.md5);
+        self
+    }
+
+    /// Sets the time it took to scan the data
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Sets the server that scanned the data
+    pub fn with_backend(mut self, backend: impl Into<String>) -> Self {
+        self.backend = Some(backend.into());
+        self
+    }
+
+    /// Sets what was done with the file after scanning
+    pub fn with_action(mut self, action: impl Into<String>) -> Self {
+        self.action = Some(action.into());
+        self
+    }
+
+    /// Writes the record as a single line of JSON
+    ///
+    /// Calling this for every record as soon as it is available produces
+    /// newline-delimited JSON without buffering the whole report.
+    pub fn write_ndjson<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        serde_json::to_writer(&mut writer, self)?;
+        writer.write_all(b"\n")
+    }
+}
+
+/// Counts and total duration of a [`ScanReport`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportSummary {
+    /// Number of files or streams that were scanned successfully
+    pub scanned: usize,
+    /// Number of files or streams in which a virus was found
+    pub infected: usize,
+    /// Number of files or streams that could not be scanned
+    pub errors: usize,
+    /// Wall-clock time of the whole scan
+    #[serde(rename = "duration_ms", with = "duration_ms", default)]
+    pub duration: Duration,
+}
+
+/// Collection of [`ScanRecord`]s with a [`ReportSummary`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScanReport {
+    records: Vec<ScanRecord>,
+    summary: ReportSummary,
+}
+
+impl ScanReport {
+    /// Creates an empty report
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a record and updates the summary
+    pub fn push(&mut self, record: ScanRecord) {
+        match record.status {
+            ScanStatus::Clean => self.summary.scanned += 1,
+            ScanStatus::Infected => {
+                self.summary.scanned += 1;
+                self.summary.infected += 1;
+            }
+            ScanStatus::Error => self.summary.errors += 1,
+        }
+        self.records.push(record);
+    }
+
+    /// Sets the wall-clock time of the whole scan
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.summary.duration = duration;
+    }
+
+    /// Returns the records in the order they were added
+    pub fn records(&self) -> &[ScanRecord] {
+        &self.records
+    }
+
+    /// Returns the summary
+    pub fn summary(&self) -> &ReportSummary {
+        &self.summary
+    }
+
+    /// Writes the report in the given format
+    pub fn write<W: io::Write>(&self, format: ReportFormat, writer: W) -> io::Result<()> {
+        match format {
+            ReportFormat::Json => self.write_json(writer),
+            ReportFormat::Ndjson => self.write_ndjson(writer),
+            ReportFormat::Sarif => self.write_sarif(writer),
+        }
+    }
+
+    /// Writes the records and the summary as a single JSON document
+    pub fn write_json<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")
+    }
+
+    /// Writes one line of JSON per record, the summary is omitted
+    pub fn write_ndjson<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        for record in &self.records {
+            record.write_ndjson(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the report as a SARIF 2.1.0 log
+    ///
+    /// Every matched signature becomes a result with the signature name as
+    /// rule ID. Records with errors are reported as tool execution
+    /// notifications, which mark the invocation as unsuccessful.
+    pub fn write_sarif<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut writer, &self.to_sarif())?;
+        writer.write_all(b"\n")
+    }
+
+    fn to_sarif(&self) -> Value {
+        let mut rules: Vec<&str> = Vec::new();
+        let mut results = Vec::new();
+        let mut notifications = Vec::new();
+
+        for record in &self.records {
+            let location = json!({
+                "physicalLocation": {
+                    "artifactLocation": { "uri": artifact_uri(&record.path) }
+                }
+            });
+            let mut properties = serde_json::Map::new();
+            if let Some(size) = record.size {
+                properties.insert("size".into(), size.into());
+            }
+            if let Some(sha256) = &record.sha256 {
+                properties.insert("sha256".into(), sha256.as_str().into());
+            }
+            if let Some(md5) = &record.md5 {
+                properties.insert("md5".into(), md5.as_str().into());
+            }
+
+            for signature in &record.signatures {
+                let index = match rules.iter().position(|rule| rule == signature) {
+                    Some(index) => index,
+                    None => {
+                        rules.push(signature);
+                        rules.len() - 1
+                    }
+                };
+                results.push(json!({
+                    "ruleId": signature,
+                    "ruleIndex": index,
+                    "level": "error",
+                    "message": { "text": format!("{}: {signature} FOUND", record.path) },
+                    "locations": [location],
+                    "properties": properties,
+                }));
+            }
+            if let Some(error) = &record.error {
+                notifications.push(json!({
+                    "level": "error",
+                    "message": { "text": format!("{}: {error}", record.path) },
+                    "locations": [location],
+                }));
+            }
+        }
+
+        let rules: Vec<Value> = rules
+            .into_iter()
+            .map(|rule| {
+                json!({
+                    "id": rule,
+                    "shortDescription": { "text": format!("ClamAV signature {rule}") },
+                })
+            })
+            .collect();
+
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                        "rules": rules,
+                    }
+                },
+                "invocations": [{
+                    "executionSuccessful": notifications.is_empty(),
+                    "toolExecutionNotifications": notifications,
+                }],
+                "results": results,
+            }]
+        })
+    }
+}
+
+/// Output format of a [`ScanReport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// A single JSON document with records and summary
+    Json,
+    /// One JSON document per line and record
+    Ndjson,
+    /// SARIF 2.1.0 log
+    Sarif,
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Ndjson => "ndjson",
+            ReportFormat::Sarif => "sarif",
+        })
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ReportFormat::Json),
+            "ndjson" | "jsonl" => Ok(ReportFormat::Ndjson),
+            "sarif" => Ok(ReportFormat::Sarif),
+            _ => Err(format!("unknown report format: {s}")),
+        }
+    }
+}
+
+/// Converts a file path to a relative or absolute URI reference
+fn artifact_uri(path: &str) -> String {
+    let mut uri = String::with_capacity(path.len());
+    for byte in path.replace('\\', "/").bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+mod duration_ms {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let millis = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(millis / 1000.0).map_err(serde::de::Error::custom)
+    }
+}
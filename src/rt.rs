@@ -0,0 +1,354 @@
+//! Runtime support for the async API
+//!
+//! [`ClamAvAsync`](crate::ClamAvAsync) is written against the runtime
+//! agnostic `futures` traits, but opening files, timers and blocking work
+//! depend on a runtime:
+//!
+//! - with the `async` feature, the async-io stack is used, which works with
+//!   any executor,
+//! - with only the `tokio` feature, Tokio is used, so the async API must run
+//!   inside a Tokio runtime with the time driver enabled,
+//! - otherwise files are read and directories are walked on the calling
+//!   thread, and timers wait on a helper thread.
+
+#[cfg(feature = "async")]
+mod imp {
+    use std::{
+        future::Future,
+        io,
+        path::Path,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use futures_lite::Stream;
+
+    pub(crate) type File = async_fs::File;
+
+    pub(crate) async fn open(path: &Path) -> io::Result<File> {
+        File::open(path).await
+    }
+
+    pub(crate) async fn file_len(file: &File) -> io::Result<u64> {
+        Ok(file.metadata().await?.len())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) async fn file_size(path: &Path) -> io::Result<u64> {
+        Ok(async_fs::metadata(path).await?.len())
+    }
+
+    /// Future that completes after a delay
+    #[derive(Debug)]
+    pub(crate) struct Timer(async_io::Timer);
+
+    impl Timer {
+        pub(crate) fn after(duration: Duration) -> Self {
+            Timer(async_io::Timer::after(duration))
+        }
+    }
+
+    impl Future for Timer {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            Pin::new(&mut self.0).poll(cx).map(drop)
+        }
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) async fn unblock<T, F>(f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        blocking::unblock(f).await
+    }
+
+    /// Stream of the items of an iterator that is run on a blocking thread
+    pub(crate) struct Unblock<I>(blocking::Unblock<I>);
+
+    impl<I: Iterator + Send + 'static> Unblock<I>
+    where
+        I::Item: Send + 'static,
+    {
+        pub(crate) fn new(iter: I, capacity: usize) -> Self {
+            Unblock(blocking::Unblock::with_capacity(capacity, iter))
+        }
+    }
+
+    impl<I: Iterator + Send + 'static> Stream for Unblock<I>
+    where
+        I::Item: Send + 'static,
+    {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Pin::new(&mut self.0).poll_next(cx)
+        }
+    }
+}
+
+#[cfg(all(feature = "tokio", not(feature = "async")))]
+mod imp {
+    use std::{
+        future::Future,
+        io,
+        path::Path,
+        pin::Pin,
+        task::{ready, Context, Poll},
+        time::Duration,
+    };
+
+    use ::tokio::{io::AsyncSeek as _, sync::mpsc, task};
+    use futures_lite::{AsyncRead, AsyncSeek, Stream};
+
+    use crate::tokio::Compat;
+
+    /// Tokio file that implements the `futures` I/O traits
+    #[derive(Debug)]
+    pub(crate) struct File {
+        inner: Compat<::tokio::fs::File>,
+        seeking: bool,
+    }
+
+    pub(crate) async fn open(path: &Path) -> io::Result<File> {
+        let file = ::tokio::fs::File::open(path).await?;
+        Ok(File {
+            inner: Compat(file),
+            seeking: false,
+        })
+    }
+
+    pub(crate) async fn file_len(file: &File) -> io::Result<u64> {
+        Ok(file.inner.0.metadata().await?.len())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) async fn file_size(path: &Path) -> io::Result<u64> {
+        Ok(::tokio::fs::metadata(path).await?.len())
+    }
+
+    impl AsyncRead for File {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncSeek for File {
+        fn poll_seek(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            pos: io::SeekFrom,
+        ) -> Poll<io::Result<u64>> {
+            let this = &mut *self;
+            // Tokio starts a seek and polls for its completion separately
+            if !this.seeking {
+                Pin::new(&mut this.inner.0).start_seek(pos)?;
+                this.seeking = true;
+            }
+            let result = ready!(Pin::new(&mut this.inner.0).poll_complete(cx));
+            this.seeking = false;
+            Poll::Ready(result)
+        }
+    }
+
+    /// Future that completes after a delay
+    #[derive(Debug)]
+    pub(crate) struct Timer(Pin<Box<::tokio::time::Sleep>>);
+
+    impl Timer {
+        pub(crate) fn after(duration: Duration) -> Self {
+            Timer(Box::pin(::tokio::time::sleep(duration)))
+        }
+    }
+
+    impl Future for Timer {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            self.0.as_mut().poll(cx)
+        }
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) async fn unblock<T, F>(f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        match task::spawn_blocking(f).await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// Stream of the items of an iterator that is run on a blocking thread
+    pub(crate) enum Unblock<I: Iterator> {
+        /// The iterator is started on the first poll, inside the runtime
+        Idle(I, usize),
+        Running(mpsc::Receiver<I::Item>),
+    }
+
+    // The iterator is moved to the blocking thread, it is never pinned
+    impl<I: Iterator> Unpin for Unblock<I> {}
+
+    impl<I: Iterator + Send + 'static> Unblock<I>
+    where
+        I::Item: Send + 'static,
+    {
+        pub(crate) fn new(iter: I, capacity: usize) -> Self {
+            Unblock::Idle(iter, capacity)
+        }
+    }
+
+    impl<I: Iterator + Send + 'static> Stream for Unblock<I>
+    where
+        I::Item: Send + 'static,
+    {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            let this = &mut *self;
+            if let Unblock::Idle(_, capacity) = *this {
+                let (sender, receiver) = mpsc::channel(capacity.max(1));
+                if let Unblock::Idle(iter, _) = std::mem::replace(this, Unblock::Running(receiver))
+                {
+                    task::spawn_blocking(move || {
+                        for item in iter {
+                            if sender.blocking_send(item).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+            }
+            match this {
+                Unblock::Running(receiver) => receiver.poll_recv(cx),
+                Unblock::Idle(..) => Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(not(any(feature = "async", feature = "tokio")))]
+mod imp {
+    use std::{
+        fmt,
+        future::Future,
+        io,
+        path::Path,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use futures_lite::{io::AssertAsync, Stream};
+
+    pub(crate) type File = AssertAsync<std::fs::File>;
+
+    pub(crate) async fn open(path: &Path) -> io::Result<File> {
+        std::fs::File::open(path).map(AssertAsync::new)
+    }
+
+    pub(crate) async fn file_len(file: &File) -> io::Result<u64> {
+        Ok(file.get_ref().metadata()?.len())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) async fn file_size(path: &Path) -> io::Result<u64> {
+        Ok(std::fs::metadata(path)?.len())
+    }
+
+    /// Future that completes after a delay, a helper thread wakes it
+    pub(crate) struct Timer {
+        deadline: Instant,
+        waker: Option<Arc<Mutex<Waker>>>,
+    }
+
+    impl fmt::Debug for Timer {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Timer")
+                .field("deadline", &self.deadline)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Timer {
+        pub(crate) fn after(duration: Duration) -> Self {
+            Timer {
+                deadline: Instant::now() + duration,
+                waker: None,
+            }
+        }
+    }
+
+    impl Future for Timer {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let now = Instant::now();
+            if now >= self.deadline {
+                return Poll::Ready(());
+            }
+            match &self.waker {
+                Some(waker) => waker
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .clone_from(cx.waker()),
+                None => {
+                    let waker = Arc::new(Mutex::new(cx.waker().clone()));
+                    let delay = self.deadline - now;
+                    let shared = waker.clone();
+                    thread::spawn(move || {
+                        thread::sleep(delay);
+                        shared
+                            .lock()
+                            .unwrap_or_else(|err| err.into_inner())
+                            .wake_by_ref();
+                    });
+                    self.waker = Some(waker);
+                }
+            }
+            Poll::Pending
+        }
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) async fn unblock<T, F>(f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        f()
+    }
+
+    /// Stream of the items of an iterator that is run on the calling thread
+    pub(crate) struct Unblock<I>(I);
+
+    impl<I: Iterator + Send + 'static> Unblock<I>
+    where
+        I::Item: Send + 'static,
+    {
+        pub(crate) fn new(iter: I, _capacity: usize) -> Self {
+            Unblock(iter)
+        }
+    }
+
+    impl<I: Iterator + Unpin> Stream for Unblock<I> {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+}
+
+pub(crate) use imp::*;
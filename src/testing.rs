@@ -0,0 +1,341 @@
+//! Utilities for testing code that uses this crate without a ClamAV server
+//!
+//! [`MockClamd`] speaks the parts of the clamd protocol that this crate uses
+//! (`PING`, `VERSION`, `INSTREAM`, `IDSESSION`, `STATS` and `SHUTDOWN`) over
+//! TCP or a Unix socket. It runs on background threads, so it can be used
+//! with the synchronous client and with any async runtime.
+//!
+//! ```
+//! use clamav_client::{testing::MockClamd, ClamAvSync};
+//!
+//! let server = MockClamd::new()
+//!     .signature("Test-Signature", b"malicious")
+//!     .bind_tcp("127.0.0.1:0")
+//!     .unwrap();
+//!
+//! let response = server.transport().scan_buffer(b"some malicious data", None).unwrap();
+//! assert_eq!(&response, b"stream: Test-Signature FOUND\0");
+//! ```
+//!
+//! [`FaultInjector`] wraps any transport and injects faults like refused
+//! connections, delays or garbage responses to test error handling.
+//!
+//! [`Recorder`] captures the exact bytes exchanged with a real ClamAV server
+//! as a [`Fixture`], which [`Replay`] serves back without a server.
+
+use std::{
+    io::{self, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+};
+
+use crate::{
+    wire::{read_command, read_instream, write_reply, SIZE_LIMIT_EXCEEDED},
+    Tcp,
+};
+
+mod fault;
+pub use fault::{Fault, FaultInjector, FaultPlan, FaultyStream};
+
+mod replay;
+pub use replay::{
+    Direction, Exchange, Fixture, Recorder, RecordingStream, Replay, ReplayStream, Session,
+};
+
+#[cfg(unix)]
+use crate::Socket;
+
+pub use crate::health::EICAR;
+
+/// Default `StreamMaxLength` of clamd (25 MB)
+const DEFAULT_STREAM_MAX_LENGTH: u64 = 25 * 1024 * 1024;
+
+/// Default version reported by the mock server, in the format of clamd 1.4
+pub const DEFAULT_VERSION: &str = "ClamAV 1.4.1/27430/Wed Oct 16 08:37:08 2024";
+
+/// Builder and configuration for a mock ClamAV server
+#[derive(Debug, Clone)]
+pub struct MockClamd {
+    stream_max_length: u64,
+    version: String,
+    signatures: Vec<(String, Vec<u8>)>,
+}
+
+impl Default for MockClamd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClamd {
+    /// Creates a mock server configuration with clamd's defaults
+    ///
+    /// Data starting with the [`EICAR`] test string is reported as
+    /// `Eicar-Signature`, like ClamAV does.
+    pub fn new() -> Self {
+        MockClamd {
+            stream_max_length: DEFAULT_STREAM_MAX_LENGTH,
+            version: String::from(DEFAULT_VERSION),
+            signatures: Vec::new(),
+        }
+    }
+
+    /// Sets the maximum number of bytes accepted by `INSTREAM`
+    pub fn stream_max_length(mut self, stream_max_length: u64) -> Self {
+        self.stream_max_length = stream_max_length;
+        self
+    }
+
+    /// Sets the response to the `VERSION` command
+    pub fn version<S: Into<String>>(mut self, version: S) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Registers a signature that matches data containing `pattern`
+    pub fn signature<S: Into<String>, B: Into<Vec<u8>>>(mut self, name: S, pattern: B) -> Self {
+        self.signatures.push((name.into(), pattern.into()));
+        self
+    }
+
+    /// Starts the server on a TCP address
+    ///
+    /// Use port 0 to let the operating system pick a free port.
+    pub fn bind_tcp<A: ToSocketAddrs>(self, address: A) -> io::Result<MockClamdServer<Tcp>> {
+        let listener = TcpListener::bind(address)?;
+        let transport = Tcp(listener.local_addr()?);
+        let (state, handle) = self.spawn(transport.clone(), move || {
+            listener.accept().map(|(stream, _)| stream)
+        });
+        Ok(MockClamdServer {
+            transport,
+            state,
+            handle: Some(handle),
+        })
+    }
+
+    /// Starts the server on a Unix socket
+    ///
+    /// The socket file is removed when the server is dropped.
+    #[cfg(unix)]
+    pub fn bind_socket<P: AsRef<Path>>(self, path: P) -> io::Result<MockClamdServer<Socket>> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let transport = Socket(path);
+        let (state, handle) = self.spawn(transport.clone(), move || {
+            listener.accept().map(|(stream, _)| stream)
+        });
+        Ok(MockClamdServer {
+            transport,
+            state,
+            handle: Some(handle),
+        })
+    }
+
+    fn spawn<T, S, A>(self, transport: T, mut accept: A) -> (Arc<State>, JoinHandle<()>)
+    where
+        T: MockTransport + Send + Sync + 'static,
+        S: Read + Write + Send + 'static,
+        A: FnMut() -> io::Result<S> + Send + 'static,
+    {
+        let state = Arc::new(State {
+            config: self,
+            stopped: AtomicBool::new(false),
+            connections: AtomicU64::new(0),
+            scans: AtomicU64::new(0),
+            wake: Box::new(move || transport.wake()),
+        });
+        let handle = {
+            let state = state.clone();
+            thread::spawn(move || loop {
+                let stream = accept();
+                if state.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    spawn_connection(stream, state.clone());
+                }
+            })
+        };
+        (state, handle)
+    }
+}
+
+/// Running mock ClamAV server
+///
+/// The server is stopped when this handle is dropped.
+pub struct MockClamdServer<T: MockTransport> {
+    transport: T,
+    state: Arc<State>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<T: MockTransport> MockClamdServer<T> {
+    /// Returns a transport connected to this server
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns the number of connections accepted so far
+    pub fn connections(&self) -> u64 {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of `INSTREAM` scans performed so far
+    pub fn scans(&self) -> u64 {
+        self.state.scans.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` once the server received a `SHUTDOWN` command or was stopped
+    pub fn is_stopped(&self) -> bool {
+        self.state.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Stops the server and waits for the listener thread to exit
+    pub fn stop(&mut self) {
+        self.state.stop();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<T: MockTransport> Drop for MockClamdServer<T> {
+    fn drop(&mut self) {
+        self.stop();
+        self.transport.cleanup();
+    }
+}
+
+/// Transports a [`MockClamdServer`] can listen on, i.e. [`Tcp`] and [`Socket`]
+pub trait MockTransport {
+    /// Unblocks the listener so that it notices the server was stopped
+    fn wake(&self);
+
+    /// Removes resources left behind by the listener
+    fn cleanup(&self) {}
+}
+
+impl MockTransport for Tcp {
+    fn wake(&self) {
+        let _ = TcpStream::connect(self.0);
+    }
+}
+
+#[cfg(unix)]
+impl MockTransport for Socket {
+    fn wake(&self) {
+        let _ = UnixStream::connect(&self.0);
+    }
+
+    fn cleanup(&self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+struct State {
+    config: MockClamd,
+    stopped: AtomicBool,
+    connections: AtomicU64,
+    scans: AtomicU64,
+    wake: Box<dyn Fn() + Send + Sync>,
+}
+
+impl State {
+    fn stop(&self) {
+        if !self.stopped.swap(true, Ordering::SeqCst) {
+            (self.wake)();
+        }
+    }
+}
+
+fn spawn_connection<S: Read + Write + Send + 'static>(stream: S, state: Arc<State>) {
+    state.connections.fetch_add(1, Ordering::SeqCst);
+    thread::spawn(move || {
+        let mut stream = BufReader::new(stream);
+        let _ = handle_connection(&mut stream, &state);
+    });
+}
+
+fn handle_connection<S: Read + Write>(stream: &mut BufReader<S>, state: &State) -> io::Result<()> {
+    let Some((command, delimiter)) = read_command(stream)? else {
+        return Ok(());
+    };
+
+    if command == "IDSESSION" {
+        let mut id = 0;
+        while let Some((command, delimiter)) = read_command(stream)? {
+            if command == "END" || state.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            id += 1;
+            let reply = match execute(&command, stream, state)? {
+                Some(reply) => reply,
+                None => break,
+            };
+            let reply = format!("{id}: {reply}");
+            write_reply(stream.get_mut(), &reply, delimiter)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(reply) = execute(&command, stream, state)? {
+        write_reply(stream.get_mut(), &reply, delimiter)?;
+    }
+    Ok(())
+}
+
+fn execute<S: Read + Write>(
+    command: &str,
+    stream: &mut BufReader<S>,
+    state: &State,
+) -> io::Result<Option<String>> {
+    let reply = match command {
+        "PING" => String::from("PONG"),
+        "VERSION" => state.config.version.clone(),
+        "STATS" => stats(),
+        "INSTREAM" => instream(stream, state)?,
+        "SHUTDOWN" => {
+            state.stop();
+            return Ok(None);
+        }
+        _ => String::from("UNKNOWN COMMAND"),
+    };
+    Ok(Some(reply))
+}
+
+fn instream<R: Read>(stream: &mut R, state: &State) -> io::Result<String> {
+    state.scans.fetch_add(1, Ordering::SeqCst);
+    let Some(data) = read_instream(stream, state.config.stream_max_length)? else {
+        return Ok(String::from(SIZE_LIMIT_EXCEEDED));
+    };
+
+    if data.starts_with(EICAR) {
+        return Ok(String::from("stream: Eicar-Signature FOUND"));
+    }
+    let signature = state.config.signatures.iter().find(|(_, pattern)| {
+        !pattern.is_empty() && data.windows(pattern.len()).any(|window| window == pattern)
+    });
+    Ok(match signature {
+        Some((name, _)) => format!("stream: {name} FOUND"),
+        None => String::from("stream: OK"),
+    })
+}
+
+fn stats() -> String {
+    String::from(
+        "POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 10 idle-timeout 30\n\
+         QUEUE: 0 items\n\tSTATS 0.000000\n\nMEMSTATS: heap N/A mmap N/A used N/A free N/A \
+         releasable N/A pools 1 pools_used 0.000M pools_total 0.000M\nEND",
+    )
+}
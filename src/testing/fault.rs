@@ -0,0 +1,396 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{ClamAvSync, Timeouts};
+
+/// Misbehavior injected into a single connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Connecting fails with [`io::ErrorKind::ConnectionRefused`]
+    ConnectRefused,
+    /// Connecting and every read and write are delayed
+    Delay(Duration),
+    /// Reads return at most the given number of bytes
+    PartialReads(usize),
+    /// The response ends after the given number of bytes
+    TruncateResponse(usize),
+    /// Writes fail with [`io::ErrorKind::ConnectionReset`] once the given
+    /// number of bytes has been sent
+    ResetAfter(usize),
+    /// The response is replaced with the given bytes
+    Garbage(Vec<u8>),
+}
+
+/// Determines which faults are injected into which connection
+#[derive(Debug, Clone)]
+pub enum FaultPlan {
+    /// Faults for consecutive connections, connections beyond the end of the
+    /// script are not affected
+    Script(VecDeque<Vec<Fault>>),
+    /// Each connection is affected with the given probability by one of the
+    /// faults, chosen uniformly at random
+    Random {
+        /// Probability in the range `0.0..=1.0` that a connection is affected
+        probability: f64,
+        /// Faults to choose from
+        faults: Vec<Fault>,
+        /// Seed of the random number generator, for reproducible test runs
+        seed: u64,
+    },
+}
+
+impl FaultPlan {
+    fn next(&mut self) -> Vec<Fault> {
+        match self {
+            FaultPlan::Script(script) => script.pop_front().unwrap_or_default(),
+            FaultPlan::Random {
+                probability,
+                faults,
+                seed,
+            } => {
+                if faults.is_empty() || next_random(seed) >= *probability {
+                    return Vec::new();
+                }
+                let index = (next_random(seed) * faults.len() as f64) as usize;
+                vec![faults[index.min(faults.len() - 1)].clone()]
+            }
+        }
+    }
+}
+
+/// Returns a pseudo-random number in `0.0..1.0` using xorshift64*
+fn next_random(state: &mut u64) -> f64 {
+    if *state == 0 {
+        *state = 0x9E37_79B9_7F4A_7C15;
+    }
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    let value = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Transport wrapper that injects faults into connections
+///
+/// The wrapper implements [`ClamAvSync`] and
+/// [`ClamAvAsync`](crate::ClamAvAsync) if the wrapped transport does, so it
+/// can be used wherever a real transport is expected.
+///
+/// ```
+/// use clamav_client::testing::{Fault, FaultInjector, MockClamd};
+/// use clamav_client::ClamAvSync;
+///
+/// let server = MockClamd::new().bind_tcp("127.0.0.1:0").unwrap();
+/// let transport = FaultInjector::scripted(
+///     server.transport().clone(),
+///     [vec![Fault::ConnectRefused], vec![]],
+/// );
+///
+/// assert!(transport.ping().is_err());
+/// assert_eq!(&transport.ping().unwrap(), clamav_client::PONG);
+/// ```
+#[derive(Debug)]
+pub struct FaultInjector<T> {
+    inner: T,
+    plan: Mutex<FaultPlan>,
+    injected: AtomicU64,
+}
+
+impl<T> FaultInjector<T> {
+    /// Wraps a transport, injecting faults according to `plan`
+    pub fn new(inner: T, plan: FaultPlan) -> Self {
+        FaultInjector {
+            inner,
+            plan: Mutex::new(plan),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    /// Wraps a transport, injecting the given faults into consecutive connections
+    pub fn scripted<I: IntoIterator<Item = Vec<Fault>>>(inner: T, script: I) -> Self {
+        Self::new(inner, FaultPlan::Script(script.into_iter().collect()))
+    }
+
+    /// Wraps a transport, injecting one of `faults` into a connection with the
+    /// given probability
+    pub fn random(inner: T, probability: f64, faults: Vec<Fault>, seed: u64) -> Self {
+        Self::new(
+            inner,
+            FaultPlan::Random {
+                probability,
+                faults,
+                seed,
+            },
+        )
+    }
+
+    /// Returns the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the number of connections that were affected by a fault
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::SeqCst)
+    }
+
+    fn next_faults(&self) -> Faults {
+        let faults = self
+            .plan
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .next();
+        if !faults.is_empty() {
+            self.injected.fetch_add(1, Ordering::SeqCst);
+        }
+        Faults::new(faults)
+    }
+}
+
+/// Faults of a single connection and the state needed to apply them
+#[derive(Debug, Default)]
+struct Faults {
+    refuse: bool,
+    delay: Option<Duration>,
+    partial_reads: Option<usize>,
+    truncate: Option<usize>,
+    reset_after: Option<usize>,
+    garbage: Option<Vec<u8>>,
+    written: usize,
+    read: usize,
+}
+
+impl Faults {
+    fn new(faults: Vec<Fault>) -> Self {
+        let mut state = Faults::default();
+        for fault in faults {
+            match fault {
+                Fault::ConnectRefused => state.refuse = true,
+                Fault::Delay(delay) => state.delay = Some(delay),
+                Fault::PartialReads(len) => state.partial_reads = Some(len.max(1)),
+                Fault::TruncateResponse(len) => state.truncate = Some(len),
+                Fault::ResetAfter(len) => state.reset_after = Some(len),
+                Fault::Garbage(bytes) => state.garbage = Some(bytes),
+            }
+        }
+        state
+    }
+
+    fn check_connect(&self) -> io::Result<()> {
+        if self.refuse {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "injected connection refusal",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns how many of `len` bytes may be written
+    fn write_limit(&self, len: usize) -> io::Result<usize> {
+        match self.reset_after {
+            Some(limit) if self.written >= limit && len > 0 => Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "injected connection reset",
+            )),
+            Some(limit) => Ok(len.min(limit - self.written)),
+            None => Ok(len),
+        }
+    }
+
+    /// Returns how many bytes may be read into a buffer of `len` bytes
+    fn read_limit(&self, len: usize) -> usize {
+        let mut limit = len;
+        if let Some(partial) = self.partial_reads {
+            limit = limit.min(partial);
+        }
+        if let Some(truncate) = self.truncate {
+            limit = limit.min(truncate.saturating_sub(self.read));
+        }
+        limit
+    }
+
+    /// Serves the injected garbage response instead of the real one
+    fn read_garbage(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let garbage = self.garbage.as_ref()?;
+        let limit = self.read_limit(buf.len());
+        let rest = &garbage[self.read.min(garbage.len())..];
+        let len = rest.len().min(limit);
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.read += len;
+        Some(len)
+    }
+}
+
+/// Stream returned by [`FaultInjector`], applying the faults of its connection
+#[derive(Debug)]
+pub struct FaultyStream<S> {
+    inner: S,
+    faults: Faults,
+    #[cfg(feature = "async-core")]
+    timer: Option<crate::rt::Timer>,
+}
+
+impl<S> FaultyStream<S> {
+    fn new(inner: S, faults: Faults) -> Self {
+        FaultyStream {
+            inner,
+            faults,
+            #[cfg(feature = "async-core")]
+            timer: None,
+        }
+    }
+
+    /// Returns the wrapped stream
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for FaultyStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(delay) = self.faults.delay {
+            std::thread::sleep(delay);
+        }
+        if let Some(len) = self.faults.read_garbage(buf) {
+            return Ok(len);
+        }
+        let limit = self.faults.read_limit(buf.len());
+        if limit == 0 && !buf.is_empty() {
+            return Ok(0);
+        }
+        let len = self.inner.read(&mut buf[..limit])?;
+        self.faults.read += len;
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for FaultyStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(delay) = self.faults.delay {
+            std::thread::sleep(delay);
+        }
+        let limit = self.faults.write_limit(buf.len())?;
+        let len = self.inner.write(&buf[..limit])?;
+        self.faults.written += len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: ClamAvSync> ClamAvSync for FaultInjector<T> {
+    type Stream = FaultyStream<T::Stream>;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        self.connect_with_timeouts(&Timeouts::default())
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        let faults = self.next_faults();
+        if let Some(delay) = faults.delay {
+            std::thread::sleep(delay);
+        }
+        faults.check_connect()?;
+        let stream = self.inner.connect_with_timeouts(timeouts)?;
+        Ok(FaultyStream::new(stream, faults))
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_fault {
+    use std::{
+        future::Future,
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use crate::rt::Timer;
+    use futures_lite::{ready, AsyncRead, AsyncWrite};
+
+    use super::{FaultInjector, FaultyStream};
+    use crate::ClamAvAsync;
+
+    impl<S> FaultyStream<S> {
+        /// Waits for the injected delay before an operation
+        fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+            if let Some(delay) = self.faults.delay {
+                let timer = self.timer.get_or_insert_with(|| Timer::after(delay));
+                ready!(Pin::new(timer).poll(cx));
+            }
+            Poll::Ready(())
+        }
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for FaultyStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            ready!(this.poll_delay(cx));
+            if let Some(len) = this.faults.read_garbage(buf) {
+                this.timer = None;
+                return Poll::Ready(Ok(len));
+            }
+            let limit = this.faults.read_limit(buf.len());
+            if limit == 0 && !buf.is_empty() {
+                this.timer = None;
+                return Poll::Ready(Ok(0));
+            }
+            let len = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..limit]))?;
+            this.timer = None;
+            this.faults.read += len;
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for FaultyStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            ready!(this.poll_delay(cx));
+            let limit = this.faults.write_limit(buf.len())?;
+            let len = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..limit]))?;
+            this.timer = None;
+            this.faults.written += len;
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_close(cx)
+        }
+    }
+
+    impl<T: ClamAvAsync> ClamAvAsync for FaultInjector<T> {
+        type Stream = FaultyStream<T::Stream>;
+
+        async fn connect(&self) -> io::Result<Self::Stream> {
+            let faults = self.next_faults();
+            if let Some(delay) = faults.delay {
+                Timer::after(delay).await;
+            }
+            faults.check_connect()?;
+            let stream = self.inner.connect().await?;
+            Ok(FaultyStream::new(stream, faults))
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use std::{
+    collections::VecDeque,
+    fmt, fs,
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{ClamAvSync, Timeouts};
+
+/// Direction of recorded data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Data sent by the client to ClamAV
+    Sent,
+    /// Data received by the client from ClamAV
+    Received,
+}
+
+/// Contiguous data sent or received on a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// Direction of the data
+    pub direction: Direction,
+    /// The exact bytes, including INSTREAM chunk headers and terminators
+    pub data: Vec<u8>,
+}
+
+/// All data exchanged on a single connection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Session {
+    /// Exchanges in the order they happened
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Session {
+    fn push(&mut self, direction: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        match self.exchanges.last_mut() {
+            Some(last) if last.direction == direction => last.data.extend_from_slice(data),
+            _ => self.exchanges.push(Exchange {
+                direction,
+                data: data.to_vec(),
+            }),
+        }
+    }
+}
+
+/// Recorded sessions with ClamAV
+///
+/// Fixtures are stored in a line based text format. Every session starts with
+/// a `session` line, followed by one line per exchange: `>` for sent and `<`
+/// for received data, each followed by the data encoded as hex. Lines
+/// starting with `#` are comments.
+///
+/// ```text
+/// # PING
+/// session
+/// > 7a50494e4700
+/// < 504f4e4700
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fixture {
+    /// Sessions in the order the connections were established
+    pub sessions: Vec<Session>,
+}
+
+impl Fixture {
+    /// Parses a fixture from its text format
+    pub fn parse(text: &str) -> io::Result<Fixture> {
+        let mut sessions: Vec<Session> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "session" {
+                sessions.push(Session::default());
+                continue;
+            }
+            let invalid = |message: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, message),
+                )
+            };
+            let (direction, hex) = if let Some(hex) = line.strip_prefix('>') {
+                (Direction::Sent, hex)
+            } else if let Some(hex) = line.strip_prefix('<') {
+                (Direction::Received, hex)
+            } else {
+                return Err(invalid("expected `session`, `>` or `<`"));
+            };
+            let data = decode_hex(hex.trim()).ok_or_else(|| invalid("invalid hex data"))?;
+            sessions
+                .last_mut()
+                .ok_or_else(|| invalid("exchange outside of a session"))?
+                .push(direction, &data);
+        }
+        Ok(Fixture { sessions })
+    }
+
+    /// Loads a fixture from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Fixture> {
+        Fixture::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Saves the fixture to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for Fixture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for session in &self.sessions {
+            writeln!(f, "session")?;
+            for exchange in &session.exchanges {
+                let marker = match exchange.direction {
+                    Direction::Sent => '>',
+                    Direction::Received => '<',
+                };
+                write!(f, "{marker} ")?;
+                for byte in &exchange.data {
+                    write!(f, "{byte:02x}")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Transport wrapper that records all data exchanged with ClamAV
+///
+/// The recorded sessions can be saved as a [`Fixture`] and served by a
+/// [`Replay`] transport later, e.g. in CI without a ClamAV server.
+///
+/// ```no_run
+/// use clamav_client::testing::Recorder;
+/// use clamav_client::{ClamAvSync, Tcp};
+///
+/// let recorder = Recorder::new(Tcp("127.0.0.1:3310".parse().unwrap()));
+/// recorder.scan_file("tests/data/eicar.txt", None).unwrap();
+/// recorder.save("tests/data/eicar-session.fixture").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Recorder<T> {
+    inner: T,
+    sessions: Arc<Mutex<Vec<Session>>>,
+}
+
+impl<T> Recorder<T> {
+    /// Wraps a transport to record its connections
+    pub fn new(inner: T) -> Self {
+        Recorder {
+            inner,
+            sessions: Arc::default(),
+        }
+    }
+
+    /// Returns the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the sessions recorded so far
+    pub fn fixture(&self) -> Fixture {
+        Fixture {
+            sessions: lock(&self.sessions).clone(),
+        }
+    }
+
+    /// Saves the sessions recorded so far to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.fixture().save(path)
+    }
+
+    fn start_session<S>(&self, inner: S) -> RecordingStream<S> {
+        let mut sessions = lock(&self.sessions);
+        sessions.push(Session::default());
+        RecordingStream {
+            inner,
+            sessions: self.sessions.clone(),
+            index: sessions.len() - 1,
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Stream returned by [`Recorder`]
+#[derive(Debug)]
+pub struct RecordingStream<S> {
+    inner: S,
+    sessions: Arc<Mutex<Vec<Session>>>,
+    index: usize,
+}
+
+impl<S> RecordingStream<S> {
+    fn record(&self, direction: Direction, data: &[u8]) {
+        lock(&self.sessions)[self.index].push(direction, data);
+    }
+}
+
+impl<S: Read> Read for RecordingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.record(Direction::Received, &buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for RecordingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.record(Direction::Sent, &buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: ClamAvSync> ClamAvSync for Recorder<T> {
+    type Stream = RecordingStream<T::Stream>;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        Ok(self.start_session(self.inner.connect()?))
+    }
+
+    fn connect_with_timeouts(&self, timeouts: &Timeouts) -> io::Result<Self::Stream> {
+        Ok(self.start_session(self.inner.connect_with_timeouts(timeouts)?))
+    }
+}
+
+/// Transport that serves recorded sessions instead of connecting to ClamAV
+///
+/// Every connection replays the next session of the [`Fixture`]. The data
+/// written by the client is compared with the recorded data, and a mismatch
+/// results in an error of kind [`io::ErrorKind::InvalidData`]. Connecting
+/// after all sessions have been replayed fails with
+/// [`io::ErrorKind::ConnectionRefused`].
+#[derive(Debug)]
+pub struct Replay {
+    sessions: Mutex<VecDeque<Session>>,
+}
+
+impl Replay {
+    /// Creates a transport replaying the sessions of `fixture`
+    pub fn new(fixture: Fixture) -> Self {
+        Replay {
+            sessions: Mutex::new(fixture.sessions.into()),
+        }
+    }
+
+    /// Creates a transport replaying the sessions stored in a file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Replay::new(Fixture::load(path)?))
+    }
+
+    /// Returns the number of sessions that have not been replayed yet
+    pub fn remaining(&self) -> usize {
+        lock(&self.sessions).len()
+    }
+
+    fn next_session(&self) -> io::Result<ReplayStream> {
+        let session = lock(&self.sessions).pop_front().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "no recorded session left to replay",
+            )
+        })?;
+        Ok(ReplayStream {
+            exchanges: session.exchanges.into(),
+            position: 0,
+        })
+    }
+}
+
+/// Stream returned by [`Replay`]
+#[derive(Debug)]
+pub struct ReplayStream {
+    exchanges: VecDeque<Exchange>,
+    position: usize,
+}
+
+impl ReplayStream {
+    fn advance(&mut self, len: usize) {
+        self.position += len;
+        if self
+            .exchanges
+            .front()
+            .is_some_and(|exchange| self.position >= exchange.data.len())
+        {
+            self.exchanges.pop_front();
+            self.position = 0;
+        }
+    }
+
+    fn replay_write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let expected = match self.exchanges.front() {
+            Some(exchange) if exchange.direction == Direction::Sent => {
+                &exchange.data[self.position..]
+            }
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "client sent data while the recording expects a response",
+                ))
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "recorded session has ended",
+                ))
+            }
+        };
+        let len = buf.len().min(expected.len());
+        if buf[..len] != expected[..len] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "client sent data that differs from the recording",
+            ));
+        }
+        self.advance(len);
+        Ok(len)
+    }
+
+    fn replay_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = match self.exchanges.front() {
+            Some(exchange) if exchange.direction == Direction::Received => {
+                &exchange.data[self.position..]
+            }
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "client reads a response before sending the recorded request",
+                ))
+            }
+            None => return Ok(0),
+        };
+        let len = buf.len().min(available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.advance(len);
+        Ok(len)
+    }
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.replay_read(buf)
+    }
+}
+
+impl Write for ReplayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.replay_write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ClamAvSync for Replay {
+    type Stream = ReplayStream;
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        self.next_session()
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_replay {
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use futures_lite::{AsyncRead, AsyncWrite};
+
+    use super::{Recorder, RecordingStream, Replay, ReplayStream};
+    use crate::ClamAvAsync;
+
+    impl<S: AsyncRead + Unpin> AsyncRead for RecordingStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let len = futures_lite::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            this.record(super::Direction::Received, &buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for RecordingStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let len = futures_lite::ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+            this.record(super::Direction::Sent, &buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_close(cx)
+        }
+    }
+
+    impl AsyncRead for ReplayStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(self.get_mut().replay_read(buf))
+        }
+    }
+
+    impl AsyncWrite for ReplayStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(self.get_mut().replay_write(buf))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<T: ClamAvAsync> ClamAvAsync for Recorder<T> {
+        type Stream = RecordingStream<T::Stream>;
+
+        async fn connect(&self) -> io::Result<Self::Stream> {
+            Ok(self.start_session(self.inner.connect().await?))
+        }
+    }
+
+    impl ClamAvAsync for Replay {
+        type Stream = ReplayStream;
+
+        async fn connect(&self) -> io::Result<Self::Stream> {
+            self.next_session()
+        }
+    }
+}
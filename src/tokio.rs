@@ -0,0 +1,157 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use ::tokio::io::ReadBuf;
+use futures_lite::{AsyncRead, AsyncWrite};
+
+use crate::{trace::Operation, ClamAvAsync, IoResult};
+
+/// Use a Tokio TCP connection to communicate with a ClamAV server
+#[derive(Debug, Clone)]
+pub struct Tcp(pub SocketAddr);
+
+/// Use a Tokio Unix socket connection to communicate with a ClamAV server
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct Socket(pub PathBuf);
+
+impl ClamAvAsync for Tcp {
+    type Stream = Compat<::tokio::net::TcpStream>;
+
+    fn connect(&self) -> impl std::future::Future<Output = std::io::Result<Self::Stream>> + Send {
+        let address = self.0;
+        async move {
+            let operation = Operation::connect(&address);
+            let result = operation
+                .instrument(::tokio::net::TcpStream::connect(address))
+                .await
+                .map(Compat);
+            operation.finish(&result);
+            result
+        }
+    }
+}
+
+#[cfg(unix)]
+impl ClamAvAsync for Socket {
+    type Stream = Compat<::tokio::net::UnixStream>;
+
+    fn connect(&self) -> impl std::future::Future<Output = std::io::Result<Self::Stream>> + Send {
+        let path = self.0.clone();
+        async move {
+            let operation = Operation::connect(&path.display());
+            let result = operation
+                .instrument(::tokio::net::UnixStream::connect(&path))
+                .await
+                .map(Compat);
+            operation.finish(&result);
+            result
+        }
+    }
+}
+
+/// Adapter that exposes a Tokio I/O object through the `futures` I/O traits
+///
+/// [`ClamAvAsync`] is written against the runtime agnostic `futures` traits.
+/// This wrapper lets Tokio streams and readers be used with it without an
+/// additional reactor.
+#[derive(Debug)]
+pub struct Compat<T>(pub T);
+
+impl<T> Compat<T> {
+    /// Returns the wrapped Tokio I/O object
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: ::tokio::io::AsyncRead + Unpin> AsyncRead for Compat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(Pin::new(&mut self.0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl<T: ::tokio::io::AsyncWrite + Unpin> AsyncWrite for Compat<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Scans a Tokio reader for viruses
+///
+/// This function reads data from any [`tokio::io::AsyncRead`](::tokio::io::AsyncRead),
+/// such as a `tokio::fs::File`, and streams it to a ClamAV server for scanning.
+///
+/// # Arguments
+///
+/// * `client`: The ClamAV instance to scan with
+/// * `reader`: The reader to be scanned
+/// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+///
+/// # Returns
+///
+/// An [`IoResult`] containing the server's response as a vector of bytes
+pub async fn scan_reader<C, R>(client: &C, reader: R, chunk_size: Option<usize>) -> IoResult
+where
+    C: ClamAvAsync,
+    R: ::tokio::io::AsyncRead + Unpin + Send,
+{
+    client.scan_reader(Compat(reader), chunk_size).await
+}
+
+/// Scans a Tokio stream for viruses
+///
+/// This function sends the items of a [`tokio_stream::Stream`] to a ClamAV
+/// server for scanning. Unlike [`ClamAvAsync::scan_stream`], the items can be
+/// of any type that converts into [`bytes::Bytes`], e.g. `Vec<u8>`.
+///
+/// # Arguments
+///
+/// * `client`: The ClamAV instance to scan with
+/// * `input_stream`: The stream to be scanned
+/// * `chunk_size`: An optional chunk size for reading data. If [`None`], a default chunk size is used
+///
+/// # Returns
+///
+/// An [`IoResult`] containing the server's response as a vector of bytes
+#[cfg(feature = "tokio-stream")]
+pub async fn scan_stream<C, S, B>(
+    client: &C,
+    input_stream: S,
+    chunk_size: Option<usize>,
+) -> IoResult
+where
+    C: ClamAvAsync,
+    S: tokio_stream::Stream<Item = io::Result<B>> + Send,
+    B: Into<bytes::Bytes>,
+{
+    use tokio_stream::StreamExt;
+
+    let input_stream = input_stream.map(|bytes| bytes.map(Into::into));
+    client.scan_stream(input_stream, chunk_size).await
+}
@@ -0,0 +1,626 @@
+//! [`tower`](https://docs.rs/tower) middleware that scans request bodies
+//! before they reach the inner service
+//!
+//! A [`ScanLayer`] wraps any service that takes an [`http::Request`], so it
+//! can be used with axum's `Router::layer`, `tower::ServiceBuilder` or with
+//! hyper through `hyper_util::service::TowerToHyperService`.
+//!
+//! Request bodies are streamed to clamd with [`ClamAvAsync::scan_stream`]
+//! while they arrive. At the same time they are kept in memory, or spooled
+//! to a temporary file if they are larger than the [maximum memory
+//! size](ScanLayer::max_memory_size), so a clean body can be forwarded to the
+//! inner service unchanged as a [`ScannedBody`].
+//!
+//! - Clean bodies are forwarded, the [`Verdict`] is added to the request
+//!   extensions.
+//! - Infected bodies are answered with `422 Unprocessable Entity` and the
+//!   `X-Virus-ID` header.
+//! - If a body cannot be scanned, the [`VerdictPolicy`] decides: fail open
+//!   forwards the body, fail closed and strict answer with `503 Service
+//!   Unavailable`.
+//! - Bodies larger than the [maximum body size](ScanLayer::max_body_size) are
+//!   answered with `413 Payload Too Large`.
+//!
+//! Requests without a body and requests whose content type is not
+//! [selected](ScanLayer::content_types) are forwarded without scanning.
+//! Trailers of scanned bodies are dropped.
+//!
+//! ```no_run
+//! use axum::{routing::post, Router};
+//! use clamav_client::{tower::ScanLayer, Tcp};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let clamd = Tcp("127.0.0.1:3310".parse().unwrap());
+//! let app = Router::new()
+//!     .route("/upload", post(|body: axum::body::Bytes| async move { body.len().to_string() }))
+//!     .layer(
+//!         ScanLayer::new(clamd)
+//!             .content_types(["application/", "multipart/form-data"])
+//!             .max_body_size(100 * 1024 * 1024),
+//!     );
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await.unwrap();
+//! axum::serve(listener, app).await.unwrap();
+//! # }
+//! ```
+
+use std::{
+    error::Error as StdError,
+    fmt,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+#[cfg(unix)]
+use async_fs::unix::OpenOptionsExt;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_lite::{stream, AsyncRead, AsyncWriteExt};
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{BodyExt, Either, Full};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{ClamAvAsync, Verdict, VerdictPolicy};
+
+/// Default maximum body size, clamd's default `StreamMaxLength` (25 MB)
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 25 * 1024 * 1024;
+
+/// Default size up to which bodies are kept in memory (1 MiB)
+pub const DEFAULT_MAX_MEMORY_SIZE: usize = 1024 * 1024;
+
+/// Size of the chunks read from spooled bodies
+const SPOOL_READ_SIZE: usize = 64 * 1024;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Layer that scans request bodies with ClamAV
+///
+/// Clones share the transport.
+#[derive(Debug)]
+pub struct ScanLayer<T> {
+    transport: Arc<T>,
+    content_types: Vec<String>,
+    max_body_size: u64,
+    max_memory_size: usize,
+    spool_dir: PathBuf,
+    verdict_policy: VerdictPolicy,
+    chunk_size: Option<usize>,
+}
+
+impl<T> Clone for ScanLayer<T> {
+    fn clone(&self) -> Self {
+        ScanLayer {
+            transport: self.transport.clone(),
+            content_types: self.content_types.clone(),
+            max_body_size: self.max_body_size,
+            max_memory_size: self.max_memory_size,
+            spool_dir: self.spool_dir.clone(),
+            verdict_policy: self.verdict_policy,
+            chunk_size: self.chunk_size,
+        }
+    }
+}
+
+impl<T: ClamAvAsync + Send + Sync + 'static> ScanLayer<T> {
+    /// Creates a layer that scans with `transport`
+    pub fn new(transport: T) -> Self {
+        ScanLayer {
+            transport: Arc::new(transport),
+            content_types: Vec::new(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_memory_size: DEFAULT_MAX_MEMORY_SIZE,
+            spool_dir: std::env::temp_dir(),
+            verdict_policy: VerdictPolicy::FailClosed,
+            chunk_size: None,
+        }
+    }
+
+    /// Only scans bodies with one of the given content types
+    ///
+    /// Entries ending with `/` match every subtype, e.g. `image/`; other
+    /// entries match the media type exactly, parameters such as `charset`
+    /// are ignored. Bodies without a `Content-Type` header are always
+    /// scanned. By default, every body is scanned.
+    pub fn content_types<I, S>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.content_types = content_types
+            .into_iter()
+            .map(|content_type| content_type.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Sets the maximum number of bytes of a request body
+    ///
+    /// Larger bodies are answered with `413 Payload Too Large`, based on the
+    /// `Content-Length` header if there is one.
+    pub fn max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Sets the number of bytes up to which a body is kept in memory
+    ///
+    /// Larger bodies are spooled to a temporary file in the [spool
+    /// directory](Self::spool_dir), which is deleted when the forwarded body
+    /// is dropped. The file gets a random name and, on Unix, is only
+    /// accessible by the owner.
+    pub fn max_memory_size(mut self, max_memory_size: usize) -> Self {
+        self.max_memory_size = max_memory_size;
+        self
+    }
+
+    /// Sets the directory for spooled bodies, the system's temporary
+    /// directory by default
+    pub fn spool_dir(mut self, spool_dir: impl Into<PathBuf>) -> Self {
+        self.spool_dir = spool_dir.into();
+        self
+    }
+
+    /// Sets how bodies that cannot be scanned are handled
+    pub fn verdict_policy(mut self, verdict_policy: VerdictPolicy) -> Self {
+        self.verdict_policy = verdict_policy;
+        self
+    }
+
+    /// Sets the chunk size for streaming bodies to clamd
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Returns the transport used for scanning
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns `true` if a body with these headers is scanned
+    fn selects(&self, headers: &http::HeaderMap) -> bool {
+        if self.content_types.is_empty() {
+            return true;
+        }
+        let Some(value) = headers.get(header::CONTENT_TYPE) else {
+            return true;
+        };
+        let media_type = String::from_utf8_lossy(value.as_bytes());
+        let media_type = media_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types.iter().any(|content_type| {
+            if content_type.ends_with('/') {
+                media_type.starts_with(content_type.as_str())
+            } else {
+                media_type == *content_type
+            }
+        })
+    }
+
+    /// Scans the body of `request` and returns the request to forward, or
+    /// the response rejecting it
+    async fn scan<B>(
+        &self,
+        request: Request<B>,
+    ) -> Result<Request<ScannedBody<B>>, Response<Full<Bytes>>>
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        let (parts, body) = request.into_parts();
+        let body = Box::pin(body);
+        if body.is_end_stream() || !self.selects(&parts.headers) {
+            let body = ScannedBody(Kind::Unscanned(body));
+            return Ok(Request::from_parts(parts, body));
+        }
+        let content_length = parts
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if content_length.is_some_and(|length| length > self.max_body_size) {
+            return Err(reject(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "request body too large",
+            ));
+        }
+
+        let mut collector = Collector {
+            body,
+            sink: Sink::Memory(BytesMut::new()),
+            size: 0,
+            failure: None,
+        };
+        let data = stream::unfold(&mut collector, |collector| async move {
+            let data = collector.next(self).await?;
+            Some((Ok(data), collector))
+        });
+        let response = self.transport.scan_stream(data, self.chunk_size).await;
+        if let Some(failure) = collector.failure {
+            return Err(failure.into_response());
+        }
+
+        let verdict = match response {
+            Ok(response) => {
+                Verdict::parse(&response).unwrap_or_else(|err| Verdict::Error(err.to_string()))
+            }
+            Err(err) => Verdict::Error(err.to_string()),
+        };
+        match self.verdict_policy.apply(verdict.clone()) {
+            Ok(Verdict::Clean) => {}
+            Ok(Verdict::Infected(signatures)) => {
+                let signatures = signatures.join(", ");
+                let mut response = reject(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    &format!("virus found: {signatures}"),
+                );
+                if let Ok(value) = HeaderValue::from_str(&signatures) {
+                    response.headers_mut().insert("X-Virus-ID", value);
+                }
+                return Err(response);
+            }
+            Ok(Verdict::Error(_)) | Err(_) => {
+                return Err(reject(StatusCode::SERVICE_UNAVAILABLE, "virus scan failed"));
+            }
+        }
+
+        // clamd may answer before the end of the body, e.g. if it is too large
+        while collector.next(self).await.is_some() {}
+        if let Some(failure) = collector.failure {
+            return Err(failure.into_response());
+        }
+        let body = collector
+            .sink
+            .finish()
+            .await
+            .map_err(|err| Failure::Spool(err).into_response())?;
+        let mut request = Request::from_parts(parts, body);
+        request.extensions_mut().insert(verdict);
+        Ok(request)
+    }
+}
+
+impl<S, T> Layer<S> for ScanLayer<T> {
+    type Service = ScanService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ScanService {
+            inner,
+            layer: Arc::new(self.clone()),
+        }
+    }
+}
+
+/// Service created by [`ScanLayer`]
+///
+/// Responses of the inner service are returned as [`Either::Left`],
+/// rejections as [`Either::Right`].
+pub struct ScanService<S, T> {
+    inner: S,
+    layer: Arc<ScanLayer<T>>,
+}
+
+impl<S: Clone, T> Clone for ScanService<S, T> {
+    fn clone(&self) -> Self {
+        ScanService {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, T: fmt::Debug> fmt::Debug for ScanService<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScanService")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S, T, B, ResBody> Service<Request<B>> for ScanService<S, T>
+where
+    S: Service<Request<ScannedBody<B>>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    T: ClamAvAsync + Send + Sync + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<Either<ResBody, Full<Bytes>>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // The ready service is used for this request, the clone for the next
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            match layer.scan(request).await {
+                Ok(request) => Ok(inner.call(request).await?.map(Either::Left)),
+                Err(response) => Ok(response.map(Either::Right)),
+            }
+        })
+    }
+}
+
+/// Reason why a body could not be collected
+enum Failure {
+    TooLarge,
+    Body(BoxError),
+    Spool(io::Error),
+}
+
+impl Failure {
+    fn into_response(self) -> Response<Full<Bytes>> {
+        match self {
+            Failure::TooLarge => reject(StatusCode::PAYLOAD_TOO_LARGE, "request body too large"),
+            Failure::Body(err) => reject(
+                StatusCode::BAD_REQUEST,
+                &format!("reading the request body failed: {err}"),
+            ),
+            Failure::Spool(err) => reject(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("storing the request body failed: {err}"),
+            ),
+        }
+    }
+}
+
+fn reject(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(format!("{message}\n"))));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+/// Reads a body and keeps its data for forwarding
+struct Collector<B> {
+    body: Pin<Box<B>>,
+    sink: Sink,
+    size: u64,
+    failure: Option<Failure>,
+}
+
+impl<B> Collector<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    /// Returns the next data of the body, [`None`] at its end or after a
+    /// failure
+    async fn next<T>(&mut self, layer: &ScanLayer<T>) -> Option<Bytes> {
+        if self.failure.is_some() {
+            return None;
+        }
+        loop {
+            let frame = match self.body.frame().await? {
+                Ok(frame) => frame,
+                Err(err) => {
+                    self.failure = Some(Failure::Body(err.into()));
+                    return None;
+                }
+            };
+            let Ok(mut data) = frame.into_data() else {
+                continue;
+            };
+            let data = data.copy_to_bytes(data.remaining());
+            self.size += data.len() as u64;
+            if self.size > layer.max_body_size {
+                self.failure = Some(Failure::TooLarge);
+                return None;
+            }
+            if let Err(err) = self.sink.write(&data, layer).await {
+                self.failure = Some(Failure::Spool(err));
+                return None;
+            }
+            if !data.is_empty() {
+                return Some(data);
+            }
+        }
+    }
+}
+
+/// Storage of a body while it is scanned
+enum Sink {
+    Memory(BytesMut),
+    File(async_fs::File, SpoolPath),
+}
+
+impl Sink {
+    async fn write<T>(&mut self, data: &[u8], layer: &ScanLayer<T>) -> io::Result<()> {
+        match self {
+            Sink::Memory(buffer) if buffer.len() + data.len() <= layer.max_memory_size => {
+                buffer.extend_from_slice(data);
+            }
+            Sink::Memory(buffer) => {
+                let (mut file, path) = SpoolPath::create(&layer.spool_dir).await?;
+                file.write_all(buffer).await?;
+                file.write_all(data).await?;
+                *self = Sink::File(file, path);
+            }
+            Sink::File(file, _) => file.write_all(data).await?,
+        }
+        Ok(())
+    }
+
+    async fn finish<B>(self) -> io::Result<ScannedBody<B>> {
+        let kind = match self {
+            Sink::Memory(buffer) => Kind::Buffered(Some(buffer.freeze())),
+            Sink::File(mut file, path) => {
+                file.flush().await?;
+                let remaining = file.metadata().await?.len();
+                drop(file);
+                let file = async_fs::File::open(&path.0).await?;
+                Kind::Spooled(Spool {
+                    file,
+                    path,
+                    remaining,
+                    buffer: vec![0; SPOOL_READ_SIZE],
+                })
+            }
+        };
+        Ok(ScannedBody(kind))
+    }
+}
+
+/// Path of a spooled body, the file is deleted on drop
+struct SpoolPath(PathBuf);
+
+impl SpoolPath {
+    /// Creates a file with an unpredictable name in `dir` that only the
+    /// owner can access
+    async fn create(dir: &Path) -> io::Result<(async_fs::File, SpoolPath)> {
+        loop {
+            let path = dir.join(format!("clamav-client-{:016x}.spool", fastrand::u64(..)));
+            let mut options = async_fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+            match options.open(&path).await {
+                Ok(file) => return Ok((file, SpoolPath(path))),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for SpoolPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Body spooled to a temporary file
+struct Spool {
+    // Declared before the path, so the file is closed before it is deleted
+    file: async_fs::File,
+    path: SpoolPath,
+    remaining: u64,
+    buffer: Vec<u8>,
+}
+
+enum Kind<B> {
+    Buffered(Option<Bytes>),
+    Spooled(Spool),
+    Unscanned(Pin<Box<B>>),
+}
+
+/// Request body forwarded by [`ScanService`]
+///
+/// Scanned bodies are read from memory or from their temporary file, bodies
+/// that were not scanned are passed through.
+pub struct ScannedBody<B>(Kind<B>);
+
+impl<B> ScannedBody<B> {
+    /// Returns `true` if the body was scanned
+    pub fn is_scanned(&self) -> bool {
+        !matches!(self.0, Kind::Unscanned(_))
+    }
+
+    /// Returns the path of the temporary file, if the body was spooled
+    pub fn spool_path(&self) -> Option<&Path> {
+        match &self.0 {
+            Kind::Spooled(spool) => Some(&spool.path.0),
+            _ => None,
+        }
+    }
+}
+
+impl<B> fmt::Debug for ScannedBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match &self.0 {
+            Kind::Buffered(_) => "Buffered",
+            Kind::Spooled(_) => "Spooled",
+            Kind::Unscanned(_) => "Unscanned",
+        };
+        f.debug_tuple("ScannedBody").field(&kind).finish()
+    }
+}
+
+impl<B> Body for ScannedBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        match &mut self.get_mut().0 {
+            Kind::Buffered(data) => Poll::Ready(
+                data.take()
+                    .filter(|data| !data.is_empty())
+                    .map(|data| Ok(Frame::data(data))),
+            ),
+            Kind::Spooled(spool) => {
+                if spool.remaining == 0 {
+                    return Poll::Ready(None);
+                }
+                let size = spool.buffer.len().min(spool.remaining as usize);
+                match Pin::new(&mut spool.file).poll_read(cx, &mut spool.buffer[..size]) {
+                    Poll::Ready(Ok(0)) => Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "spooled body truncated",
+                    )
+                    .into()))),
+                    Poll::Ready(Ok(read)) => {
+                        spool.remaining -= read as u64;
+                        let data = Bytes::copy_from_slice(&spool.buffer[..read]);
+                        Poll::Ready(Some(Ok(Frame::data(data))))
+                    }
+                    Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+            Kind::Unscanned(body) => match body.as_mut().poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => Poll::Ready(Some(Ok(
+                    frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+                ))),
+                Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
+                Poll::Ready(None) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.0 {
+            Kind::Buffered(data) => data.as_ref().map_or(true, Bytes::is_empty),
+            Kind::Spooled(spool) => spool.remaining == 0,
+            Kind::Unscanned(body) => body.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.0 {
+            Kind::Buffered(data) => {
+                SizeHint::with_exact(data.as_ref().map_or(0, |data| data.len() as u64))
+            }
+            Kind::Spooled(spool) => SizeHint::with_exact(spool.remaining),
+            Kind::Unscanned(body) => body.size_hint(),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Optional `tracing` instrumentation
+//!
+//! Every operation with ClamAV runs in an [`Operation`], which opens a
+//! `tracing` span if the `tracing` feature is enabled and compiles to nothing
+//! otherwise. Spans only carry metadata: endpoints, commands, sizes, durations
+//! and verdicts, never the scanned data.
+//!
+//! | Span              | Fields                                                       |
+//! |-------------------|--------------------------------------------------------------|
+//! | `clamav.connect`  | `endpoint`, `duration_ms`                                    |
+//! | `clamav.command`  | `command`, `response_bytes`, `duration_ms`                   |
+//! | `clamav.instream` | `chunk_size`, `bytes`, `chunks`, `response_bytes`, `duration_ms` |
+//! | `clamav.parse`    | `verdict`, `signature`, `signatures`, `error`                |
+//!
+//! Completed operations emit a `DEBUG` event, failed ones a `WARN` event with
+//! the error.
+
+use std::{fmt::Display, io};
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+use crate::Verdict;
+
+/// A traced operation with ClamAV
+pub(crate) struct Operation {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    started: Instant,
+}
+
+impl Operation {
+    #[cfg(feature = "tracing")]
+    fn new(span: tracing::Span) -> Self {
+        Operation {
+            span,
+            started: Instant::now(),
+        }
+    }
+
+    /// Connecting to a TCP address or Unix socket path
+    pub(crate) fn connect(endpoint: &dyn Display) -> Self {
+        #[cfg(feature = "tracing")]
+        return Self::new(tracing::debug_span!(
+            "clamav.connect",
+            endpoint = %endpoint,
+            duration_ms = tracing::field::Empty,
+        ));
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = endpoint;
+            Operation {}
+        }
+    }
+
+    /// Sending a command such as `zPING\0` and reading the reply
+    pub(crate) fn command(command: &[u8]) -> Self {
+        #[cfg(feature = "tracing")]
+        return Self::new(tracing::debug_span!(
+            "clamav.command",
+            command = command_name(command),
+            response_bytes = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+        ));
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = command;
+            Operation {}
+        }
+    }
+
+    /// Streaming data with `INSTREAM` and reading the reply
+    pub(crate) fn instream(chunk_size: usize) -> Self {
+        #[cfg(feature = "tracing")]
+        return Self::new(tracing::debug_span!(
+            "clamav.instream",
+            chunk_size,
+            bytes = tracing::field::Empty,
+            chunks = tracing::field::Empty,
+            response_bytes = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+        ));
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = chunk_size;
+            Operation {}
+        }
+    }
+
+    /// Runs `f` inside the span
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(f);
+        #[cfg(not(feature = "tracing"))]
+        f()
+    }
+
+    /// Polls `future` inside the span
+    #[cfg(feature = "async-core")]
+    pub(crate) async fn instrument<F: std::future::Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        return tracing::Instrument::instrument(future, self.span.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        future.await
+    }
+
+    /// Records the number of bytes and chunks that were sent
+    pub(crate) fn sent(&self, sent: &Sent) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("bytes", sent.bytes);
+            self.span.record("chunks", sent.chunks);
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = sent;
+    }
+
+    /// Records the size of the reply, its duration and the outcome
+    pub(crate) fn reply(&self, result: &io::Result<Vec<u8>>) {
+        #[cfg(feature = "tracing")]
+        if let Ok(response) = result {
+            self.span.record("response_bytes", response.len());
+        }
+        self.finish(result);
+    }
+
+    /// Records the duration and the outcome of an operation
+    pub(crate) fn finish<T>(&self, result: &io::Result<T>) {
+        #[cfg(feature = "tracing")]
+        {
+            let duration_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+            self.span.record("duration_ms", duration_ms);
+            let _entered = self.span.enter();
+            match result {
+                Ok(_) => tracing::debug!(duration_ms, "completed"),
+                Err(err) => tracing::warn!(duration_ms, error = %err, "failed"),
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = result;
+    }
+}
+
+/// Number of bytes and chunks sent with `INSTREAM`
+#[derive(Debug, Default)]
+pub(crate) struct Sent {
+    bytes: u64,
+    chunks: u64,
+}
+
+impl Sent {
+    pub(crate) fn chunk(&mut self, len: usize) {
+        self.bytes += len as u64;
+        self.chunks += 1;
+    }
+}
+
+/// Records a parsed verdict
+///
+/// Signature names and error messages are reported by ClamAV and do not
+/// contain scanned data.
+pub(crate) fn verdict(verdict: &Verdict) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = match verdict {
+            Verdict::Clean => tracing::debug_span!("clamav.parse", verdict = "clean"),
+            Verdict::Infected(signatures) => tracing::debug_span!(
+                "clamav.parse",
+                verdict = "infected",
+                signature = %signatures[0],
+                signatures = signatures.len(),
+            ),
+            Verdict::Error(message) => {
+                tracing::debug_span!("clamav.parse", verdict = "error", error = %message)
+            }
+        };
+        span.in_scope(|| tracing::debug!("parsed reply"));
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = verdict;
+}
+
+/// Returns the name of a command without the `z` prefix and terminator
+#[cfg(feature = "tracing")]
+fn command_name(command: &[u8]) -> &str {
+    let command = command.strip_prefix(b"z").unwrap_or(command);
+    let command = command.strip_suffix(b"\0").unwrap_or(command);
+    std::str::from_utf8(command).unwrap_or("?")
+}
@@ -0,0 +1,116 @@
+use std::str::Utf8Error;
+
+/// Parsed result of a ClamAV scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Verdict {
+    /// No virus was found
+    Clean,
+    /// One or more signatures matched, contains the signature names
+    Infected(Vec<String>),
+    /// ClamAV could not scan the data, contains the error message
+    Error(String),
+}
+
+impl Verdict {
+    /// Parses a ClamAV scan response
+    ///
+    /// Every line of the response is inspected, so responses with multiple
+    /// matches (`AllMatchScan`) and session responses prefixed with a request
+    /// number are supported. If any line reports a match, the verdict is
+    /// [`Verdict::Infected`], even if other lines report errors. Otherwise an
+    /// error on any line makes the verdict [`Verdict::Error`].
+    ///
+    /// # Returns
+    ///
+    /// A [`Result`] containing the parsed [`Verdict`] or an error if the
+    /// response is not valid UTF-8
+    pub fn parse(response: &[u8]) -> Result<Verdict, Utf8Error> {
+        let verdict = Self::parse_lines(std::str::from_utf8(response)?);
+        crate::trace::verdict(&verdict);
+        Ok(verdict)
+    }
+
+    /// Parses a response without recording a `clamav.parse` event, for
+    /// internal checks of responses that callers parse themselves
+    pub(crate) fn parse_lines(response: &str) -> Verdict {
+        let mut signatures = Vec::new();
+        let mut error = None;
+        let mut clean = false;
+
+        for line in response
+            .split(['\0', '\n'])
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            if let Some(rest) = line.strip_suffix(" FOUND") {
+                let signature = rest.rsplit_once(": ").map_or(rest, |(_, name)| name);
+                signatures.push(signature.to_owned());
+            } else if line.ends_with("OK") {
+                clean = true;
+            } else if error.is_none() {
+                // Keep looking for matches, an error must not hide them
+                error = Some(line.to_owned());
+            }
+        }
+
+        if !signatures.is_empty() {
+            Verdict::Infected(signatures)
+        } else if let Some(error) = error {
+            Verdict::Error(error)
+        } else if clean {
+            Verdict::Clean
+        } else {
+            Verdict::Error(String::from("empty response"))
+        }
+    }
+
+    /// Returns `true` if no virus was found
+    pub fn is_clean(&self) -> bool {
+        matches!(self, Verdict::Clean)
+    }
+
+    /// Returns the names of the matched signatures
+    pub fn signatures(&self) -> &[String] {
+        match self {
+            Verdict::Infected(signatures) => signatures,
+            _ => &[],
+        }
+    }
+}
+
+/// Determines how ClamAV error responses are treated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum VerdictPolicy {
+    /// Keep errors as [`Verdict::Error`], which is not considered clean
+    #[default]
+    FailClosed,
+    /// Treat errors as [`Verdict::Clean`]
+    FailOpen,
+    /// Turn errors into an [`std::io::Error`]
+    Strict,
+}
+
+impl VerdictPolicy {
+    /// Applies the policy to a parsed [`Verdict`]
+    pub fn apply(self, verdict: Verdict) -> std::io::Result<Verdict> {
+        match (self, verdict) {
+            (VerdictPolicy::FailOpen, Verdict::Error(_)) => Ok(Verdict::Clean),
+            (VerdictPolicy::Strict, Verdict::Error(message)) => Err(std::io::Error::other(message)),
+            (_, verdict) => Ok(verdict),
+        }
+    }
+
+    /// Parses a scan response and applies the policy
+    ///
+    /// Responses that are not valid UTF-8 result in an error of kind
+    /// [`std::io::ErrorKind::InvalidData`].
+    pub(crate) fn parse(self, response: &[u8]) -> std::io::Result<Verdict> {
+        let verdict = Verdict::parse(response)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        self.apply(verdict)
+    }
+}
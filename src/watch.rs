@@ -0,0 +1,617 @@
+//! Scanning new and modified files as they appear in a directory
+//!
+//! A [`Watcher`] subscribes to file system events (inotify on Linux), waits
+//! until no event arrived for a file during the debounce interval and its
+//! size and modification time did not change during the settle interval,
+//! and then yields the file for scanning. This avoids scanning files that are
+//! still being uploaded, e.g. by SFTP.
+//!
+//! [`Watcher::run`] scans the settled files with [`ClamAvSync`],
+//! [`Watcher::run_async`] with [`ClamAvAsync`](crate::ClamAvAsync). Both apply
+//! the configured [`Action`] and pass a [`WatchEvent`] to a callback.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use clamav_client::dir::ScanDirOptions;
+//! use clamav_client::watch::{Action, WatchOptions, Watcher};
+//!
+//! let options = WatchOptions::new()
+//!     .filter(ScanDirOptions::new().exclude("*.part"))
+//!     .settle(Duration::from_secs(2))
+//!     .on_infected(Action::MoveTo("/var/quarantine".into()));
+//! let watcher = Watcher::new("/srv/sftp/uploads", options).unwrap();
+//!
+//! let clamd_tcp = clamav_client::Tcp("127.0.0.1:3310".parse().unwrap());
+//! watcher.run(&clamd_tcp, |event| {
+//!     println!("{}: {:?}", event.path.display(), event.response);
+//! });
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use notify::{
+    event::{AccessKind, AccessMode, CreateKind, ModifyKind, RenameMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _,
+};
+
+#[cfg(feature = "quarantine")]
+use crate::quarantine::{Quarantine, QuarantineEntry};
+use crate::{
+    dir::{ScanDirOptions, Walked, Walker},
+    ClamAvSync, IoResult, Verdict, VerdictPolicy,
+};
+
+/// What to do with a file after it was scanned
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Action {
+    /// Leave the file where it is
+    #[default]
+    Keep,
+    /// Delete the file
+    Delete,
+    /// Move the file into the given directory, which should not be inside
+    /// the watched directory
+    MoveTo(PathBuf),
+    /// Move the file into a quarantine, which should not be inside the
+    /// watched directory
+    #[cfg(feature = "quarantine")]
+    Quarantine(Quarantine),
+}
+
+/// Action that was applied to a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionTaken {
+    /// The file was deleted
+    Deleted,
+    /// The file was moved to the given path
+    Moved(PathBuf),
+    /// The file was moved into a quarantine
+    #[cfg(feature = "quarantine")]
+    Quarantined(QuarantineEntry),
+}
+
+impl Action {
+    /// Applies the action to a file
+    ///
+    /// # Returns
+    ///
+    /// [`None`] for [`Action::Keep`], otherwise an [`io::Result`] containing
+    /// the [`ActionTaken`]
+    pub fn apply(&self, path: &Path) -> Option<io::Result<ActionTaken>> {
+        self.apply_with_signatures(path, &[])
+    }
+
+    /// Applies the action to a file in which the given signatures matched
+    fn apply_with_signatures(
+        &self,
+        path: &Path,
+        #[allow(unused_variables)] signatures: &[String],
+    ) -> Option<io::Result<ActionTaken>> {
+        match self {
+            Action::Keep => None,
+            Action::Delete => Some(fs::remove_file(path).map(|_| ActionTaken::Deleted)),
+            Action::MoveTo(dir) => Some(move_into(path, dir).map(ActionTaken::Moved)),
+            #[cfg(feature = "quarantine")]
+            Action::Quarantine(quarantine) => Some(
+                quarantine
+                    .quarantine(path, signatures)
+                    .map(ActionTaken::Quarantined),
+            ),
+        }
+    }
+}
+
+/// Moves a file into a directory without overwriting existing files
+///
+/// The target name is claimed atomically, so a file created concurrently
+/// under the same name is never replaced; the next numbered name is tried
+/// instead.
+fn move_into(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut target = dir.join(name);
+    let mut n = 1;
+    loop {
+        match claim(path, &target) {
+            Ok(()) => break,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                let mut numbered = name.to_os_string();
+                numbered.push(format!(".{n}"));
+                target = dir.join(numbered);
+                n += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    fs::remove_file(path)?;
+    Ok(target)
+}
+
+/// Creates `target` with the contents of `path`, failing with
+/// [`io::ErrorKind::AlreadyExists`] if it exists
+fn claim(path: &Path, target: &Path) -> io::Result<()> {
+    match fs::hard_link(path, target) {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+            // Linking fails across file systems
+            let mut source = fs::File::open(path)?;
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(target)?;
+            let copied = io::copy(&mut source, &mut file)
+                .and_then(|_| file.set_permissions(source.metadata()?.permissions()));
+            if copied.is_err() {
+                let _ = fs::remove_file(target);
+            }
+            copied
+        }
+        linked => linked,
+    }
+}
+
+/// Options for watching a directory
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    filter: ScanDirOptions,
+    debounce: Duration,
+    settle: Duration,
+    scan_existing: bool,
+    on_infected: Action,
+    on_error: Action,
+    verdict_policy: VerdictPolicy,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            filter: ScanDirOptions::default(),
+            debounce: Duration::from_millis(200),
+            settle: Duration::from_secs(1),
+            scan_existing: false,
+            on_infected: Action::Keep,
+            on_error: Action::Keep,
+            verdict_policy: VerdictPolicy::default(),
+        }
+    }
+}
+
+impl WatchOptions {
+    /// Creates options that watch recursively and keep all files
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the recursion, symlink, include/exclude, size and hidden-file
+    /// filters, the concurrency setting is ignored
+    pub fn filter(mut self, filter: ScanDirOptions) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Sets how long no event may arrive for a file before it is checked,
+    /// defaults to 200 ms
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Sets how long the size and modification time of a file must stay the
+    /// same before it is scanned, defaults to 1 s
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// Sets whether files that already exist are scanned at startup,
+    /// defaults to `false`
+    pub fn scan_existing(mut self, scan_existing: bool) -> Self {
+        self.scan_existing = scan_existing;
+        self
+    }
+
+    /// Sets the action for infected files, defaults to [`Action::Keep`]
+    pub fn on_infected(mut self, action: Action) -> Self {
+        self.on_infected = action;
+        self
+    }
+
+    /// Sets the action for files that could not be scanned, defaults to
+    /// [`Action::Keep`]
+    pub fn on_error(mut self, action: Action) -> Self {
+        self.on_error = action;
+        self
+    }
+
+    /// Sets the policy used to derive [`WatchEvent::verdict`] and choose the
+    /// action, defaults to [`VerdictPolicy::FailClosed`]
+    pub fn verdict_policy(mut self, verdict_policy: VerdictPolicy) -> Self {
+        self.verdict_policy = verdict_policy;
+        self
+    }
+
+    /// Parses a scan result and applies the configured [`VerdictPolicy`]
+    fn verdict(&self, response: &IoResult) -> io::Result<Verdict> {
+        match response {
+            Ok(response) => self.verdict_policy.parse(response),
+            Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+        }
+    }
+
+    /// Applies the action for a verdict to a file
+    fn apply(&self, path: &Path, verdict: &io::Result<Verdict>) -> Option<io::Result<ActionTaken>> {
+        let signatures = verdict.as_ref().map_or(&[][..], Verdict::signatures);
+        self.action(verdict).apply_with_signatures(path, signatures)
+    }
+
+    /// Returns the action for a verdict
+    fn action(&self, verdict: &io::Result<Verdict>) -> &Action {
+        match verdict {
+            Ok(Verdict::Clean) => &Action::Keep,
+            Ok(Verdict::Infected(_)) => &self.on_infected,
+            // The file disappeared before it could be scanned
+            Err(err) if err.kind() == io::ErrorKind::NotFound => &Action::Keep,
+            _ => &self.on_error,
+        }
+    }
+
+    /// Returns `true` if the path is inside a directory files are moved to
+    fn is_target(&self, path: &Path) -> bool {
+        [&self.on_infected, &self.on_error]
+            .into_iter()
+            .any(|action| match action {
+                Action::MoveTo(dir) => path.starts_with(dir),
+                #[cfg(feature = "quarantine")]
+                Action::Quarantine(quarantine) => path.starts_with(quarantine.dir()),
+                _ => false,
+            })
+    }
+}
+
+/// Result of scanning a file that appeared or changed
+#[derive(Debug)]
+pub struct WatchEvent {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Size of the file in bytes when it was scanned, if known
+    pub size: Option<u64>,
+    /// The server's response, or the error that occurred while watching or
+    /// scanning the file
+    pub response: IoResult,
+    /// The response parsed with the configured [`VerdictPolicy`], which
+    /// determined the action
+    pub verdict: io::Result<Verdict>,
+    /// Result of the action applied to the file, [`None`] if the file was kept
+    pub action: Option<io::Result<ActionTaken>>,
+}
+
+/// Handle to stop a running [`Watcher`] from another thread or task
+#[derive(Debug, Clone)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Stops the watcher, it returns within a fraction of the debounce interval
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    last_event: Instant,
+    snapshot: Option<(u64, Option<SystemTime>)>,
+    stable_since: Instant,
+}
+
+/// Watches a directory and scans files once they have settled
+///
+/// [`run`](Self::run) and [`run_async`](Self::run_async) return after
+/// [`StopHandle::stop`] was called.
+#[derive(Debug)]
+pub struct Watcher {
+    settler: Settler,
+}
+
+/// Iterator that blocks until the next file has settled
+struct Settler {
+    root: PathBuf,
+    options: WatchOptions,
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<Event>>,
+    pending: HashMap<PathBuf, Pending>,
+    ready: VecDeque<Walked>,
+    stop: Arc<AtomicBool>,
+}
+
+impl std::fmt::Debug for Settler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Settler")
+            .field("root", &self.root)
+            .field("options", &self.options)
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Watcher {
+    /// Starts watching a directory
+    ///
+    /// # Returns
+    ///
+    /// An [`io::Result`] containing the [`Watcher`], or an error if the
+    /// directory cannot be watched
+    pub fn new<P: AsRef<Path>>(root: P, options: WatchOptions) -> io::Result<Self> {
+        // Events carry absolute paths, which must start with the root
+        let root = fs::canonicalize(root)?;
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(notify_error)?;
+        let mode = if options.filter.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(&root, mode).map_err(notify_error)?;
+
+        let mut settler = Settler {
+            root,
+            options,
+            _watcher: watcher,
+            events,
+            pending: HashMap::new(),
+            ready: VecDeque::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        if settler.options.scan_existing {
+            let walker = Walker::new(&settler.root, settler.options.filter.clone());
+            settler.track(walker, Instant::now());
+        }
+        Ok(Watcher { settler })
+    }
+
+    /// Returns a handle to stop the watcher
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle(Arc::clone(&self.settler.stop))
+    }
+
+    /// Scans files with a synchronous client until the watcher is stopped
+    ///
+    /// # Arguments
+    ///
+    /// * `client`: The client used to scan the files
+    /// * `callback`: Called with the result of every scan and every error
+    pub fn run<C: ClamAvSync>(self, client: &C, mut callback: impl FnMut(WatchEvent)) {
+        let options = self.settler.options.clone();
+        for walked in self.settler {
+            let event = match walked {
+                Walked::File(path, size) => {
+                    let response = client.scan_file(&path, options.filter.chunk_size);
+                    let verdict = options.verdict(&response);
+                    let action = options.apply(&path, &verdict);
+                    WatchEvent {
+                        path,
+                        size: Some(size),
+                        response,
+                        verdict,
+                        action,
+                    }
+                }
+                Walked::Error(path, err) => {
+                    let response = Err(err);
+                    WatchEvent {
+                        verdict: options.verdict(&response),
+                        path,
+                        size: None,
+                        response,
+                        action: None,
+                    }
+                }
+            };
+            callback(event);
+        }
+    }
+}
+
+impl Settler {
+    fn tick(&self) -> Duration {
+        (self.options.debounce.min(self.options.settle) / 2)
+            .clamp(Duration::from_millis(10), Duration::from_millis(250))
+    }
+
+    /// Adds all files yielded by a walker to the pending files
+    fn track(&mut self, walker: Walker, now: Instant) {
+        for walked in walker {
+            match walked {
+                Walked::File(path, _) => {
+                    let pending = self.pending.entry(path).or_insert(Pending {
+                        last_event: now,
+                        snapshot: None,
+                        stable_since: now,
+                    });
+                    pending.last_event = now;
+                }
+                Walked::Error(..) => self.ready.push_back(walked),
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event, now: Instant) {
+        let changed = match event.kind {
+            EventKind::Create(CreateKind::File | CreateKind::Folder | CreateKind::Any) => {
+                event.paths
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let Some(from) = event.paths.first() {
+                    self.pending.remove(from);
+                }
+                event.paths.into_iter().skip(1).collect()
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+                for path in &event.paths {
+                    self.pending.remove(path);
+                }
+                Vec::new()
+            }
+            EventKind::Modify(ModifyKind::Metadata(_)) => Vec::new(),
+            EventKind::Modify(_) | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                event.paths
+            }
+            _ => Vec::new(),
+        };
+
+        for path in changed {
+            if self.options.is_target(&path) {
+                continue;
+            }
+            let walker = Walker::below(&self.root, path, self.options.filter.clone());
+            self.track(walker, now);
+        }
+    }
+
+    /// Moves pending files that have settled to the ready queue
+    fn check_pending(&mut self, now: Instant) {
+        let debounce = self.options.debounce;
+        let settle = self.options.settle;
+        let max_file_size = self.options.filter.max_file_size;
+        let ready = &mut self.ready;
+
+        self.pending.retain(|path, pending| {
+            if now.duration_since(pending.last_event) < debounce {
+                return true;
+            }
+            let metadata = match fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return false,
+                Err(err) => {
+                    ready.push_back(Walked::Error(path.clone(), err));
+                    return false;
+                }
+            };
+            let snapshot = Some((metadata.len(), metadata.modified().ok()));
+            if pending.snapshot != snapshot {
+                pending.snapshot = snapshot;
+                pending.stable_since = now;
+                return true;
+            }
+            if now.duration_since(pending.stable_since) < settle {
+                return true;
+            }
+            if max_file_size.map_or(true, |max| metadata.len() <= max) {
+                ready.push_back(Walked::File(path.clone(), metadata.len()));
+            }
+            false
+        });
+    }
+}
+
+impl Iterator for Settler {
+    type Item = Walked;
+
+    fn next(&mut self) -> Option<Walked> {
+        loop {
+            if let Some(walked) = self.ready.pop_front() {
+                return Some(walked);
+            }
+            if self.stop.load(Ordering::SeqCst) {
+                return None;
+            }
+            match self.events.recv_timeout(self.tick()) {
+                Ok(Ok(event)) => self.handle(event, Instant::now()),
+                Ok(Err(err)) => {
+                    let path = err.paths.first().cloned().unwrap_or(self.root.clone());
+                    self.ready.push_back(Walked::Error(path, notify_error(err)));
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+            self.check_pending(Instant::now());
+        }
+    }
+}
+
+fn notify_error(err: notify::Error) -> io::Error {
+    match err.kind {
+        notify::ErrorKind::Io(err) => err,
+        notify::ErrorKind::PathNotFound => {
+            io::Error::new(io::ErrorKind::NotFound, "path not found")
+        }
+        _ => io::Error::other(err),
+    }
+}
+
+#[cfg(feature = "async-core")]
+mod async_watch {
+    use futures_lite::StreamExt;
+
+    use super::{WatchEvent, Watcher};
+    use crate::{
+        dir::Walked,
+        rt::{self, Unblock},
+        ClamAvAsync, Verdict,
+    };
+
+    impl Watcher {
+        /// Scans files with an asynchronous client until the watcher is stopped
+        ///
+        /// The watcher runs on a blocking thread pool, files are scanned one
+        /// at a time.
+        ///
+        /// # Arguments
+        ///
+        /// * `client`: The client used to scan the files
+        /// * `callback`: Called with the result of every scan and every error
+        pub async fn run_async<C: ClamAvAsync>(
+            self,
+            client: &C,
+            mut callback: impl FnMut(WatchEvent) + Send,
+        ) {
+            let options = self.settler.options.clone();
+            let mut settled = Unblock::new(self.settler, 1);
+            while let Some(walked) = settled.next().await {
+                let event = match walked {
+                    Walked::File(path, size) => {
+                        let response = client.scan_file(&path, options.filter.chunk_size).await;
+                        let verdict = options.verdict(&response);
+                        let action = options.action(&verdict).clone();
+                        let signatures = verdict
+                            .as_ref()
+                            .map_or(&[][..], Verdict::signatures)
+                            .to_vec();
+                        let (path, action) = rt::unblock(move || {
+                            let taken = action.apply_with_signatures(&path, &signatures);
+                            (path, taken)
+                        })
+                        .await;
+                        WatchEvent {
+                            path,
+                            size: Some(size),
+                            response,
+                            verdict,
+                            action,
+                        }
+                    }
+                    Walked::Error(path, err) => {
+                        let response = Err(err);
+                        WatchEvent {
+                            verdict: options.verdict(&response),
+                            path,
+                            size: None,
+                            response,
+                            action: None,
+                        }
+                    }
+                };
+                callback(event);
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Server side of the clamd protocol, shared by the mock server and the proxy
+
+use std::io::{self, BufRead, Read, Write};
+
+/// How a command was delimited, which determines the reply delimiter
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Delimiter {
+    Null,
+    Newline,
+}
+
+impl Delimiter {
+    fn byte(self) -> u8 {
+        match self {
+            Delimiter::Null => b'\0',
+            Delimiter::Newline => b'\n',
+        }
+    }
+}
+
+/// Maximum length of a command line, longer commands fail the connection
+pub(crate) const MAX_COMMAND_LENGTH: u64 = 8 * 1024;
+
+/// Reads a `z`- or `n`-prefixed or unprefixed command
+///
+/// Returns [`None`] if the client closed the connection, and an error if the
+/// command is longer than [`MAX_COMMAND_LENGTH`].
+pub(crate) fn read_command<R: BufRead>(reader: &mut R) -> io::Result<Option<(String, Delimiter)>> {
+    let mut prefix = [0; 1];
+    if reader.read(&mut prefix)? == 0 {
+        return Ok(None);
+    }
+    let delimiter = match prefix[0] {
+        b'z' => Delimiter::Null,
+        _ => Delimiter::Newline,
+    };
+    let mut command = Vec::new();
+    if !matches!(prefix[0], b'z' | b'n') {
+        command.push(prefix[0]);
+    }
+    reader
+        .take(MAX_COMMAND_LENGTH)
+        .read_until(delimiter.byte(), &mut command)?;
+    if command.last() == Some(&delimiter.byte()) {
+        command.pop();
+    } else if command.len() as u64 >= MAX_COMMAND_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "command too long",
+        ));
+    }
+    let command = String::from_utf8_lossy(&command).trim_end().to_owned();
+    Ok(Some((command, delimiter)))
+}
+
+pub(crate) fn write_reply<W: Write>(
+    stream: &mut W,
+    reply: &str,
+    delimiter: Delimiter,
+) -> io::Result<()> {
+    stream.write_all(reply.as_bytes())?;
+    stream.write_all(&[delimiter.byte()])?;
+    stream.flush()
+}
+
+/// Reads the chunks of an `INSTREAM` command up to the terminating empty chunk
+///
+/// Returns [`None`] if the data exceeds `max_length`. Unlike clamd, the
+/// remaining chunks are drained before replying, so that clients never fail
+/// with a broken pipe while still sending.
+pub(crate) fn read_instream<R: Read>(
+    stream: &mut R,
+    max_length: u64,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut exceeded = false;
+    loop {
+        let mut length = [0; 4];
+        stream.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as u64;
+        if length == 0 {
+            break;
+        }
+        exceeded |= data.len() as u64 + length > max_length;
+        if exceeded {
+            io::copy(&mut stream.take(length), &mut io::sink())?;
+        } else {
+            stream.take(length).read_to_end(&mut data)?;
+        }
+    }
+    Ok((!exceeded).then_some(data))
+}
+
+/// Reply of clamd to streams longer than `StreamMaxLength`
+pub(crate) const SIZE_LIMIT_EXCEEDED: &str = "INSTREAM size limit exceeded. ERROR";
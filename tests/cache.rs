@@ -0,0 +1,269 @@
+#![cfg(all(feature = "cache", feature = "testing"))]
+
+mod common;
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use clamav_client::cache::{CacheKey, CacheStore, CachedClient, DiskCache, MemoryCache};
+use clamav_client::testing::{MockClamd, MockClamdServer, DEFAULT_VERSION, EICAR};
+use clamav_client::Tcp;
+
+const EICAR_TEST_FILE_PATH: &str = "tests/data/eicar.txt";
+const EICAR_FILE_SIGNATURE_FOUND_RESPONSE: &[u8] = b"stream: Eicar-Signature FOUND\0";
+const OK_RESPONSE: &[u8] = b"stream: OK\0";
+
+fn mock_clamd(version: &str) -> MockClamdServer<Tcp> {
+    common::spawn(MockClamd::new().version(version).stream_max_length(1_000))
+}
+
+/// Temporary cache directory that is removed on drop
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        TempDir(std::env::temp_dir().join(format!(
+            "clamav-client-cache-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        )))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn key(byte: u8, db_version: &str) -> CacheKey {
+    CacheKey {
+        sha256: [byte; 32],
+        db_version: db_version.to_string(),
+    }
+}
+
+#[test]
+fn memory_cache_evicts_least_recently_used() {
+    let cache = MemoryCache::new(2);
+    cache.insert(key(1, "1"), b"one".to_vec());
+    cache.insert(key(2, "1"), b"two".to_vec());
+    assert_eq!(cache.get(&key(1, "1")), Some(b"one".to_vec()));
+
+    cache.insert(key(3, "1"), b"three".to_vec());
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&key(2, "1")), None);
+    assert_eq!(cache.get(&key(1, "1")), Some(b"one".to_vec()));
+
+    cache.insert(key(4, "2"), b"four".to_vec());
+    cache.retain_version("2");
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.get(&key(4, "2")), Some(b"four".to_vec()));
+}
+
+#[test]
+fn disk_cache_survives_reopening() {
+    let dir = TempDir::new();
+    let cache = DiskCache::open(&dir.0).unwrap();
+    cache.insert(key(1, "27430"), OK_RESPONSE.to_vec());
+    cache.insert(key(2, "27431"), OK_RESPONSE.to_vec());
+
+    let cache = DiskCache::open(&dir.0).unwrap();
+    assert_eq!(cache.get(&key(1, "27430")), Some(OK_RESPONSE.to_vec()));
+    assert_eq!(cache.get(&key(1, "27431")), None);
+
+    cache.retain_version("27431");
+    assert_eq!(cache.get(&key(1, "27430")), None);
+    assert_eq!(cache.get(&key(2, "27431")), Some(OK_RESPONSE.to_vec()));
+    cache.clear();
+    assert_eq!(cache.get(&key(2, "27431")), None);
+}
+
+#[test]
+fn disk_cache_concurrent_inserts() {
+    let dir = TempDir::new();
+    let cache = DiskCache::open(&dir.0).unwrap();
+    let responses: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 64 * 1024]).collect();
+    std::thread::scope(|scope| {
+        for response in &responses {
+            let cache = &cache;
+            scope.spawn(move || {
+                for _ in 0..10 {
+                    cache.insert(key(1, "27430"), response.clone());
+                }
+            });
+        }
+    });
+
+    // The entry is one of the complete responses and no partial files remain
+    let entry = cache.get(&key(1, "27430")).unwrap();
+    assert!(responses.contains(&entry));
+    let files = fs::read_dir(dir.0.join("27430")).unwrap().count();
+    assert_eq!(files, 1);
+}
+
+mod test_cache_sync {
+    use super::*;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn identical_data_is_scanned_once() {
+        let server = mock_clamd(DEFAULT_VERSION);
+        let client = CachedClient::new(server.transport().clone(), MemoryCache::new(100));
+
+        let response = client.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        let response = client.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        // The test file ends with a newline, so its digest differs
+        for _ in 0..2 {
+            let response = client.scan_file(EICAR_TEST_FILE_PATH, None).unwrap();
+            assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        }
+        let response = client.scan_buffer(b"clean", None).unwrap();
+        assert_eq!(&response, OK_RESPONSE);
+
+        assert_eq!(client.hits(), 2);
+        assert_eq!(client.misses(), 3);
+        assert_eq!(server.scans(), 3);
+        assert_eq!(client.store().len(), 3);
+
+        client.invalidate();
+        assert!(client.store().is_empty());
+        client.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(server.scans(), 4);
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let server = mock_clamd(DEFAULT_VERSION);
+        let client = CachedClient::new(server.transport().clone(), MemoryCache::new(100));
+        let oversized = vec![0; 2_000];
+
+        for _ in 0..2 {
+            let response = client.scan_buffer(&oversized, None).unwrap();
+            assert!(response.ends_with(b"ERROR\0"));
+        }
+        assert_eq!(client.hits(), 0);
+        assert!(client.store().is_empty());
+    }
+
+    #[test]
+    fn signature_updates_invalidate_entries() {
+        let dir = TempDir::new();
+        let old = mock_clamd(DEFAULT_VERSION);
+        let new = mock_clamd("ClamAV 1.4.1/27431/Thu Oct 17 08:36:52 2024");
+
+        let client = CachedClient::new(old.transport().clone(), DiskCache::open(&dir.0).unwrap());
+        client.scan_buffer(EICAR, None).unwrap();
+        client.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(old.scans(), 1);
+        assert!(dir.0.join("27430").is_dir());
+
+        let client = CachedClient::new(new.transport().clone(), DiskCache::open(&dir.0).unwrap())
+            .version_ttl(Duration::ZERO);
+        client.scan_buffer(EICAR, None).unwrap();
+        client.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(new.scans(), 1);
+        assert!(!dir.0.join("27430").exists());
+        assert!(dir.0.join("27431").is_dir());
+    }
+
+    /// Transport that replaces the content of a file before it is scanned
+    struct Rewrite {
+        inner: Tcp,
+        path: PathBuf,
+    }
+
+    impl ClamAvSync for Rewrite {
+        type Stream = std::net::TcpStream;
+
+        fn connect(&self) -> std::io::Result<Self::Stream> {
+            self.inner.connect()
+        }
+
+        fn scan_reader<R: std::io::Read>(
+            &self,
+            reader: R,
+            chunk_size: Option<usize>,
+        ) -> clamav_client::IoResult {
+            fs::write(&self.path, EICAR)?;
+            self.inner.scan_reader(reader, chunk_size)
+        }
+    }
+
+    #[test]
+    fn file_changed_while_scanning() {
+        let server = mock_clamd(DEFAULT_VERSION);
+        let dir = TempDir::new();
+        fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("upload.bin");
+        fs::write(&path, b"clean").unwrap();
+        let inner = Rewrite {
+            inner: server.transport().clone(),
+            path: path.clone(),
+        };
+        let client = CachedClient::new(inner, MemoryCache::new(100));
+
+        let response = client.scan_file(&path, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+
+        // The verdict is cached for the content that was scanned
+        assert_eq!(client.scan_buffer(b"clean", None).unwrap(), OK_RESPONSE);
+        assert_eq!(client.hits(), 0);
+        assert_eq!(
+            client.scan_buffer(EICAR, None).unwrap(),
+            EICAR_FILE_SIGNATURE_FOUND_RESPONSE
+        );
+        assert_eq!(client.hits(), 1);
+    }
+
+    #[test]
+    fn unreachable_server() {
+        let mut server = mock_clamd(DEFAULT_VERSION);
+        let client = CachedClient::new(server.transport().clone(), MemoryCache::new(100));
+        server.stop();
+
+        assert!(client.scan_buffer(EICAR, None).is_err());
+        assert!(client.store().is_empty());
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_cache_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn streams_populate_the_cache() {
+        let server = mock_clamd(DEFAULT_VERSION);
+        let client = CachedClient::new(server.transport().clone(), MemoryCache::new(100));
+
+        let chunks = EICAR
+            .chunks(10)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let response = client
+            .scan_stream(futures_lite::stream::iter(chunks), None)
+            .await
+            .unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+
+        let response = client.scan_buffer(EICAR, None).await.unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eq!(client.hits(), 1);
+        assert_eq!(server.scans(), 1);
+
+        for _ in 0..2 {
+            let response = client.scan_file(EICAR_TEST_FILE_PATH, None).await.unwrap();
+            assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        }
+        assert_eq!(client.hits(), 2);
+        assert_eq!(server.scans(), 2);
+    }
+}
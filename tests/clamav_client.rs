@@ -654,3 +654,387 @@ mod test_stream_asnc_std {
         assert_eq!(clamav_client::clean(&response), Ok(false));
     }
 }
+
+#[cfg(feature = "tokio")]
+mod test_tokio_native {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    static TOKIO_TCP: LazyLock<clamav_client::tokio::Tcp> =
+        LazyLock::new(|| clamav_client::tokio::Tcp(TEST_HOST_ADDRESS.parse().unwrap()));
+
+    #[cfg(unix)]
+    static TOKIO_SOCKET: LazyLock<clamav_client::tokio::Socket> =
+        LazyLock::new(|| clamav_client::tokio::Socket(TEST_SOCKET_PATH.into()));
+
+    #[tokio::test]
+    async fn ping_tcp() {
+        let err_msg = format!("Could not ping clamd via TCP at {}", TOKIO_TCP.0);
+        let response = TOKIO_TCP.ping().await.expect(&err_msg);
+        assert_eq!(&response, clamav_client::PONG);
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn ping_socket() {
+        let err_msg = format!(
+            "Could not ping clamd via Unix socket at {:?}",
+            TOKIO_SOCKET.0
+        );
+        let response = TOKIO_SOCKET.ping().await.expect(&err_msg);
+        assert_eq!(&response, clamav_client::PONG);
+    }
+
+    #[tokio::test]
+    async fn scan_tcp_infected_buffer() {
+        let err_msg = format!(
+            "Could not scan EICAR test string via TCP at {}",
+            TOKIO_TCP.0
+        );
+        let buffer = include_bytes!("data/eicar.txt");
+        let response = TOKIO_TCP.scan_buffer(buffer, None).await.expect(&err_msg);
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eq!(clamav_client::clean(&response), Ok(false));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn scan_socket_clean_file() {
+        let err_msg = format!(
+            "Could not scan test file {} via socket at {:?}",
+            CLEAN_TEST_FILE_PATH, TOKIO_SOCKET.0
+        );
+        let response = TOKIO_SOCKET
+            .scan_file(CLEAN_TEST_FILE_PATH, None)
+            .await
+            .expect(&err_msg);
+        assert_eq!(&response, OK_RESPONSE);
+        assert_eq!(clamav_client::clean(&response), Ok(true));
+    }
+
+    #[tokio::test]
+    async fn scan_tcp_infected_reader() {
+        let file = tokio::fs::File::open(EICAR_TEST_FILE_PATH).await.unwrap();
+        let err_msg = format!(
+            "Could not scan test file {} via TCP at {}",
+            EICAR_TEST_FILE_PATH, TOKIO_TCP.0
+        );
+        let response = clamav_client::tokio::scan_reader(&*TOKIO_TCP, file, None)
+            .await
+            .expect(&err_msg);
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eq!(clamav_client::clean(&response), Ok(false));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "tokio-stream")]
+    async fn scan_tcp_infected_tokio_stream() {
+        let chunks = include_bytes!("data/eicar.txt")
+            .chunks(8)
+            .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec()));
+        let stream = tokio_stream::iter(chunks);
+        let err_msg = format!(
+            "Could not scan EICAR test stream via TCP at {}",
+            TOKIO_TCP.0
+        );
+        let response = clamav_client::tokio::scan_stream(&*TOKIO_TCP, stream, None)
+            .await
+            .expect(&err_msg);
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eq!(clamav_client::clean(&response), Ok(false));
+    }
+}
+
+mod test_dyn_sync {
+    use super::*;
+    use clamav_client::DynClamAvSync;
+    use std::sync::Arc;
+
+    fn clients() -> Vec<Arc<dyn DynClamAvSync + Send + Sync>> {
+        #[allow(unused_mut)]
+        let mut clients: Vec<Arc<dyn DynClamAvSync + Send + Sync>> = vec![Arc::new(TCP.clone())];
+        #[cfg(unix)]
+        clients.push(Arc::new(SOCKET.clone()));
+        clients
+    }
+
+    #[test]
+    fn ping_dyn() {
+        for client in clients() {
+            let response = client.ping().expect("Could not ping clamd");
+            assert_eq!(&response, clamav_client::PONG);
+        }
+    }
+
+    #[test]
+    fn scan_dyn_infected_file() {
+        for client in clients() {
+            let response = client
+                .scan_file(EICAR_TEST_FILE_PATH.as_ref(), None)
+                .expect("Could not scan test file");
+            assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        }
+    }
+
+    #[test]
+    fn scan_dyn_clean_reader() {
+        for client in clients() {
+            let mut file = std::fs::File::open(CLEAN_TEST_FILE_PATH).unwrap();
+            let response = client
+                .scan_reader(&mut file, None)
+                .expect("Could not scan test file");
+            assert_eq!(&response, OK_RESPONSE);
+        }
+    }
+
+    #[test]
+    fn scan_dyn_reader_with_client_limits() {
+        let client: Arc<dyn DynClamAvSync + Send + Sync> = Arc::new(
+            clamav_client::ClamAvClient::builder(TCP.clone())
+                .max_stream_length(10)
+                .build(),
+        );
+        let err = client.scan_reader(&mut &[0; 11][..], None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_dyn_async {
+    use super::*;
+    use clamav_client::DynClamAvAsync;
+    use std::sync::Arc;
+
+    fn clients() -> Vec<Arc<dyn DynClamAvAsync>> {
+        #[allow(unused_mut)]
+        let mut clients: Vec<Arc<dyn DynClamAvAsync>> = vec![Arc::new(TCP.clone())];
+        #[cfg(unix)]
+        clients.push(Arc::new(SOCKET.clone()));
+        clients
+    }
+
+    #[tokio::test]
+    async fn ping_dyn() {
+        for client in clients() {
+            let response = client.ping().await.expect("Could not ping clamd");
+            assert_eq!(&response, clamav_client::PONG);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_dyn_infected_buffer() {
+        let buffer = include_bytes!("data/eicar.txt");
+        for client in clients() {
+            let response = client
+                .scan_buffer(buffer, None)
+                .await
+                .expect("Could not scan EICAR test string");
+            assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_dyn_clean_reader() {
+        for client in clients() {
+            let mut file = async_std::fs::File::open(CLEAN_TEST_FILE_PATH)
+                .await
+                .unwrap();
+            let response = client
+                .scan_reader(&mut file, None)
+                .await
+                .expect("Could not scan test file");
+            assert_eq!(&response, OK_RESPONSE);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_dyn_infected_stream() {
+        for client in clients() {
+            let chunks = include_bytes!("data/eicar.txt")
+                .chunks(8)
+                .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)));
+            let stream = futures_lite::stream::iter(chunks);
+            let response = client
+                .scan_stream(Box::pin(stream), None)
+                .await
+                .expect("Could not scan EICAR test stream");
+            assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_dyn_reader_with_client_limits() {
+        let client: Arc<dyn DynClamAvAsync> = Arc::new(
+            clamav_client::ClamAvClient::builder(TCP.clone())
+                .max_stream_length(10)
+                .build(),
+        );
+        let err = client
+            .scan_reader(&mut &[0; 11][..], None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
+
+mod test_verdict {
+    use super::*;
+    use clamav_client::Verdict;
+
+    #[test]
+    fn parse_responses() {
+        assert_eq!(Verdict::parse(OK_RESPONSE), Ok(Verdict::Clean));
+        assert_eq!(
+            Verdict::parse(EICAR_FILE_SIGNATURE_FOUND_RESPONSE),
+            Ok(Verdict::Infected(vec!["Eicar-Signature".into()]))
+        );
+        assert_eq!(
+            Verdict::parse(SIZE_LIMIT_EXCEEDED_ERROR_RESPONSE),
+            Ok(Verdict::Error("INSTREAM size limit exceeded. ERROR".into()))
+        );
+        assert_eq!(
+            Verdict::parse(b"1: stream: Eicar-Signature FOUND\n1: stream: Other FOUND\n"),
+            Ok(Verdict::Infected(vec![
+                "Eicar-Signature".into(),
+                "Other".into()
+            ]))
+        );
+        assert!(Verdict::parse(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn matches_are_not_hidden_by_errors() {
+        use clamav_client::VerdictPolicy;
+
+        let response = b"1: stream: Eicar-Signature FOUND
+2: Can't allocate memory ERROR
+";
+        let infected = Verdict::Infected(vec!["Eicar-Signature".into()]);
+        assert_eq!(Verdict::parse(response), Ok(infected.clone()));
+        let verdict = Verdict::parse(response).unwrap();
+        assert_eq!(VerdictPolicy::FailOpen.apply(verdict).unwrap(), infected);
+
+        let response = b"1: stream: OK
+2: Can't allocate memory ERROR
+";
+        assert_eq!(
+            Verdict::parse(response),
+            Ok(Verdict::Error("2: Can't allocate memory ERROR".into()))
+        );
+    }
+}
+
+mod test_client_sync {
+    use super::*;
+    use clamav_client::{ClamAvClient, ClamAvSync, RetryPolicy, Verdict, VerdictPolicy};
+    use std::time::Duration;
+
+    #[test]
+    fn scan_tcp_infected_file_with_defaults() {
+        let client = ClamAvClient::builder(TCP.clone())
+            .chunk_size(16)
+            .connect_timeout(Duration::from_secs(5))
+            .read_timeout(Duration::from_secs(30))
+            .build();
+        let response = client.scan_file(EICAR_TEST_FILE_PATH, None).unwrap();
+        assert_eq!(
+            client.verdict(&response).unwrap(),
+            Verdict::Infected(vec!["Eicar-Signature".into()])
+        );
+    }
+
+    #[test]
+    fn reject_oversized_file() {
+        let client = ClamAvClient::builder(TCP.clone())
+            .max_stream_length(1_000_000)
+            .build();
+        let err = client
+            .scan_file(OVERSIZED_TEST_FILE_PATH, None)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let err = client.scan_buffer(&[0; 1_000_001], None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn apply_verdict_policy() {
+        let fail_open = ClamAvClient::builder(TCP.clone())
+            .verdict_policy(VerdictPolicy::FailOpen)
+            .build();
+        let response = fail_open.scan_file(OVERSIZED_TEST_FILE_PATH, None).unwrap();
+        assert_eq!(fail_open.verdict(&response).unwrap(), Verdict::Clean);
+
+        let strict = ClamAvClient::builder(TCP.clone())
+            .verdict_policy(VerdictPolicy::Strict)
+            .build();
+        assert!(strict.verdict(&response).is_err());
+    }
+
+    #[test]
+    fn retry_failed_connection() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let retry_policy = RetryPolicy {
+            max_retries: 2,
+            backoff: Duration::from_millis(10),
+        };
+        let client = ClamAvClient::builder(Tcp(address))
+            .retry_policy(retry_policy)
+            .build();
+        let start = std::time::Instant::now();
+        assert!(client.ping().is_err());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_client_async {
+    use super::*;
+    use clamav_client::{ClamAvAsync, ClamAvClient, Verdict};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn scan_tcp_infected_stream_with_defaults() {
+        let client = ClamAvClient::builder(TCP.clone())
+            .chunk_size(16)
+            .connect_timeout(Duration::from_secs(5))
+            .read_timeout(Duration::from_secs(30))
+            .build();
+        let chunks = include_bytes!("data/eicar.txt")
+            .chunks(8)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)));
+        let response = client
+            .scan_stream(futures_lite::stream::iter(chunks), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.verdict(&response).unwrap(),
+            Verdict::Infected(vec!["Eicar-Signature".into()])
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_oversized_stream() {
+        let client = ClamAvClient::builder(TCP.clone())
+            .max_stream_length(1_000_000)
+            .build();
+        let chunks = (0..11).map(|_| Ok(bytes::Bytes::from(vec![0; 100_000])));
+        let err = client
+            .scan_stream(futures_lite::stream::iter(chunks), None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn read_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = ClamAvClient::builder(Tcp(listener.local_addr().unwrap()))
+            .read_timeout(Duration::from_millis(50))
+            .build();
+        let err = client.ping().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
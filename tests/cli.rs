@@ -0,0 +1,349 @@
+#![cfg(all(feature = "cli", feature = "testing"))]
+
+mod common;
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+use clamav_client::testing::{MockClamd, MockClamdServer, EICAR};
+use clamav_client::Tcp;
+use common::limited_mock_clamd;
+
+const EICAR_TEST_FILE_PATH: &str = "tests/data/eicar.txt";
+const CLEAN_TEST_FILE_PATH: &str = "README.md";
+const OVERSIZED_TEST_FILE_PATH: &str = "tests/data/stream-max-length-test-file.bin";
+const EICAR_SHA256: &str = "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f";
+const EICAR_MD5: &str = "44d88612fea8a8f36de82e1278abb02f";
+const EICAR_FILE_SHA256: &str = "131f95c51cc819465fa1797f6ccacf9d494aaaff46fa3eac73ae63ffbdfd8267";
+const EICAR_FILE_MD5: &str = "69630e4574ec6798239b091cda43dca0";
+
+fn cli(server: &MockClamdServer<Tcp>) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_clamav-client"));
+    command.arg("--tcp").arg(server.transport().0.to_string());
+    command
+}
+
+fn read_stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn clean_file() {
+    let server = limited_mock_clamd(1_000_000);
+    let output = cli(&server).arg(CLEAN_TEST_FILE_PATH).output().unwrap();
+    let stdout = read_stdout(&output);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout.contains("README.md: OK\n"));
+    assert!(stdout.contains("Scanned files: 1\n"));
+    assert!(stdout.contains("Infected files: 0\n"));
+    assert!(stdout.contains("Time: "));
+}
+
+#[test]
+fn infected_file() {
+    let server = limited_mock_clamd(1_000_000);
+    let output = cli(&server)
+        .args([CLEAN_TEST_FILE_PATH, EICAR_TEST_FILE_PATH])
+        .output()
+        .unwrap();
+    let stdout = read_stdout(&output);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("tests/data/eicar.txt: Eicar-Signature FOUND\n"));
+    assert!(stdout.contains("Scanned files: 2\n"));
+    assert!(stdout.contains("Infected files: 1\n"));
+}
+
+#[test]
+fn infected_only() {
+    let server = limited_mock_clamd(1_000_000);
+    let output = cli(&server)
+        .args(["--infected", "--no-summary"])
+        .args([CLEAN_TEST_FILE_PATH, EICAR_TEST_FILE_PATH])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        read_stdout(&output),
+        "tests/data/eicar.txt: Eicar-Signature FOUND\n"
+    );
+}
+
+#[test]
+fn directory() {
+    let server = limited_mock_clamd(1_000_000);
+    let output = cli(&server)
+        .args(["--jobs", "4", "tests"])
+        .output()
+        .unwrap();
+    let stdout = read_stdout(&output);
+
+    // Without recursion only the files directly inside `tests` are scanned
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout.contains("tests/cli.rs: OK\n"));
+    assert!(!stdout.contains("eicar.txt"));
+
+    let output = cli(&server)
+        .args(["--recursive", "--jobs", "4", "tests"])
+        .output()
+        .unwrap();
+    let stdout = read_stdout(&output);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("tests/cli.rs: OK\n"));
+    assert!(stdout.contains("tests/data/eicar.txt: Eicar-Signature FOUND\n"));
+    assert!(stdout.contains("Infected files: 1\n"));
+}
+
+#[test]
+fn stdin() {
+    let server = limited_mock_clamd(1_000_000);
+    let mut child = cli(&server)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(EICAR).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(read_stdout(&output).contains("stream: Eicar-Signature FOUND\n"));
+
+    // The limit applies while streaming
+    let mut child = cli(&server)
+        .args(["--max-stream-length", "10", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let _ = child.stdin.take().unwrap().write_all(&[0; 100_000]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(read_stdout(&output).contains("input exceeds the maximum stream length ERROR\n"));
+}
+
+#[test]
+fn errors() {
+    let server = limited_mock_clamd(1_000_000);
+    let output = cli(&server)
+        .args(["tests/data/missing.txt", OVERSIZED_TEST_FILE_PATH])
+        .output()
+        .unwrap();
+    let stdout = read_stdout(&output);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout.contains("tests/data/missing.txt: "));
+    assert!(stdout.contains("INSTREAM size limit exceeded. ERROR\n"));
+    assert!(stdout.contains("Total errors: 2\n"));
+
+    let output = cli(&server)
+        .args(["--max-stream-length", "10", CLEAN_TEST_FILE_PATH])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(read_stdout(&output).contains("input exceeds the maximum stream length ERROR\n"));
+}
+
+#[test]
+fn unreachable_server() {
+    let mut server = limited_mock_clamd(1_000_000);
+    server.stop();
+    let output = cli(&server).arg(CLEAN_TEST_FILE_PATH).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(read_stdout(&output).contains("Total errors: 1\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket() {
+    let path = std::env::temp_dir().join(format!("clamav-client-cli-{}.sock", std::process::id()));
+    let server = MockClamd::new().bind_socket(&path).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_clamav-client"))
+        .arg("--socket")
+        .arg(&server.transport().0)
+        .arg(EICAR_TEST_FILE_PATH)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(read_stdout(&output).contains("Eicar-Signature FOUND"));
+}
+
+#[test]
+fn json_format() {
+    let server = limited_mock_clamd(1_000_000);
+    let output = cli(&server)
+        .args([
+            "--format",
+            "json",
+            CLEAN_TEST_FILE_PATH,
+            EICAR_TEST_FILE_PATH,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["records"].as_array().unwrap().len(), 2);
+    assert_eq!(value["summary"]["infected"], 1);
+    let backend = format!("tcp://{}", server.transport().0);
+    assert_eq!(value["records"][0]["backend"], backend.as_str());
+}
+
+#[test]
+fn ndjson_format() {
+    let server = limited_mock_clamd(1_000_000);
+    let output = cli(&server)
+        .args(["--format", "ndjson", "--recursive", "tests/data"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let records: Vec<serde_json::Value> = read_stdout(&output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let eicar = records
+        .iter()
+        .find(|record| record["path"] == "tests/data/eicar.txt")
+        .unwrap();
+    assert_eq!(eicar["status"], "infected");
+    assert_eq!(eicar["size"], 69);
+    assert_eq!(eicar["sha256"], EICAR_FILE_SHA256);
+    assert_eq!(eicar["md5"], EICAR_FILE_MD5);
+    assert!(records.iter().any(|record| record["status"] == "error"));
+
+    // Standard input is hashed while streaming
+    let mut child = cli(&server)
+        .args(["--format", "ndjson", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(EICAR).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let record: serde_json::Value = serde_json::from_str(read_stdout(&output).trim()).unwrap();
+    assert_eq!(record["size"], EICAR.len());
+    assert_eq!(record["sha256"], EICAR_SHA256);
+    assert_eq!(record["md5"], EICAR_MD5);
+}
+
+#[test]
+fn sarif_output_file() {
+    let server = limited_mock_clamd(1_000_000);
+    let path = std::env::temp_dir().join(format!("clamav-client-cli-{}.sarif", std::process::id()));
+    let output = cli(&server)
+        .args(["--format", "sarif", EICAR_TEST_FILE_PATH, "--output"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let sarif = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&sarif).unwrap();
+    assert_eq!(value["runs"][0]["results"][0]["ruleId"], "Eicar-Signature");
+}
+
+#[test]
+fn watch_directory() {
+    use std::io::{BufRead, BufReader};
+
+    let server = limited_mock_clamd(1_000_000);
+    let dir = std::env::temp_dir().join(format!("clamav-client-cli-watch-{}", std::process::id()));
+    let quarantine = dir.with_extension("moved");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::create_dir_all(&quarantine).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_clamav-client"))
+        .arg("watch")
+        .arg("--tcp")
+        .arg(server.transport().0.to_string())
+        .args(["--settle", "100", "--on-infected", "move", "--move-to"])
+        .arg(&quarantine)
+        .args(["--format", "ndjson"])
+        .arg(&dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Give the watcher time to start
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    std::fs::write(dir.join("eicar.com"), EICAR).unwrap();
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(record["status"], "infected");
+    assert_eq!(record["signatures"][0], "Eicar-Signature");
+    assert!(record["path"].as_str().unwrap().ends_with("eicar.com"));
+    assert!(record["action"].as_str().unwrap().starts_with("moved to"));
+    assert!(quarantine.join("eicar.com").exists());
+    assert!(!dir.join("eicar.com").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&quarantine).unwrap();
+}
+
+#[cfg(feature = "quarantine")]
+#[test]
+fn watch_quarantine() {
+    use std::io::{BufRead, BufReader};
+
+    use clamav_client::quarantine::Quarantine;
+
+    let server = limited_mock_clamd(1_000_000);
+    let dir = std::env::temp_dir().join(format!(
+        "clamav-client-cli-watch-quarantine-{}",
+        std::process::id()
+    ));
+    let quarantine = dir.with_extension("quarantine");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_clamav-client"))
+        .arg("watch")
+        .arg("--tcp")
+        .arg(server.transport().0.to_string())
+        .args(["--settle", "100", "--on-infected", "quarantine"])
+        .arg("--quarantine-dir")
+        .arg(&quarantine)
+        .args(["--format", "ndjson"])
+        .arg(&dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Give the watcher time to start
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    std::fs::write(dir.join("eicar.com"), EICAR).unwrap();
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert!(record["action"]
+        .as_str()
+        .unwrap()
+        .starts_with("quarantined as"));
+    let entries = Quarantine::open(&quarantine).unwrap().list().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].signatures, vec!["Eicar-Signature".to_string()]);
+    assert!(!dir.join("eicar.com").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&quarantine).unwrap();
+}
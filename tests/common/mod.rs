@@ -0,0 +1,22 @@
+//! Helpers shared by the integration tests
+#![cfg(feature = "testing")]
+#![allow(dead_code)]
+
+use clamav_client::testing::{MockClamd, MockClamdServer};
+use clamav_client::Tcp;
+
+/// Starts a mock clamd with clamd's default settings on a free local port
+pub fn mock_clamd() -> MockClamdServer<Tcp> {
+    spawn(MockClamd::new())
+}
+
+/// Starts a mock clamd that accepts at most `stream_max_length` bytes per
+/// `INSTREAM` session
+pub fn limited_mock_clamd(stream_max_length: u64) -> MockClamdServer<Tcp> {
+    spawn(MockClamd::new().stream_max_length(stream_max_length))
+}
+
+/// Starts a configured mock clamd on a free local port
+pub fn spawn(mock: MockClamd) -> MockClamdServer<Tcp> {
+    mock.bind_tcp("127.0.0.1:0").unwrap()
+}
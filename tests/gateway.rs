@@ -0,0 +1,270 @@
+#![cfg(all(feature = "gateway", feature = "testing"))]
+
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+use clamav_client::gateway::{Gateway, ScanResponse};
+use clamav_client::testing::{MockClamdServer, EICAR};
+use clamav_client::Tcp;
+use common::mock_clamd;
+use serde_json::Value;
+
+fn tokio_transport(clamd: &MockClamdServer<Tcp>) -> clamav_client::tokio::Tcp {
+    clamav_client::tokio::Tcp(clamd.transport().0)
+}
+
+/// Serves the gateway on a background thread and returns its address
+fn start(gateway: Gateway<clamav_client::tokio::Tcp>) -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    listener.set_nonblocking(true).unwrap();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            let _ = gateway.serve(listener).await;
+        });
+    });
+    address
+}
+
+/// Sends a request and returns the status code and the JSON body
+fn request(address: SocketAddr, target: &str, headers: &str, body: &[u8]) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{target} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\r\n"
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let response = String::from_utf8(response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    assert!(head.contains("content-type: application/json"), "{head}");
+    (status, serde_json::from_str(body).unwrap())
+}
+
+fn get(address: SocketAddr, path: &str) -> (u16, Value) {
+    request(address, &format!("GET {path}"), "", b"")
+}
+
+fn post(address: SocketAddr, content_type: &str, body: &[u8]) -> (u16, Value) {
+    let headers = format!(
+        "Content-Type: {content_type}\r\nContent-Length: {}\r\n",
+        body.len()
+    );
+    request(address, "POST /scan", &headers, body)
+}
+
+fn multipart(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, file_name, data) in parts {
+        body.extend_from_slice(b"--BOUNDARY\r\n");
+        match file_name {
+            Some(file_name) => write!(
+                body,
+                "Content-Disposition: form-data; name=\"{name}\"; filename=\"{file_name}\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n"
+            ),
+            None => write!(
+                body,
+                "Content-Disposition: form-data; name=\"{name}\"\r\n\r\n"
+            ),
+        }
+        .unwrap();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(b"--BOUNDARY--\r\n");
+    body
+}
+
+#[test]
+fn scan_raw_body() {
+    let clamd = mock_clamd();
+    let address = start(Gateway::new(tokio_transport(&clamd)));
+
+    let (status, body) = post(address, "application/octet-stream", EICAR);
+    assert_eq!(status, 200);
+    let response: ScanResponse = serde_json::from_value(body).unwrap();
+    assert!(!response.clean);
+    assert_eq!(response.files.len(), 1);
+    let file = &response.files[0];
+    assert_eq!(file.record.path, "stream");
+    assert_eq!(file.record.signatures, ["Eicar-Signature"]);
+    assert_eq!(file.record.size, Some(EICAR.len() as u64));
+    assert_eq!(
+        file.content_type.as_deref(),
+        Some("application/octet-stream")
+    );
+
+    let (status, body) = post(address, "text/plain", b"clean");
+    assert_eq!(status, 200);
+    assert_eq!(body["clean"], true);
+    assert_eq!(body["files"][0]["status"], "clean");
+    assert_eq!(clamd.scans(), 2);
+}
+
+#[test]
+fn scan_multipart() {
+    let clamd = mock_clamd();
+    let address = start(Gateway::new(tokio_transport(&clamd)));
+
+    let body = multipart(&[
+        ("comment", None, b"a comment"),
+        ("first", Some("clean.txt"), b"clean"),
+        ("second", Some("eicar.com"), EICAR),
+    ]);
+    let (status, body) = post(address, "multipart/form-data; boundary=BOUNDARY", &body);
+    assert_eq!(status, 200);
+    let response: ScanResponse = serde_json::from_value(body).unwrap();
+    assert!(!response.clean);
+    assert_eq!(response.files.len(), 3);
+    assert_eq!(response.files[0].field.as_deref(), Some("comment"));
+    assert_eq!(response.files[0].record.path, "stream");
+    assert_eq!(response.files[1].field.as_deref(), Some("first"));
+    assert_eq!(response.files[1].record.path, "clean.txt");
+    assert!(response.files[1].record.signatures.is_empty());
+    assert_eq!(response.files[2].field.as_deref(), Some("second"));
+    assert_eq!(response.files[2].record.path, "eicar.com");
+    assert_eq!(response.files[2].record.signatures, ["Eicar-Signature"]);
+    assert_eq!(response.files[2].record.size, Some(EICAR.len() as u64));
+    assert_eq!(clamd.scans(), 3);
+
+    // Form fields are scanned too
+    let body = multipart(&[("comment", None, EICAR)]);
+    let (status, body) = post(address, "multipart/form-data; boundary=BOUNDARY", &body);
+    assert_eq!(status, 200);
+    assert_eq!(body["clean"], false);
+    assert_eq!(body["files"][0]["signatures"][0], "Eicar-Signature");
+
+    // Nothing is reported clean without being scanned
+    let (status, _) = post(
+        address,
+        "multipart/form-data; boundary=BOUNDARY",
+        b"--BOUNDARY--\r\n",
+    );
+    assert_eq!(status, 400);
+    let (status, _) = post(address, "multipart/form-data; boundary=BOUNDARY", EICAR);
+    assert_eq!(status, 400);
+    assert_eq!(clamd.scans(), 4);
+}
+
+#[test]
+fn body_too_large() {
+    let clamd = mock_clamd();
+    let address = start(Gateway::new(tokio_transport(&clamd)).max_body_size(4));
+
+    let (status, body) = post(address, "text/plain", b"clean");
+    assert_eq!(status, 413);
+    assert_eq!(body["error"], "body too large");
+    assert_eq!(clamd.scans(), 0);
+
+    // Without Content-Length the limit applies while streaming
+    let headers = "Transfer-Encoding: chunked\r\n";
+    let (status, _) = request(
+        address,
+        "POST /scan",
+        headers,
+        b"3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n",
+    );
+    assert_eq!(status, 413);
+
+    let body = multipart(&[("file", Some("a.txt"), b"clean")]);
+    let (status, _) = post(address, "multipart/form-data; boundary=BOUNDARY", &body);
+    assert_eq!(status, 413);
+}
+
+#[test]
+fn concurrency_limit() {
+    let clamd = mock_clamd();
+    let address = start(Gateway::new(tokio_transport(&clamd)).max_concurrent_scans(0));
+
+    let (status, body) = post(address, "text/plain", b"clean");
+    assert_eq!(status, 503);
+    assert_eq!(body["error"], "too many concurrent scans");
+}
+
+#[test]
+fn clamd_unreachable() {
+    let mut clamd = mock_clamd();
+    clamd.stop();
+    let address = start(Gateway::new(tokio_transport(&clamd)));
+
+    let (status, body) = post(address, "text/plain", b"clean");
+    assert_eq!(status, 502);
+    assert_eq!(body["clean"], false);
+    assert_eq!(body["files"][0]["status"], "error");
+
+    let (status, body) = get(address, "/health");
+    assert_eq!(status, 503);
+    assert_eq!(body["status"], "unhealthy");
+    assert_eq!(body["reachable"], false);
+
+    let (status, _) = get(address, "/version");
+    assert_eq!(status, 502);
+}
+
+#[test]
+fn info_routes() {
+    let clamd = mock_clamd();
+    let address = start(Gateway::new(tokio_transport(&clamd)));
+
+    let (status, body) = get(address, "/version");
+    assert_eq!(status, 200);
+    assert_eq!(body["engine"], "1.4.1");
+    assert_eq!(body["database"], 27430);
+
+    let (status, body) = get(address, "/health");
+    assert!(status == 200 || status == 503, "{status}");
+    assert_eq!(body["reachable"], true);
+    assert_eq!(body["self_test"]["status"], "passed");
+    assert_eq!(body["version"]["database"], 27430);
+
+    let (status, body) = get(address, "/stats");
+    assert_eq!(status, 200);
+    assert!(body["stats"].as_str().unwrap().ends_with("END"));
+
+    let (status, _) = get(address, "/scan");
+    assert_eq!(status, 405);
+    let (status, body) = get(address, "/nothing");
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "not found");
+}
+
+#[cfg(feature = "gateway-cli")]
+#[test]
+fn binary() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let clamd = mock_clamd();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_clamav-gateway"))
+        .args(["--listen", "127.0.0.1:0", "--tcp"])
+        .arg(clamd.transport().0.to_string())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let address = line.trim().strip_prefix("Listening on ").unwrap();
+
+    let (status, body) = post(address.parse().unwrap(), "text/plain", EICAR);
+    assert_eq!(status, 200);
+    assert_eq!(body["files"][0]["signatures"][0], "Eicar-Signature");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
@@ -0,0 +1,177 @@
+#![cfg(all(feature = "hash", feature = "testing"))]
+
+mod common;
+
+use clamav_client::hash::{HashAlgorithms, ScanDigest};
+use clamav_client::testing::EICAR;
+use clamav_client::ClamAvClient;
+use common::limited_mock_clamd;
+
+const EICAR_TEST_FILE_PATH: &str = "tests/data/eicar.txt";
+const EICAR_FILE_SIGNATURE_FOUND_RESPONSE: &[u8] = b"stream: Eicar-Signature FOUND\0";
+
+const EICAR_SHA256: &str = "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f";
+const EICAR_MD5: &str = "44d88612fea8a8f36de82e1278abb02f";
+const EICAR_FILE_SHA256: &str = "131f95c51cc819465fa1797f6ccacf9d494aaaff46fa3eac73ae63ffbdfd8267";
+const EICAR_FILE_MD5: &str = "69630e4574ec6798239b091cda43dca0";
+
+fn assert_eicar_digest(digest: &ScanDigest, chunks: u64) {
+    assert_eq!(digest.bytes, EICAR.len() as u64);
+    assert_eq!(digest.chunks, chunks);
+    assert_eq!(digest.sha256_hex().unwrap(), EICAR_SHA256);
+    assert_eq!(digest.md5_hex().unwrap(), EICAR_MD5);
+}
+
+mod test_hash_sync {
+    use super::*;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn scan_buffer_hashed() {
+        let server = limited_mock_clamd(1_000);
+        let scan = server
+            .transport()
+            .scan_buffer_hashed(EICAR, Some(10), HashAlgorithms::all())
+            .unwrap();
+
+        assert_eq!(&scan.response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eicar_digest(&scan.digest, 7);
+    }
+
+    #[test]
+    fn scan_file_hashed() {
+        let server = limited_mock_clamd(1_000);
+        let scan = server
+            .transport()
+            .scan_file_hashed(EICAR_TEST_FILE_PATH, None, HashAlgorithms::all())
+            .unwrap();
+
+        assert_eq!(&scan.response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eq!(scan.digest.bytes, 69);
+        assert_eq!(scan.digest.chunks, 1);
+        assert_eq!(scan.digest.sha256_hex().unwrap(), EICAR_FILE_SHA256);
+        assert_eq!(scan.digest.md5_hex().unwrap(), EICAR_FILE_MD5);
+    }
+
+    #[test]
+    fn selected_algorithms() {
+        let server = limited_mock_clamd(1_000);
+        let scan = server
+            .transport()
+            .scan_buffer_hashed(b"", None, HashAlgorithms::new())
+            .unwrap();
+        assert_eq!(scan.digest, ScanDigest::default());
+
+        let scan = server
+            .transport()
+            .scan_buffer_hashed(EICAR, None, HashAlgorithms::new().md5(true))
+            .unwrap();
+        assert_eq!(scan.digest.sha256, None);
+        assert_eq!(scan.digest.md5_hex().unwrap(), EICAR_MD5);
+    }
+
+    #[test]
+    fn client_settings_apply() {
+        let server = limited_mock_clamd(1_000);
+        let client = ClamAvClient::builder(server.transport().clone())
+            .chunk_size(16)
+            .max_stream_length(100)
+            .build();
+
+        let scan = client
+            .scan_buffer_hashed(EICAR, None, HashAlgorithms::all())
+            .unwrap();
+        assert_eicar_digest(&scan.digest, 5);
+
+        let err = client
+            .scan_buffer_hashed(&[0; 101], None, HashAlgorithms::all())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn report_record() {
+        use clamav_client::report::ScanRecord;
+
+        let server = limited_mock_clamd(1_000);
+        let scan = server
+            .transport()
+            .scan_buffer_hashed(EICAR, None, HashAlgorithms::all())
+            .unwrap();
+        let verdict = clamav_client::Verdict::parse(&scan.response).unwrap();
+        let record = ScanRecord::from_verdict("eicar.com", verdict).with_digest(&scan.digest);
+
+        assert_eq!(record.size, Some(68));
+        assert_eq!(record.sha256.as_deref(), Some(EICAR_SHA256));
+        assert_eq!(record.md5.as_deref(), Some(EICAR_MD5));
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_hash_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn scan_file_and_buffer_hashed() {
+        let server = limited_mock_clamd(1_000);
+        let scan = server
+            .transport()
+            .scan_file_hashed(EICAR_TEST_FILE_PATH, Some(32), HashAlgorithms::all())
+            .await
+            .unwrap();
+        assert_eq!(&scan.response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eq!(scan.digest.bytes, 69);
+        assert_eq!(scan.digest.chunks, 3);
+        assert_eq!(scan.digest.sha256_hex().unwrap(), EICAR_FILE_SHA256);
+
+        let scan = server
+            .transport()
+            .scan_buffer_hashed(EICAR, Some(10), HashAlgorithms::all())
+            .await
+            .unwrap();
+        assert_eicar_digest(&scan.digest, 7);
+    }
+
+    #[async_std::test]
+    async fn scan_stream_hashed() {
+        let server = limited_mock_clamd(1_000);
+        let chunks: Vec<_> = EICAR
+            .chunks(10)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect();
+        let scan = server
+            .transport()
+            .scan_stream_hashed(
+                futures_lite::stream::iter(chunks),
+                Some(4),
+                HashAlgorithms::all(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(&scan.response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        // Six items of 10 bytes are sent as 3 chunks each, the last 8 bytes as 2
+        assert_eicar_digest(&scan.digest, 20);
+    }
+
+    #[tokio::test]
+    async fn client_settings_apply() {
+        let server = limited_mock_clamd(1_000);
+        let client = ClamAvClient::builder(server.transport().clone())
+            .chunk_size(16)
+            .build();
+        let chunks = vec![Ok(bytes::Bytes::from_static(EICAR))];
+        let scan = client
+            .scan_stream_hashed(
+                futures_lite::stream::iter(chunks),
+                None,
+                HashAlgorithms::all(),
+            )
+            .await
+            .unwrap();
+
+        assert_eicar_digest(&scan.digest, 5);
+    }
+}
@@ -0,0 +1,186 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use clamav_client::health::{EngineVersion, HealthStatus, HealthThresholds, SelfTest};
+use clamav_client::testing::{MockClamd, MockClamdServer, DEFAULT_VERSION};
+use clamav_client::{IoResult, Tcp};
+
+const STALE_VERSION: &str = "ClamAV 0.103.0/25000/Tue Feb 29 23:59:59 2000";
+
+fn mock_clamd(version: &str) -> MockClamdServer<Tcp> {
+    common::spawn(MockClamd::new().version(version))
+}
+
+/// Thresholds under which the fixed database dates are never too old
+fn lenient() -> HealthThresholds {
+    HealthThresholds {
+        max_signature_age: Duration::from_secs(u64::MAX / 2),
+        unhealthy_signature_age: Duration::from_secs(u64::MAX / 2),
+        ..HealthThresholds::default()
+    }
+}
+
+#[test]
+fn parse_version() {
+    let version = EngineVersion::parse(format!("{DEFAULT_VERSION}\0").as_bytes()).unwrap();
+    assert_eq!(version.engine, "1.4.1");
+    assert_eq!(version.database, Some(27430));
+    assert_eq!(
+        version.database_time,
+        Some(UNIX_EPOCH + Duration::from_secs(1_729_067_828))
+    );
+
+    let version = EngineVersion::parse(STALE_VERSION.as_bytes()).unwrap();
+    assert_eq!(
+        version.database_time,
+        Some(UNIX_EPOCH + Duration::from_secs(951_868_799))
+    );
+
+    let version = EngineVersion::parse(b"ClamAV 1.4.1\0").unwrap();
+    assert_eq!(version.engine, "1.4.1");
+    assert_eq!(version.database, None);
+    assert_eq!(version.database_time, None);
+
+    for time in [
+        "Wed Oct 16 24:00:00 2024",
+        "Wed Oct 16 08:60:00 2024",
+        "Wed Oct 16 08:37:61 2024",
+        "Wed Oct 16 08:37:08:00 2024",
+        "Wed Oct 16 -1:37:08 2024",
+        "Wed Oct 32 08:37:08 2024",
+        "Wed Oct 0 08:37:08 2024",
+        "Wed Oct 16 08:37:08 9223372036854775807",
+        "Wed Jan 16 08:37:08 -9223372036854775808",
+    ] {
+        let version = EngineVersion::parse(format!("ClamAV 1.4.1/27430/{time}").as_bytes());
+        assert_eq!(version.unwrap().database_time, None, "{time}");
+    }
+
+    assert_eq!(EngineVersion::parse(b"UNKNOWN COMMAND\0"), None);
+}
+
+/// Transport whose scans never detect anything
+struct Blind(Tcp);
+
+impl clamav_client::ClamAvSync for Blind {
+    type Stream = std::net::TcpStream;
+
+    fn connect(&self) -> std::io::Result<Self::Stream> {
+        self.0.connect()
+    }
+
+    fn scan_buffer(&self, _buffer: &[u8], _chunk_size: Option<usize>) -> IoResult {
+        Ok(b"stream: OK\0".to_vec())
+    }
+}
+
+mod test_health_sync {
+    use super::*;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn healthy() {
+        let server = mock_clamd(DEFAULT_VERSION);
+        let report = server.transport().health(&lenient());
+
+        assert_eq!(
+            report.status,
+            HealthStatus::Healthy,
+            "{:?}",
+            report.problems
+        );
+        assert!(report.is_healthy());
+        assert!(report.reachable);
+        assert!(report.latency.is_some());
+        assert_eq!(report.version.unwrap().database, Some(27430));
+        assert!(report.signature_age.is_some());
+        assert_eq!(report.self_test, SelfTest::Passed("Eicar-Signature".into()));
+        assert!(report.problems.is_empty());
+        assert_eq!(server.scans(), 1);
+    }
+
+    #[test]
+    fn outdated_signatures() {
+        let server = mock_clamd(STALE_VERSION);
+        let thresholds = HealthThresholds {
+            unhealthy_signature_age: Duration::from_secs(u64::MAX / 2),
+            ..HealthThresholds::default()
+        };
+        let report = server.transport().health(&thresholds);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.problems.len(), 1);
+
+        let report = server.transport().health(&HealthThresholds::default());
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert!(report.signature_age.unwrap() > Duration::from_secs(20 * 365 * 86_400));
+    }
+
+    #[test]
+    fn latency_and_missing_database() {
+        let server = mock_clamd("ClamAV 1.4.1");
+        let thresholds = HealthThresholds {
+            max_latency: Duration::ZERO,
+            self_test: false,
+            ..lenient()
+        };
+        let report = server.transport().health(&thresholds);
+
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.problems.len(), 2);
+        assert_eq!(report.signature_age, None);
+        assert_eq!(report.self_test, SelfTest::Skipped);
+        assert_eq!(server.scans(), 0);
+    }
+
+    #[test]
+    fn failed_self_test() {
+        let server = mock_clamd(DEFAULT_VERSION);
+        let client: Box<dyn clamav_client::DynClamAvSync> =
+            Box::new(Blind(server.transport().clone()));
+        let report = client.health(&lenient());
+
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert!(matches!(report.self_test, SelfTest::Failed(_)));
+    }
+
+    #[test]
+    fn unreachable() {
+        let mut server = mock_clamd(DEFAULT_VERSION);
+        server.stop();
+        let report = server.transport().health(&lenient());
+
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert!(!report.reachable);
+        assert_eq!(report.latency, None);
+        assert_eq!(report.version, None);
+        assert_eq!(report.self_test, SelfTest::Skipped);
+        assert_eq!(report.problems.len(), 1);
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_health_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn healthy_and_unreachable() {
+        let mut server = mock_clamd(DEFAULT_VERSION);
+        let report = server.transport().health(&lenient()).await;
+        assert_eq!(
+            report.status,
+            HealthStatus::Healthy,
+            "{:?}",
+            report.problems
+        );
+        assert_eq!(report.self_test, SelfTest::Passed("Eicar-Signature".into()));
+
+        server.stop();
+        let report = server.transport().health(&lenient()).await;
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert!(!report.reachable);
+    }
+}
@@ -0,0 +1,327 @@
+#![cfg(all(feature = "icap", feature = "testing"))]
+
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+};
+
+use clamav_client::icap::{IcapListener, IcapServer};
+use clamav_client::testing::EICAR;
+use clamav_client::VerdictPolicy;
+use common::mock_clamd;
+
+const HTTP_REQUEST: &str =
+    "GET http://example.com/file?a=<b> HTTP/1.1\r\nHost: example.com\r\n\r\n";
+const HTTP_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n";
+
+fn connect(listener: &IcapListener) -> BufReader<TcpStream> {
+    BufReader::new(TcpStream::connect(listener.local_addr()).unwrap())
+}
+
+/// Encodes a RESPMOD request for [`HTTP_REQUEST`] and [`HTTP_RESPONSE`]
+fn respmod(headers: &str, body: &[u8], last_chunk: &str) -> Vec<u8> {
+    let res_hdr = HTTP_REQUEST.len();
+    let res_body = res_hdr + HTTP_RESPONSE.len();
+    let mut request = format!(
+        "RESPMOD icap://127.0.0.1/respmod ICAP/1.0\r\nHost: 127.0.0.1\r\n{headers}\
+         Encapsulated: req-hdr=0, res-hdr={res_hdr}, res-body={res_body}\r\n\r\n\
+         {HTTP_REQUEST}{HTTP_RESPONSE}"
+    )
+    .into_bytes();
+    request.extend_from_slice(&chunk(body));
+    request.extend_from_slice(last_chunk.as_bytes());
+    request
+}
+
+fn chunk(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+#[derive(Debug)]
+struct IcapResponse {
+    status: String,
+    headers: Vec<String>,
+    sections: String,
+    body: Vec<u8>,
+}
+
+impl IcapResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+    }
+}
+
+fn read_response(reader: &mut BufReader<TcpStream>) -> IcapResponse {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let status = lines.remove(0);
+    let mut response = IcapResponse {
+        status,
+        headers: lines,
+        sections: String::new(),
+        body: Vec::new(),
+    };
+
+    let encapsulated = response.header("Encapsulated").unwrap_or("").to_string();
+    let (_, offset) = encapsulated.rsplit_once('=').unwrap_or(("", "0"));
+    let mut sections = vec![0; offset.parse().unwrap()];
+    reader.read_exact(&mut sections).unwrap();
+    response.sections = String::from_utf8(sections).unwrap();
+    if !encapsulated.contains("null-body") && !encapsulated.is_empty() {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).unwrap();
+            let size = usize::from_str_radix(size.trim(), 16).unwrap();
+            let mut data = vec![0; size + 2];
+            reader.read_exact(&mut data).unwrap();
+            if size == 0 {
+                break;
+            }
+            response.body.extend_from_slice(&data[..size]);
+        }
+    }
+    response
+}
+
+fn exchange(reader: &mut BufReader<TcpStream>, request: &[u8]) -> IcapResponse {
+    reader.get_mut().write_all(request).unwrap();
+    read_response(reader)
+}
+
+#[test]
+fn options() {
+    let clamd = mock_clamd();
+    let listener = IcapServer::new(clamd.transport().clone())
+        .service("Test")
+        .preview(100)
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let mut stream = connect(&listener);
+
+    let response = exchange(
+        &mut stream,
+        b"OPTIONS icap://127.0.0.1/respmod ICAP/1.0\r\nHost: 127.0.0.1\r\n\r\n",
+    );
+    assert_eq!(response.status, "ICAP/1.0 200 OK");
+    assert_eq!(response.header("Methods"), Some("REQMOD, RESPMOD"));
+    assert_eq!(response.header("Service"), Some("Test"));
+    assert_eq!(response.header("ISTag"), Some("\"clamav-27430\""));
+    assert_eq!(response.header("Preview"), Some("100"));
+    assert_eq!(response.header("Allow"), Some("204"));
+    assert_eq!(response.header("Max-Connections"), Some("100"));
+    assert_eq!(response.header("Encapsulated"), Some("null-body=0"));
+}
+
+#[test]
+fn clean_preview_with_ieof() {
+    let clamd = mock_clamd();
+    let listener = IcapServer::new(clamd.transport().clone())
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let mut stream = connect(&listener);
+
+    let request = respmod("Preview: 1024\r\n", b"clean", "0; ieof\r\n\r\n");
+    let response = exchange(&mut stream, &request);
+    assert_eq!(response.status, "ICAP/1.0 204 No Content");
+    assert_eq!(clamd.scans(), 1);
+}
+
+#[test]
+fn infected_after_continue() {
+    let clamd = mock_clamd();
+    let listener = IcapServer::new(clamd.transport().clone())
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let mut stream = connect(&listener);
+
+    let preview = respmod("Preview: 4\r\nAllow: 204\r\n", &EICAR[..4], "0\r\n\r\n");
+    stream.get_mut().write_all(&preview).unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).unwrap();
+    assert_eq!(line, "ICAP/1.0 100 Continue\r\n");
+    stream.read_line(&mut line).unwrap();
+
+    let mut rest = chunk(&EICAR[4..]);
+    rest.extend_from_slice(b"0\r\n\r\n");
+    let response = exchange(&mut stream, &rest);
+    assert_eq!(response.status, "ICAP/1.0 200 OK");
+    assert_eq!(response.header("X-Virus-ID"), Some("Eicar-Signature"));
+    assert_eq!(
+        response.header("X-Infection-Found"),
+        Some("Type=0; Resolution=2; Threat=Eicar-Signature;")
+    );
+    assert!(response.sections.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    let page = String::from_utf8(response.body).unwrap();
+    assert!(page.contains("virus found: Eicar-Signature"), "{page}");
+    assert!(
+        page.contains("http://example.com/file?a=&lt;b&gt;"),
+        "{page}"
+    );
+    assert!(response
+        .sections
+        .contains(&format!("Content-Length: {}\r\n", page.len())));
+}
+
+#[test]
+fn reqmod_echo_without_204() {
+    let clamd = mock_clamd();
+    let listener = IcapServer::new(clamd.transport().clone())
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let mut stream = connect(&listener);
+
+    let http = "POST http://example.com/upload HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    let mut request = format!(
+        "REQMOD icap://127.0.0.1/reqmod ICAP/1.0\r\nHost: 127.0.0.1\r\n\
+         Encapsulated: req-hdr=0, req-body={}\r\n\r\n{http}",
+        http.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&chunk(b"hello "));
+    request.extend_from_slice(&chunk(b"world"));
+    request.extend_from_slice(b"0\r\n\r\n");
+
+    let response = exchange(&mut stream, &request);
+    assert_eq!(response.status, "ICAP/1.0 200 OK");
+    assert_eq!(
+        response.header("Encapsulated"),
+        Some(format!("req-hdr=0, req-body={}", http.len()).as_str())
+    );
+    assert_eq!(response.sections, http);
+    assert_eq!(response.body, b"hello world");
+    assert_eq!(clamd.scans(), 1);
+}
+
+#[test]
+fn limits() {
+    let clamd = mock_clamd();
+    let listener = IcapServer::new(clamd.transport().clone())
+        .max_body_size(8)
+        .max_connections(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let mut stream = connect(&listener);
+
+    let response = exchange(&mut stream, &respmod("", b"small", "0\r\n\r\n"));
+    assert_eq!(response.status, "ICAP/1.0 200 OK");
+    assert_eq!(response.body, b"small");
+
+    let response = exchange(&mut stream, &respmod("", b"too large", "0\r\n\r\n"));
+    assert_eq!(response.status, "ICAP/1.0 200 OK");
+    assert!(String::from_utf8(response.body)
+        .unwrap()
+        .contains("message too large to return unmodified"));
+
+    // The first connection is still open
+    let mut second = connect(&listener);
+    assert_eq!(second.read(&mut [0; 1]).unwrap(), 0);
+    drop(stream);
+    // The server notices the closed connection asynchronously
+    let options = b"OPTIONS icap://127.0.0.1/respmod ICAP/1.0\r\n\r\n";
+    let response = (0..100)
+        .find_map(|_| {
+            let mut stream = connect(&listener);
+            let _ = stream.get_mut().write_all(options);
+            match stream.fill_buf() {
+                Ok(buffer) if !buffer.is_empty() => Some(read_response(&mut stream)),
+                _ => {
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    None
+                }
+            }
+        })
+        .unwrap();
+    assert_eq!(response.header("Max-Connections"), Some("1"));
+}
+
+#[test]
+fn clamd_unreachable() {
+    let mut clamd = mock_clamd();
+    clamd.stop();
+    let request = respmod("Allow: 204\r\n", b"clean", "0\r\n\r\n");
+
+    let fail_closed = IcapServer::new(clamd.transport().clone());
+    let fail_open = fail_closed.clone().verdict_policy(VerdictPolicy::FailOpen);
+    let strict = fail_closed.clone().verdict_policy(VerdictPolicy::Strict);
+
+    let listener = fail_closed.bind("127.0.0.1:0").unwrap();
+    let response = exchange(&mut connect(&listener), &request);
+    assert_eq!(response.status, "ICAP/1.0 200 OK");
+    assert!(String::from_utf8(response.body)
+        .unwrap()
+        .contains("scan failed"));
+
+    let listener = fail_open.bind("127.0.0.1:0").unwrap();
+    let response = exchange(&mut connect(&listener), &request);
+    assert_eq!(response.status, "ICAP/1.0 204 No Content");
+
+    let listener = strict.bind("127.0.0.1:0").unwrap();
+    let response = exchange(&mut connect(&listener), &request);
+    assert_eq!(response.status, "ICAP/1.0 500 Server Error");
+}
+
+#[test]
+fn persistent_connection() {
+    let clamd = mock_clamd();
+    let listener = IcapServer::new(clamd.transport().clone())
+        .istag("fixed")
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let mut stream = connect(&listener);
+
+    let clean = respmod("Allow: 204\r\n", b"clean", "0\r\n\r\n");
+    let infected = respmod("Allow: 204\r\nConnection: close\r\n", EICAR, "0\r\n\r\n");
+    let response = exchange(&mut stream, &clean);
+    assert_eq!(response.status, "ICAP/1.0 204 No Content");
+    assert_eq!(response.header("ISTag"), Some("\"fixed\""));
+
+    let response = exchange(&mut stream, &infected);
+    assert_eq!(response.status, "ICAP/1.0 200 OK");
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    assert_eq!(clamd.scans(), 2);
+}
+
+#[test]
+fn bad_requests() {
+    let clamd = mock_clamd();
+    let listener = IcapServer::new(clamd.transport().clone())
+        .bind("127.0.0.1:0")
+        .unwrap();
+
+    let mut stream = connect(&listener);
+    let response = exchange(&mut stream, b"GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(response.status, "ICAP/1.0 400 Bad Request");
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+    let mut stream = connect(&listener);
+    let response = exchange(
+        &mut stream,
+        b"DELETE icap://127.0.0.1/ ICAP/1.0\r\nEncapsulated: null-body=0\r\n\r\n",
+    );
+    assert_eq!(response.status, "ICAP/1.0 501 Method Not Implemented");
+
+    let mut stream = connect(&listener);
+    let request = respmod("Allow: 204\r\n", b"", "zz\r\n\r\n");
+    let response = exchange(&mut stream, &request);
+    assert_eq!(response.status, "ICAP/1.0 400 Bad Request");
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
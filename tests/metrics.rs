@@ -0,0 +1,311 @@
+#![cfg(all(feature = "metrics", feature = "testing"))]
+
+mod common;
+
+use std::time::Duration;
+
+use clamav_client::metrics::{ClamdStats, MeteredClient, Metrics, StatsCollector};
+use clamav_client::testing::EICAR;
+use common::limited_mock_clamd;
+
+const EICAR_TEST_FILE_PATH: &str = "tests/data/eicar.txt";
+
+/// Returns the value of the sample with exactly this name and labels
+fn sample(encoded: &str, series: &str) -> Option<String> {
+    encoded.lines().find_map(|line| {
+        let (name, value) = line.rsplit_once(' ')?;
+        (name == series).then(|| value.to_string())
+    })
+}
+
+#[test]
+fn parse_stats() {
+    let stats = ClamdStats::parse(
+        b"POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 3  idle 2 max 12 idle-timeout 30\n\
+          QUEUE: 4 items\n\tSTATS 0.000000\n\nEND",
+    )
+    .unwrap();
+    assert_eq!(
+        stats,
+        ClamdStats {
+            pools: 1,
+            threads_live: 3,
+            threads_idle: 2,
+            threads_max: 12,
+            queue_items: 4,
+        }
+    );
+
+    let err = ClamdStats::parse(b"UNKNOWN COMMAND\0").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn encode_histogram_and_escaping() {
+    let metrics = Metrics::with_buckets(&[1.0, 0.1]);
+    let endpoint = "clamd \"a\"";
+    metrics.record_scan(
+        endpoint,
+        &Ok(b"stream: OK\0".to_vec()),
+        10,
+        Duration::from_millis(50),
+    );
+    metrics.record_scan(
+        endpoint,
+        &Ok(b"stream: OK\0".to_vec()),
+        5,
+        Duration::from_secs(2),
+    );
+    metrics.set_idle_connections(endpoint, 3);
+
+    let encoded = metrics.encode();
+    let labels = r#"endpoint="clamd \"a\"""#;
+    let bucket = |le: &str| {
+        sample(
+            &encoded,
+            &format!("clamav_scan_duration_seconds_bucket{{{labels},le=\"{le}\"}}"),
+        )
+    };
+    assert_eq!(bucket("0.1").as_deref(), Some("1"));
+    assert_eq!(bucket("1.0").as_deref(), Some("1"));
+    assert_eq!(bucket("+Inf").as_deref(), Some("2"));
+    assert_eq!(
+        sample(
+            &encoded,
+            &format!("clamav_scan_duration_seconds_count{{{labels}}}")
+        )
+        .as_deref(),
+        Some("2")
+    );
+    assert_eq!(
+        sample(&encoded, &format!("clamav_scanned_bytes_total{{{labels}}}")).as_deref(),
+        Some("15")
+    );
+    assert_eq!(
+        sample(
+            &encoded,
+            &format!("clamav_connections{{{labels},state=\"idle\"}}")
+        )
+        .as_deref(),
+        Some("3")
+    );
+    assert!(encoded.contains("# TYPE clamav_scan_duration_seconds histogram\n"));
+    assert!(encoded.ends_with("# EOF\n"));
+}
+
+mod test_metrics_sync {
+    use super::*;
+    #[cfg(feature = "hash")]
+    use clamav_client::hash::HashAlgorithms;
+    use clamav_client::health::HealthThresholds;
+    use clamav_client::progress::ScanProgress;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn scans_by_verdict_and_errors_by_kind() {
+        let mut server = limited_mock_clamd(1_000);
+        let metrics = Metrics::new();
+        let client = MeteredClient::new(server.transport().clone(), metrics.clone(), "mock");
+
+        client.scan_file(EICAR_TEST_FILE_PATH, None).unwrap();
+        client.scan_buffer(EICAR, None).unwrap();
+        client.scan_buffer(b"clean", None).unwrap();
+        client.scan_buffer(&[0; 1_001], None).unwrap();
+        server.stop();
+        assert!(client.scan_buffer(b"clean", None).is_err());
+
+        let encoded = metrics.encode();
+        let value = |series: &str| sample(&encoded, series);
+        assert_eq!(
+            value(r#"clamav_scans_total{endpoint="mock",verdict="infected"}"#).as_deref(),
+            Some("2")
+        );
+        assert_eq!(
+            value(r#"clamav_scans_total{endpoint="mock",verdict="clean"}"#).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            value(r#"clamav_scans_total{endpoint="mock",verdict="error"}"#).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            value(r#"clamav_errors_total{endpoint="mock",kind="clamd"}"#).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            value(r#"clamav_errors_total{endpoint="mock",kind="connection_refused"}"#).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            value(r#"clamav_scanned_bytes_total{endpoint="mock"}"#).as_deref(),
+            Some("1143")
+        );
+        assert_eq!(
+            value(r#"clamav_scan_duration_seconds_count{endpoint="mock"}"#).as_deref(),
+            Some("5")
+        );
+        assert_eq!(
+            value(r#"clamav_connections{endpoint="mock",state="active"}"#).as_deref(),
+            Some("0")
+        );
+    }
+
+    #[test]
+    fn all_scan_paths_recorded() {
+        let server = limited_mock_clamd(1_000);
+        let metrics = Metrics::new();
+        let client = MeteredClient::new(server.transport().clone(), metrics.clone(), "mock");
+
+        client
+            .scan_buffer_with_progress(EICAR, None, &|_: &ScanProgress| {})
+            .unwrap();
+        client
+            .scan_file_with_progress(EICAR_TEST_FILE_PATH, None, &|_: &ScanProgress| {})
+            .unwrap();
+        #[cfg(feature = "hash")]
+        client
+            .scan_buffer_hashed(b"clean", None, HashAlgorithms::all())
+            .unwrap();
+        let dynamic: &dyn clamav_client::DynClamAvSync = &client;
+        dynamic.scan_reader(&mut &b"clean"[..], None).unwrap();
+
+        // The health self-test is not a user scan
+        let report = client.health(&HealthThresholds::default());
+        assert!(report.reachable);
+
+        let encoded = metrics.encode();
+        let value = |series: &str| sample(&encoded, series);
+        assert_eq!(
+            value(r#"clamav_scans_total{endpoint="mock",verdict="infected"}"#).as_deref(),
+            Some("2")
+        );
+        let clean = if cfg!(feature = "hash") { "2" } else { "1" };
+        assert_eq!(
+            value(r#"clamav_scans_total{endpoint="mock",verdict="clean"}"#).as_deref(),
+            Some(clean)
+        );
+    }
+
+    #[cfg(feature = "proxy")]
+    #[test]
+    fn pool_connection_gauges() {
+        use clamav_client::proxy::{Backend, Pool};
+
+        let server = limited_mock_clamd(1_000);
+        let metrics = Metrics::new();
+        let backend = Backend::Tcp(server.transport().clone());
+        let series = format!(r#"clamav_connections{{endpoint="{backend}",state="active"}}"#);
+        let pool = Pool::new(vec![backend]).metrics(metrics.clone());
+
+        let stream = pool.connect().unwrap();
+        assert_eq!(sample(&metrics.encode(), &series).as_deref(), Some("1"));
+        drop(stream);
+        assert_eq!(sample(&metrics.encode(), &series).as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn collect_stats() {
+        let mut server = limited_mock_clamd(1_000);
+        let metrics = Metrics::new();
+        let stats = metrics.collect_stats(server.transport(), "mock").unwrap();
+        assert_eq!(stats.threads_max, 10);
+
+        let encoded = metrics.encode();
+        assert_eq!(
+            sample(&encoded, r#"clamd_up{endpoint="mock"}"#).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            sample(&encoded, r#"clamd_queue_items{endpoint="mock"}"#).as_deref(),
+            Some("0")
+        );
+        assert_eq!(
+            sample(&encoded, r#"clamd_threads{endpoint="mock",state="live"}"#).as_deref(),
+            Some("1")
+        );
+
+        server.stop();
+        assert!(metrics.collect_stats(server.transport(), "mock").is_err());
+        let encoded = metrics.encode();
+        assert_eq!(
+            sample(&encoded, r#"clamd_up{endpoint="mock"}"#).as_deref(),
+            Some("0")
+        );
+        assert_eq!(
+            sample(&encoded, r#"clamd_queue_items{endpoint="mock"}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn stats_collector() {
+        let server = limited_mock_clamd(1_000);
+        let metrics = Metrics::new();
+        let collector = StatsCollector::spawn(
+            server.transport().clone(),
+            metrics.clone(),
+            "mock",
+            Duration::from_millis(10),
+        );
+        std::thread::sleep(Duration::from_millis(100));
+        collector.stop();
+
+        let connections = server.connections();
+        assert!(connections >= 2);
+        assert_eq!(
+            sample(
+                &metrics.encode(),
+                r#"clamd_threads{endpoint="mock",state="max"}"#
+            )
+            .as_deref(),
+            Some("10")
+        );
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(server.connections(), connections);
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod test_metrics_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn scan_stream_and_collect_stats() {
+        let server = limited_mock_clamd(1_000);
+        let metrics = Metrics::new();
+        let transport = clamav_client::tokio::Tcp(server.transport().0);
+        let client = MeteredClient::new(transport.clone(), metrics.clone(), "mock");
+
+        let chunks = EICAR
+            .chunks(10)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        client
+            .scan_stream(futures_lite::stream::iter(chunks), None)
+            .await
+            .unwrap();
+        metrics
+            .collect_stats_async(&transport, "mock")
+            .await
+            .unwrap();
+
+        let encoded = metrics.encode();
+        assert_eq!(
+            sample(
+                &encoded,
+                r#"clamav_scans_total{endpoint="mock",verdict="infected"}"#
+            )
+            .as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            sample(&encoded, r#"clamav_scanned_bytes_total{endpoint="mock"}"#).as_deref(),
+            Some("68")
+        );
+        assert_eq!(
+            sample(&encoded, r#"clamd_up{endpoint="mock"}"#).as_deref(),
+            Some("1")
+        );
+    }
+}
@@ -0,0 +1,262 @@
+#![cfg(all(feature = "milter", feature = "testing"))]
+
+mod common;
+
+use clamav_client::milter::{Milter, MilterAction, MilterClient, MilterListener, MilterResponse};
+use clamav_client::testing::{MockClamd, MockClamdServer};
+use clamav_client::Tcp;
+
+const CLEAN_MESSAGE: &[u8] = b"From: sender@example.com\r\n\
+    To: recipient@example.com\r\n\
+    Subject: Hello\r\n\
+    \r\n\
+    Nothing to see here.\r\n";
+
+const INFECTED_MESSAGE: &[u8] = b"From: sender@example.com\r\n\
+    Subject: Invoice\r\n\
+    \tfor October\r\n\
+    \r\n\
+    Please open TEST-VIRUS-PAYLOAD now.\r\n";
+
+fn mock_clamd() -> MockClamdServer<Tcp> {
+    common::spawn(MockClamd::new().signature("Test-Virus", "TEST-VIRUS-PAYLOAD"))
+}
+
+fn connect(listener: &MilterListener) -> MilterClient<std::net::TcpStream> {
+    MilterClient::connect_tcp(listener.local_addr().unwrap()).unwrap()
+}
+
+fn send(
+    client: &mut MilterClient<std::net::TcpStream>,
+    message: &[u8],
+) -> clamav_client::milter::EndOfMessage {
+    client
+        .send_message("sender@example.com", &["recipient@example.com"], message)
+        .unwrap()
+}
+
+#[test]
+fn clean_message_accepted() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .scanned_header("ClamAV test")
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let mut client = connect(&listener);
+    assert!(client.can_add_headers());
+    let reply = send(&mut client, CLEAN_MESSAGE);
+    assert_eq!(reply.response, MilterResponse::Accept);
+    assert!(reply.response.is_accepted());
+    assert_eq!(reply.header("X-Virus-Scanned"), Some("ClamAV test"));
+    assert_eq!(reply.header("X-Virus-Status"), Some("Clean"));
+    assert_eq!(reply.quarantine, None);
+    client.quit().unwrap();
+    assert_eq!(clamd.scans(), 1);
+}
+
+#[test]
+fn infected_message_rejected() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let mut client = connect(&listener);
+    let reply = send(&mut client, INFECTED_MESSAGE);
+    assert_eq!(
+        reply.response,
+        MilterResponse::ReplyCode(String::from("550 5.7.1 Virus found: Test-Virus"))
+    );
+    assert!(!reply.response.is_accepted());
+    assert!(reply.added_headers.is_empty());
+
+    // The connection serves the next message
+    let reply = send(&mut client, CLEAN_MESSAGE);
+    assert_eq!(reply.response, MilterResponse::Accept);
+    assert_eq!(clamd.scans(), 2);
+}
+
+#[test]
+fn infected_message_quarantined() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .on_infected(MilterAction::Quarantine)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let mut client = connect(&listener);
+    let reply = send(&mut client, INFECTED_MESSAGE);
+    assert_eq!(reply.response, MilterResponse::Accept);
+    assert_eq!(reply.quarantine.as_deref(), Some("Virus found: Test-Virus"));
+    assert_eq!(reply.header("X-Virus-Scanned"), Some("clamav-client"));
+    assert_eq!(
+        reply.header("X-Virus-Status"),
+        Some("Infected (Test-Virus)")
+    );
+}
+
+#[test]
+fn quarantine_not_offered() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .on_infected(MilterAction::Quarantine)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    // The message is rejected rather than delivered
+    let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let mut client = MilterClient::with_quarantine(stream, false).unwrap();
+    assert!(!client.can_quarantine());
+    let reply = send(&mut client, INFECTED_MESSAGE);
+    assert_eq!(
+        reply.response,
+        MilterResponse::ReplyCode(String::from("550 5.7.1 Virus found: Test-Virus"))
+    );
+    assert_eq!(reply.quarantine, None);
+    assert!(reply.added_headers.is_empty());
+
+    let reply = send(&mut client, CLEAN_MESSAGE);
+    assert_eq!(reply.response, MilterResponse::Accept);
+}
+
+#[test]
+fn infected_message_discarded() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .on_infected(MilterAction::Discard)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let reply = send(&mut connect(&listener), INFECTED_MESSAGE);
+    assert_eq!(reply.response, MilterResponse::Discard);
+}
+
+#[test]
+fn clamd_unreachable() {
+    let mut clamd = mock_clamd();
+    clamd.stop();
+    let listener = Milter::new(clamd.transport().clone())
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let reply = send(&mut connect(&listener), CLEAN_MESSAGE);
+    assert_eq!(
+        reply.response,
+        MilterResponse::ReplyCode(String::from("451 4.7.1 Virus scan failed"))
+    );
+
+    let listener = Milter::new(clamd.transport().clone())
+        .on_error(MilterAction::Accept)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+    let reply = send(&mut connect(&listener), CLEAN_MESSAGE);
+    assert_eq!(reply.response, MilterResponse::Accept);
+    assert_eq!(reply.header("X-Virus-Status"), Some("Error"));
+}
+
+#[test]
+fn message_too_large() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .max_message_size(16)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let reply = send(&mut connect(&listener), CLEAN_MESSAGE);
+    assert_eq!(
+        reply.response,
+        MilterResponse::ReplyCode(String::from("451 4.7.1 Virus scan failed"))
+    );
+    assert_eq!(clamd.scans(), 0);
+}
+
+#[test]
+fn max_connections() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .max_connections(1)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let mut first = connect(&listener);
+    // The second connection is closed before the negotiation
+    assert!(MilterClient::connect_tcp(listener.local_addr().unwrap()).is_err());
+    assert!(send(&mut first, CLEAN_MESSAGE).response.is_accepted());
+    first.quit().unwrap();
+
+    // The server notices the closed connection asynchronously
+    let mut client = (0..100)
+        .find_map(|_| {
+            MilterClient::connect_tcp(listener.local_addr().unwrap())
+                .map_err(|_| std::thread::sleep(std::time::Duration::from_millis(20)))
+                .ok()
+        })
+        .unwrap();
+    assert!(send(&mut client, CLEAN_MESSAGE).response.is_accepted());
+}
+
+#[test]
+fn aborted_message_discarded() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let mut client = connect(&listener);
+    assert_eq!(
+        client.header("Subject", "TEST-VIRUS-PAYLOAD").unwrap(),
+        MilterResponse::Continue
+    );
+    client.abort().unwrap();
+    assert_eq!(client.end_of_headers().unwrap(), MilterResponse::Continue);
+    assert_eq!(client.body(b"clean").unwrap(), MilterResponse::Continue);
+    let reply = client.end_of_message().unwrap();
+    assert_eq!(reply.response, MilterResponse::Accept);
+    assert_eq!(clamd.scans(), 1);
+}
+
+#[test]
+fn large_body_in_chunks() {
+    let clamd = mock_clamd();
+    let listener = Milter::new(clamd.transport().clone())
+        .chunk_size(4096)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let mut message = b"Subject: Large\r\n\r\n".to_vec();
+    message.resize(200_000, b'a');
+    message.extend_from_slice(b"TEST-VIRUS-PAYLOAD");
+    let reply = send(&mut connect(&listener), &message);
+    assert_eq!(
+        reply.response,
+        MilterResponse::ReplyCode(String::from("550 5.7.1 Virus found: Test-Virus"))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket() {
+    let dir = std::env::temp_dir().join(format!("clamav-milter-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let clamd = mock_clamd();
+    let mut listener = Milter::new(clamd.transport().clone())
+        .bind_socket(dir.join("milter.sock"))
+        .unwrap();
+    assert_eq!(listener.local_addr(), None);
+
+    let mut client = MilterClient::connect_socket(dir.join("milter.sock")).unwrap();
+    let reply = client
+        .send_message(
+            "sender@example.com",
+            &["recipient@example.com"],
+            CLEAN_MESSAGE,
+        )
+        .unwrap();
+    assert_eq!(reply.response, MilterResponse::Accept);
+    client.quit().unwrap();
+
+    listener.stop();
+    assert!(!dir.join("milter.sock").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
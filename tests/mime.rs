@@ -0,0 +1,196 @@
+#![cfg(feature = "mime")]
+
+#[cfg(feature = "testing")]
+mod common;
+
+use clamav_client::mime::{self, MimePart};
+
+const INFECTED_EML_PATH: &str = "tests/data/infected.eml";
+
+fn part<'a>(parts: &'a [MimePart], id: &str) -> &'a MimePart {
+    parts.iter().find(|part| part.id == id).unwrap()
+}
+
+#[test]
+fn parse_nested_parts() {
+    let message = std::fs::read(INFECTED_EML_PATH).unwrap();
+    let parts = mime::parse(&message);
+    let ids: Vec<&str> = parts.iter().map(|part| part.id.as_str()).collect();
+    assert_eq!(
+        ids,
+        ["preamble", "1.1", "1.2", "2", "3", "4.1", "4.2", "epilogue"]
+    );
+    assert_eq!(
+        part(&parts, "preamble").data,
+        b"This is a multi-part message in MIME format.\r\n"
+    );
+    assert_eq!(part(&parts, "epilogue").data, b"Epilogue\r\n");
+
+    let text = part(&parts, "1.1");
+    assert_eq!(text.content_type, "text/plain");
+    assert!(!text.attachment);
+    assert_eq!(
+        String::from_utf8(text.data.clone()).unwrap(),
+        "Please find the documents attached. Grüße, this line is continued."
+    );
+    assert_eq!(part(&parts, "1.2").content_type, "text/html");
+
+    let eicar = part(&parts, "2");
+    assert_eq!(eicar.content_type, "application/octet-stream");
+    assert_eq!(eicar.filename.as_deref(), Some("eicar.com"));
+    assert!(eicar.attachment);
+    assert_eq!(eicar.data.len(), 68);
+    assert!(eicar.data.starts_with(b"X5O!P%@AP"));
+
+    let pdf = part(&parts, "3");
+    assert_eq!(pdf.filename.as_deref(), Some("résumé.pdf"));
+    assert_eq!(pdf.data, b"%PDF-1.4 clean document");
+
+    // Parts of an attached message are numbered below the attachment
+    let forwarded = part(&parts, "4.1");
+    assert_eq!(forwarded.content_type, "text/plain");
+    assert_eq!(forwarded.data, b"Forwarded message");
+    let nested = part(&parts, "4.2");
+    assert_eq!(nested.content_type, "application/x-msdownload");
+    assert_eq!(nested.filename.as_deref(), Some("Überweisung.exe"));
+    assert_eq!(nested.data, eicar.data);
+}
+
+#[test]
+fn parse_single_part() {
+    let parts = mime::parse(b"Subject: Hello\r\n\r\nHello world\r\n");
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].id, "1");
+    assert_eq!(parts[0].content_type, "text/plain");
+    assert_eq!(parts[0].filename, None);
+    assert_eq!(parts[0].data, b"Hello world\r\n");
+
+    // Data without headers is a single part
+    let parts = mime::parse(b"not a message");
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].data, b"not a message");
+}
+
+#[test]
+fn parse_malformed() {
+    // A missing closing boundary ends the last part at the end of the message
+    let message = b"Content-Type: multipart/mixed; boundary=b\n\n--b\n\
+        Content-Type: image/png; name=\"a \\\"b\\\".png\"\n\
+        Content-Transfer-Encoding: base64\n\nAAEC\nAw=\n";
+    let parts = mime::parse(message);
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].id, "1");
+    assert_eq!(parts[0].filename.as_deref(), Some("a \"b\".png"));
+    assert_eq!(parts[0].data, [0, 1, 2, 3]);
+
+    // Padding in the middle of the body does not end it
+    let message = b"Content-Transfer-Encoding: base64\n\nQQ==Qg==\nQ0Q=\nRQ\n";
+    let parts = mime::parse(message);
+    assert_eq!(parts[0].data, b"ABCDE");
+
+    // A multipart without a boundary is scanned as a whole
+    let parts = mime::parse(b"Content-Type: multipart/mixed\r\n\r\n--x\r\ndata\r\n");
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].content_type, "multipart/mixed");
+
+    // A multipart whose boundary never appears is scanned as a whole
+    let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--c\r\nhidden\r\n";
+    let parts = mime::parse(message);
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].id, "1");
+    assert_eq!(parts[0].data, b"--c\r\nhidden\r\n");
+
+    let parts = mime::parse(b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b--\r\n");
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].data, b"--b--\r\n");
+
+    // Data outside of the parts is scanned, blank preambles are skipped
+    let message = b"Content-Type: multipart/mixed; boundary=b\n\n\n--b\n\nfirst\n--b--\nafter\n";
+    let parts = mime::parse(message);
+    let ids: Vec<&str> = parts.iter().map(|part| part.id.as_str()).collect();
+    assert_eq!(ids, ["1", "epilogue"]);
+    assert_eq!(parts[1].data, b"after\n");
+
+    // Invalid quoted-printable escapes are kept
+    let parts =
+        mime::parse(b"Content-Transfer-Encoding: Quoted-Printable\r\n\r\na=3Db =ZZ c=\r\nd=");
+    assert_eq!(parts[0].data, b"a=b =ZZ cd=");
+}
+
+#[cfg(feature = "testing")]
+mod scan {
+    use super::*;
+    use clamav_client::Verdict;
+
+    use crate::common::mock_clamd;
+
+    fn assert_verdicts(parts: &[clamav_client::mime::ScannedPart]) {
+        assert_eq!(parts.len(), 8);
+        for part in parts {
+            let expected = match part.id.as_str() {
+                "2" | "4.2" => Verdict::Infected(vec![String::from("Eicar-Signature")]),
+                _ => Verdict::Clean,
+            };
+            assert_eq!(part.verdict(), expected, "part {}", part.id);
+        }
+        assert_eq!(parts[2].content_type, "text/html");
+        assert_eq!(parts[3].filename.as_deref(), Some("eicar.com"));
+        assert_eq!(parts[3].size, 68);
+        assert_eq!(parts[6].filename.as_deref(), Some("Überweisung.exe"));
+    }
+
+    mod sync {
+        use super::*;
+        use clamav_client::{ClamAvClient, ClamAvSync};
+
+        #[test]
+        fn scan_mime() {
+            let server = mock_clamd();
+            let message = std::fs::read(INFECTED_EML_PATH).unwrap();
+            let parts = server.transport().scan_mime(&message, Some(16));
+            assert_verdicts(&parts);
+            assert_eq!(server.scans(), 8);
+        }
+
+        #[test]
+        fn client_settings_apply() {
+            let server = mock_clamd();
+            let client = ClamAvClient::builder(server.transport().clone())
+                .max_stream_length(40)
+                .build();
+            let message = std::fs::read(INFECTED_EML_PATH).unwrap();
+            let parts = client.scan_mime(&message, None);
+
+            // Parts larger than the limit are not sent
+            let eicar = parts.iter().find(|part| part.id == "2").unwrap();
+            assert!(matches!(eicar.verdict(), Verdict::Error(_)));
+            let pdf = parts.iter().find(|part| part.id == "3").unwrap();
+            assert_eq!(pdf.verdict(), Verdict::Clean);
+        }
+
+        #[test]
+        fn clamd_unreachable() {
+            let mut server = mock_clamd();
+            server.stop();
+            let parts = server.transport().scan_mime(b"Subject: Hi\r\n\r\nHi", None);
+            assert_eq!(parts.len(), 1);
+            assert!(parts[0].response.is_err());
+            assert!(matches!(parts[0].verdict(), Verdict::Error(_)));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    mod nonblocking {
+        use super::*;
+        use clamav_client::ClamAvAsync;
+
+        #[tokio::test]
+        async fn scan_mime() {
+            let server = mock_clamd();
+            let message = std::fs::read(INFECTED_EML_PATH).unwrap();
+            let parts = server.transport().scan_mime(&message, None).await;
+            assert_verdicts(&parts);
+            assert_eq!(server.scans(), 8);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+#![cfg(all(feature = "multipart", feature = "async"))]
+
+#[cfg(feature = "testing")]
+mod common;
+
+use std::io;
+
+use bytes::Bytes;
+use clamav_client::multipart::{self, Multipart};
+use futures_lite::{stream, Stream};
+
+const BODY: &str = "preamble\r\n\
+    --BOUNDARY\r\n\
+    Content-Disposition: form-data; name=\"comment\"\r\n\
+    \r\n\
+    first line\r\nsecond line\r\n\
+    --BOUNDARY  \r\n\
+    Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+    Content-Type: text/plain\r\n\
+    \r\n\
+    \r\n--BOUNDAR\r\n\
+    --BOUNDARY\r\n\
+    Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\
+    \r\n\
+    \r\n\
+    --BOUNDARY--\r\n\
+    epilogue";
+
+/// Splits the body into chunks of `size` bytes
+fn chunked(body: &[u8], size: usize) -> impl Stream<Item = io::Result<Bytes>> + Send + Unpin {
+    let chunks: Vec<io::Result<Bytes>> = body
+        .chunks(size)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect();
+    stream::iter(chunks)
+}
+
+async fn read_part<S: Stream<Item = io::Result<Bytes>> + Unpin>(
+    multipart: &mut Multipart<S>,
+) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    while let Some(chunk) = multipart.chunk().await? {
+        assert!(!chunk.is_empty());
+        content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+}
+
+#[test]
+fn boundary() {
+    assert_eq!(
+        multipart::boundary("multipart/form-data; boundary=abc").as_deref(),
+        Some("abc")
+    );
+    assert_eq!(
+        multipart::boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b;c\"").as_deref(),
+        Some("a b;c")
+    );
+    assert_eq!(multipart::boundary("multipart/form-data"), None);
+    assert_eq!(multipart::boundary("text/plain; boundary=abc"), None);
+}
+
+#[tokio::test]
+async fn parse_in_any_chunk_size() {
+    for size in [1, 2, 3, 7, 16, 1000] {
+        let mut multipart = Multipart::new(chunked(BODY.as_bytes(), size), "BOUNDARY");
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name.as_deref(), Some("comment"));
+        assert!(!part.is_file());
+        assert_eq!(
+            read_part(&mut multipart).await.unwrap(),
+            b"first line\r\nsecond line"
+        );
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name.as_deref(), Some("upload"));
+        assert_eq!(part.filename.as_deref(), Some("a.txt"));
+        assert_eq!(part.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(part.headers.len(), 2);
+        assert_eq!(read_part(&mut multipart).await.unwrap(), b"\r\n--BOUNDAR");
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.filename.as_deref(), Some(""));
+        assert_eq!(read_part(&mut multipart).await.unwrap(), b"");
+
+        assert_eq!(multipart.next_part().await.unwrap(), None);
+        assert_eq!(multipart.chunk().await.unwrap(), None);
+    }
+}
+
+#[tokio::test]
+async fn skip_parts() {
+    let mut multipart = Multipart::new(chunked(BODY.as_bytes(), 5), "BOUNDARY");
+    let names = [
+        multipart.next_part().await.unwrap().unwrap().name,
+        multipart.next_part().await.unwrap().unwrap().name,
+        multipart.next_part().await.unwrap().unwrap().name,
+    ];
+    assert_eq!(
+        names,
+        [Some("comment"), Some("upload"), Some("empty")].map(|n| n.map(String::from))
+    );
+    assert_eq!(multipart.next_part().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn malformed_bodies() {
+    // Missing closing boundary
+    let body = "--B\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue";
+    let mut multipart = Multipart::new(chunked(body.as_bytes(), 4), "B");
+    multipart.next_part().await.unwrap().unwrap();
+    let err = read_part(&mut multipart).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    // No boundary at all
+    let mut multipart = Multipart::new(chunked(b"just data", 4), "B");
+    let err = multipart.next_part().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    // Invalid header line
+    let body = "--B\r\nnot a header\r\n\r\nvalue\r\n--B--\r\n";
+    let mut multipart = Multipart::new(chunked(body.as_bytes(), 4), "B");
+    let err = multipart.next_part().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // Headers without end
+    let body = format!("--B\r\nX-Long: {}", "a".repeat(20_000));
+    let mut multipart = Multipart::new(chunked(body.as_bytes(), 1000), "B");
+    let err = multipart.next_part().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // Errors of the body stream are returned
+    let body = stream::iter(vec![
+        Ok(Bytes::from_static(b"--B\r\n\r\nvalue")),
+        Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+    ]);
+    let mut multipart = Multipart::new(body, "B");
+    multipart.next_part().await.unwrap().unwrap();
+    let err = read_part(&mut multipart).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+}
+
+#[cfg(feature = "testing")]
+mod scan {
+    use super::*;
+    use clamav_client::multipart::MultipartOptions;
+    use clamav_client::testing::EICAR;
+    use clamav_client::{ClamAvAsync, Verdict};
+
+    use crate::common::limited_mock_clamd;
+
+    fn upload(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, filename, data) in parts {
+            body.extend_from_slice(b"--BOUNDARY\r\n");
+            let disposition = match filename {
+                Some(filename) => format!(
+                    "Content-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n"
+                ),
+                None => format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n"),
+            };
+            body.extend_from_slice(disposition.as_bytes());
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--BOUNDARY--\r\n");
+        body
+    }
+
+    #[tokio::test]
+    async fn scan_files_and_pass_fields() {
+        let server = limited_mock_clamd(1_000);
+        let body = upload(&[
+            ("title", None, b"Quarterly report"),
+            ("document", Some("report.pdf"), b"%PDF-1.4 clean"),
+            ("document", Some("eicar.com"), EICAR),
+            ("tags", None, b"a,b"),
+        ]);
+        let scanned = server
+            .transport()
+            .scan_multipart(
+                chunked(&body, 7),
+                "BOUNDARY",
+                MultipartOptions::new().chunk_size(10),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(scanned.fields.len(), 2);
+        assert_eq!(scanned.field("title").unwrap(), "Quarterly report");
+        assert_eq!(scanned.field("tags").unwrap(), "a,b");
+        assert_eq!(scanned.files.len(), 2);
+        assert!(!scanned.is_clean());
+
+        let report = scanned.file("document", "report.pdf").unwrap();
+        assert_eq!(report.verdict(), Verdict::Clean);
+        assert_eq!(report.size, 14);
+        assert_eq!(
+            report.content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+        let eicar = scanned.file("document", "eicar.com").unwrap();
+        assert_eq!(
+            eicar.verdict(),
+            Verdict::Infected(vec![String::from("Eicar-Signature")])
+        );
+        assert_eq!(eicar.size, EICAR.len() as u64);
+        assert_eq!(server.scans(), 2);
+    }
+
+    #[tokio::test]
+    async fn file_too_large_for_clamd() {
+        let server = limited_mock_clamd(1_000);
+        let large = vec![b'a'; 5_000];
+        let body = upload(&[
+            ("large", Some("large.bin"), &large),
+            ("small", Some("small.bin"), b"clean"),
+        ]);
+        let scanned = server
+            .transport()
+            .scan_multipart(chunked(&body, 100), "BOUNDARY", MultipartOptions::new())
+            .await
+            .unwrap();
+
+        // The rest of the large file is skipped and the next file is scanned
+        let large = scanned.file("large", "large.bin").unwrap();
+        assert_eq!(large.size, 5_000);
+        assert!(matches!(large.verdict(), Verdict::Error(_)));
+        let small = scanned.file("small", "small.bin").unwrap();
+        assert_eq!(small.verdict(), Verdict::Clean);
+    }
+
+    #[tokio::test]
+    async fn field_too_large() {
+        let server = limited_mock_clamd(1_000);
+        let body = upload(&[("comment", None, &[b'a'; 100])]);
+        let err = server
+            .transport()
+            .scan_multipart(
+                chunked(&body, 10),
+                "BOUNDARY",
+                MultipartOptions::new().max_field_size(99),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(server.scans(), 0);
+    }
+
+    #[tokio::test]
+    async fn broken_upload() {
+        let server = limited_mock_clamd(1_000);
+        let mut body = upload(&[("file", Some("a.bin"), &[b'a'; 100])]);
+        body.truncate(body.len() - 50);
+        let err = server
+            .transport()
+            .scan_multipart(chunked(&body, 10), "BOUNDARY", MultipartOptions::new())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn clamd_unreachable() {
+        let mut server = limited_mock_clamd(1_000);
+        server.stop();
+        let body = upload(&[
+            ("first", Some("a.bin"), b"a"),
+            ("second", Some("b.bin"), b"b"),
+        ]);
+        let scanned = server
+            .transport()
+            .scan_multipart(chunked(&body, 10), "BOUNDARY", MultipartOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(scanned.files.len(), 2);
+        assert!(scanned.files.iter().all(|file| file.response.is_err()));
+        assert_eq!(scanned.files[1].size, 1);
+        assert!(!scanned.is_clean());
+    }
+}
@@ -0,0 +1,305 @@
+#![cfg(feature = "policy")]
+
+#[cfg(feature = "testing")]
+mod common;
+
+use std::io;
+
+use clamav_client::policy::{Policy, SignaturePattern};
+use clamav_client::{Verdict, VerdictPolicy};
+
+const EICAR_SHA256: [u8; 32] = [
+    0x27, 0x5a, 0x02, 0x1b, 0xbf, 0xb6, 0x48, 0x9e, 0x54, 0xd4, 0x71, 0x89, 0x9f, 0x7d, 0xb9, 0xd1,
+    0x66, 0x3f, 0xc6, 0x95, 0xec, 0x2f, 0xe2, 0xa2, 0xc4, 0x53, 0x8a, 0xab, 0xf6, 0x51, 0xfd, 0x0f,
+];
+
+const POLICY_TOML: &str = r#"
+on_error = "fail_open"
+allow_sha256 = ["275A021BBFB6489E54D471899F7DB9D1663FC695EC2FE2A2C4538AABF651FD0F"]
+
+[[ignore]]
+glob = "PUA.*"
+
+[[ignore]]
+name = "Heuristics.Encrypted.PDF"
+
+[[escalate]]
+regex = "^PUA\\.Win\\.Exploit\\."
+"#;
+
+fn infected(signatures: &[&str]) -> Verdict {
+    Verdict::Infected(signatures.iter().map(|s| s.to_string()).collect())
+}
+
+#[test]
+fn signature_patterns() {
+    let name = SignaturePattern::name("Heuristics.Encrypted.PDF");
+    assert!(name.matches("Heuristics.Encrypted.PDF"));
+    assert!(!name.matches("Heuristics.Encrypted.Zip"));
+
+    let glob = SignaturePattern::glob("PUA.Win.*");
+    assert!(glob.matches("PUA.Win.Packer.Upx-1"));
+    assert!(!glob.matches("Win.Trojan.Agent-1"));
+
+    let regex = SignaturePattern::regex(r"^Win\.Trojan\.").unwrap();
+    assert!(regex.matches("Win.Trojan.Agent-1"));
+    assert!(!regex.matches("PUA.Win.Trojan.Agent-1"));
+
+    let err = SignaturePattern::regex("(").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn ignore_and_escalate() {
+    let policy = Policy::new()
+        .ignore(SignaturePattern::glob("PUA.*"))
+        .escalate(SignaturePattern::glob("PUA.Win.Exploit.*"));
+
+    let verdict = policy.apply(infected(&["PUA.Pdf.Trojan-1"]), None).unwrap();
+    assert_eq!(verdict, Verdict::Clean);
+
+    let verdict = policy
+        .apply(infected(&["PUA.Pdf.Trojan-1", "Win.Trojan.Agent-1"]), None)
+        .unwrap();
+    assert_eq!(verdict, infected(&["Win.Trojan.Agent-1"]));
+
+    let verdict = policy
+        .apply(infected(&["PUA.Win.Exploit.CVE-1"]), None)
+        .unwrap();
+    assert_eq!(verdict, infected(&["PUA.Win.Exploit.CVE-1"]));
+    assert!(!policy.is_ignored("PUA.Win.Exploit.CVE-1"));
+    assert!(policy.is_escalated("PUA.Win.Exploit.CVE-1"));
+}
+
+#[test]
+fn allowlisted_hashes() {
+    let policy = Policy::new()
+        .allow_sha256(EICAR_SHA256)
+        .escalate(SignaturePattern::name("Win.Ransomware.Locky-1"));
+
+    let verdict = policy
+        .apply(infected(&["Eicar-Signature"]), Some(&EICAR_SHA256))
+        .unwrap();
+    assert_eq!(verdict, Verdict::Clean);
+
+    // Without a digest the allowlist cannot apply
+    let verdict = policy.apply(infected(&["Eicar-Signature"]), None).unwrap();
+    assert_eq!(verdict, infected(&["Eicar-Signature"]));
+
+    let verdict = policy
+        .apply(infected(&["Win.Ransomware.Locky-1"]), Some(&EICAR_SHA256))
+        .unwrap();
+    assert_eq!(verdict, infected(&["Win.Ransomware.Locky-1"]));
+}
+
+#[test]
+fn errors() {
+    let error = || Verdict::Error("INSTREAM size limit exceeded. ERROR".to_string());
+
+    assert_eq!(Policy::new().apply(error(), None).unwrap(), error());
+    let policy = Policy::new().on_error(VerdictPolicy::FailOpen);
+    assert_eq!(policy.apply(error(), None).unwrap(), Verdict::Clean);
+    let policy = Policy::new().on_error(VerdictPolicy::Strict);
+    assert!(policy.apply(error(), None).is_err());
+    assert_eq!(policy.apply(Verdict::Clean, None).unwrap(), Verdict::Clean);
+}
+
+#[test]
+fn from_toml_and_json() {
+    let policy = Policy::from_toml(POLICY_TOML).unwrap();
+    assert!(policy.is_ignored("PUA.Doc.Packed-1"));
+    assert!(policy.is_ignored("Heuristics.Encrypted.PDF"));
+    assert!(policy.is_escalated("PUA.Win.Exploit.CVE-1"));
+    assert!(policy.is_allowed(&EICAR_SHA256));
+    assert_eq!(
+        policy.apply(Verdict::Error("ERROR".into()), None).unwrap(),
+        Verdict::Clean
+    );
+
+    let policy =
+        Policy::from_json(r#"{"on_error": "strict", "ignore": [{"regex": "^Heuristics\\."}]}"#)
+            .unwrap();
+    assert!(policy.is_ignored("Heuristics.Phishing.Email"));
+    assert!(!policy.is_allowed(&EICAR_SHA256));
+    assert!(policy.apply(Verdict::Error("ERROR".into()), None).is_err());
+}
+
+#[test]
+fn invalid_config() {
+    for toml in [
+        "on_error = \"sometimes\"",
+        "allow_sha256 = [\"275a\"]",
+        "[[ignore]]\nregex = \"(\"",
+        "[[ignore]]\nprefix = \"PUA\"",
+        "unknown = true",
+    ] {
+        let err = Policy::from_toml(toml).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{toml}");
+    }
+}
+
+#[test]
+fn from_file() {
+    let path =
+        std::env::temp_dir().join(format!("clamav-client-policy-{}.toml", std::process::id()));
+    std::fs::write(&path, POLICY_TOML).unwrap();
+    let policy = Policy::from_file(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(policy.unwrap().is_ignored("PUA.Doc.Packed-1"));
+
+    let err = Policy::from_file(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[cfg(feature = "testing")]
+mod test_policy_client {
+    use super::*;
+    use clamav_client::policy::PolicyClient;
+    use clamav_client::testing::{MockClamd, MockClamdServer, EICAR};
+    use clamav_client::Tcp;
+
+    use crate::common;
+
+    const OK_RESPONSE: &[u8] = b"stream: OK\0";
+
+    fn mock_clamd() -> MockClamdServer<Tcp> {
+        common::spawn(
+            MockClamd::new()
+                .signature("PUA.Doc.Packed-1", "packed")
+                .signature("Win.Trojan.Agent-1", "trojan")
+                .stream_max_length(1_000),
+        )
+    }
+
+    fn policy() -> Policy {
+        Policy::from_toml(POLICY_TOML)
+            .unwrap()
+            .on_error(VerdictPolicy::Strict)
+    }
+
+    mod sync {
+        use super::*;
+        use clamav_client::hash::HashAlgorithms;
+        use clamav_client::ClamAvSync;
+
+        #[test]
+        fn responses_are_rewritten() {
+            let server = mock_clamd();
+            let client = PolicyClient::new(server.transport().clone(), policy());
+
+            assert_eq!(client.scan_buffer(b"packed", None).unwrap(), OK_RESPONSE);
+            assert_eq!(client.scan_buffer(EICAR, None).unwrap(), OK_RESPONSE);
+            assert_eq!(
+                client.scan_buffer(b"trojan", None).unwrap(),
+                b"stream: Win.Trojan.Agent-1 FOUND\0"
+            );
+            // The test file ends with a newline, so its digest is not allowlisted
+            assert_eq!(
+                client.scan_file("tests/data/eicar.txt", None).unwrap(),
+                b"stream: Eicar-Signature FOUND\0"
+            );
+
+            let err = client.scan_buffer(&[0; 2_000], None).unwrap_err();
+            assert_eq!(err.to_string(), "INSTREAM size limit exceeded. ERROR");
+        }
+
+        #[test]
+        fn all_entry_points_observe_the_policy() {
+            let server = mock_clamd();
+            let client = PolicyClient::new(server.transport().clone(), policy());
+
+            let progress = |_: &clamav_client::progress::ScanProgress| {};
+            let response = client
+                .scan_buffer_with_progress(b"packed", None, &progress)
+                .unwrap();
+            assert_eq!(response, OK_RESPONSE);
+            let scan = client
+                .scan_buffer_hashed(b"packed", None, HashAlgorithms::new())
+                .unwrap();
+            assert_eq!(scan.response, OK_RESPONSE);
+            let dyn_client: &dyn clamav_client::DynClamAvSync = &client;
+            let response = dyn_client.scan_reader(&mut &EICAR[..], None).unwrap();
+            assert_eq!(response, OK_RESPONSE);
+            #[cfg(feature = "mime")]
+            {
+                let message = b"Content-Type: text/plain\r\n\r\npacked";
+                let parts = client.scan_mime(message, None);
+                assert_eq!(parts[0].response.as_deref().unwrap(), OK_RESPONSE);
+            }
+        }
+
+        /// Transport that replaces the content of a file after it was scanned
+        struct Swap {
+            inner: Tcp,
+            path: std::path::PathBuf,
+        }
+
+        impl ClamAvSync for Swap {
+            type Stream = std::net::TcpStream;
+
+            fn connect(&self) -> io::Result<Self::Stream> {
+                self.inner.connect()
+            }
+
+            fn scan_reader<R: io::Read>(
+                &self,
+                reader: R,
+                chunk_size: Option<usize>,
+            ) -> clamav_client::IoResult {
+                let response = self.inner.scan_reader(reader, chunk_size);
+                std::fs::write(&self.path, EICAR)?;
+                response
+            }
+        }
+
+        #[test]
+        fn allowlist_checks_the_scanned_bytes() {
+            let server = mock_clamd();
+            let path = std::env::temp_dir()
+                .join(format!("clamav-client-policy-{}.bin", std::process::id()));
+            std::fs::write(&path, b"trojan").unwrap();
+            let inner = Swap {
+                inner: server.transport().clone(),
+                path: path.clone(),
+            };
+            let client = PolicyClient::new(inner, policy());
+
+            // The allowlisted content is only written after the scan
+            let response = client.scan_file(&path, None);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(response.unwrap(), b"stream: Win.Trojan.Agent-1 FOUND\0");
+        }
+    }
+
+    #[cfg(feature = "async")]
+    mod nonblocking {
+        use super::*;
+        use clamav_client::ClamAvAsync;
+
+        #[tokio::test]
+        async fn responses_are_rewritten() {
+            let server = mock_clamd();
+            let client = PolicyClient::new(server.transport().clone(), policy());
+
+            let response = client.scan_buffer(b"packed", None).await.unwrap();
+            assert_eq!(response, OK_RESPONSE);
+            let response = client
+                .scan_file("tests/data/eicar.txt", None)
+                .await
+                .unwrap();
+            assert_eq!(response, b"stream: Eicar-Signature FOUND\0");
+
+            let chunks = EICAR
+                .chunks(10)
+                .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+                .collect::<Vec<_>>();
+            let response = client
+                .scan_stream(futures_lite::stream::iter(chunks), None)
+                .await
+                .unwrap();
+            assert_eq!(response, OK_RESPONSE);
+
+            assert!(client.scan_buffer(&[0; 2_000], None).await.is_err());
+        }
+    }
+}
@@ -0,0 +1,234 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use std::sync::Mutex;
+
+use clamav_client::progress::{ScanPhase, ScanProgress};
+use clamav_client::testing::EICAR;
+use clamav_client::{ClamAvClient, Tcp};
+use common::limited_mock_clamd;
+
+const EICAR_TEST_FILE_PATH: &str = "tests/data/eicar.txt";
+const EICAR_FILE_SIGNATURE_FOUND_RESPONSE: &[u8] = b"stream: Eicar-Signature FOUND\0";
+
+/// Observer that records every report
+#[derive(Default)]
+struct Recorder(Mutex<Vec<ScanProgress>>);
+
+impl Recorder {
+    fn observe(&self) -> impl Fn(&ScanProgress) + Send + Sync + '_ {
+        |progress| self.0.lock().unwrap().push(*progress)
+    }
+
+    fn reports(&self) -> Vec<ScanProgress> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Checks the order of phases and counters of a successful scan
+fn assert_reports(reports: &[ScanProgress], bytes: u64, chunks: u64, total: Option<u64>) {
+    let phases: Vec<_> = reports.iter().map(|report| report.phase).collect();
+    let mut expected = vec![ScanPhase::Connecting];
+    expected.extend(std::iter::repeat(ScanPhase::Streaming).take(chunks as usize + 1));
+    expected.extend([ScanPhase::AwaitingVerdict, ScanPhase::Finished]);
+    assert_eq!(phases, expected);
+
+    for (sent, report) in reports[2..].iter().take(chunks as usize).enumerate() {
+        assert_eq!(report.chunks_sent, sent as u64 + 1);
+    }
+    assert!(
+        reports
+            .windows(2)
+            .all(|pair| pair[0].bytes_sent <= pair[1].bytes_sent
+                && pair[0].elapsed <= pair[1].elapsed)
+    );
+
+    let last = reports.last().unwrap();
+    assert_eq!(last.bytes_sent, bytes);
+    assert_eq!(last.chunks_sent, chunks);
+    assert!(reports.iter().all(|report| report.total_bytes == total));
+    if total.is_some() {
+        assert_eq!(last.fraction(), Some(1.0));
+    }
+}
+
+#[test]
+fn fraction() {
+    let mut progress = ScanProgress {
+        phase: ScanPhase::Streaming,
+        bytes_sent: 25,
+        chunks_sent: 1,
+        total_bytes: Some(100),
+        elapsed: std::time::Duration::ZERO,
+    };
+    assert_eq!(progress.fraction(), Some(0.25));
+    progress.total_bytes = Some(0);
+    assert_eq!(progress.fraction(), Some(1.0));
+    progress.total_bytes = None;
+    assert_eq!(progress.fraction(), None);
+}
+
+mod test_progress_sync {
+    use super::*;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn scan_buffer_with_progress() {
+        let server = limited_mock_clamd(1_000);
+        let recorder = Recorder::default();
+        let response = server
+            .transport()
+            .scan_buffer_with_progress(EICAR, Some(10), &recorder.observe())
+            .unwrap();
+
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_reports(&recorder.reports(), 68, 7, Some(68));
+    }
+
+    #[test]
+    fn scan_file_with_progress() {
+        let server = limited_mock_clamd(1_000);
+        let recorder = Recorder::default();
+        let response = server
+            .transport()
+            .scan_file_with_progress(EICAR_TEST_FILE_PATH, None, &recorder.observe())
+            .unwrap();
+
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_reports(&recorder.reports(), 69, 1, Some(69));
+    }
+
+    #[test]
+    fn client_settings_apply() {
+        let server = limited_mock_clamd(1_000);
+        let client = ClamAvClient::builder(server.transport().clone())
+            .chunk_size(16)
+            .max_stream_length(100)
+            .build();
+        let recorder = Recorder::default();
+        client
+            .scan_buffer_with_progress(EICAR, None, &recorder.observe())
+            .unwrap();
+        assert_reports(&recorder.reports(), 68, 5, Some(68));
+
+        let recorder = Recorder::default();
+        let err = client
+            .scan_buffer_with_progress(&[0; 101], None, &recorder.observe())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(recorder.reports().is_empty());
+    }
+
+    #[test]
+    fn unreachable_server() {
+        let mut server = limited_mock_clamd(1_000);
+        server.stop();
+        let recorder = Recorder::default();
+        assert!(server
+            .transport()
+            .scan_buffer_with_progress(EICAR, None, &recorder.observe())
+            .is_err());
+
+        let phases: Vec<_> = recorder.reports().iter().map(|r| r.phase).collect();
+        assert_eq!(phases, [ScanPhase::Connecting]);
+    }
+
+    /// Client that counts the scans sent through `scan_reader`
+    struct Wrapper {
+        inner: ClamAvClient<Tcp>,
+        scans: std::sync::atomic::AtomicUsize,
+    }
+
+    impl ClamAvSync for Wrapper {
+        type Stream = std::net::TcpStream;
+
+        fn connect(&self) -> std::io::Result<Self::Stream> {
+            self.inner.connect()
+        }
+
+        fn scan_reader<R: std::io::Read>(
+            &self,
+            reader: R,
+            chunk_size: Option<usize>,
+        ) -> clamav_client::IoResult {
+            self.scans.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.scan_reader(reader, chunk_size)
+        }
+    }
+
+    #[test]
+    fn wrapper_overrides_apply() {
+        let server = limited_mock_clamd(1_000);
+        let client = Wrapper {
+            inner: ClamAvClient::builder(server.transport().clone())
+                .chunk_size(16)
+                .build(),
+            scans: Default::default(),
+        };
+        let recorder = Recorder::default();
+        client
+            .scan_buffer_with_progress(EICAR, None, &recorder.observe())
+            .unwrap();
+        assert_reports(&recorder.reports(), 68, 5, Some(68));
+
+        let recorder = Recorder::default();
+        client
+            .scan_file_with_progress(EICAR_TEST_FILE_PATH, None, &recorder.observe())
+            .unwrap();
+        assert_reports(&recorder.reports(), 69, 5, Some(69));
+        assert_eq!(client.scans.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_progress_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn scan_file_and_buffer_with_progress() {
+        let server = limited_mock_clamd(1_000);
+        let recorder = Recorder::default();
+        let response = server
+            .transport()
+            .scan_file_with_progress(EICAR_TEST_FILE_PATH, Some(32), &recorder.observe())
+            .await
+            .unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_reports(&recorder.reports(), 69, 3, Some(69));
+
+        let recorder = Recorder::default();
+        server
+            .transport()
+            .scan_buffer_with_progress(EICAR, Some(10), &recorder.observe())
+            .await
+            .unwrap();
+        assert_reports(&recorder.reports(), 68, 7, Some(68));
+    }
+
+    #[async_std::test]
+    async fn scan_stream_with_progress() {
+        let server = limited_mock_clamd(1_000);
+        let client = ClamAvClient::builder(server.transport().clone())
+            .chunk_size(4)
+            .build();
+        let chunks: Vec<_> = EICAR
+            .chunks(10)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect();
+        let recorder = Recorder::default();
+        let response = client
+            .scan_stream_with_progress(
+                futures_lite::stream::iter(chunks),
+                None,
+                &recorder.observe(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        // Six items of 10 bytes are sent as 3 chunks each, the last 8 bytes as 2
+        assert_reports(&recorder.reports(), 68, 20, None);
+    }
+}
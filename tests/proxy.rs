@@ -0,0 +1,443 @@
+#![cfg(all(feature = "proxy", feature = "testing"))]
+
+mod common;
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use clamav_client::cache::{CachedClient, MemoryCache};
+use clamav_client::proxy::{AccessEntry, Backend, Pool, Proxy, ProxyServer, Strategy};
+use clamav_client::testing::{MockClamd, MockClamdServer, DEFAULT_VERSION, EICAR};
+use clamav_client::{ClamAvSync, Tcp};
+use common::mock_clamd;
+
+const EICAR_FILE_SIGNATURE_FOUND_RESPONSE: &[u8] = b"stream: Eicar-Signature FOUND\0";
+const OK_RESPONSE: &[u8] = b"stream: OK\0";
+
+/// Asserts that the proxy closed the connection
+fn assert_closed<R: Read>(stream: &mut R) {
+    match stream.read(&mut [0; 1]) {
+        Ok(read) => assert_eq!(read, 0),
+        Err(err) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+    }
+}
+
+fn pool(backends: &[&MockClamdServer<Tcp>]) -> Pool<Tcp> {
+    Pool::new(backends.iter().map(|b| b.transport().clone()).collect())
+}
+
+/// Access log that keeps every entry
+#[derive(Clone, Default)]
+struct Entries(Arc<Mutex<Vec<AccessEntry>>>);
+
+impl Entries {
+    fn log(&self) -> impl Fn(&AccessEntry) + Send + Sync + 'static {
+        let entries = self.0.clone();
+        move |entry| entries.lock().unwrap().push(entry.clone())
+    }
+
+    fn get(&self) -> Vec<AccessEntry> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn forwards_commands() {
+    let backend = mock_clamd();
+    let entries = Entries::default();
+    let server = Proxy::new(pool(&[&backend]))
+        .access_log(entries.log())
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+    let proxy = server.transport();
+
+    assert_eq!(proxy.ping().unwrap(), b"PONG\0");
+    assert_eq!(
+        proxy.get_version().unwrap(),
+        format!("{DEFAULT_VERSION}\0").as_bytes()
+    );
+    assert!(proxy.get_stats().unwrap().ends_with(b"END\0"));
+    assert_eq!(
+        proxy.scan_buffer(EICAR, Some(16)).unwrap(),
+        EICAR_FILE_SIGNATURE_FOUND_RESPONSE
+    );
+    assert_eq!(proxy.scan_buffer(b"clean", None).unwrap(), OK_RESPONSE);
+    assert_eq!(proxy.shutdown().unwrap(), b"UNKNOWN COMMAND\0");
+    assert!(!backend.is_stopped());
+    assert_eq!(backend.scans(), 2);
+
+    let entries = entries.get();
+    let commands: Vec<_> = entries.iter().map(|e| e.command.as_str()).collect();
+    assert_eq!(
+        commands,
+        ["PING", "VERSION", "STATS", "INSTREAM", "INSTREAM", "SHUTDOWN"]
+    );
+    assert_eq!(entries[3].bytes, EICAR.len() as u64);
+    assert_eq!(entries[3].reply, "stream: Eicar-Signature FOUND");
+    assert_eq!(entries[3].peer.split(':').next(), Some("127.0.0.1"));
+    assert!(entries[3].to_string().contains("INSTREAM bytes=68"));
+}
+
+#[test]
+fn sessions() {
+    let backend = mock_clamd();
+    let entries = Entries::default();
+    let server = Proxy::new(pool(&[&backend]))
+        .access_log(entries.log())
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let mut stream = TcpStream::connect(server.transport().0).unwrap();
+    stream.write_all(b"zIDSESSION\0zPING\0zINSTREAM\0").unwrap();
+    stream
+        .write_all(&(EICAR.len() as u32).to_be_bytes())
+        .unwrap();
+    stream.write_all(EICAR).unwrap();
+    stream.write_all(&[0; 4]).unwrap();
+    stream.write_all(b"zVERSION\0zEND\0").unwrap();
+
+    let mut replies = Vec::new();
+    let mut reader = BufReader::new(stream);
+    for _ in 0..3 {
+        let mut reply = Vec::new();
+        reader.read_until(b'\0', &mut reply).unwrap();
+        replies.push(String::from_utf8(reply).unwrap());
+    }
+    assert_eq!(replies[0], "1: PONG\0");
+    assert_eq!(replies[1], "2: stream: Eicar-Signature FOUND\0");
+    assert!(replies[2].starts_with("3: ClamAV 1.4.1/"));
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+
+    let ids: Vec<_> = entries.get().iter().map(|e| e.session_id).collect();
+    assert_eq!(ids, [Some(1), Some(2), Some(3)]);
+}
+
+#[test]
+fn stream_max_length() {
+    let backend = mock_clamd();
+    let server = Proxy::new(pool(&[&backend]))
+        .max_stream_length(10)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let response = server.transport().scan_buffer(&[0; 11], Some(4)).unwrap();
+    assert_eq!(response, b"INSTREAM size limit exceeded. ERROR\0");
+    assert_eq!(backend.scans(), 0);
+}
+
+#[test]
+fn connection_limit() {
+    let backend = mock_clamd();
+    let server = Proxy::new(pool(&[&backend]))
+        .max_connections(1)
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    // The second connection is closed while the first one is served
+    let mut first = TcpStream::connect(server.transport().0).unwrap();
+    let mut second = TcpStream::connect(server.transport().0).unwrap();
+    second
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_closed(&mut second);
+
+    first.write_all(b"zPING\0").unwrap();
+    let mut reply = Vec::new();
+    first.read_to_end(&mut reply).unwrap();
+    assert_eq!(reply, b"PONG\0");
+    drop(first);
+
+    // The slot is released when the connection ends
+    let mut retries = 0;
+    while server.transport().ping().is_err() && retries < 50 {
+        std::thread::sleep(Duration::from_millis(10));
+        retries += 1;
+    }
+    assert_eq!(server.transport().ping().unwrap(), b"PONG\0");
+}
+
+#[test]
+fn client_timeouts_and_limits() {
+    let backend = mock_clamd();
+    let server = Proxy::new(pool(&[&backend]))
+        .read_timeout(Duration::from_millis(100))
+        .idle_timeout(Duration::from_millis(100))
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+    let connect = |server: &ProxyServer<Tcp>| {
+        let stream = TcpStream::connect(server.transport().0).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    };
+
+    // A stream that stalls is dropped after the read timeout
+    let mut stream = connect(&server);
+    stream.write_all(b"zINSTREAM\0\0\0\0\x10abc").unwrap();
+    assert_closed(&mut stream);
+
+    // A session without a next command is closed after the idle timeout
+    let stream = connect(&server);
+    stream
+        .try_clone()
+        .unwrap()
+        .write_all(b"zIDSESSION\0zPING\0")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut reply = Vec::new();
+    reader.read_until(b'\0', &mut reply).unwrap();
+    assert_eq!(reply, b"1: PONG\0");
+    assert_closed(&mut reader);
+
+    // Commands without a delimiter are not buffered without bound
+    let server = Proxy::new(pool(&[&backend]))
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+    let mut stream = connect(&server);
+    let _ = stream.write_all(&[b'a'; 10_000]);
+    assert_closed(&mut stream);
+}
+
+#[test]
+fn round_robin() {
+    let backends = [mock_clamd(), mock_clamd()];
+    let server = Proxy::new(pool(&[&backends[0], &backends[1]]))
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    for _ in 0..4 {
+        server.transport().scan_buffer(b"clean", None).unwrap();
+    }
+    assert_eq!(backends[0].scans(), 2);
+    assert_eq!(backends[1].scans(), 2);
+}
+
+#[test]
+fn least_connections() {
+    let backends = [mock_clamd(), mock_clamd()];
+    let pool = pool(&[&backends[0], &backends[1]]).strategy(Strategy::LeastConnections);
+
+    // An open connection to the first backend makes the second one preferred
+    let open = pool.connect().unwrap();
+    let busy = pool
+        .active_connections()
+        .iter()
+        .position(|&n| n == 1)
+        .unwrap();
+    for _ in 0..3 {
+        pool.scan_buffer(b"clean", None).unwrap();
+    }
+    assert_eq!(backends[busy].scans(), 0);
+    assert_eq!(backends[1 - busy].scans(), 3);
+
+    drop(open);
+    assert_eq!(pool.active_connections(), [0, 0]);
+}
+
+#[test]
+fn failover() {
+    let mut backends = [mock_clamd(), mock_clamd()];
+    backends[0].stop();
+    let pool = pool(&[&backends[0], &backends[1]]).cooldown(Duration::from_secs(60));
+    let proxy = Proxy::new(pool);
+    let server = proxy.bind_tcp("127.0.0.1:0").unwrap();
+
+    for _ in 0..4 {
+        let response = server.transport().scan_buffer(EICAR, None).unwrap();
+        assert_eq!(response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+    assert_eq!(backends[1].scans(), 4);
+    assert_eq!(proxy.upstream().available(), [false, true]);
+}
+
+/// Backend whose connections fail after answering `answer`
+#[derive(Clone, Default)]
+struct Broken {
+    answer: &'static [u8],
+    timeout: bool,
+    connects: Arc<AtomicUsize>,
+}
+
+struct BrokenStream(&'static [u8], io::ErrorKind);
+
+impl ClamAvSync for Broken {
+    type Stream = BrokenStream;
+
+    fn connect(&self) -> io::Result<BrokenStream> {
+        self.connects.fetch_add(1, Ordering::SeqCst);
+        let kind = if self.timeout {
+            io::ErrorKind::TimedOut
+        } else {
+            io::ErrorKind::ConnectionReset
+        };
+        Ok(BrokenStream(self.answer, kind))
+    }
+}
+
+impl Read for BrokenStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Err(self.1.into());
+        }
+        self.0.read(buf)
+    }
+}
+
+impl Write for BrokenStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failover_after_request_errors() {
+    // A backend that fails before answering is skipped without cooling down
+    let backends = [Broken::default(), Broken::default()];
+    let pool = Pool::new(backends.to_vec()).cooldown(Duration::from_secs(60));
+    let err = pool.scan_buffer(b"clean", None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    assert_eq!(backends[0].connects.load(Ordering::SeqCst), 1);
+    assert_eq!(backends[1].connects.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.available(), [true, true]);
+
+    // A backend that started answering may have scanned the data
+    let answering = Broken {
+        answer: b"stream: ",
+        ..Broken::default()
+    };
+    let pool = Pool::new(vec![answering.clone(), answering.clone()]);
+    let err = pool.scan_buffer(b"clean", None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    assert_eq!(answering.connects.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.available(), [true, true]);
+
+    // Backends that time out cool down
+    let backends = [Broken {
+        timeout: true,
+        ..Broken::default()
+    }];
+    let pool = Pool::new(backends.to_vec());
+    let err = pool.scan_buffer(b"clean", None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(pool.available(), [false]);
+}
+
+#[test]
+fn all_backends_down() {
+    let mut backend = mock_clamd();
+    backend.stop();
+    let server = Proxy::new(pool(&[&backend]))
+        .bind_tcp("127.0.0.1:0")
+        .unwrap();
+
+    let response = server.transport().scan_buffer(b"clean", None).unwrap();
+    assert!(response.ends_with(b" ERROR\0"), "{response:?}");
+    let verdict = clamav_client::Verdict::parse(&response).unwrap();
+    assert!(matches!(verdict, clamav_client::Verdict::Error(_)));
+}
+
+#[test]
+fn caching() {
+    let backend = mock_clamd();
+    let upstream = CachedClient::new(pool(&[&backend]), MemoryCache::new(10));
+    let server = Proxy::new(upstream).bind_tcp("127.0.0.1:0").unwrap();
+
+    for _ in 0..3 {
+        let response = server.transport().scan_buffer(EICAR, None).unwrap();
+        assert_eq!(response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+    assert_eq!(backend.scans(), 1);
+}
+
+#[test]
+fn backends_from_str() {
+    let backend: Backend = "127.0.0.1:3310".parse().unwrap();
+    assert!(matches!(backend, Backend::Tcp(_)));
+    assert_eq!(backend.to_string(), "127.0.0.1:3310");
+
+    #[cfg(unix)]
+    {
+        let backend: Backend = "unix:/run/clamd.sock".parse().unwrap();
+        assert!(matches!(backend, Backend::Socket(_)));
+        assert_eq!(backend.to_string(), "unix:/run/clamd.sock");
+    }
+
+    assert!("not an address".parse::<Backend>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_listener_and_backend() {
+    let dir = std::env::temp_dir().join(format!("clamav-proxy-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let backend = MockClamd::new()
+        .bind_socket(dir.join("clamd.sock"))
+        .unwrap();
+    let backends = vec![Backend::Socket(backend.transport().clone())];
+
+    let proxy = Proxy::new(Pool::new(backends));
+    let mut server = proxy.bind_socket(dir.join("proxy.sock")).unwrap();
+    let tcp = proxy.bind_tcp("127.0.0.1:0").unwrap();
+
+    assert_eq!(
+        server.transport().scan_buffer(EICAR, None).unwrap(),
+        EICAR_FILE_SIGNATURE_FOUND_RESPONSE
+    );
+    assert_eq!(tcp.transport().ping().unwrap(), b"PONG\0");
+    assert_eq!(backend.scans(), 1);
+
+    server.stop();
+    assert!(!dir.join("proxy.sock").exists());
+    drop(backend);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "proxy-cli")]
+#[test]
+fn binary() {
+    use std::process::{Command, Stdio};
+
+    let backend = mock_clamd();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_clamav-proxy"))
+        .args(["--tcp", "127.0.0.1:0", "--backend"])
+        .arg(backend.transport().0.to_string())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let address = line.trim().strip_prefix("Listening on ").unwrap();
+    let proxy = Tcp(address.parse().unwrap());
+
+    for _ in 0..2 {
+        assert_eq!(
+            proxy.scan_buffer(EICAR, None).unwrap(),
+            EICAR_FILE_SIGNATURE_FOUND_RESPONSE
+        );
+    }
+    // The second scan is answered from the cache
+    assert_eq!(backend.scans(), 1);
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert!(line.contains(" INSTREAM bytes=68 "), "{line}");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
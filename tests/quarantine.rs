@@ -0,0 +1,226 @@
+#![cfg(feature = "quarantine")]
+
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use clamav_client::quarantine::{Obfuscation, Quarantine};
+
+const EICAR: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+const EICAR_SHA256: &str = "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f";
+
+/// Directory with files to quarantine and a quarantine directory, removed on drop
+struct Dirs {
+    base: PathBuf,
+    files: PathBuf,
+}
+
+impl Dirs {
+    fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let base = std::env::temp_dir().join(format!(
+            "clamav-client-quarantine-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let files = base.join("files");
+        fs::create_dir_all(&files).unwrap();
+        Dirs {
+            files: fs::canonicalize(files).unwrap(),
+            base,
+        }
+    }
+
+    fn quarantine(&self) -> Quarantine {
+        Quarantine::open(self.base.join("quarantine")).unwrap()
+    }
+
+    fn file(&self, name: &str, content: &[u8]) -> PathBuf {
+        let path = self.files.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Dirs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.base);
+    }
+}
+
+fn signatures() -> Vec<String> {
+    vec!["Eicar-Signature".to_string()]
+}
+
+#[test]
+fn quarantine_and_restore() {
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine();
+    let path = dirs.file("eicar.com", EICAR);
+
+    let entry = quarantine.quarantine(&path, &signatures()).unwrap();
+    assert!(!path.exists());
+    assert_eq!(entry.original_path, path);
+    assert_eq!(entry.signatures, signatures());
+    assert_eq!(entry.size, EICAR.len() as u64);
+    assert_eq!(entry.sha256, EICAR_SHA256);
+    assert_eq!(entry.obfuscation, Obfuscation::default());
+
+    // The stored content must not contain the original bytes
+    let stored = fs::read(quarantine.dir().join(format!("{}.bin", entry.id))).unwrap();
+    assert_eq!(stored.len(), EICAR.len());
+    assert!(!stored.windows(5).any(|window| window == b"EICAR"));
+
+    assert_eq!(quarantine.list().unwrap(), vec![entry.clone()]);
+    assert_eq!(quarantine.get(&entry.id).unwrap(), entry);
+
+    assert_eq!(quarantine.restore(&entry.id).unwrap(), path);
+    assert_eq!(fs::read(&path).unwrap(), EICAR);
+    assert!(quarantine.list().unwrap().is_empty());
+    assert_eq!(
+        quarantine.get(&entry.id).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn no_obfuscation() {
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine().obfuscation(Obfuscation::None);
+    let path = dirs.file("eicar.com", EICAR);
+
+    let entry = quarantine.quarantine(&path, &signatures()).unwrap();
+    let stored = fs::read(quarantine.dir().join(format!("{}.bin", entry.id))).unwrap();
+    assert_eq!(stored, EICAR);
+
+    // Restoring uses the obfuscation recorded in the sidecar
+    let quarantine = quarantine.obfuscation(Obfuscation::Xor(vec![1, 2, 3]));
+    quarantine.restore(&entry.id).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), EICAR);
+}
+
+#[test]
+fn restore_to_and_conflicts() {
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine();
+    let path = dirs.file("eicar.com", EICAR);
+    let entry = quarantine.quarantine(&path, &signatures()).unwrap();
+
+    fs::write(&path, b"replacement").unwrap();
+    let err = quarantine.restore(&entry.id).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read(&path).unwrap(), b"replacement");
+
+    let target = dirs.files.join("restored/eicar.com");
+    assert_eq!(quarantine.restore_to(&entry.id, &target).unwrap(), target);
+    assert_eq!(fs::read(&target).unwrap(), EICAR);
+}
+
+#[cfg(unix)]
+#[test]
+fn planted_temporary_file() {
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine();
+    let path = dirs.file("eicar.com", EICAR);
+    let victim = dirs.file("victim.txt", b"victim");
+    let entry = quarantine.quarantine(&path, &signatures()).unwrap();
+
+    let part = dirs.files.join(format!("eicar.com.{}.part", entry.id));
+    std::os::unix::fs::symlink(&victim, &part).unwrap();
+    let err = quarantine.restore(&entry.id).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read(&victim).unwrap(), b"victim");
+    assert!(part.symlink_metadata().is_ok());
+
+    fs::remove_file(&part).unwrap();
+    assert_eq!(quarantine.restore(&entry.id).unwrap(), path);
+    assert_eq!(fs::read(&path).unwrap(), EICAR);
+    assert!(part.symlink_metadata().is_err());
+}
+
+#[test]
+fn tampered_content_is_not_restored() {
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine();
+    let path = dirs.file("eicar.com", EICAR);
+    let entry = quarantine.quarantine(&path, &signatures()).unwrap();
+
+    fs::write(
+        quarantine.dir().join(format!("{}.bin", entry.id)),
+        b"tampered",
+    )
+    .unwrap();
+    let err = quarantine.restore(&entry.id).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(!path.exists());
+    assert_eq!(quarantine.list().unwrap().len(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine();
+    let path = dirs.file("script.sh", b"#!/bin/sh\n");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+    let entry = quarantine.quarantine(&path, &[]).unwrap();
+    assert_eq!(entry.mode, Some(0o750));
+    // Stored files are only accessible by the owner, regardless of the umask
+    for extension in ["bin", "json"] {
+        let stored = quarantine.dir().join(format!("{}.{extension}", entry.id));
+        let mode = fs::metadata(stored).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    quarantine.restore(&entry.id).unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o750);
+}
+
+#[test]
+fn purge() {
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine();
+    let first = quarantine
+        .quarantine(dirs.file("first", EICAR), &signatures())
+        .unwrap();
+    let second = quarantine
+        .quarantine(dirs.file("second", EICAR), &signatures())
+        .unwrap();
+    assert_ne!(first.id, second.id);
+    assert_eq!(quarantine.list().unwrap(), vec![first.clone(), second]);
+
+    quarantine.purge(&first.id).unwrap();
+    assert_eq!(quarantine.list().unwrap().len(), 1);
+    assert_eq!(
+        quarantine
+            .purge_older_than(Duration::from_secs(3600))
+            .unwrap(),
+        0
+    );
+    assert_eq!(quarantine.purge_older_than(Duration::ZERO).unwrap(), 1);
+    assert!(quarantine.list().unwrap().is_empty());
+    assert_eq!(fs::read_dir(quarantine.dir()).unwrap().count(), 0);
+}
+
+#[test]
+fn invalid_input() {
+    let dirs = Dirs::new();
+    let quarantine = dirs.quarantine();
+
+    let err = quarantine.quarantine(&dirs.files, &[]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = quarantine.get("../files").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = quarantine
+        .quarantine(dirs.files.join("missing"), &[])
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs::read_dir(quarantine.dir()).unwrap().count(), 0);
+}
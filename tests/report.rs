@@ -0,0 +1,160 @@
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use clamav_client::report::{ReportFormat, ScanRecord, ScanReport, ScanStatus};
+use clamav_client::Verdict;
+use serde_json::Value;
+
+fn report() -> ScanReport {
+    let mut report = ScanReport::new();
+    report.push(
+        ScanRecord::from_verdict("README.md", Verdict::Clean)
+            .with_size(1024)
+            .with_duration(Duration::from_millis(3)),
+    );
+    report.push(
+        ScanRecord::from_verdict(
+            "tests/data/eicar test.txt",
+            Verdict::parse(b"stream: Eicar-Signature FOUND\0").unwrap(),
+        )
+        .with_size(68)
+        .with_sha256("275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f")
+        .with_backend("tcp://127.0.0.1:3310"),
+    );
+    report.push(ScanRecord::from_result(
+        "missing.txt",
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No such file or directory",
+        )),
+    ));
+    report.set_duration(Duration::from_millis(250));
+    report
+}
+
+fn write(report: &ScanReport, format: ReportFormat) -> String {
+    let mut output = Vec::new();
+    report.write(format, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn summary() {
+    let report = report();
+    let summary = report.summary();
+    assert_eq!(summary.scanned, 2);
+    assert_eq!(summary.infected, 1);
+    assert_eq!(summary.errors, 1);
+    assert_eq!(summary.duration, Duration::from_millis(250));
+
+    let statuses: Vec<_> = report.records().iter().map(|r| r.status).collect();
+    assert_eq!(
+        statuses,
+        [ScanStatus::Clean, ScanStatus::Infected, ScanStatus::Error]
+    );
+}
+
+#[test]
+fn verdict_errors() {
+    let record = ScanRecord::from_verdict(
+        "stream",
+        Verdict::Error("INSTREAM size limit exceeded. ERROR".into()),
+    );
+    assert_eq!(record.status, ScanStatus::Error);
+    assert_eq!(
+        record.error.as_deref(),
+        Some("INSTREAM size limit exceeded. ERROR")
+    );
+    assert!(record.signatures.is_empty());
+}
+
+#[test]
+fn json() {
+    let report = report();
+    let output = write(&report, ReportFormat::Json);
+    let value: Value = serde_json::from_str(&output).unwrap();
+
+    let records = value["records"].as_array().unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0]["status"], "clean");
+    assert_eq!(records[0]["size"], 1024);
+    assert_eq!(records[0]["duration_ms"], 3.0);
+    assert!(records[0].get("sha256").is_none());
+    assert_eq!(records[1]["status"], "infected");
+    assert_eq!(records[1]["signatures"][0], "Eicar-Signature");
+    assert_eq!(records[1]["backend"], "tcp://127.0.0.1:3310");
+    assert_eq!(records[2]["status"], "error");
+    assert_eq!(records[2]["error"], "No such file or directory");
+    assert_eq!(value["summary"]["infected"], 1);
+    assert_eq!(value["summary"]["duration_ms"], 250.0);
+
+    let parsed: ScanReport = serde_json::from_str(&output).unwrap();
+    assert_eq!(parsed, report);
+}
+
+#[test]
+fn ndjson() {
+    let report = report();
+    let output = write(&report, ReportFormat::Ndjson);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3);
+
+    for (line, record) in lines.iter().zip(report.records()) {
+        let parsed: ScanRecord = serde_json::from_str(line).unwrap();
+        assert_eq!(&parsed, record);
+    }
+}
+
+#[test]
+fn sarif() {
+    let output = write(&report(), ReportFormat::Sarif);
+    let value: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(value["version"], "2.1.0");
+
+    let run = &value["runs"][0];
+    assert_eq!(run["tool"]["driver"]["name"], "clamav-client");
+    assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "Eicar-Signature");
+
+    let results = run["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["ruleId"], "Eicar-Signature");
+    assert_eq!(results[0]["ruleIndex"], 0);
+    assert_eq!(results[0]["level"], "error");
+    assert_eq!(
+        results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+        "tests/data/eicar%20test.txt"
+    );
+    assert_eq!(results[0]["properties"]["size"], 68);
+
+    let invocation = &run["invocations"][0];
+    assert_eq!(invocation["executionSuccessful"], false);
+    let notifications = invocation["toolExecutionNotifications"].as_array().unwrap();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(
+        notifications[0]["message"]["text"],
+        "missing.txt: No such file or directory"
+    );
+}
+
+#[test]
+fn verdict_serde() {
+    let verdict = Verdict::Infected(vec!["Eicar-Signature".into()]);
+    let json = serde_json::to_string(&verdict).unwrap();
+    assert_eq!(json, r#"{"infected":["Eicar-Signature"]}"#);
+    assert_eq!(serde_json::from_str::<Verdict>(&json).unwrap(), verdict);
+    assert_eq!(
+        serde_json::to_string(&Verdict::Clean).unwrap(),
+        r#""clean""#
+    );
+}
+
+#[test]
+fn format_from_str() {
+    assert_eq!("json".parse(), Ok(ReportFormat::Json));
+    assert_eq!("NDJSON".parse(), Ok(ReportFormat::Ndjson));
+    assert_eq!("jsonl".parse(), Ok(ReportFormat::Ndjson));
+    assert_eq!("sarif".parse(), Ok(ReportFormat::Sarif));
+    assert!("xml".parse::<ReportFormat>().is_err());
+    assert_eq!(ReportFormat::Sarif.to_string(), "sarif");
+}
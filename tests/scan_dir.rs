@@ -0,0 +1,346 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use clamav_client::dir::{ScanDirOptions, ScannedFile, SymlinkPolicy};
+use clamav_client::testing::EICAR;
+use common::mock_clamd;
+
+/// Temporary directory tree that is removed on drop
+///
+/// ```text
+/// clean.txt
+/// eicar.com
+/// big.bin           2000 bytes
+/// .hidden/eicar.txt
+/// sub/notes.log
+/// sub/deep/eicar.txt
+/// link-file         -> eicar.com (Unix only)
+/// link-dir          -> sub (Unix only)
+/// sub/deep/loop     -> .. (Unix only)
+/// ```
+struct Tree(PathBuf);
+
+impl Tree {
+    fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "clamav-client-scan-dir-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        fs::create_dir_all(root.join("sub/deep")).unwrap();
+        fs::write(root.join("clean.txt"), b"clean").unwrap();
+        fs::write(root.join("eicar.com"), EICAR).unwrap();
+        fs::write(root.join("big.bin"), vec![0; 2000]).unwrap();
+        fs::write(root.join(".hidden/eicar.txt"), EICAR).unwrap();
+        fs::write(root.join("sub/notes.log"), b"notes").unwrap();
+        fs::write(root.join("sub/deep/eicar.txt"), EICAR).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(root.join("eicar.com"), root.join("link-file")).unwrap();
+            symlink(root.join("sub"), root.join("link-dir")).unwrap();
+            symlink(root.join("sub"), root.join("sub/deep/loop")).unwrap();
+        }
+        Tree(root)
+    }
+
+    /// Maps paths relative to the tree to whether the file is infected
+    fn results(&self, results: impl IntoIterator<Item = ScannedFile>) -> BTreeMap<String, bool> {
+        results
+            .into_iter()
+            .map(|scanned| {
+                let path = relative(&self.0, &scanned.path);
+                let response = scanned.response.unwrap();
+                (path, !clamav_client::clean(&response).unwrap())
+            })
+            .collect()
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap();
+    let parts: Vec<_> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    parts.join("/")
+}
+
+fn expected(entries: &[(&str, bool)]) -> BTreeMap<String, bool> {
+    entries
+        .iter()
+        .map(|&(path, infected)| (path.to_string(), infected))
+        .collect()
+}
+
+mod test_scan_dir_sync {
+    use super::*;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn recursive() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let results = server.transport().scan_dir(&tree.0, ScanDirOptions::new());
+
+        assert_eq!(
+            tree.results(results),
+            expected(&[
+                (".hidden/eicar.txt", true),
+                ("big.bin", false),
+                ("clean.txt", false),
+                ("eicar.com", true),
+                ("sub/deep/eicar.txt", true),
+                ("sub/notes.log", false),
+            ])
+        );
+        assert_eq!(server.scans(), 6);
+    }
+
+    #[test]
+    fn non_recursive() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let options = ScanDirOptions::new().recursive(false).concurrency(1);
+        let results = server.transport().scan_dir(&tree.0, options);
+
+        assert_eq!(
+            tree.results(results),
+            expected(&[
+                ("big.bin", false),
+                ("clean.txt", false),
+                ("eicar.com", true)
+            ])
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+
+        let options = ScanDirOptions::new().include("*.txt").skip_hidden(true);
+        let results = server.transport().scan_dir(&tree.0, options);
+        assert_eq!(
+            tree.results(results),
+            expected(&[("clean.txt", false), ("sub/deep/eicar.txt", true)])
+        );
+
+        let options = ScanDirOptions::new()
+            .exclude("**/deep")
+            .exclude(".hidden")
+            .exclude("*.[bl][io][ng]")
+            .max_file_size(100);
+        let results = server.transport().scan_dir(&tree.0, options);
+        assert_eq!(
+            tree.results(results),
+            expected(&[("clean.txt", false), ("eicar.com", true)])
+        );
+
+        let options = ScanDirOptions::new().include("sub/**/*.txt");
+        let results = server.transport().scan_dir(&tree.0, options);
+        assert_eq!(
+            tree.results(results),
+            expected(&[("sub/deep/eicar.txt", true)])
+        );
+    }
+
+    #[test]
+    fn backtracking_pattern() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let name = format!("{}.txt", "a".repeat(60));
+        fs::write(tree.0.join(&name), b"clean").unwrap();
+
+        // Consecutive wildcards do not backtrack exponentially
+        let options = ScanDirOptions::new()
+            .recursive(false)
+            .include("*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b")
+            .include("**a**a**a**a**a**a**a**a**a**a**a**a**a**a**a*.txt");
+        let results = server.transport().scan_dir(&tree.0, options);
+        assert_eq!(tree.results(results), expected(&[(name.as_str(), false)]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+
+        let options = ScanDirOptions::new()
+            .symlinks(SymlinkPolicy::FollowFiles)
+            .include("*.com")
+            .include("link-*");
+        let results = server.transport().scan_dir(&tree.0, options);
+        assert_eq!(
+            tree.results(results),
+            expected(&[("eicar.com", true), ("link-file", true)])
+        );
+
+        // `link-dir` and `sub/deep/loop` point to `sub`, which is scanned once
+        let options = ScanDirOptions::new().symlinks(SymlinkPolicy::Follow);
+        let results = tree.results(server.transport().scan_dir(&tree.0, options));
+        assert_eq!(results.len(), 7);
+        assert_eq!(results.get("link-file"), Some(&true));
+        let notes: Vec<_> = results
+            .keys()
+            .filter(|path| path.ends_with("notes.log"))
+            .collect();
+        assert_eq!(notes.len(), 1);
+    }
+
+    #[test]
+    fn single_file() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let results: Vec<_> = server
+            .transport()
+            .scan_dir(
+                tree.0.join("eicar.com"),
+                ScanDirOptions::new().include("*.txt"),
+            )
+            .collect();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].size, Some(EICAR.len() as u64));
+    }
+
+    #[test]
+    fn walk_errors() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let missing = tree.0.join("missing");
+        let results: Vec<_> = server
+            .transport()
+            .scan_dir(&missing, ScanDirOptions::new())
+            .collect();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, missing);
+        assert_eq!(results[0].size, None);
+        let err = results[0].response.as_ref().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn early_drop() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let mut results = server
+            .transport()
+            .scan_dir(&tree.0, ScanDirOptions::new().concurrency(1));
+
+        assert!(results.next().unwrap().response.is_ok());
+        drop(results);
+        assert!(server.scans() < 6 + 1);
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_scan_dir_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+    use futures_lite::StreamExt;
+
+    #[tokio::test]
+    async fn recursive() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let results: Vec<_> = server
+            .transport()
+            .scan_dir(&tree.0, ScanDirOptions::new().concurrency(2))
+            .collect()
+            .await;
+
+        assert_eq!(
+            tree.results(results),
+            expected(&[
+                (".hidden/eicar.txt", true),
+                ("big.bin", false),
+                ("clean.txt", false),
+                ("eicar.com", true),
+                ("sub/deep/eicar.txt", true),
+                ("sub/notes.log", false),
+            ])
+        );
+    }
+
+    #[async_std::test]
+    async fn filters() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let options = ScanDirOptions::new()
+            .include("*.txt")
+            .skip_hidden(true)
+            .concurrency(1);
+        let results: Vec<_> = server
+            .transport()
+            .scan_dir(&tree.0, options)
+            .collect()
+            .await;
+
+        assert_eq!(
+            tree.results(results),
+            expected(&[("clean.txt", false), ("sub/deep/eicar.txt", true)])
+        );
+    }
+
+    #[tokio::test]
+    async fn walk_errors() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let mut results = server
+            .transport()
+            .scan_dir(tree.0.join("missing"), ScanDirOptions::new());
+
+        let result = results.next().await.unwrap();
+        assert!(result.response.is_err());
+        assert!(results.next().await.is_none());
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod test_scan_dir_tokio {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+    use futures_lite::StreamExt;
+
+    #[tokio::test]
+    async fn recursive() {
+        let server = mock_clamd();
+        let tree = Tree::new();
+        let transport = clamav_client::tokio::Tcp(server.transport().0);
+        let results: Vec<_> = transport
+            .scan_dir(&tree.0, ScanDirOptions::new().concurrency(2))
+            .collect()
+            .await;
+
+        assert_eq!(
+            tree.results(results),
+            expected(&[
+                (".hidden/eicar.txt", true),
+                ("big.bin", false),
+                ("clean.txt", false),
+                ("eicar.com", true),
+                ("sub/deep/eicar.txt", true),
+                ("sub/notes.log", false),
+            ])
+        );
+    }
+}
@@ -0,0 +1,388 @@
+#![cfg(feature = "testing")]
+
+use std::io::{Read, Write};
+
+use clamav_client::testing::{MockClamd, EICAR};
+
+const EICAR_TEST_FILE_PATH: &str = "tests/data/eicar.txt";
+const CLEAN_TEST_FILE_PATH: &str = "README.md";
+const OVERSIZED_TEST_FILE_PATH: &str = "tests/data/stream-max-length-test-file.bin";
+
+const EICAR_FILE_SIGNATURE_FOUND_RESPONSE: &[u8] = b"stream: Eicar-Signature FOUND\0";
+const OK_RESPONSE: &[u8] = b"stream: OK\0";
+const SIZE_LIMIT_EXCEEDED_ERROR_RESPONSE: &[u8] = b"INSTREAM size limit exceeded. ERROR\0";
+
+fn mock_clamd() -> MockClamd {
+    MockClamd::new().stream_max_length(1_000_000)
+}
+
+mod test_mock_sync {
+    use super::*;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn ping_and_version_tcp() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let response = server.transport().ping().unwrap();
+        assert_eq!(&response, clamav_client::PONG);
+        let response = server.transport().get_version().unwrap();
+        assert!(response.starts_with(b"ClamAV"));
+    }
+
+    #[test]
+    fn scan_tcp_files() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let tcp = server.transport();
+
+        let response = tcp.scan_file(EICAR_TEST_FILE_PATH, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        let response = tcp.scan_file(CLEAN_TEST_FILE_PATH, Some(7)).unwrap();
+        assert_eq!(&response, OK_RESPONSE);
+        let response = tcp.scan_file(OVERSIZED_TEST_FILE_PATH, None).unwrap();
+        assert_eq!(&response, SIZE_LIMIT_EXCEEDED_ERROR_RESPONSE);
+        assert_eq!(server.scans(), 3);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn scan_socket_buffer() {
+        let path = std::env::temp_dir().join(format!("mock-clamd-{}.sock", std::process::id()));
+        let server = mock_clamd().bind_socket(&path).unwrap();
+        let response = server.transport().scan_buffer(EICAR, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn scan_custom_signature() {
+        let server = mock_clamd()
+            .signature("Custom.Test-1", "needle")
+            .bind_tcp("127.0.0.1:0")
+            .unwrap();
+        let response = server
+            .transport()
+            .scan_buffer(b"haystack with a needle inside", Some(4))
+            .unwrap();
+        assert_eq!(&response, b"stream: Custom.Test-1 FOUND\0");
+    }
+
+    #[test]
+    fn session_commands() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let mut stream = std::net::TcpStream::connect(server.transport().0).unwrap();
+        stream.write_all(b"zIDSESSION\0zPING\0zINSTREAM\0").unwrap();
+        stream
+            .write_all(&(EICAR.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(EICAR).unwrap();
+        stream.write_all(&[0; 4]).unwrap();
+        stream.write_all(b"zSTATS\0zEND\0").unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let response = String::from_utf8(response).unwrap();
+        let replies: Vec<&str> = response.split_terminator('\0').collect();
+        assert_eq!(replies[0], "1: PONG");
+        assert_eq!(replies[1], "2: stream: Eicar-Signature FOUND");
+        assert!(replies[2].starts_with("3: POOLS: 1"));
+        assert!(replies[2].ends_with("END"));
+    }
+
+    #[test]
+    fn newline_delimited_command() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let mut stream = std::net::TcpStream::connect(server.transport().0).unwrap();
+        stream.write_all(b"nPING\n").unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert_eq!(&response, b"PONG\n");
+    }
+
+    #[test]
+    fn shutdown() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let response = server.transport().shutdown().unwrap();
+        assert!(response.is_empty());
+        while !server.is_stopped() {
+            std::thread::yield_now();
+        }
+        assert!(server.transport().ping().map_or(true, |r| r.is_empty()));
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_mock_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn scan_tcp_stream() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let chunks = EICAR
+            .chunks(8)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)));
+        let response = server
+            .transport()
+            .scan_stream(futures_lite::stream::iter(chunks), None)
+            .await
+            .unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+
+    #[async_std::test]
+    async fn scan_tcp_oversized_file() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let response = server
+            .transport()
+            .scan_file(OVERSIZED_TEST_FILE_PATH, None)
+            .await
+            .unwrap();
+        assert_eq!(&response, SIZE_LIMIT_EXCEEDED_ERROR_RESPONSE);
+    }
+}
+
+mod test_fault_sync {
+    use super::*;
+    use clamav_client::testing::{Fault, FaultInjector};
+    use clamav_client::{ClamAvClient, ClamAvSync, RetryPolicy};
+    use std::time::Duration;
+
+    #[test]
+    fn refuse_connection() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport = FaultInjector::scripted(
+            server.transport().clone(),
+            [vec![Fault::ConnectRefused], vec![]],
+        );
+        let err = transport.ping().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(&transport.ping().unwrap(), clamav_client::PONG);
+        assert_eq!(transport.injected(), 1);
+    }
+
+    #[test]
+    fn retry_refused_connection() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport = FaultInjector::scripted(
+            server.transport().clone(),
+            [vec![Fault::ConnectRefused], vec![Fault::ConnectRefused]],
+        );
+        let client = ClamAvClient::builder(transport)
+            .retry_policy(RetryPolicy {
+                max_retries: 2,
+                backoff: Duration::from_millis(1),
+            })
+            .build();
+        let response = client.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+
+    #[test]
+    fn corrupt_responses() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport = FaultInjector::scripted(
+            server.transport().clone(),
+            [
+                vec![Fault::TruncateResponse(8)],
+                vec![Fault::Garbage(b"\xff\xfe".to_vec())],
+                vec![
+                    Fault::PartialReads(1),
+                    Fault::Delay(Duration::from_millis(1)),
+                ],
+            ],
+        );
+        let response = transport.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(&response, b"stream: ");
+        let response = transport.scan_buffer(EICAR, None).unwrap();
+        assert!(clamav_client::clean(&response).is_err());
+        let response = transport.scan_buffer(EICAR, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+
+    #[test]
+    fn reset_mid_stream() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport =
+            FaultInjector::scripted(server.transport().clone(), [vec![Fault::ResetAfter(20)]]);
+        let err = transport.scan_buffer(&[0; 1024], Some(16)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn random_faults_are_reproducible() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let outcomes = || {
+            let transport = FaultInjector::random(
+                server.transport().clone(),
+                0.5,
+                vec![Fault::ConnectRefused],
+                42,
+            );
+            (0..20)
+                .map(|_| transport.ping().is_ok())
+                .collect::<Vec<_>>()
+        };
+        let first = outcomes();
+        assert!(first.contains(&true) && first.contains(&false));
+        assert_eq!(first, outcomes());
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_fault_async {
+    use super::*;
+    use clamav_client::testing::{Fault, FaultInjector};
+    use clamav_client::{ClamAvAsync, ClamAvClient};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn delay_exceeds_read_timeout() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport = FaultInjector::scripted(
+            server.transport().clone(),
+            [vec![Fault::Delay(Duration::from_millis(200))]],
+        );
+        let client = ClamAvClient::builder(transport)
+            .connect_timeout(Duration::from_millis(50))
+            .build();
+        let err = client.ping().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[async_std::test]
+    async fn garbage_and_partial_reads() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport = FaultInjector::scripted(
+            server.transport().clone(),
+            [
+                vec![Fault::Garbage(b"PANG\0".to_vec()), Fault::PartialReads(2)],
+                vec![Fault::PartialReads(3)],
+            ],
+        );
+        assert_eq!(&transport.ping().await.unwrap(), b"PANG\0");
+        let response = transport.scan_buffer(EICAR, None).await.unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+
+    #[tokio::test]
+    async fn reset_mid_stream() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport =
+            FaultInjector::scripted(server.transport().clone(), [vec![Fault::ResetAfter(20)]]);
+        let err = transport
+            .scan_buffer(&[0; 1024], Some(16))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+    }
+}
+
+mod test_replay_sync {
+    use super::*;
+    use clamav_client::testing::{Direction, Fixture, Recorder, Replay};
+    use clamav_client::ClamAvSync;
+
+    const FIXTURE_PATH: &str = "tests/data/clamd-session.fixture";
+
+    #[test]
+    fn replay_fixture() {
+        let replay = Replay::load(FIXTURE_PATH).unwrap();
+        assert_eq!(&replay.ping().unwrap(), clamav_client::PONG);
+        assert!(replay.get_version().unwrap().starts_with(b"ClamAV 1.4.1"));
+        let response = replay.scan_file(EICAR_TEST_FILE_PATH, None).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        assert_eq!(replay.remaining(), 0);
+
+        let err = replay.ping().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let recorder = Recorder::new(server.transport().clone());
+        recorder.ping().unwrap();
+        recorder.scan_buffer(EICAR, Some(10)).unwrap();
+
+        let fixture = recorder.fixture();
+        assert_eq!(fixture.sessions.len(), 2);
+        let exchanges = &fixture.sessions[1].exchanges;
+        assert_eq!(exchanges[0].direction, Direction::Sent);
+        assert!(exchanges[0].data.starts_with(b"zINSTREAM\0\0\0\0\x0a"));
+        assert_eq!(exchanges[1].direction, Direction::Received);
+        assert_eq!(&exchanges[1].data, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+
+        let fixture = Fixture::parse(&fixture.to_string()).unwrap();
+        assert_eq!(fixture, recorder.fixture());
+
+        let replay = Replay::new(fixture);
+        assert_eq!(&replay.ping().unwrap(), clamav_client::PONG);
+        let response = replay.scan_buffer(EICAR, Some(10)).unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+
+    #[test]
+    fn reject_unexpected_request() {
+        let replay = Replay::load(FIXTURE_PATH).unwrap();
+        let err = replay.get_version().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reject_invalid_fixture() {
+        assert!(Fixture::parse("> 00").is_err());
+        assert!(Fixture::parse("session\n> 0").is_err());
+        assert!(Fixture::parse("session\n? 00").is_err());
+        assert!(Fixture::parse("session\n→ 00").is_err());
+    }
+}
+
+#[cfg(feature = "async")]
+mod test_replay_async {
+    use super::*;
+    use clamav_client::testing::{Recorder, Replay};
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn record_and_replay_stream() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let recorder = Recorder::new(server.transport().clone());
+        let chunks = || {
+            let chunks = EICAR
+                .chunks(16)
+                .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)));
+            futures_lite::stream::iter(chunks)
+        };
+        let recorded = recorder.scan_stream(chunks(), None).await.unwrap();
+
+        let replay = Replay::new(recorder.fixture());
+        let replayed = replay.scan_stream(chunks(), None).await.unwrap();
+        assert_eq!(recorded, replayed);
+        assert_eq!(&replayed, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod test_fault_tokio {
+    use super::*;
+    use clamav_client::testing::{Fault, FaultInjector};
+    use clamav_client::{ClamAvAsync, ClamAvClient};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn delay_exceeds_read_timeout() {
+        let server = mock_clamd().bind_tcp("127.0.0.1:0").unwrap();
+        let transport = FaultInjector::scripted(
+            clamav_client::tokio::Tcp(server.transport().0),
+            [vec![], vec![Fault::Delay(Duration::from_millis(200))]],
+        );
+        let client = ClamAvClient::builder(transport)
+            .read_timeout(Duration::from_millis(50))
+            .build();
+        let response = client.scan_file(EICAR_TEST_FILE_PATH, None).await.unwrap();
+        assert_eq!(&response, EICAR_FILE_SIGNATURE_FOUND_RESPONSE);
+        let err = client.ping().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,208 @@
+#![cfg(all(feature = "tower", feature = "testing"))]
+
+mod common;
+
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use clamav_client::testing::EICAR;
+use clamav_client::tower::{ScanLayer, ScannedBody};
+use clamav_client::{Tcp, Verdict, VerdictPolicy};
+use common::limited_mock_clamd;
+use http::{header, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use tower::{service_fn, Layer, ServiceExt};
+
+fn request(content_type: &str, body: impl Into<Bytes>) -> Request<Full<Bytes>> {
+    Request::post("/upload")
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Full::new(body.into()))
+        .unwrap()
+}
+
+/// Answers with the body, whether it was scanned and the permissions of the
+/// spool file, `none` if it was not spooled
+async fn echo(
+    request: Request<ScannedBody<Full<Bytes>>>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let verdict = request.extensions().get::<Verdict>().cloned();
+    let scanned = request.body().is_scanned();
+    let spooled = match request.body().spool_path() {
+        #[cfg(unix)]
+        Some(path) => {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            format!("{:o}", mode & 0o777)
+        }
+        #[cfg(not(unix))]
+        Some(path) => path.exists().to_string(),
+        None => String::from("none"),
+    };
+    let body = request.into_body().collect().await.unwrap().to_bytes();
+    let response = Response::builder()
+        .header("X-Verdict", format!("{verdict:?}"))
+        .header("X-Scanned", scanned.to_string())
+        .header("X-Spooled", spooled)
+        .body(Full::new(body))
+        .unwrap();
+    Ok(response)
+}
+
+async fn call(layer: &ScanLayer<Tcp>, request: Request<Full<Bytes>>) -> (Response<()>, Bytes) {
+    let response = layer
+        .layer(service_fn(echo))
+        .oneshot(request)
+        .await
+        .unwrap();
+    let (parts, body) = response.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    (Response::from_parts(parts, ()), body)
+}
+
+#[tokio::test]
+async fn clean_body_forwarded() {
+    let server = limited_mock_clamd(1_000);
+    let layer = ScanLayer::new(server.transport().clone()).chunk_size(4);
+    let (response, body) = call(&layer, request("text/plain", "clean data")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "clean data");
+    assert_eq!(response.headers()["X-Verdict"], "Some(Clean)");
+    assert_eq!(response.headers()["X-Spooled"], "none");
+    assert_eq!(server.scans(), 1);
+}
+
+#[tokio::test]
+async fn infected_body_rejected() {
+    let server = limited_mock_clamd(1_000);
+    let layer = ScanLayer::new(server.transport().clone());
+    let (response, body) = call(&layer, request("application/octet-stream", EICAR)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.headers()["X-Virus-ID"], "Eicar-Signature");
+    assert_eq!(body, "virus found: Eicar-Signature\n");
+}
+
+#[tokio::test]
+async fn clamd_unreachable() {
+    let mut server = limited_mock_clamd(1_000);
+    server.stop();
+    let layer = ScanLayer::new(server.transport().clone());
+    let (response, _) = call(&layer, request("text/plain", "data")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let layer = layer.verdict_policy(VerdictPolicy::Strict);
+    let (response, _) = call(&layer, request("text/plain", "data")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // The handler sees the error although the body is forwarded
+    let layer = layer.verdict_policy(VerdictPolicy::FailOpen);
+    let (response, body) = call(&layer, request("text/plain", "data")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "data");
+    assert!(response.headers()["X-Verdict"]
+        .to_str()
+        .unwrap()
+        .starts_with("Some(Error("));
+}
+
+#[tokio::test]
+async fn content_type_filter() {
+    let server = limited_mock_clamd(1_000);
+    let layer =
+        ScanLayer::new(server.transport().clone()).content_types(["Image/", "application/pdf"]);
+
+    let (response, body) = call(&layer, request("text/plain", EICAR)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, EICAR);
+    assert_eq!(response.headers()["X-Scanned"], "false");
+    assert_eq!(response.headers()["X-Verdict"], "None");
+    assert_eq!(server.scans(), 0);
+
+    for content_type in ["image/png", "application/pdf; charset=binary"] {
+        let (response, _) = call(&layer, request(content_type, EICAR)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    assert_eq!(server.scans(), 2);
+
+    // Bodies without a content type are scanned, empty bodies are not
+    let (response, _) = call(&layer, Request::new(Full::new(Bytes::from_static(EICAR)))).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let (response, _) = call(&layer, request("image/png", Bytes::new())).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(server.scans(), 3);
+}
+
+#[tokio::test]
+async fn body_too_large() {
+    let server = limited_mock_clamd(1_000);
+    let layer = ScanLayer::new(server.transport().clone()).max_body_size(10);
+
+    // Rejected by the Content-Length header before scanning
+    let mut large = request("text/plain", "eleven char");
+    large
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, "11".parse().unwrap());
+    let (response, _) = call(&layer, large).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(server.scans(), 0);
+
+    // Rejected while streaming
+    let (response, _) = call(&layer, request("text/plain", "eleven char")).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let (response, body) = call(&layer, request("text/plain", "ten chars!")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "ten chars!");
+}
+
+#[tokio::test]
+async fn large_body_spooled() {
+    let dir = std::env::temp_dir().join(format!("clamav-tower-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let server = limited_mock_clamd(1_000);
+    let layer = ScanLayer::new(server.transport().clone())
+        .max_memory_size(100)
+        .spool_dir(&dir)
+        .chunk_size(64);
+
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    // The body is larger than clamd's limit
+    let (response, _) = call(&layer, request("application/octet-stream", data.clone())).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // clamd answers before the end of the body, the rest is still forwarded
+    let layer = layer.verdict_policy(VerdictPolicy::FailOpen);
+    let (response, body) = call(&layer, request("application/octet-stream", data.clone())).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    if cfg!(unix) {
+        assert_eq!(response.headers()["X-Spooled"], "600");
+    }
+    assert_eq!(body, data);
+
+    // Spool files are deleted with the body, and when the body is rejected
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn axum_router() {
+    use axum::{body::Body, routing::post, Router};
+
+    let server = limited_mock_clamd(1_000);
+    let app = Router::new()
+        .route(
+            "/upload",
+            post(|body: Bytes| async move { format!("{} bytes", body.len()) }),
+        )
+        .layer(ScanLayer::new(server.transport().clone()));
+
+    let request = Request::post("/upload").body(Body::from("clean")).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, "5 bytes");
+
+    let request = Request::post("/upload").body(Body::from(EICAR)).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(server.scans(), 2);
+}
@@ -0,0 +1,225 @@
+#![cfg(all(feature = "tracing", feature = "testing"))]
+
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use clamav_client::testing::EICAR;
+use clamav_client::Verdict;
+use common::limited_mock_clamd;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+/// Span with the fields recorded so far
+#[derive(Debug, Clone, Default)]
+struct RecordedSpan {
+    name: &'static str,
+    fields: BTreeMap<String, String>,
+    events: Vec<BTreeMap<String, String>>,
+}
+
+/// Subscriber that keeps every span and the events inside it
+#[derive(Clone, Default)]
+struct Recorder {
+    spans: Arc<Mutex<Vec<RecordedSpan>>>,
+    current: Arc<Mutex<Vec<u64>>>,
+}
+
+struct FieldVisitor<'a>(&'a mut BTreeMap<String, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(
+            field.name().to_string(),
+            format!("{value:?}").replace('"', ""),
+        );
+    }
+}
+
+impl Recorder {
+    fn spans(&self, name: &str) -> Vec<RecordedSpan> {
+        let spans = self.spans.lock().unwrap();
+        spans
+            .iter()
+            .filter(|span| span.name == name)
+            .cloned()
+            .collect()
+    }
+
+    fn all_values(&self) -> Vec<String> {
+        let spans = self.spans.lock().unwrap();
+        spans
+            .iter()
+            .flat_map(|span| {
+                span.fields
+                    .values()
+                    .chain(span.events.iter().flat_map(|e| e.values()))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &span::Attributes<'_>) -> span::Id {
+        let mut span = RecordedSpan {
+            name: attributes.metadata().name(),
+            ..RecordedSpan::default()
+        };
+        attributes.record(&mut FieldVisitor(&mut span.fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(span);
+        span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        let span = &mut spans[id.into_u64() as usize - 1];
+        values.record(&mut FieldVisitor(&mut span.fields));
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = BTreeMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        fields.insert("level".to_string(), event.metadata().level().to_string());
+        if let Some(&id) = self.current.lock().unwrap().last() {
+            self.spans.lock().unwrap()[id as usize - 1]
+                .events
+                .push(fields);
+        }
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.current.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, _span: &span::Id) {
+        self.current.lock().unwrap().pop();
+    }
+}
+
+fn assert_instream(recorder: &Recorder, bytes: &str, chunks: &str) {
+    let spans = recorder.spans("clamav.instream");
+    assert_eq!(spans.len(), 1);
+    let fields = &spans[0].fields;
+    assert_eq!(fields["bytes"], bytes);
+    assert_eq!(fields["chunks"], chunks);
+    assert_eq!(fields["response_bytes"], "30");
+    assert!(fields.contains_key("duration_ms"));
+    assert_eq!(spans[0].events.last().unwrap()["message"], "completed");
+}
+
+mod test_tracing_sync {
+    use super::*;
+    use clamav_client::ClamAvSync;
+
+    #[test]
+    fn spans_for_commands_and_scans() {
+        let server = limited_mock_clamd(1_000);
+        let recorder = Recorder::default();
+        let endpoint = server.transport().0.to_string();
+
+        let verdict = tracing::subscriber::with_default(recorder.clone(), || {
+            server.transport().get_version().unwrap();
+            let response = server.transport().scan_buffer(EICAR, Some(32)).unwrap();
+            Verdict::parse(&response).unwrap()
+        });
+        assert!(!verdict.is_clean());
+
+        let connects = recorder.spans("clamav.connect");
+        assert_eq!(connects.len(), 2);
+        assert!(connects
+            .iter()
+            .all(|span| span.fields["endpoint"] == endpoint));
+
+        let commands = recorder.spans("clamav.command");
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].fields["command"], "VERSION");
+        assert_eq!(commands[0].fields["response_bytes"], "44");
+
+        assert_instream(&recorder, "68", "3");
+
+        let parsed = recorder.spans("clamav.parse");
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].fields["verdict"], "infected");
+        assert_eq!(parsed[0].fields["signature"], "Eicar-Signature");
+
+        // Scanned data is never recorded
+        assert!(recorder
+            .all_values()
+            .iter()
+            .all(|value| !value.contains("EICAR-STANDARD")));
+    }
+
+    #[test]
+    fn failures_are_warnings() {
+        let mut server = limited_mock_clamd(1_000);
+        server.stop();
+        let recorder = Recorder::default();
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            assert!(server.transport().ping().is_err());
+        });
+
+        let connects = recorder.spans("clamav.connect");
+        assert_eq!(connects.len(), 1);
+        let event = connects[0].events.last().unwrap();
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["message"], "failed");
+        assert!(event.contains_key("error"));
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn cached_scans_are_parsed_once() {
+        use clamav_client::cache::{CachedClient, MemoryCache};
+
+        let server = limited_mock_clamd(1_000);
+        let client = CachedClient::new(server.transport().clone(), MemoryCache::new(10));
+        let recorder = Recorder::default();
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let response = client.scan_buffer(EICAR, None).unwrap();
+            Verdict::parse(&response).unwrap()
+        });
+        assert_eq!(recorder.spans("clamav.parse").len(), 1);
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod test_tracing_async {
+    use super::*;
+    use clamav_client::ClamAvAsync;
+
+    #[tokio::test]
+    async fn spans_for_streams() {
+        let server = limited_mock_clamd(1_000);
+        let recorder = Recorder::default();
+        let _default = tracing::subscriber::set_default(recorder.clone());
+
+        let chunks = EICAR
+            .chunks(10)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let transport = clamav_client::tokio::Tcp(server.transport().0);
+        transport
+            .scan_stream(futures_lite::stream::iter(chunks), None)
+            .await
+            .unwrap();
+
+        assert_eq!(recorder.spans("clamav.connect").len(), 1);
+        assert_instream(&recorder, "68", "7");
+    }
+}
@@ -0,0 +1,290 @@
+#![cfg(all(feature = "watch", feature = "testing"))]
+
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use clamav_client::dir::ScanDirOptions;
+use clamav_client::testing::{MockClamdServer, EICAR};
+use clamav_client::watch::{Action, ActionTaken, WatchEvent, WatchOptions, Watcher};
+use clamav_client::{Tcp, Verdict, VerdictPolicy};
+use common::{limited_mock_clamd, mock_clamd};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Watched directory and a quarantine directory next to it, removed on drop
+struct Dirs {
+    base: PathBuf,
+    watched: PathBuf,
+    quarantine: PathBuf,
+}
+
+impl Dirs {
+    fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let base = std::env::temp_dir().join(format!(
+            "clamav-client-watch-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let watched = base.join("watched");
+        fs::create_dir_all(&watched).unwrap();
+        Dirs {
+            watched: fs::canonicalize(&watched).unwrap(),
+            quarantine: base.join("quarantine"),
+            base,
+        }
+    }
+}
+
+impl Drop for Dirs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.base);
+    }
+}
+
+fn options() -> WatchOptions {
+    WatchOptions::new()
+        .debounce(Duration::from_millis(50))
+        .settle(Duration::from_millis(200))
+}
+
+/// Runs a watcher on a background thread and forwards its events
+fn spawn(
+    server: &MockClamdServer<Tcp>,
+    dirs: &Dirs,
+    options: WatchOptions,
+) -> (mpsc::Receiver<WatchEvent>, clamav_client::watch::StopHandle) {
+    let watcher = Watcher::new(&dirs.watched, options).unwrap();
+    let stop = watcher.stop_handle();
+    let transport = server.transport().clone();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        watcher.run(&transport, |event| {
+            let _ = tx.send(event);
+        })
+    });
+    (rx, stop)
+}
+
+#[test]
+fn scan_new_files() {
+    let server = mock_clamd();
+    let dirs = Dirs::new();
+    let options = options().on_infected(Action::MoveTo(dirs.quarantine.clone()));
+    let (events, stop) = spawn(&server, &dirs, options);
+
+    fs::write(dirs.watched.join("clean.txt"), b"clean").unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(event.path, dirs.watched.join("clean.txt"));
+    assert_eq!(event.size, Some(5));
+    assert!(clamav_client::clean(&event.response.unwrap()).unwrap());
+    assert!(event.action.is_none());
+
+    fs::create_dir(dirs.watched.join("sub")).unwrap();
+    fs::write(dirs.watched.join("sub/eicar.com"), EICAR).unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(event.path, dirs.watched.join("sub/eicar.com"));
+    assert!(!clamav_client::clean(&event.response.unwrap()).unwrap());
+    let moved = dirs.quarantine.join("eicar.com");
+    assert_eq!(
+        event.action.unwrap().unwrap(),
+        ActionTaken::Moved(moved.clone())
+    );
+    assert!(moved.exists());
+    assert!(!dirs.watched.join("sub/eicar.com").exists());
+
+    stop.stop();
+    assert!(events.recv_timeout(TIMEOUT).is_err());
+    assert_eq!(server.scans(), 2);
+}
+
+#[test]
+fn wait_for_writes_to_settle() {
+    let server = mock_clamd();
+    let dirs = Dirs::new();
+    let (events, stop) = spawn(&server, &dirs, options());
+
+    let mut file = fs::File::create(dirs.watched.join("upload.bin")).unwrap();
+    for _ in 0..5 {
+        file.write_all(&[0; 1000]).unwrap();
+        file.flush().unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+    drop(file);
+
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(event.size, Some(5000));
+    assert!(events.recv_timeout(Duration::from_millis(500)).is_err());
+    stop.stop();
+}
+
+#[test]
+fn scan_existing_and_delete() {
+    let server = mock_clamd();
+    let dirs = Dirs::new();
+    fs::write(dirs.watched.join("eicar.com"), EICAR).unwrap();
+    let options = options().scan_existing(true).on_infected(Action::Delete);
+    let (events, stop) = spawn(&server, &dirs, options);
+
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(event.action.unwrap().unwrap(), ActionTaken::Deleted);
+    assert!(!dirs.watched.join("eicar.com").exists());
+    stop.stop();
+}
+
+#[test]
+fn move_without_overwriting() {
+    let server = mock_clamd();
+    let dirs = Dirs::new();
+    fs::create_dir_all(&dirs.quarantine).unwrap();
+    fs::write(dirs.quarantine.join("eicar.com"), b"first").unwrap();
+    fs::write(dirs.quarantine.join("eicar.com.1"), b"second").unwrap();
+    let options = options().on_infected(Action::MoveTo(dirs.quarantine.clone()));
+    let (events, stop) = spawn(&server, &dirs, options);
+
+    fs::write(dirs.watched.join("eicar.com"), EICAR).unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    let moved = dirs.quarantine.join("eicar.com.2");
+    assert_eq!(
+        event.action.unwrap().unwrap(),
+        ActionTaken::Moved(moved.clone())
+    );
+    assert_eq!(fs::read(moved).unwrap(), EICAR);
+    assert_eq!(
+        fs::read(dirs.quarantine.join("eicar.com")).unwrap(),
+        b"first"
+    );
+    assert_eq!(
+        fs::read(dirs.quarantine.join("eicar.com.1")).unwrap(),
+        b"second"
+    );
+    assert!(!dirs.watched.join("eicar.com").exists());
+    stop.stop();
+}
+
+#[test]
+fn verdict_policy() {
+    let server = limited_mock_clamd(10);
+    let dirs = Dirs::new();
+    let (events, stop) = spawn(&server, &dirs, options().on_error(Action::Delete));
+
+    fs::write(dirs.watched.join("large.txt"), b"more than ten bytes").unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert!(matches!(event.verdict, Ok(Verdict::Error(_))));
+    assert_eq!(event.action.unwrap().unwrap(), ActionTaken::Deleted);
+    stop.stop();
+
+    let dirs = Dirs::new();
+    let fail_open = options()
+        .on_error(Action::Delete)
+        .verdict_policy(VerdictPolicy::FailOpen);
+    let (events, stop) = spawn(&server, &dirs, fail_open);
+
+    fs::write(dirs.watched.join("large.txt"), b"more than ten bytes").unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(event.verdict.unwrap(), Verdict::Clean);
+    assert!(event.action.is_none());
+    assert!(dirs.watched.join("large.txt").exists());
+    stop.stop();
+}
+
+#[cfg(feature = "quarantine")]
+#[test]
+fn quarantine_infected_files() {
+    use clamav_client::quarantine::Quarantine;
+
+    let server = mock_clamd();
+    let dirs = Dirs::new();
+    let quarantine = Quarantine::open(&dirs.quarantine).unwrap();
+    let options = options().on_infected(Action::Quarantine(quarantine.clone()));
+    let (events, stop) = spawn(&server, &dirs, options);
+
+    fs::write(dirs.watched.join("eicar.com"), EICAR).unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    let entry = match event.action.unwrap().unwrap() {
+        ActionTaken::Quarantined(entry) => entry,
+        taken => panic!("unexpected action: {taken:?}"),
+    };
+    assert_eq!(entry.original_path, dirs.watched.join("eicar.com"));
+    assert_eq!(entry.signatures, vec!["Eicar-Signature".to_string()]);
+    assert_eq!(quarantine.list().unwrap(), vec![entry]);
+    assert!(!dirs.watched.join("eicar.com").exists());
+    stop.stop();
+}
+
+#[test]
+fn filters_and_renames() {
+    let server = mock_clamd();
+    let dirs = Dirs::new();
+    let filter = ScanDirOptions::new().exclude("*.part").skip_hidden(true);
+    let (events, stop) = spawn(&server, &dirs, options().filter(filter));
+
+    fs::write(dirs.watched.join(".hidden"), b"hidden").unwrap();
+    fs::write(dirs.watched.join("upload.part"), EICAR).unwrap();
+    assert!(events.recv_timeout(Duration::from_millis(600)).is_err());
+
+    fs::rename(
+        dirs.watched.join("upload.part"),
+        dirs.watched.join("upload.txt"),
+    )
+    .unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(event.path, dirs.watched.join("upload.txt"));
+    assert!(!clamav_client::clean(&event.response.unwrap()).unwrap());
+    stop.stop();
+}
+
+#[test]
+fn server_errors() {
+    let mut server = mock_clamd();
+    let dirs = Dirs::new();
+    let options = options().on_error(Action::MoveTo(dirs.quarantine.clone()));
+    let (events, stop) = spawn(&server, &dirs, options);
+    server.stop();
+
+    fs::write(dirs.watched.join("unscanned.txt"), b"data").unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert!(event.response.is_err());
+    assert!(matches!(event.action, Some(Ok(ActionTaken::Moved(_)))));
+    assert!(dirs.quarantine.join("unscanned.txt").exists());
+    stop.stop();
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn run_async() {
+    let server = mock_clamd();
+    let dirs = Dirs::new();
+    let watcher = Watcher::new(&dirs.watched, options()).unwrap();
+    let stop = watcher.stop_handle();
+    let (tx, rx) = mpsc::channel();
+
+    let path = dirs.watched.join("eicar.com");
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        fs::write(path, EICAR).unwrap();
+        let event: WatchEvent = rx.recv_timeout(TIMEOUT).unwrap();
+        stop.stop();
+        event
+    });
+    watcher
+        .run_async(server.transport(), |event| {
+            let _ = tx.send(event);
+        })
+        .await;
+
+    let event = writer.join().unwrap();
+    assert_eq!(event.size, Some(EICAR.len() as u64));
+    assert!(!clamav_client::clean(&event.response.unwrap()).unwrap());
+}